@@ -0,0 +1,255 @@
+-- Size of a clustering grid cell (in Web Mercator meters) for a given zoom level, computed from
+-- the `cartography_cluster` option with the same defaults as the backend
+CREATE OR REPLACE FUNCTION cluster_cell_size(zoom_level INT) RETURNS DOUBLE PRECISION AS $$
+    SELECT GREATEST(
+        COALESCE((o.value->>'characteristic_distance')::double precision, 5.0)
+            * 100000
+            * power(COALESCE((o.value->>'declustering_speed')::double precision, 1.65), -zoom_level),
+        -- Below this size, cells coordinates would not fit in a cluster identifier
+        2.5
+    )
+    FROM (SELECT (SELECT value FROM options WHERE name = 'cartography_cluster') AS value) o
+$$ LANGUAGE sql STABLE;
+
+-- Stable identifier of a grid cell, packing the zoom level and the cell coordinates in a BIGINT
+-- (kept under 2^53 so it can be safely used as a JavaScript number)
+CREATE OR REPLACE FUNCTION cluster_cell_id(zoom_level INT, cell_x BIGINT, cell_y BIGINT) RETURNS BIGINT AS $$
+    SELECT (zoom_level::bigint << 48) | ((cell_x + 8388608) << 24) | (cell_y + 8388608)
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Precomputed grid cell of each cached entity for every clustered zoom level
+CREATE MATERIALIZED VIEW entities_caches_clusters AS
+SELECT
+    ec.id AS cache_id,
+    ec.family_id,
+    zl.zoom_level,
+    cluster_cell_id(
+        zl.zoom_level,
+        floor(ST_X(ec.web_mercator_location) / zl.cell_size)::bigint,
+        floor(ST_Y(ec.web_mercator_location) / zl.cell_size)::bigint
+    ) AS cluster_id
+FROM entities_caches ec
+CROSS JOIN (
+    SELECT zoom_level, cluster_cell_size(zoom_level) AS cell_size
+    FROM generate_series(0, 18) AS zoom_level
+) zl
+WHERE ec.web_mercator_location IS NOT NULL;
+
+CREATE UNIQUE INDEX entities_caches_clusters_id_idx ON entities_caches_clusters(cache_id, zoom_level);
+CREATE INDEX entities_caches_clusters_cluster_id_idx ON entities_caches_clusters(cluster_id);
+
+-- Refresh the clusters along with the entities cache
+CREATE OR REPLACE FUNCTION refresh_entities_caches() RETURNS void AS $$
+BEGIN
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches;
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches_clusters;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Clusters depend on the `cartography_cluster` option, refresh them when it changes
+CREATE OR REPLACE FUNCTION trigger_refresh_entities_caches_clusters()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD.value IS NOT DISTINCT FROM NEW.value THEN
+        RETURN NULL;
+    END IF;
+
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches_clusters;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER refresh_entities_caches_clusters_on_insert_or_update
+AFTER INSERT OR UPDATE ON options
+FOR EACH ROW
+WHEN (NEW.name = 'cartography_cluster')
+EXECUTE FUNCTION trigger_refresh_entities_caches_clusters();
+
+CREATE TRIGGER refresh_entities_caches_clusters_on_delete
+AFTER DELETE ON options
+FOR EACH ROW
+WHEN (OLD.name = 'cartography_cluster')
+EXECUTE FUNCTION trigger_refresh_entities_caches_clusters();
+
+-- Replace the viewport based DBSCAN clustering with the precomputed grid clustering. Entities that
+-- are not part of a cluster are only returned when they are within the requested view, not within
+-- the view extended to the borders of the grid cells.
+DROP FUNCTION fetch_entities_within_view(
+    DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    DOUBLE PRECISION, INT,
+    UUID[], UUID[], UUID[], JSONB
+);
+
+CREATE OR REPLACE FUNCTION fetch_entities_within_view(
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    cluster_zoom_level INT,
+    cluster_min_points INT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_x DOUBLE PRECISION,
+    web_mercator_y DOUBLE PRECISION,
+    plain_text_location TEXT,
+    cluster_id BIGINT,
+    cluster_center_x DOUBLE PRECISION,
+    cluster_center_y DOUBLE PRECISION
+) AS $$
+DECLARE
+    clustering BOOL := cluster_zoom_level >= 0 AND cluster_min_points > 0;
+    cell_size DOUBLE PRECISION;
+    view_envelope GEOMETRY := ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857);
+BEGIN
+    -- When clustering, the view is extended to the borders of the grid cells it overlaps
+    -- so every cluster is computed over all its members, regardless of the viewport
+    IF clustering THEN
+        cell_size := cluster_cell_size(cluster_zoom_level);
+        input_xmin := floor(input_xmin / cell_size) * cell_size;
+        input_ymin := floor(input_ymin / cell_size) * cell_size;
+        input_xmax := (floor(input_xmax / cell_size) + 1) * cell_size;
+        input_ymax := (floor(input_ymax / cell_size) + 1) * cell_size;
+    END IF;
+
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.id,
+            ec.entity_id,
+            ec.category_id,
+            ec.tags_ids,
+            ec.family_id,
+            ec.display_name,
+            ec.parent_id,
+            ec.parent_display_name,
+            ec.web_mercator_location,
+            ec.plain_text_location,
+            ec.enums
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Geographic filter
+            AND ST_Intersects(
+                ec.web_mercator_location,
+                ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857)
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE
+            -- Categories filter
+            (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags filter
+            AND (at_allow_all_tags OR ie.tags_ids && at_allowed_tags_ids)
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+    ),
+    parent_entities AS (
+        SELECT
+            DISTINCT ie.id,
+            ie.entity_id,
+            ie.category_id,
+            ie.tags_ids,
+            ie.family_id,
+            ie.display_name,
+            ie.parent_id,
+            ie.parent_display_name,
+            ie.web_mercator_location,
+            ie.plain_text_location,
+            ie.enums
+        FROM included_entities ie
+        WHERE ie.entity_id IN (SELECT DISTINCT fe.parent_id FROM filtered_entities fe)
+    ),
+    combined_entities AS (
+        SELECT * FROM filtered_entities fe WHERE fe.parent_id IS NULL
+        UNION
+        SELECT * FROM parent_entities
+    ),
+    celled_entities AS (
+        SELECT
+            ce.*,
+            ecc.cluster_id AS cell_id
+        FROM combined_entities ce
+        LEFT JOIN entities_caches_clusters ecc
+            ON clustering
+            AND ecc.cache_id = ce.id
+            AND ecc.zoom_level = cluster_zoom_level
+    ),
+    clusters AS (
+        SELECT
+            cle.cell_id,
+            AVG(ST_X(cle.web_mercator_location)) AS cluster_center_x,
+            AVG(ST_Y(cle.web_mercator_location)) AS cluster_center_y
+        FROM celled_entities cle
+        WHERE cle.cell_id IS NOT NULL
+        GROUP BY cle.cell_id
+        HAVING COUNT(*) >= cluster_min_points
+    )
+    SELECT
+        cle.id,
+        cle.entity_id,
+        cle.category_id,
+        cle.tags_ids,
+        cle.family_id,
+        cle.display_name,
+        cle.parent_id,
+        cle.parent_display_name,
+        ST_X(cle.web_mercator_location) AS web_mercator_x,
+        ST_Y(cle.web_mercator_location) AS web_mercator_y,
+        cle.plain_text_location,
+        cl.cell_id AS cluster_id,
+        cl.cluster_center_x,
+        cl.cluster_center_y
+    FROM celled_entities cle
+    LEFT JOIN clusters cl ON cle.cell_id = cl.cell_id
+    WHERE cl.cell_id IS NOT NULL OR ST_Intersects(cle.web_mercator_location, view_envelope);
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,127 @@
+CREATE OR REPLACE FUNCTION fetch_cluster_members(
+    input_cluster_id BIGINT,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB,
+
+    current_page BIGINT,
+    page_size BIGINT
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_x DOUBLE PRECISION,
+    web_mercator_y DOUBLE PRECISION,
+    plain_text_location TEXT,
+    cluster_xmin DOUBLE PRECISION,
+    cluster_ymin DOUBLE PRECISION,
+    cluster_xmax DOUBLE PRECISION,
+    cluster_ymax DOUBLE PRECISION,
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.*
+        FROM entities_caches ec
+        JOIN entities_caches_clusters ecc ON ecc.cache_id = ec.id
+        WHERE
+            -- Cluster filter
+            ecc.cluster_id = input_cluster_id
+            -- Family filter
+            AND ec.family_id = input_family_id
+            -- Geographic filter
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE
+            -- Categories filter
+            (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags filter
+            AND (at_allow_all_tags OR ie.tags_ids && at_allowed_tags_ids)
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+    ),
+    -- Same members as the cluster returned by fetch_entities_within_view: entities without
+    -- parents, and parents of the filtered entities
+    combined_entities AS (
+        SELECT fe.* FROM filtered_entities fe WHERE fe.parent_id IS NULL
+        UNION
+        SELECT ie.* FROM included_entities ie
+        WHERE ie.entity_id IN (SELECT DISTINCT fe.parent_id FROM filtered_entities fe)
+    ),
+    bounds AS (
+        SELECT
+            COUNT(*) AS total_results,
+            MIN(ST_X(ce.web_mercator_location)) AS cluster_xmin,
+            MIN(ST_Y(ce.web_mercator_location)) AS cluster_ymin,
+            MAX(ST_X(ce.web_mercator_location)) AS cluster_xmax,
+            MAX(ST_Y(ce.web_mercator_location)) AS cluster_ymax
+        FROM combined_entities ce
+    )
+    SELECT
+        ce.id,
+        ce.entity_id,
+        ce.category_id,
+        ce.family_id,
+        ce.display_name,
+        ce.parent_id,
+        ce.parent_display_name,
+        ST_X(ce.web_mercator_location) AS web_mercator_x,
+        ST_Y(ce.web_mercator_location) AS web_mercator_y,
+        ce.plain_text_location,
+        b.cluster_xmin,
+        b.cluster_ymin,
+        b.cluster_xmax,
+        b.cluster_ymax,
+        b.total_results,
+        CEIL(b.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+        current_page AS response_current_page
+    FROM combined_entities ce, bounds b
+    ORDER BY ce.display_name, ce.id
+    LIMIT page_size
+    OFFSET (current_page - 1) * page_size;
+END;
+$$ LANGUAGE plpgsql;
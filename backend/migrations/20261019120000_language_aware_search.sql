@@ -0,0 +1,688 @@
+CREATE EXTENSION IF NOT EXISTS "unaccent";
+
+-- `unaccent` is only stable as it depends on the search path, pin the dictionary to index its results
+CREATE OR REPLACE FUNCTION immutable_unaccent(input TEXT) RETURNS TEXT AS $$
+    SELECT public.unaccent('public.unaccent'::regdictionary, input)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- For each built-in text search configuration, create an accent-insensitive copy named
+-- `<language>_unaccent` that strips accents before stemming
+DO $$
+DECLARE
+    cfg RECORD;
+    word_dictionaries TEXT;
+BEGIN
+    FOR cfg IN
+        SELECT c.oid, c.cfgname
+        FROM pg_ts_config c
+        WHERE c.cfgnamespace = 'pg_catalog'::regnamespace
+    LOOP
+        SELECT string_agg(m.mapdict::regdictionary::text, ', ' ORDER BY m.mapseqno)
+        INTO word_dictionaries
+        FROM pg_ts_config_map m
+        WHERE m.mapcfg = cfg.oid
+            AND m.maptokentype = (SELECT tokid FROM ts_token_type('default') WHERE alias = 'word');
+
+        EXECUTE format(
+            'CREATE TEXT SEARCH CONFIGURATION public.%I (COPY = pg_catalog.%I)',
+            cfg.cfgname || '_unaccent',
+            cfg.cfgname
+        );
+        EXECUTE format(
+            'ALTER TEXT SEARCH CONFIGURATION public.%I ALTER MAPPING FOR hword, hword_part, word WITH public.unaccent, %s',
+            cfg.cfgname || '_unaccent',
+            word_dictionaries
+        );
+    END LOOP;
+END;
+$$;
+
+-- Text search language of a family, when not set the database default language is used
+ALTER TABLE families ADD COLUMN text_search_language TEXT;
+
+-- Accent-insensitive text search configuration for a given language, if it exists
+CREATE OR REPLACE FUNCTION find_text_search_config(language TEXT) RETURNS regconfig AS $$
+    SELECT c.oid::regconfig
+    FROM pg_ts_config c
+    WHERE c.cfgnamespace = 'public'::regnamespace
+        AND c.cfgname = language || '_unaccent'
+$$ LANGUAGE sql STABLE;
+
+-- Accent-insensitive text search configuration for a given language, falling back on the
+-- database default language, then on the simple configuration
+CREATE OR REPLACE FUNCTION text_search_config(language TEXT) RETURNS regconfig AS $$
+    SELECT COALESCE(
+        find_text_search_config(
+            COALESCE(
+                language,
+                regexp_replace(current_setting('default_text_search_config'), '^.*\.', '')
+            )
+        ),
+        'simple_unaccent'::regconfig
+    )
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION check_family_text_search_language() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.text_search_language IS NOT NULL
+        AND find_text_search_config(NEW.text_search_language) IS NULL THEN
+        RAISE EXCEPTION 'sh_code_invalid_text_search_language';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER check_family_text_search_language
+BEFORE INSERT OR UPDATE ON families
+FOR EACH ROW
+EXECUTE FUNCTION check_family_text_search_language();
+
+-- Recreate the cache with language-aware search vectors and an unaccented text for fuzzy matching
+DROP MATERIALIZED VIEW entities_caches CASCADE;
+
+CREATE MATERIALIZED VIEW entities_caches AS
+-- Get the indexed fields for each family
+WITH families_indexed_fields AS (
+    SELECT
+        f.id AS family_id,
+        f.text_search_language,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('EnumSingleOption', 'EnumMultiOption')
+        ) AS indexed_enums,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('SingleLineText', 'MultiLineText', 'RichText')
+        ) AS indexed_strings
+    FROM families f
+),
+-- For each location of each parent, get a row with the parent and its location flattened
+transitive_locations AS (
+    SELECT
+        ee.child_id,
+        e.id AS parent_id,
+        e.display_name AS parent_display_name,
+        parent_location.value,
+        parent_location.ordinality AS location_index
+    FROM entities_entities ee
+    JOIN entities e ON ee.parent_id = e.id
+    -- Join the locations from the array of locations
+    LEFT JOIN LATERAL (
+        SELECT value, ordinality
+        FROM jsonb_array_elements(e.locations) WITH ORDINALITY AS location(value, ordinality)
+    ) AS parent_location ON true
+    WHERE e.moderated
+),
+-- For each location of each entity, get a row with the entity and its location
+direct_locations AS (
+    SELECT
+        e.id AS entity_id,
+        e.category_id,
+        e.display_name,
+        c.family_id,
+        e.hidden,
+        location.value as location,
+        location.ordinality AS location_index,
+        array_remove(array_agg(DISTINCT et.tag_id), NULL) AS tags_ids,
+        COALESCE(
+            jsonb_object_agg(
+                key,
+                CASE
+                    WHEN jsonb_typeof(transformed_fields.value) = 'array' THEN transformed_fields.value
+                    ELSE
+                        CASE
+                            WHEN transformed_fields.value IS NULL THEN '[]'::jsonb
+                            ELSE jsonb_build_array(transformed_fields.value)
+                        END
+                    END
+            ) FILTER (WHERE key IS NOT NULL),
+            '{}'::jsonb
+        )AS enums,
+        (
+            SELECT string_agg(value::text, ' ')
+            FROM jsonb_each_text(e.data)
+            WHERE key IN (
+                SELECT jsonb_object_keys(f.indexed_strings)
+                FROM families_indexed_fields f
+                WHERE f.family_id = c.family_id
+            )
+        ) AS indexed_string_values
+    FROM entities e
+    JOIN categories c ON e.category_id = c.id
+    LEFT JOIN entity_tags et ON e.id = et.entity_id
+    LEFT JOIN entities_entities ee ON e.id = ee.parent_id
+    LEFT JOIN entities e2 ON ee.child_id = e2.id
+    LEFT JOIN entity_tags cet ON ee.child_id = cet.entity_id
+    LEFT JOIN LATERAL (
+        SELECT value, ordinality
+        FROM jsonb_array_elements(e.locations) WITH ORDINALITY AS location(value, ordinality)
+    ) AS location ON true
+    LEFT JOIN LATERAL (
+        SELECT
+            key,
+            value
+        FROM jsonb_each(e.data)
+        WHERE key IN (
+            SELECT jsonb_object_keys(f.indexed_enums)
+            FROM families_indexed_fields f
+            WHERE f.family_id = c.family_id
+        )
+    ) AS transformed_fields ON true
+    WHERE e.moderated
+    GROUP BY e.id, c.family_id, e.display_name, e.category_id, location.value, location.ordinality
+),
+-- Searchable text of each entity, along with the text search language of its family
+searchable_locations AS (
+    SELECT
+        dl.*,
+        dl.display_name || ' ' || COALESCE(dl.indexed_string_values, '') AS full_text,
+        text_search_config(fif.text_search_language) AS search_config
+    FROM direct_locations dl
+    JOIN families_indexed_fields fif ON fif.family_id = dl.family_id
+)
+-- Add the entities with their locations to the materialized view
+SELECT
+    md5(sl.entity_id::text || COALESCE(sl.location_index, -1)::text || 'alone_loc')::uuid AS id,
+    sl.entity_id,
+    sl.category_id,
+    sl.display_name,
+    sl.family_id,
+    sl.location_index,
+    (sl.location ->> 'long')::double precision AS longitude,
+    (sl.location ->> 'lat')::double precision AS latitude,
+    ST_Transform(ST_SetSRID(ST_MakePoint((sl.location ->> 'long')::double precision, (sl.location ->> 'lat')::double precision), 4326), 3857) AS web_mercator_location,
+    sl.location ->> 'plain_text' AS plain_text_location,
+    sl.tags_ids,
+    NULL AS parent_id,
+    NULL AS parent_display_name,
+    sl.hidden,
+    sl.full_text,
+    sl.search_config,
+    to_tsvector(sl.search_config, sl.full_text) AS full_text_search_ts,
+    immutable_unaccent(lower(sl.full_text)) AS trigram_search_text,
+    sl.enums
+FROM searchable_locations sl
+
+UNION
+
+-- Add the entities with their parents locations to the materialized view
+SELECT
+    md5(tl.child_id::text || tl.parent_id::text || tl.location_index::text || 'with_parent')::uuid AS id,
+    tl.child_id AS entity_id,
+    sl.category_id,
+    sl.display_name,
+    sl.family_id,
+    tl.location_index,
+    (tl.value ->> 'long')::double precision AS longitude,
+    (tl.value ->> 'lat')::double precision AS latitude,
+    ST_Transform(ST_SetSRID(ST_MakePoint((tl.value ->> 'long')::double precision, (tl.value ->> 'lat')::double precision), 4326), 3857) AS web_mercator_location,
+    tl.value ->> 'plain_text' AS plain_text_location,
+    sl.tags_ids,
+    tl.parent_id,
+    tl.parent_display_name,
+    sl.hidden,
+    sl.full_text,
+    sl.search_config,
+    to_tsvector(sl.search_config, sl.full_text) AS full_text_search_ts,
+    immutable_unaccent(lower(sl.full_text)) AS trigram_search_text,
+    sl.enums
+FROM transitive_locations tl
+JOIN searchable_locations sl ON tl.child_id = sl.entity_id;
+
+-- Create unique index on ID for concurrency refresh
+CREATE UNIQUE INDEX entities_caches_id_idx ON entities_caches(id);
+
+-- Create indexes on the materialized view
+CREATE INDEX entities_caches_entity_id_idx ON entities_caches(entity_id);
+CREATE INDEX entities_caches_category_id_idx ON entities_caches(category_id);
+CREATE INDEX entities_caches_family_id_idx ON entities_caches(family_id);
+CREATE INDEX entities_caches_hidden_idx ON entities_caches (hidden);
+CREATE INDEX entities_caches_enums_idx ON entities_caches USING GIN (enums);
+CREATE INDEX entities_caches_gps_location_idx ON entities_caches USING GIST((ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)));
+CREATE INDEX entities_caches_web_mercator_location_idx ON entities_caches USING GIST(web_mercator_location);
+CREATE INDEX entities_caches_full_text_search_idx ON entities_caches USING GIN(full_text_search_ts);
+CREATE INDEX entities_caches_display_name_gist_trgm ON entities_caches USING GIST(display_name gist_trgm_ops);
+CREATE INDEX entities_caches_trigram_search_text_gin_trgm ON entities_caches USING GIN(trigram_search_text gin_trgm_ops);
+
+-- Recreate the clusters dropped along with the cache
+CREATE MATERIALIZED VIEW entities_caches_clusters AS
+SELECT
+    ec.id AS cache_id,
+    ec.family_id,
+    zl.zoom_level,
+    cluster_cell_id(
+        zl.zoom_level,
+        floor(ST_X(ec.web_mercator_location) / zl.cell_size)::bigint,
+        floor(ST_Y(ec.web_mercator_location) / zl.cell_size)::bigint
+    ) AS cluster_id
+FROM entities_caches ec
+CROSS JOIN (
+    SELECT zoom_level, cluster_cell_size(zoom_level) AS cell_size
+    FROM generate_series(0, 18) AS zoom_level
+) zl
+WHERE ec.web_mercator_location IS NOT NULL;
+
+CREATE UNIQUE INDEX entities_caches_clusters_id_idx ON entities_caches_clusters(cache_id, zoom_level);
+CREATE INDEX entities_caches_clusters_cluster_id_idx ON entities_caches_clusters(cluster_id);
+
+-- Escapes the HTML special characters of a text
+CREATE OR REPLACE FUNCTION html_escape(input TEXT) RETURNS TEXT AS $$
+    SELECT replace(replace(replace(replace(replace(
+        input, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;')
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE;
+
+-- Search query for substring and fuzzy matching, without the quotes of the phrases and the
+-- excluded words of the web search syntax
+CREATE OR REPLACE FUNCTION fuzzy_search_query(search_query TEXT) RETURNS TEXT AS $$
+    SELECT btrim(immutable_unaccent(lower(
+        regexp_replace(regexp_replace(search_query, '(^|\s)-\S*', ' ', 'g'), '"', '', 'g')
+    )))
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE;
+
+-- Words excluded by the web search syntax, as a query matching any of them
+CREATE OR REPLACE FUNCTION excluded_search_query(config regconfig, search_query TEXT)
+RETURNS TSQUERY AS $$
+    SELECT COALESCE(
+        websearch_to_tsquery(config, string_agg(excluded.word[1], ' or ')),
+        ''::tsquery
+    )
+    FROM regexp_matches(search_query, '(?:^|\s)-([^\s"]+)', 'g') AS excluded(word)
+$$ LANGUAGE sql STABLE;
+
+-- Whether an entity matches a search query, shared by the searches and their facets. Full-text
+-- search honours the web search operators, and every query also falls back on substring and
+-- trigram matching to tolerate typos, unless the entity contains an excluded word.
+CREATE OR REPLACE FUNCTION entity_matches_search(
+    full_text_search_ts TSVECTOR,
+    display_name TEXT,
+    trigram_search_text TEXT,
+    ts_query TSQUERY,
+    fuzzy_query TEXT,
+    excluded_query TSQUERY
+) RETURNS BOOL AS $$
+    SELECT full_text_search_ts @@ ts_query
+        OR (
+            fuzzy_query <> ''
+            AND (
+                immutable_unaccent(display_name) ILIKE '%' || fuzzy_query || '%'
+                OR fuzzy_query <% trigram_search_text
+            )
+            AND (numnode(excluded_query) = 0 OR NOT full_text_search_ts @@ excluded_query)
+        )
+$$ LANGUAGE sql STABLE;
+
+-- Search queries use the web search syntax (quoted phrases, OR, -exclusion) in the family language,
+-- and fall back on trigram similarity to tolerate typos
+DROP FUNCTION search_entities(
+    TEXT, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    BIGINT, BIGINT,
+    UUID[], UUID[], UUID[],
+    BOOL,
+    JSONB
+);
+
+CREATE OR REPLACE FUNCTION search_entities(
+    search_query TEXT,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    current_page BIGINT,
+    page_size BIGINT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+
+    require_locations BOOL,
+
+    user_enum_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parents JSONB,
+    locations JSONB,
+    highlight TEXT,
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+DECLARE
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.*
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT
+            ie.*,
+            CASE
+                WHEN has_query AND normalized_query <> '' AND
+                    (immutable_unaccent(ie.display_name) ILIKE '%' || normalized_query || '%')
+                THEN 1 ELSE 0
+            END AS exact_match_score,
+            CASE
+                WHEN has_query THEN ts_rank(ie.full_text_search_ts, ts_query)
+                ELSE 0
+            END AS text_rank,
+            CASE
+                WHEN has_query THEN word_similarity(normalized_query, ie.trigram_search_text)
+                ELSE 0
+            END AS similarity
+        FROM included_entities ie
+        WHERE
+            (
+                NOT has_query OR entity_matches_search(
+                    ie.full_text_search_ts, ie.display_name, ie.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ie.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            AND ie.family_id = input_family_id
+            AND NOT ie.hidden
+            -- Categories
+            AND (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags
+            AND (at_allow_all_tags OR (ie.tags_ids && at_allowed_tags_ids))
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+    ),
+    aggregated_entities AS (
+        SELECT
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'id', fe.parent_id,
+                        'display_name', fe.parent_display_name
+                    )
+                ) FILTER (
+                    WHERE fe.parent_id IS NOT NULL
+                        AND fe.parent_id IS NOT NULL
+                        AND fe.parent_display_name IS NOT NULL
+                ),
+                '[]'::jsonb
+            ) AS parents,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'x', ST_X(fe.web_mercator_location),
+                        'y', ST_Y(fe.web_mercator_location),
+                        'plain_text', fe.plain_text_location
+                    )
+                ) FILTER (
+                    WHERE web_mercator_location IS NOT NULL
+                        AND fe.plain_text_location IS NOT NULL),
+                '[]'::jsonb
+            ) AS locations,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config
+        FROM filtered_entities fe
+        GROUP BY
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config
+    ),
+    ranked_entities AS (
+        SELECT
+            ae.*,
+            RANK() OVER (
+                ORDER BY
+                    ae.exact_match_score DESC,
+                    ae.text_rank DESC,
+                    ae.similarity DESC
+            ) AS rank
+        FROM aggregated_entities ae
+        WHERE ((NOT require_locations) OR jsonb_array_length(ae.locations) > 0)
+    ),
+    total_count AS (
+        SELECT COUNT(*) AS total_results FROM ranked_entities
+    ),
+    paginated_results AS (
+        SELECT
+            re.entity_id AS id,
+            re.entity_id,
+            re.category_id,
+            re.tags_ids,
+            re.family_id,
+            re.display_name,
+            re.parents,
+            re.locations,
+            re.full_text,
+            re.search_config,
+            re.rank,
+            tc.total_results,
+            CEIL(tc.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+            current_page as response_current_page
+        FROM ranked_entities re, total_count tc
+        ORDER BY re.rank, re.display_name, re.entity_id
+        LIMIT page_size
+        OFFSET (current_page - 1) * page_size
+    )
+    SELECT
+        pr.id,
+        pr.entity_id,
+        pr.category_id,
+        pr.tags_ids,
+        pr.family_id,
+        pr.display_name,
+        pr.parents,
+        pr.locations,
+        -- Snippets are only computed for the returned page, they are costly. The text is escaped
+        -- before being marked, so that the marks are the only markup of the snippets.
+        CASE
+            WHEN has_query AND numnode(ts_query) > 0 THEN
+                ts_headline(
+                    pr.search_config,
+                    html_escape(pr.full_text),
+                    ts_query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=8, MaxFragments=2'
+                )
+            ELSE NULL
+        END AS highlight,
+        pr.total_results,
+        pr.total_pages,
+        pr.response_current_page
+    FROM paginated_results pr
+    ORDER BY pr.rank, pr.display_name, pr.entity_id;
+END;
+$$ LANGUAGE plpgsql
+-- Lower than the default 0.6 to tolerate single typos in short words
+SET pg_trgm.word_similarity_threshold = 0.5;
+
+CREATE OR REPLACE FUNCTION search_entities_admin(
+    search_query TEXT,
+    input_family_id UUID,
+
+    current_page BIGINT,
+    page_size BIGINT,
+
+    active_categories_ids UUID[],
+    required_tags_ids UUID[],
+    excluded_tags_ids UUID[],
+
+    enum_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    hidden BOOL,
+
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+DECLARE
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+BEGIN
+    RETURN QUERY
+    WITH filtered_entities AS (
+        SELECT
+            ec.*,
+            CASE
+                WHEN has_query AND normalized_query <> '' AND
+                    immutable_unaccent(ec.display_name) ILIKE '%' || normalized_query || '%'
+                THEN 1 ELSE 0
+            END AS exact_match_score,
+            CASE
+                WHEN has_query THEN ts_rank(ec.full_text_search_ts, ts_query)
+                ELSE 0
+            END AS text_rank,
+            CASE
+                WHEN has_query THEN word_similarity(normalized_query, ec.trigram_search_text)
+                ELSE 0
+            END AS similarity
+        FROM entities_caches ec
+        WHERE
+            (
+                NOT has_query OR entity_matches_search(
+                    ec.full_text_search_ts, ec.display_name, ec.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+            AND ec.family_id = input_family_id
+            AND ec.category_id = ANY(active_categories_ids)
+            -- Categories and tags constraints
+            AND (array_length(required_tags_ids, 1) = 0 OR required_tags_ids <@ ec.tags_ids)
+            AND NOT (ec.tags_ids && excluded_tags_ids)
+            -- Enum constraints
+            AND (
+                enum_constraints IS NULL OR
+                enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ec.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ec.enums ? key
+                )
+            )
+    ),
+    distinct_entities AS (
+        SELECT DISTINCT ON (fe.entity_id)
+            fe.id,
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            fe.hidden,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity
+        FROM filtered_entities fe
+        ORDER BY fe.entity_id
+    ),
+    ranked_entities AS (
+        SELECT
+            de.*,
+            RANK() OVER (
+                ORDER BY de.exact_match_score DESC, de.text_rank DESC, de.similarity DESC
+            ) AS rank
+        FROM distinct_entities de
+    ),
+    total_count AS (
+        SELECT COUNT(*) AS total_results FROM ranked_entities
+    ),
+    paginated_results AS (
+        SELECT
+            re.id,
+            re.entity_id,
+            re.category_id,
+            re.tags_ids,
+            re.family_id,
+            re.display_name,
+            re.hidden,
+
+            tc.total_results,
+            CEIL(tc.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+            current_page as response_current_page
+        FROM ranked_entities re, total_count tc
+        ORDER BY re.rank, re.display_name, re.entity_id
+        LIMIT page_size
+        OFFSET (current_page - 1) * page_size
+    )
+    SELECT * FROM paginated_results;
+END;
+$$ LANGUAGE plpgsql
+SET pg_trgm.word_similarity_threshold = 0.5;
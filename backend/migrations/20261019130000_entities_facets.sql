@@ -0,0 +1,460 @@
+-- Checks the enum constraints of a request against the indexed enums of a cached entity
+CREATE OR REPLACE FUNCTION enum_constraints_match(enums JSONB, constraints JSONB) RETURNS BOOL AS $$
+    SELECT constraints IS NULL OR constraints = '{}'::jsonb OR COALESCE((
+        SELECT bool_and(
+            enums->key ?| array(SELECT jsonb_array_elements_text(value))
+        )
+        FROM jsonb_each(constraints) AS constraints(key, value)
+        WHERE key IS NOT NULL AND enums ? key
+    ), false)
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Entities displayed in a view before clustering: the matching entities without parent, and
+-- the parents of the matching children in place of them. Shared by the view and its facets.
+CREATE OR REPLACE FUNCTION entities_within_view(
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_location GEOMETRY,
+    plain_text_location TEXT,
+    enums JSONB
+) AS $$
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.id,
+            ec.entity_id,
+            ec.category_id,
+            ec.tags_ids,
+            ec.family_id,
+            ec.display_name,
+            ec.parent_id,
+            ec.parent_display_name,
+            ec.web_mercator_location,
+            ec.plain_text_location,
+            ec.enums
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Geographic filter
+            AND ST_Intersects(
+                ec.web_mercator_location,
+                ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857)
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE
+            -- Categories filter
+            (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags filter
+            AND (at_allow_all_tags OR ie.tags_ids && at_allowed_tags_ids)
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND enum_constraints_match(ie.enums, user_enum_constraints)
+    ),
+    parent_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE ie.entity_id IN (SELECT DISTINCT fe.parent_id FROM filtered_entities fe)
+    ),
+    combined_entities AS (
+        SELECT * FROM filtered_entities fe WHERE fe.parent_id IS NULL
+        UNION
+        SELECT * FROM parent_entities
+    )
+    SELECT
+        ce.id,
+        ce.entity_id,
+        ce.category_id,
+        ce.tags_ids,
+        ce.family_id,
+        ce.display_name,
+        ce.parent_id,
+        ce.parent_display_name,
+        ce.web_mercator_location,
+        ce.plain_text_location,
+        ce.enums
+    FROM combined_entities ce;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Clusters the entities displayed in the view, as counted by the facets
+CREATE OR REPLACE FUNCTION fetch_entities_within_view(
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    cluster_zoom_level INT,
+    cluster_min_points INT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_x DOUBLE PRECISION,
+    web_mercator_y DOUBLE PRECISION,
+    plain_text_location TEXT,
+    cluster_id BIGINT,
+    cluster_center_x DOUBLE PRECISION,
+    cluster_center_y DOUBLE PRECISION
+) AS $$
+DECLARE
+    clustering BOOL := cluster_zoom_level >= 0 AND cluster_min_points > 0;
+    cell_size DOUBLE PRECISION;
+    view_envelope GEOMETRY := ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857);
+BEGIN
+    -- When clustering, the view is extended to the borders of the grid cells it overlaps
+    -- so every cluster is computed over all its members, regardless of the viewport
+    IF clustering THEN
+        cell_size := cluster_cell_size(cluster_zoom_level);
+        input_xmin := floor(input_xmin / cell_size) * cell_size;
+        input_ymin := floor(input_ymin / cell_size) * cell_size;
+        input_xmax := (floor(input_xmax / cell_size) + 1) * cell_size;
+        input_ymax := (floor(input_ymax / cell_size) + 1) * cell_size;
+    END IF;
+
+    RETURN QUERY
+    WITH combined_entities AS (
+        SELECT *
+        FROM entities_within_view(
+            input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction, input_family_id,
+            at_allow_all_categories, at_allow_all_tags, at_allowed_categories_ids,
+            at_allowed_tags_ids, at_excluded_categories_ids, at_excluded_tags_ids,
+            user_active_categories_ids, user_required_tags_ids, user_excluded_tags_ids,
+            user_enum_constraints
+        )
+    ),
+    celled_entities AS (
+        SELECT
+            ce.*,
+            ecc.cluster_id AS cell_id
+        FROM combined_entities ce
+        LEFT JOIN entities_caches_clusters ecc
+            ON clustering
+            AND ecc.cache_id = ce.id
+            AND ecc.zoom_level = cluster_zoom_level
+    ),
+    clusters AS (
+        SELECT
+            cle.cell_id,
+            AVG(ST_X(cle.web_mercator_location)) AS cluster_center_x,
+            AVG(ST_Y(cle.web_mercator_location)) AS cluster_center_y
+        FROM celled_entities cle
+        WHERE cle.cell_id IS NOT NULL
+        GROUP BY cle.cell_id
+        HAVING COUNT(*) >= cluster_min_points
+    )
+    SELECT
+        cle.id,
+        cle.entity_id,
+        cle.category_id,
+        cle.tags_ids,
+        cle.family_id,
+        cle.display_name,
+        cle.parent_id,
+        cle.parent_display_name,
+        ST_X(cle.web_mercator_location) AS web_mercator_x,
+        ST_Y(cle.web_mercator_location) AS web_mercator_y,
+        cle.plain_text_location,
+        cl.cell_id AS cluster_id,
+        cl.cluster_center_x,
+        cl.cluster_center_y
+    FROM celled_entities cle
+    LEFT JOIN clusters cl ON cle.cell_id = cl.cell_id
+    WHERE cl.cell_id IS NOT NULL OR ST_Intersects(cle.web_mercator_location, view_envelope);
+END;
+$$ LANGUAGE plpgsql;
+
+-- Counts the entities matching the filters of a view or search request, per category, filtering tag
+-- and indexed enum value. Category and enum counts ignore their own filter (active categories,
+-- constraint on the same enum field), so they give the number of results of each option.
+CREATE OR REPLACE FUNCTION fetch_entities_facets(
+    search_query TEXT,
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+
+    require_locations BOOL,
+
+    user_enum_constraints JSONB,
+    private_enum_keys TEXT[]
+) RETURNS TABLE (
+    facet_kind TEXT,
+    -- Category or tag identifier
+    facet_id UUID,
+    -- Enum field key and value
+    enum_key TEXT,
+    enum_value TEXT,
+    entities_count BIGINT
+) AS $$
+DECLARE
+    in_view BOOL := input_xmin IS NOT NULL
+        AND input_ymin IS NOT NULL
+        AND input_xmax IS NOT NULL
+        AND input_ymax IS NOT NULL;
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+BEGIN
+    -- The view counts the entities it displays, the parents standing for their matching children
+    IF in_view THEN
+        RETURN QUERY
+        WITH displayed_entities AS (
+            SELECT *
+            FROM entities_within_view(
+                input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction,
+                input_family_id, at_allow_all_categories, at_allow_all_tags,
+                at_allowed_categories_ids, at_allowed_tags_ids, at_excluded_categories_ids,
+                at_excluded_tags_ids, user_active_categories_ids, user_required_tags_ids,
+                user_excluded_tags_ids, user_enum_constraints
+            )
+        ),
+        any_category_entities AS (
+            SELECT *
+            FROM entities_within_view(
+                input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction,
+                input_family_id, at_allow_all_categories, at_allow_all_tags,
+                at_allowed_categories_ids, at_allowed_tags_ids, at_excluded_categories_ids,
+                at_excluded_tags_ids,
+                ARRAY(SELECT c.id FROM categories c WHERE c.family_id = input_family_id),
+                user_required_tags_ids, user_excluded_tags_ids, user_enum_constraints
+            )
+        )
+        SELECT
+            'category'::text,
+            ace.category_id,
+            NULL::text,
+            NULL::text,
+            COUNT(DISTINCT ace.entity_id)
+        FROM any_category_entities ace
+        GROUP BY ace.category_id
+
+        UNION ALL
+
+        SELECT
+            'tag'::text,
+            t.id,
+            NULL::text,
+            NULL::text,
+            COUNT(DISTINCT de.entity_id)
+        FROM displayed_entities de
+        JOIN tags t ON t.id = ANY(de.tags_ids)
+        WHERE t.is_filter
+            AND (at_allow_all_tags OR t.id = ANY(at_allowed_tags_ids))
+            AND NOT (t.id = ANY(at_excluded_tags_ids))
+        GROUP BY t.id
+
+        UNION ALL
+
+        -- Fields without constraint, counted over the displayed entities
+        SELECT
+            'enum'::text,
+            NULL::uuid,
+            field.key,
+            field_value.value,
+            COUNT(DISTINCT de.entity_id)
+        FROM displayed_entities de
+        CROSS JOIN LATERAL jsonb_each(de.enums) AS field(key, value)
+        CROSS JOIN LATERAL jsonb_array_elements_text(field.value) AS field_value(value)
+        WHERE NOT (field.key = ANY(private_enum_keys))
+            AND NOT (COALESCE(user_enum_constraints ? field.key, false))
+        GROUP BY field.key, field_value.value
+
+        UNION ALL
+
+        -- Constrained fields, counted over the entities the view would display without the
+        -- constraint on the field itself
+        SELECT
+            'enum'::text,
+            NULL::uuid,
+            constrained.key,
+            field_value.value,
+            COUNT(DISTINCT ue.entity_id)
+        FROM jsonb_object_keys(CASE
+            WHEN jsonb_typeof(user_enum_constraints) = 'object' THEN user_enum_constraints
+            ELSE '{}'::jsonb
+        END) AS constrained(key)
+        CROSS JOIN LATERAL entities_within_view(
+            input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction,
+            input_family_id, at_allow_all_categories, at_allow_all_tags,
+            at_allowed_categories_ids, at_allowed_tags_ids, at_excluded_categories_ids,
+            at_excluded_tags_ids, user_active_categories_ids, user_required_tags_ids,
+            user_excluded_tags_ids, user_enum_constraints - constrained.key
+        ) AS ue
+        CROSS JOIN LATERAL jsonb_array_elements_text(ue.enums->constrained.key) AS field_value(value)
+        WHERE NOT (constrained.key = ANY(private_enum_keys))
+        GROUP BY constrained.key, field_value.value;
+
+        RETURN;
+    END IF;
+
+    RETURN QUERY
+    WITH candidate_entities AS (
+        SELECT
+            ec.entity_id,
+            ec.category_id,
+            ec.tags_ids,
+            ec.enums,
+            ec.category_id = ANY(user_active_categories_ids) AS in_active_categories,
+            enum_constraints_match(ec.enums, user_enum_constraints) AS matches_enums
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- Access tokens whitelists
+            AND (at_allow_all_categories OR ec.category_id = ANY(at_allowed_categories_ids))
+            AND (at_allow_all_tags OR ec.tags_ids && at_allowed_tags_ids)
+            -- User tags filters
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ec.tags_ids)
+            -- Geographic filters
+            AND (
+                NOT in_view OR
+                ST_Intersects(
+                    ec.web_mercator_location,
+                    ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857)
+                )
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            AND (NOT require_locations OR ec.web_mercator_location IS NOT NULL)
+            -- Search query
+            AND (
+                NOT has_query OR entity_matches_search(
+                    ec.full_text_search_ts, ec.display_name, ec.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+    ),
+    matching_entities AS (
+        SELECT * FROM candidate_entities ce WHERE ce.in_active_categories AND ce.matches_enums
+    )
+    SELECT
+        'category'::text,
+        ce.category_id,
+        NULL::text,
+        NULL::text,
+        COUNT(DISTINCT ce.entity_id)
+    FROM candidate_entities ce
+    WHERE ce.matches_enums
+    GROUP BY ce.category_id
+
+    UNION ALL
+
+    SELECT
+        'tag'::text,
+        t.id,
+        NULL::text,
+        NULL::text,
+        COUNT(DISTINCT me.entity_id)
+    FROM matching_entities me
+    JOIN tags t ON t.id = ANY(me.tags_ids)
+    WHERE t.is_filter
+        AND (at_allow_all_tags OR t.id = ANY(at_allowed_tags_ids))
+        AND NOT (t.id = ANY(at_excluded_tags_ids))
+    GROUP BY t.id
+
+    UNION ALL
+
+    SELECT
+        'enum'::text,
+        NULL::uuid,
+        field.key,
+        field_value.value,
+        COUNT(DISTINCT ce.entity_id)
+    FROM candidate_entities ce
+    CROSS JOIN LATERAL jsonb_each(ce.enums) AS field(key, value)
+    CROSS JOIN LATERAL jsonb_array_elements_text(field.value) AS field_value(value)
+    WHERE ce.in_active_categories
+        AND NOT (field.key = ANY(private_enum_keys))
+        AND enum_constraints_match(ce.enums, user_enum_constraints - field.key)
+    GROUP BY field.key, field_value.value;
+END;
+$$ LANGUAGE plpgsql
+SET pg_trgm.word_similarity_threshold = 0.5;
@@ -0,0 +1,1122 @@
+-- Parses a date as stored by the forms, NULL if it is not a valid date
+CREATE OR REPLACE FUNCTION safe_to_timestamptz(input TEXT) RETURNS TIMESTAMPTZ AS $$
+BEGIN
+    RETURN input::timestamptz;
+EXCEPTION WHEN others THEN
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Checks the value constraints of a request against the indexed scalar values of a cached entity.
+-- Constraints are either ranges `{"min": number | null, "max": number | null}` or equalities
+-- `{"eq": number | boolean}`, dates being compared as UNIX timestamps.
+CREATE OR REPLACE FUNCTION value_constraints_match(scalars JSONB, constraints JSONB) RETURNS BOOL AS $$
+    SELECT constraints IS NULL OR constraints = '{}'::jsonb OR COALESCE((
+        SELECT bool_and(
+            COALESCE(
+                CASE
+                    WHEN value ? 'eq' THEN scalars->key = value->'eq'
+                    ELSE jsonb_typeof(scalars->key) = 'number'
+                        AND (value->>'min' IS NULL OR (scalars->>key)::float8 >= (value->>'min')::float8)
+                        AND (value->>'max' IS NULL OR (scalars->>key)::float8 <= (value->>'max')::float8)
+                END,
+                false
+            )
+        )
+        FROM jsonb_each(constraints) AS constraints(key, value)
+    ), false)
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Recreate the cache with the indexed Number, DiscreteScore, Boolean and Date fields
+DROP MATERIALIZED VIEW entities_caches CASCADE;
+
+CREATE MATERIALIZED VIEW entities_caches AS
+-- Get the indexed fields for each family
+WITH families_indexed_fields AS (
+    SELECT
+        f.id AS family_id,
+        f.text_search_language,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('EnumSingleOption', 'EnumMultiOption')
+        ) AS indexed_enums,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('SingleLineText', 'MultiLineText', 'RichText')
+        ) AS indexed_strings,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('Number', 'DiscreteScore', 'Boolean', 'Date')
+        ) AS indexed_scalars
+    FROM families f
+),
+-- For each location of each parent, get a row with the parent and its location flattened
+transitive_locations AS (
+    SELECT
+        ee.child_id,
+        e.id AS parent_id,
+        e.display_name AS parent_display_name,
+        parent_location.value,
+        parent_location.ordinality AS location_index
+    FROM entities_entities ee
+    JOIN entities e ON ee.parent_id = e.id
+    -- Join the locations from the array of locations
+    LEFT JOIN LATERAL (
+        SELECT value, ordinality
+        FROM jsonb_array_elements(e.locations) WITH ORDINALITY AS location(value, ordinality)
+    ) AS parent_location ON true
+    WHERE e.moderated
+),
+-- For each location of each entity, get a row with the entity and its location
+direct_locations AS (
+    SELECT
+        e.id AS entity_id,
+        e.category_id,
+        e.display_name,
+        c.family_id,
+        e.hidden,
+        location.value as location,
+        location.ordinality AS location_index,
+        array_remove(array_agg(DISTINCT et.tag_id), NULL) AS tags_ids,
+        COALESCE(
+            jsonb_object_agg(
+                key,
+                CASE
+                    WHEN jsonb_typeof(transformed_fields.value) = 'array' THEN transformed_fields.value
+                    ELSE
+                        CASE
+                            WHEN transformed_fields.value IS NULL THEN '[]'::jsonb
+                            ELSE jsonb_build_array(transformed_fields.value)
+                        END
+                    END
+            ) FILTER (WHERE key IS NOT NULL),
+            '{}'::jsonb
+        )AS enums,
+        (
+            SELECT string_agg(value::text, ' ')
+            FROM jsonb_each_text(e.data)
+            WHERE key IN (
+                SELECT jsonb_object_keys(f.indexed_strings)
+                FROM families_indexed_fields f
+                WHERE f.family_id = c.family_id
+            )
+        ) AS indexed_string_values,
+        COALESCE(
+            (
+                SELECT jsonb_object_agg(
+                    field_data.key,
+                    CASE
+                        WHEN indexed.field_type = 'Date' THEN to_jsonb(
+                            extract(epoch FROM safe_to_timestamptz(field_data.value #>> '{}'))::float8
+                        )
+                        ELSE field_data.value
+                    END
+                )
+                FROM jsonb_each(e.data) AS field_data
+                JOIN families_indexed_fields f ON f.family_id = c.family_id
+                JOIN jsonb_each_text(f.indexed_scalars) AS indexed(key, field_type)
+                    ON indexed.key = field_data.key
+                WHERE
+                    (
+                        indexed.field_type IN ('Number', 'DiscreteScore')
+                        AND jsonb_typeof(field_data.value) = 'number'
+                    )
+                    OR (
+                        indexed.field_type = 'Boolean'
+                        AND jsonb_typeof(field_data.value) = 'boolean'
+                    )
+                    OR (
+                        indexed.field_type = 'Date'
+                        AND safe_to_timestamptz(field_data.value #>> '{}') IS NOT NULL
+                    )
+            ),
+            '{}'::jsonb
+        ) AS scalars
+    FROM entities e
+    JOIN categories c ON e.category_id = c.id
+    LEFT JOIN entity_tags et ON e.id = et.entity_id
+    LEFT JOIN entities_entities ee ON e.id = ee.parent_id
+    LEFT JOIN entities e2 ON ee.child_id = e2.id
+    LEFT JOIN entity_tags cet ON ee.child_id = cet.entity_id
+    LEFT JOIN LATERAL (
+        SELECT value, ordinality
+        FROM jsonb_array_elements(e.locations) WITH ORDINALITY AS location(value, ordinality)
+    ) AS location ON true
+    LEFT JOIN LATERAL (
+        SELECT
+            key,
+            value
+        FROM jsonb_each(e.data)
+        WHERE key IN (
+            SELECT jsonb_object_keys(f.indexed_enums)
+            FROM families_indexed_fields f
+            WHERE f.family_id = c.family_id
+        )
+    ) AS transformed_fields ON true
+    WHERE e.moderated
+    GROUP BY e.id, c.family_id, e.display_name, e.category_id, location.value, location.ordinality
+),
+-- Searchable text of each entity, along with the text search language of its family
+searchable_locations AS (
+    SELECT
+        dl.*,
+        dl.display_name || ' ' || COALESCE(dl.indexed_string_values, '') AS full_text,
+        text_search_config(fif.text_search_language) AS search_config
+    FROM direct_locations dl
+    JOIN families_indexed_fields fif ON fif.family_id = dl.family_id
+)
+-- Add the entities with their locations to the materialized view
+SELECT
+    md5(sl.entity_id::text || COALESCE(sl.location_index, -1)::text || 'alone_loc')::uuid AS id,
+    sl.entity_id,
+    sl.category_id,
+    sl.display_name,
+    sl.family_id,
+    sl.location_index,
+    (sl.location ->> 'long')::double precision AS longitude,
+    (sl.location ->> 'lat')::double precision AS latitude,
+    ST_Transform(ST_SetSRID(ST_MakePoint((sl.location ->> 'long')::double precision, (sl.location ->> 'lat')::double precision), 4326), 3857) AS web_mercator_location,
+    sl.location ->> 'plain_text' AS plain_text_location,
+    sl.tags_ids,
+    NULL AS parent_id,
+    NULL AS parent_display_name,
+    sl.hidden,
+    sl.full_text,
+    sl.search_config,
+    to_tsvector(sl.search_config, sl.full_text) AS full_text_search_ts,
+    immutable_unaccent(lower(sl.full_text)) AS trigram_search_text,
+    sl.enums,
+    sl.scalars
+FROM searchable_locations sl
+
+UNION
+
+-- Add the entities with their parents locations to the materialized view
+SELECT
+    md5(tl.child_id::text || tl.parent_id::text || tl.location_index::text || 'with_parent')::uuid AS id,
+    tl.child_id AS entity_id,
+    sl.category_id,
+    sl.display_name,
+    sl.family_id,
+    tl.location_index,
+    (tl.value ->> 'long')::double precision AS longitude,
+    (tl.value ->> 'lat')::double precision AS latitude,
+    ST_Transform(ST_SetSRID(ST_MakePoint((tl.value ->> 'long')::double precision, (tl.value ->> 'lat')::double precision), 4326), 3857) AS web_mercator_location,
+    tl.value ->> 'plain_text' AS plain_text_location,
+    sl.tags_ids,
+    tl.parent_id,
+    tl.parent_display_name,
+    sl.hidden,
+    sl.full_text,
+    sl.search_config,
+    to_tsvector(sl.search_config, sl.full_text) AS full_text_search_ts,
+    immutable_unaccent(lower(sl.full_text)) AS trigram_search_text,
+    sl.enums,
+    sl.scalars
+FROM transitive_locations tl
+JOIN searchable_locations sl ON tl.child_id = sl.entity_id;
+
+-- Create unique index on ID for concurrency refresh
+CREATE UNIQUE INDEX entities_caches_id_idx ON entities_caches(id);
+
+-- Create indexes on the materialized view
+CREATE INDEX entities_caches_entity_id_idx ON entities_caches(entity_id);
+CREATE INDEX entities_caches_category_id_idx ON entities_caches(category_id);
+CREATE INDEX entities_caches_family_id_idx ON entities_caches(family_id);
+CREATE INDEX entities_caches_hidden_idx ON entities_caches (hidden);
+CREATE INDEX entities_caches_enums_idx ON entities_caches USING GIN (enums);
+CREATE INDEX entities_caches_scalars_idx ON entities_caches USING GIN (scalars);
+CREATE INDEX entities_caches_gps_location_idx ON entities_caches USING GIST((ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)));
+CREATE INDEX entities_caches_web_mercator_location_idx ON entities_caches USING GIST(web_mercator_location);
+CREATE INDEX entities_caches_full_text_search_idx ON entities_caches USING GIN(full_text_search_ts);
+CREATE INDEX entities_caches_display_name_gist_trgm ON entities_caches USING GIST(display_name gist_trgm_ops);
+CREATE INDEX entities_caches_trigram_search_text_gin_trgm ON entities_caches USING GIN(trigram_search_text gin_trgm_ops);
+
+-- Recreate the clusters dropped along with the cache
+CREATE MATERIALIZED VIEW entities_caches_clusters AS
+SELECT
+    ec.id AS cache_id,
+    ec.family_id,
+    zl.zoom_level,
+    cluster_cell_id(
+        zl.zoom_level,
+        floor(ST_X(ec.web_mercator_location) / zl.cell_size)::bigint,
+        floor(ST_Y(ec.web_mercator_location) / zl.cell_size)::bigint
+    ) AS cluster_id
+FROM entities_caches ec
+CROSS JOIN (
+    SELECT zoom_level, cluster_cell_size(zoom_level) AS cell_size
+    FROM generate_series(0, 18) AS zoom_level
+) zl
+WHERE ec.web_mercator_location IS NOT NULL;
+
+CREATE UNIQUE INDEX entities_caches_clusters_id_idx ON entities_caches_clusters(cache_id, zoom_level);
+CREATE INDEX entities_caches_clusters_cluster_id_idx ON entities_caches_clusters(cluster_id);
+
+DROP FUNCTION entities_within_view(
+    DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    UUID[], UUID[], UUID[], JSONB
+);
+
+CREATE OR REPLACE FUNCTION entities_within_view(
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_location GEOMETRY,
+    plain_text_location TEXT,
+    enums JSONB
+) AS $$
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.id,
+            ec.entity_id,
+            ec.category_id,
+            ec.tags_ids,
+            ec.family_id,
+            ec.display_name,
+            ec.parent_id,
+            ec.parent_display_name,
+            ec.web_mercator_location,
+            ec.plain_text_location,
+            ec.enums,
+            ec.scalars
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Geographic filter
+            AND ST_Intersects(
+                ec.web_mercator_location,
+                ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857)
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE
+            -- Categories filter
+            (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags filter
+            AND (at_allow_all_tags OR ie.tags_ids && at_allowed_tags_ids)
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND enum_constraints_match(ie.enums, user_enum_constraints)
+            -- Value constraints
+            AND value_constraints_match(ie.scalars, user_value_constraints)
+    ),
+    parent_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE ie.entity_id IN (SELECT DISTINCT fe.parent_id FROM filtered_entities fe)
+    ),
+    combined_entities AS (
+        SELECT * FROM filtered_entities fe WHERE fe.parent_id IS NULL
+        UNION
+        SELECT * FROM parent_entities
+    )
+    SELECT
+        ce.id,
+        ce.entity_id,
+        ce.category_id,
+        ce.tags_ids,
+        ce.family_id,
+        ce.display_name,
+        ce.parent_id,
+        ce.parent_display_name,
+        ce.web_mercator_location,
+        ce.plain_text_location,
+        ce.enums
+    FROM combined_entities ce;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION fetch_entities_within_view(
+    DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    INT, INT,
+    UUID[], UUID[], UUID[], JSONB
+);
+
+CREATE OR REPLACE FUNCTION fetch_entities_within_view(
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    cluster_zoom_level INT,
+    cluster_min_points INT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_x DOUBLE PRECISION,
+    web_mercator_y DOUBLE PRECISION,
+    plain_text_location TEXT,
+    cluster_id BIGINT,
+    cluster_center_x DOUBLE PRECISION,
+    cluster_center_y DOUBLE PRECISION
+) AS $$
+DECLARE
+    clustering BOOL := cluster_zoom_level >= 0 AND cluster_min_points > 0;
+    cell_size DOUBLE PRECISION;
+    view_envelope GEOMETRY := ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857);
+BEGIN
+    -- When clustering, the view is extended to the borders of the grid cells it overlaps
+    -- so every cluster is computed over all its members, regardless of the viewport
+    IF clustering THEN
+        cell_size := cluster_cell_size(cluster_zoom_level);
+        input_xmin := floor(input_xmin / cell_size) * cell_size;
+        input_ymin := floor(input_ymin / cell_size) * cell_size;
+        input_xmax := (floor(input_xmax / cell_size) + 1) * cell_size;
+        input_ymax := (floor(input_ymax / cell_size) + 1) * cell_size;
+    END IF;
+
+    RETURN QUERY
+    WITH combined_entities AS (
+        SELECT *
+        FROM entities_within_view(
+            input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction, input_family_id,
+            at_allow_all_categories, at_allow_all_tags, at_allowed_categories_ids,
+            at_allowed_tags_ids, at_excluded_categories_ids, at_excluded_tags_ids,
+            user_active_categories_ids, user_required_tags_ids, user_excluded_tags_ids,
+            user_enum_constraints, user_value_constraints
+        )
+    ),
+    celled_entities AS (
+        SELECT
+            ce.*,
+            ecc.cluster_id AS cell_id
+        FROM combined_entities ce
+        LEFT JOIN entities_caches_clusters ecc
+            ON clustering
+            AND ecc.cache_id = ce.id
+            AND ecc.zoom_level = cluster_zoom_level
+    ),
+    clusters AS (
+        SELECT
+            cle.cell_id,
+            AVG(ST_X(cle.web_mercator_location)) AS cluster_center_x,
+            AVG(ST_Y(cle.web_mercator_location)) AS cluster_center_y
+        FROM celled_entities cle
+        WHERE cle.cell_id IS NOT NULL
+        GROUP BY cle.cell_id
+        HAVING COUNT(*) >= cluster_min_points
+    )
+    SELECT
+        cle.id,
+        cle.entity_id,
+        cle.category_id,
+        cle.tags_ids,
+        cle.family_id,
+        cle.display_name,
+        cle.parent_id,
+        cle.parent_display_name,
+        ST_X(cle.web_mercator_location) AS web_mercator_x,
+        ST_Y(cle.web_mercator_location) AS web_mercator_y,
+        cle.plain_text_location,
+        cl.cell_id AS cluster_id,
+        cl.cluster_center_x,
+        cl.cluster_center_y
+    FROM celled_entities cle
+    LEFT JOIN clusters cl ON cle.cell_id = cl.cell_id
+    WHERE cl.cell_id IS NOT NULL OR ST_Intersects(cle.web_mercator_location, view_envelope);
+END;
+$$ LANGUAGE plpgsql;
+
+
+DROP FUNCTION fetch_cluster_members(
+    BIGINT, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    UUID[], UUID[], UUID[], JSONB,
+    BIGINT, BIGINT
+);
+
+CREATE OR REPLACE FUNCTION fetch_cluster_members(
+    input_cluster_id BIGINT,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB,
+
+    current_page BIGINT,
+    page_size BIGINT
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    family_id UUID,
+    display_name TEXT,
+    parent_id UUID,
+    parent_display_name TEXT,
+    web_mercator_x DOUBLE PRECISION,
+    web_mercator_y DOUBLE PRECISION,
+    plain_text_location TEXT,
+    cluster_xmin DOUBLE PRECISION,
+    cluster_ymin DOUBLE PRECISION,
+    cluster_xmax DOUBLE PRECISION,
+    cluster_ymax DOUBLE PRECISION,
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.*
+        FROM entities_caches ec
+        JOIN entities_caches_clusters ecc ON ecc.cache_id = ec.id
+        WHERE
+            -- Cluster filter
+            ecc.cluster_id = input_cluster_id
+            -- Family filter
+            AND ec.family_id = input_family_id
+            -- Geographic filter
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT *
+        FROM included_entities ie
+        WHERE
+            -- Categories filter
+            (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags filter
+            AND (at_allow_all_tags OR ie.tags_ids && at_allowed_tags_ids)
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+            -- Value constraints
+            AND value_constraints_match(ie.scalars, user_value_constraints)
+    ),
+    -- Same members as the cluster returned by fetch_entities_within_view: entities without
+    -- parents, and parents of the filtered entities
+    combined_entities AS (
+        SELECT fe.* FROM filtered_entities fe WHERE fe.parent_id IS NULL
+        UNION
+        SELECT ie.* FROM included_entities ie
+        WHERE ie.entity_id IN (SELECT DISTINCT fe.parent_id FROM filtered_entities fe)
+    ),
+    bounds AS (
+        SELECT
+            COUNT(*) AS total_results,
+            MIN(ST_X(ce.web_mercator_location)) AS cluster_xmin,
+            MIN(ST_Y(ce.web_mercator_location)) AS cluster_ymin,
+            MAX(ST_X(ce.web_mercator_location)) AS cluster_xmax,
+            MAX(ST_Y(ce.web_mercator_location)) AS cluster_ymax
+        FROM combined_entities ce
+    )
+    SELECT
+        ce.id,
+        ce.entity_id,
+        ce.category_id,
+        ce.family_id,
+        ce.display_name,
+        ce.parent_id,
+        ce.parent_display_name,
+        ST_X(ce.web_mercator_location) AS web_mercator_x,
+        ST_Y(ce.web_mercator_location) AS web_mercator_y,
+        ce.plain_text_location,
+        b.cluster_xmin,
+        b.cluster_ymin,
+        b.cluster_xmax,
+        b.cluster_ymax,
+        b.total_results,
+        CEIL(b.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+        current_page AS response_current_page
+    FROM combined_entities ce, bounds b
+    ORDER BY ce.display_name, ce.id
+    LIMIT page_size
+    OFFSET (current_page - 1) * page_size;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION search_entities(
+    TEXT, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    BIGINT, BIGINT,
+    UUID[], UUID[], UUID[],
+    BOOL,
+    JSONB
+);
+
+CREATE OR REPLACE FUNCTION search_entities(
+    search_query TEXT,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    current_page BIGINT,
+    page_size BIGINT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+
+    require_locations BOOL,
+
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parents JSONB,
+    locations JSONB,
+    highlight TEXT,
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+DECLARE
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.*
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT
+            ie.*,
+            CASE
+                WHEN has_query AND normalized_query <> '' AND
+                    (immutable_unaccent(ie.display_name) ILIKE '%' || normalized_query || '%')
+                THEN 1 ELSE 0
+            END AS exact_match_score,
+            CASE
+                WHEN has_query THEN ts_rank(ie.full_text_search_ts, ts_query)
+                ELSE 0
+            END AS text_rank,
+            CASE
+                WHEN has_query THEN word_similarity(normalized_query, ie.trigram_search_text)
+                ELSE 0
+            END AS similarity
+        FROM included_entities ie
+        WHERE
+            (
+                NOT has_query OR entity_matches_search(
+                    ie.full_text_search_ts, ie.display_name, ie.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ie.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            AND ie.family_id = input_family_id
+            AND NOT ie.hidden
+            -- Categories
+            AND (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags
+            AND (at_allow_all_tags OR (ie.tags_ids && at_allowed_tags_ids))
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+            -- Value constraints
+            AND value_constraints_match(ie.scalars, user_value_constraints)
+    ),
+    aggregated_entities AS (
+        SELECT
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'id', fe.parent_id,
+                        'display_name', fe.parent_display_name
+                    )
+                ) FILTER (
+                    WHERE fe.parent_id IS NOT NULL
+                        AND fe.parent_id IS NOT NULL
+                        AND fe.parent_display_name IS NOT NULL
+                ),
+                '[]'::jsonb
+            ) AS parents,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'x', ST_X(fe.web_mercator_location),
+                        'y', ST_Y(fe.web_mercator_location),
+                        'plain_text', fe.plain_text_location
+                    )
+                ) FILTER (
+                    WHERE web_mercator_location IS NOT NULL
+                        AND fe.plain_text_location IS NOT NULL),
+                '[]'::jsonb
+            ) AS locations,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config
+        FROM filtered_entities fe
+        GROUP BY
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config
+    ),
+    ranked_entities AS (
+        SELECT
+            ae.*,
+            RANK() OVER (
+                ORDER BY
+                    ae.exact_match_score DESC,
+                    ae.text_rank DESC,
+                    ae.similarity DESC
+            ) AS rank
+        FROM aggregated_entities ae
+        WHERE ((NOT require_locations) OR jsonb_array_length(ae.locations) > 0)
+    ),
+    total_count AS (
+        SELECT COUNT(*) AS total_results FROM ranked_entities
+    ),
+    paginated_results AS (
+        SELECT
+            re.entity_id AS id,
+            re.entity_id,
+            re.category_id,
+            re.tags_ids,
+            re.family_id,
+            re.display_name,
+            re.parents,
+            re.locations,
+            re.full_text,
+            re.search_config,
+            re.rank,
+            tc.total_results,
+            CEIL(tc.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+            current_page as response_current_page
+        FROM ranked_entities re, total_count tc
+        ORDER BY re.rank, re.display_name, re.entity_id
+        LIMIT page_size
+        OFFSET (current_page - 1) * page_size
+    )
+    SELECT
+        pr.id,
+        pr.entity_id,
+        pr.category_id,
+        pr.tags_ids,
+        pr.family_id,
+        pr.display_name,
+        pr.parents,
+        pr.locations,
+        -- Snippets are only computed for the returned page, they are costly. The text is escaped
+        -- before being marked, so that the marks are the only markup of the snippets.
+        CASE
+            WHEN has_query AND numnode(ts_query) > 0 THEN
+                ts_headline(
+                    pr.search_config,
+                    html_escape(pr.full_text),
+                    ts_query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=8, MaxFragments=2'
+                )
+            ELSE NULL
+        END AS highlight,
+        pr.total_results,
+        pr.total_pages,
+        pr.response_current_page
+    FROM paginated_results pr
+    ORDER BY pr.rank, pr.display_name, pr.entity_id;
+END;
+$$ LANGUAGE plpgsql
+-- Lower than the default 0.6 to tolerate single typos in short words
+SET pg_trgm.word_similarity_threshold = 0.5;
+
+DROP FUNCTION fetch_entities_facets(
+    TEXT,
+    DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION,
+    TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    UUID[], UUID[], UUID[],
+    BOOL,
+    JSONB, TEXT[]
+);
+
+CREATE OR REPLACE FUNCTION fetch_entities_facets(
+    search_query TEXT,
+    input_xmin DOUBLE PRECISION,
+    input_ymin DOUBLE PRECISION,
+    input_xmax DOUBLE PRECISION,
+    input_ymax DOUBLE PRECISION,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+
+    require_locations BOOL,
+
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB,
+    private_enum_keys TEXT[]
+) RETURNS TABLE (
+    facet_kind TEXT,
+    -- Category or tag identifier
+    facet_id UUID,
+    -- Enum field key and value
+    enum_key TEXT,
+    enum_value TEXT,
+    entities_count BIGINT
+) AS $$
+DECLARE
+    in_view BOOL := input_xmin IS NOT NULL
+        AND input_ymin IS NOT NULL
+        AND input_xmax IS NOT NULL
+        AND input_ymax IS NOT NULL;
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+BEGIN
+    -- The view counts the entities it displays, the parents standing for their matching children
+    IF in_view THEN
+        RETURN QUERY
+        WITH displayed_entities AS (
+            SELECT *
+            FROM entities_within_view(
+                input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction,
+                input_family_id, at_allow_all_categories, at_allow_all_tags,
+                at_allowed_categories_ids, at_allowed_tags_ids, at_excluded_categories_ids,
+                at_excluded_tags_ids, user_active_categories_ids, user_required_tags_ids,
+                user_excluded_tags_ids, user_enum_constraints, user_value_constraints
+            )
+        ),
+        any_category_entities AS (
+            SELECT *
+            FROM entities_within_view(
+                input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction,
+                input_family_id, at_allow_all_categories, at_allow_all_tags,
+                at_allowed_categories_ids, at_allowed_tags_ids, at_excluded_categories_ids,
+                at_excluded_tags_ids,
+                ARRAY(SELECT c.id FROM categories c WHERE c.family_id = input_family_id),
+                user_required_tags_ids, user_excluded_tags_ids, user_enum_constraints,
+                user_value_constraints
+            )
+        )
+        SELECT
+            'category'::text,
+            ace.category_id,
+            NULL::text,
+            NULL::text,
+            COUNT(DISTINCT ace.entity_id)
+        FROM any_category_entities ace
+        GROUP BY ace.category_id
+
+        UNION ALL
+
+        SELECT
+            'tag'::text,
+            t.id,
+            NULL::text,
+            NULL::text,
+            COUNT(DISTINCT de.entity_id)
+        FROM displayed_entities de
+        JOIN tags t ON t.id = ANY(de.tags_ids)
+        WHERE t.is_filter
+            AND (at_allow_all_tags OR t.id = ANY(at_allowed_tags_ids))
+            AND NOT (t.id = ANY(at_excluded_tags_ids))
+        GROUP BY t.id
+
+        UNION ALL
+
+        -- Fields without constraint, counted over the displayed entities
+        SELECT
+            'enum'::text,
+            NULL::uuid,
+            field.key,
+            field_value.value,
+            COUNT(DISTINCT de.entity_id)
+        FROM displayed_entities de
+        CROSS JOIN LATERAL jsonb_each(de.enums) AS field(key, value)
+        CROSS JOIN LATERAL jsonb_array_elements_text(field.value) AS field_value(value)
+        WHERE NOT (field.key = ANY(private_enum_keys))
+            AND NOT (COALESCE(user_enum_constraints ? field.key, false))
+        GROUP BY field.key, field_value.value
+
+        UNION ALL
+
+        -- Constrained fields, counted over the entities the view would display without the
+        -- constraint on the field itself
+        SELECT
+            'enum'::text,
+            NULL::uuid,
+            constrained.key,
+            field_value.value,
+            COUNT(DISTINCT ue.entity_id)
+        FROM jsonb_object_keys(CASE
+            WHEN jsonb_typeof(user_enum_constraints) = 'object' THEN user_enum_constraints
+            ELSE '{}'::jsonb
+        END) AS constrained(key)
+        CROSS JOIN LATERAL entities_within_view(
+            input_xmin, input_ymin, input_xmax, input_ymax, geographic_restriction,
+            input_family_id, at_allow_all_categories, at_allow_all_tags,
+            at_allowed_categories_ids, at_allowed_tags_ids, at_excluded_categories_ids,
+            at_excluded_tags_ids, user_active_categories_ids, user_required_tags_ids,
+            user_excluded_tags_ids, user_enum_constraints - constrained.key, user_value_constraints
+        ) AS ue
+        CROSS JOIN LATERAL jsonb_array_elements_text(ue.enums->constrained.key) AS field_value(value)
+        WHERE NOT (constrained.key = ANY(private_enum_keys))
+        GROUP BY constrained.key, field_value.value;
+
+        RETURN;
+    END IF;
+
+    RETURN QUERY
+    WITH candidate_entities AS (
+        SELECT
+            ec.entity_id,
+            ec.category_id,
+            ec.tags_ids,
+            ec.enums,
+            ec.category_id = ANY(user_active_categories_ids) AS in_active_categories,
+            enum_constraints_match(ec.enums, user_enum_constraints) AS matches_enums
+        FROM entities_caches ec
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- Access tokens whitelists
+            AND (at_allow_all_categories OR ec.category_id = ANY(at_allowed_categories_ids))
+            AND (at_allow_all_tags OR ec.tags_ids && at_allowed_tags_ids)
+            -- User tags filters
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ec.tags_ids)
+            -- Geographic filters
+            AND (
+                NOT in_view OR
+                ST_Intersects(
+                    ec.web_mercator_location,
+                    ST_MakeEnvelope(input_xmin, input_ymin, input_xmax, input_ymax, 3857)
+                )
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ec.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            AND (NOT require_locations OR ec.web_mercator_location IS NOT NULL)
+            -- Value constraints
+            AND value_constraints_match(ec.scalars, user_value_constraints)
+            -- Search query
+            AND (
+                NOT has_query OR entity_matches_search(
+                    ec.full_text_search_ts, ec.display_name, ec.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+    ),
+    matching_entities AS (
+        SELECT * FROM candidate_entities ce WHERE ce.in_active_categories AND ce.matches_enums
+    )
+    SELECT
+        'category'::text,
+        ce.category_id,
+        NULL::text,
+        NULL::text,
+        COUNT(DISTINCT ce.entity_id)
+    FROM candidate_entities ce
+    WHERE ce.matches_enums
+    GROUP BY ce.category_id
+
+    UNION ALL
+
+    SELECT
+        'tag'::text,
+        t.id,
+        NULL::text,
+        NULL::text,
+        COUNT(DISTINCT me.entity_id)
+    FROM matching_entities me
+    JOIN tags t ON t.id = ANY(me.tags_ids)
+    WHERE t.is_filter
+        AND (at_allow_all_tags OR t.id = ANY(at_allowed_tags_ids))
+        AND NOT (t.id = ANY(at_excluded_tags_ids))
+    GROUP BY t.id
+
+    UNION ALL
+
+    SELECT
+        'enum'::text,
+        NULL::uuid,
+        field.key,
+        field_value.value,
+        COUNT(DISTINCT ce.entity_id)
+    FROM candidate_entities ce
+    CROSS JOIN LATERAL jsonb_each(ce.enums) AS field(key, value)
+    CROSS JOIN LATERAL jsonb_array_elements_text(field.value) AS field_value(value)
+    WHERE ce.in_active_categories
+        AND NOT (field.key = ANY(private_enum_keys))
+        AND enum_constraints_match(ce.enums, user_enum_constraints - field.key)
+    GROUP BY field.key, field_value.value;
+END;
+$$ LANGUAGE plpgsql
+SET pg_trgm.word_similarity_threshold = 0.5;
@@ -0,0 +1,22 @@
+-- Saved filter states of the map, resolved from links with their code.
+-- Presets with a title are defined by administrators, the others are anonymous share codes.
+CREATE TABLE view_presets (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    access_token_id UUID NOT NULL,
+    code VARCHAR(64) NOT NULL,
+    title TEXT,
+    state JSONB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- Anonymous share codes are forgotten once they have not been opened for a while
+    last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (access_token_id) REFERENCES access_tokens(id) ON DELETE CASCADE,
+    UNIQUE (access_token_id, code)
+);
+
+-- Sharing the same filter state twice gives back the same code
+CREATE UNIQUE INDEX view_presets_shared_state_idx ON view_presets (access_token_id, md5(state::text))
+WHERE title IS NULL;
+
+CREATE INDEX view_presets_shared_created_at_idx ON view_presets (access_token_id, created_at)
+WHERE title IS NULL;
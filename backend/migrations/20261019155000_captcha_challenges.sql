@@ -0,0 +1,16 @@
+-- Proof-of-work challenges already solved, shared by the instances so that a solution cannot be
+-- replayed. They are forgotten once expired.
+CREATE TABLE spent_captcha_challenges (
+    challenge_hash TEXT PRIMARY KEY,
+    spent_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The captcha keys are not specific to hCaptcha anymore
+UPDATE options
+SET value = (value - 'hcaptcha_secret' - 'hcaptcha_sitekey')
+    || jsonb_strip_nulls(jsonb_build_object(
+        'captcha_secret', value->'hcaptcha_secret',
+        'captcha_sitekey', value->'hcaptcha_sitekey',
+        'captcha_provider', CASE WHEN value->>'captcha_provider' = 'h_captcha' THEN 'hcaptcha' END
+    ))
+WHERE name = 'safe_mode';
@@ -0,0 +1,25 @@
+-- Recent public submissions, used to detect bursts from the same client or access token.
+-- Client addresses are only stored hashed, and rows older than a day are pruned.
+CREATE TABLE public_submissions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    client_hash TEXT NOT NULL,
+    access_token_id UUID,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (access_token_id) REFERENCES access_tokens(id) ON DELETE SET NULL
+);
+
+CREATE INDEX public_submissions_client_hash_idx ON public_submissions (client_hash, created_at);
+CREATE INDEX public_submissions_access_token_id_idx ON public_submissions (access_token_id, created_at);
+
+-- Spam score of public submissions, NULL for the ones made by administrators
+ALTER TABLE entities
+    ADD COLUMN spam_score REAL,
+    ADD COLUMN spam_reasons TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE comments
+    ADD COLUMN spam_score REAL,
+    ADD COLUMN spam_reasons TEXT[] NOT NULL DEFAULT '{}';
+
+-- Near-duplicate detection on the names of new entities
+CREATE INDEX entities_display_name_trgm_idx ON entities USING GIN (immutable_unaccent(display_name) gin_trgm_ops);
@@ -0,0 +1,41 @@
+-- Token buckets of the rate limiter, when they are shared between instances.
+-- Losing them on a crash only resets the limits, so the table is not logged.
+CREATE UNLOGGED TABLE rate_limit_buckets (
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+-- Takes a token from a bucket, refilled continuously up to its capacity. Returns 0 when a token
+-- was available, otherwise the number of seconds to wait for the next one.
+CREATE OR REPLACE FUNCTION take_rate_limit_token(
+    bucket_key TEXT,
+    capacity DOUBLE PRECISION,
+    refill_rate DOUBLE PRECISION
+) RETURNS DOUBLE PRECISION AS $$
+DECLARE
+    available DOUBLE PRECISION;
+BEGIN
+    INSERT INTO rate_limit_buckets AS b (key, tokens, updated_at)
+    VALUES (bucket_key, capacity, clock_timestamp())
+    ON CONFLICT (key) DO UPDATE SET
+        tokens = LEAST(
+            capacity,
+            b.tokens + EXTRACT(EPOCH FROM clock_timestamp() - b.updated_at) * refill_rate
+        ),
+        updated_at = clock_timestamp()
+    RETURNING tokens INTO available;
+
+    -- Forget the buckets unused for a day from time to time
+    IF random() < 0.001 THEN
+        DELETE FROM rate_limit_buckets WHERE updated_at < clock_timestamp() - INTERVAL '1 day';
+    END IF;
+
+    IF available >= 1 THEN
+        UPDATE rate_limit_buckets SET tokens = tokens - 1 WHERE key = bucket_key;
+        RETURN 0;
+    END IF;
+
+    RETURN (1 - available) / refill_rate;
+END;
+$$ LANGUAGE plpgsql;
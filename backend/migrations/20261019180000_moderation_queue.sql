@@ -0,0 +1,71 @@
+CREATE TYPE moderation_status AS ENUM ('pending', 'approved', 'rejected', 'changes_requested');
+
+-- Moderation workflow of entities and comments. The moderated flag, used by the public queries,
+-- stays the source of truth of the visibility and is kept in sync with the status.
+ALTER TABLE entities
+    ADD COLUMN moderation_status moderation_status NOT NULL DEFAULT 'pending',
+    -- Reason given with the last decision
+    ADD COLUMN moderation_reason TEXT,
+    ADD COLUMN moderation_status_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN assigned_to UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN assigned_at TIMESTAMP;
+
+ALTER TABLE comments
+    ADD COLUMN moderation_status moderation_status NOT NULL DEFAULT 'pending',
+    ADD COLUMN moderation_reason TEXT,
+    ADD COLUMN moderation_status_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN assigned_to UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN assigned_at TIMESTAMP;
+
+UPDATE entities SET moderation_status = 'approved' WHERE moderated;
+UPDATE comments SET moderation_status = 'approved' WHERE moderated;
+
+CREATE INDEX entities_moderation_status_idx ON entities(moderation_status, moderation_status_at);
+CREATE INDEX comments_moderation_status_idx ON comments(moderation_status, moderation_status_at);
+
+CREATE OR REPLACE FUNCTION sync_moderation_status()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        IF NEW.moderated THEN
+            NEW.moderation_status := 'approved';
+        END IF;
+    ELSIF NEW.moderation_status IS DISTINCT FROM OLD.moderation_status THEN
+        NEW.moderated := NEW.moderation_status = 'approved';
+        NEW.moderation_status_at := CURRENT_TIMESTAMP;
+    ELSIF NEW.moderated IS DISTINCT FROM OLD.moderated THEN
+        -- Direct edition of the moderated flag by an administrator
+        NEW.moderation_status := CASE WHEN NEW.moderated THEN 'approved' ELSE 'pending' END::moderation_status;
+        NEW.moderation_status_at := CURRENT_TIMESTAMP;
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER sync_entities_moderation_status
+BEFORE INSERT OR UPDATE ON entities
+FOR EACH ROW
+EXECUTE FUNCTION sync_moderation_status();
+
+CREATE TRIGGER sync_comments_moderation_status
+BEFORE INSERT OR UPDATE ON comments
+FOR EACH ROW
+EXECUTE FUNCTION sync_moderation_status();
+
+-- Log of the moderation decisions. Items are not referenced, so the statistics of the moderators
+-- survive the deletion of the moderated items.
+CREATE TABLE moderation_decisions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    item_kind TEXT NOT NULL CHECK (item_kind IN ('entity', 'comment')),
+    item_id UUID NOT NULL,
+    user_id UUID,
+    decision moderation_status NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX moderation_decisions_item_idx ON moderation_decisions(item_kind, item_id);
+CREATE INDEX moderation_decisions_user_id_idx ON moderation_decisions(user_id, created_at);
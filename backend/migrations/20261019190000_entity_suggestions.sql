@@ -0,0 +1,31 @@
+-- Hash of the secret code given to the submitter of a public entity to follow it up
+ALTER TABLE entities ADD COLUMN tracking_code_hash TEXT;
+
+CREATE UNIQUE INDEX entities_tracking_code_hash_idx ON entities(tracking_code_hash)
+    WHERE tracking_code_hash IS NOT NULL;
+
+-- Edits proposed on an entity. Only the changed fields are stored, so they are compared and
+-- applied against the live version of the entity when they are moderated.
+CREATE TABLE entity_suggestions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    entity_id UUID NOT NULL,
+    -- Proposed by the submitter of the entity through its tracking code
+    from_submitter BOOLEAN NOT NULL,
+    changes JSONB NOT NULL,
+    moderation_status moderation_status NOT NULL DEFAULT 'pending',
+    moderation_reason TEXT,
+    decided_by UUID,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    decided_at TIMESTAMP,
+
+    FOREIGN KEY (entity_id) REFERENCES entities(id) ON DELETE CASCADE,
+    FOREIGN KEY (decided_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX entity_suggestions_entity_id_idx ON entity_suggestions(entity_id);
+CREATE INDEX entity_suggestions_moderation_status_idx ON entity_suggestions(moderation_status, created_at);
+
+-- Decisions on suggestions count in the statistics of the moderators
+ALTER TABLE moderation_decisions DROP CONSTRAINT moderation_decisions_item_kind_check;
+ALTER TABLE moderation_decisions ADD CONSTRAINT moderation_decisions_item_kind_check
+    CHECK (item_kind IN ('entity', 'comment', 'suggestion'));
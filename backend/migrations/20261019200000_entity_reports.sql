@@ -0,0 +1,28 @@
+CREATE TYPE report_reason AS ENUM ('harmful', 'wrong_information', 'closed', 'duplicate', 'spam', 'other');
+
+-- Entities flagged by viewers, until a moderator handles the reports
+CREATE TABLE entity_reports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    entity_id UUID NOT NULL,
+    reason report_reason NOT NULL,
+    details TEXT,
+    -- Salted hash of the client address, so a single client cannot hide an entity on its own
+    client_hash TEXT NOT NULL,
+    access_token_id UUID,
+    resolved BOOLEAN NOT NULL DEFAULT FALSE,
+    resolved_by UUID,
+    resolved_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (entity_id) REFERENCES entities(id) ON DELETE CASCADE,
+    FOREIGN KEY (access_token_id) REFERENCES access_tokens(id) ON DELETE SET NULL,
+    FOREIGN KEY (resolved_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX entity_reports_entity_id_idx ON entity_reports(entity_id) WHERE NOT resolved;
+
+-- Entities hidden automatically after being reported, to cap the number of these hides per hour
+ALTER TABLE entities ADD COLUMN reports_hidden_at TIMESTAMP;
+
+CREATE INDEX entities_reports_hidden_at_idx ON entities(reports_hidden_at)
+    WHERE reports_hidden_at IS NOT NULL;
@@ -0,0 +1,149 @@
+CREATE TYPE webhook_event AS ENUM (
+    'entity_created',
+    'entity_updated',
+    'entity_moderated',
+    'entity_deleted',
+    'comment_created',
+    'report_filed'
+);
+
+CREATE TABLE webhooks (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    title TEXT NOT NULL,
+    url TEXT NOT NULL,
+    -- Key of the HMAC signature of the payloads
+    secret TEXT NOT NULL,
+    events webhook_event[] NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER update_webhooks_updated_at
+BEFORE UPDATE ON webhooks
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at_column();
+
+-- Events to deliver to each webhook, kept as the delivery log
+CREATE TABLE webhook_deliveries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    webhook_id UUID NOT NULL,
+    event webhook_event NOT NULL,
+    payload JSONB NOT NULL,
+    -- pending until delivered, or failed once out of attempts
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    response_status INT,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered_at TIMESTAMP,
+
+    FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries(webhook_id, created_at);
+
+-- Queues an event for the active webhooks subscribed to it, and wakes the delivering instances up
+CREATE OR REPLACE FUNCTION enqueue_webhook_event(given_event webhook_event, given_payload JSONB)
+RETURNS VOID AS $$
+BEGIN
+    INSERT INTO webhook_deliveries (webhook_id, event, payload)
+    SELECT w.id, given_event, jsonb_build_object(
+        'event', given_event,
+        'occurred_at', CURRENT_TIMESTAMP,
+        'text', given_payload->>'text',
+        'data', given_payload - 'text'
+    )
+    FROM webhooks w
+    WHERE w.active AND given_event = ANY(w.events);
+
+    IF FOUND THEN
+        PERFORM pg_notify('webhook_deliveries', given_event::TEXT);
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION entities_webhook_events()
+RETURNS TRIGGER AS $$
+DECLARE
+    entity entities;
+    event webhook_event;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        entity := OLD;
+        event := 'entity_deleted';
+    ELSIF TG_OP = 'INSERT' THEN
+        entity := NEW;
+        event := 'entity_created';
+    ELSIF NEW.moderation_status IS DISTINCT FROM OLD.moderation_status THEN
+        entity := NEW;
+        event := 'entity_moderated';
+    ELSIF (NEW.display_name, NEW.category_id, NEW.locations, NEW.data, NEW.hidden)
+        IS DISTINCT FROM (OLD.display_name, OLD.category_id, OLD.locations, OLD.data, OLD.hidden) THEN
+        entity := NEW;
+        event := 'entity_updated';
+    ELSE
+        RETURN NULL;
+    END IF;
+
+    PERFORM enqueue_webhook_event(event, jsonb_build_object(
+        'text', format('%s: %s (%s)', replace(event::TEXT, '_', ' '), entity.display_name, entity.moderation_status),
+        'id', entity.id,
+        'display_name', entity.display_name,
+        'category_id', entity.category_id,
+        'moderation_status', entity.moderation_status,
+        'moderated', entity.moderated,
+        'hidden', entity.hidden
+    ));
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER entities_webhook_events
+AFTER INSERT OR UPDATE OR DELETE ON entities
+FOR EACH ROW
+EXECUTE FUNCTION entities_webhook_events();
+
+CREATE OR REPLACE FUNCTION comments_webhook_events()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM enqueue_webhook_event('comment_created', jsonb_build_object(
+        'text', format('comment created by %s (%s)', NEW.author, NEW.moderation_status),
+        'id', NEW.id,
+        'entity_id', NEW.entity_id,
+        'author', NEW.author,
+        'moderation_status', NEW.moderation_status,
+        'moderated', NEW.moderated
+    ));
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER comments_webhook_events
+AFTER INSERT ON comments
+FOR EACH ROW
+EXECUTE FUNCTION comments_webhook_events();
+
+CREATE OR REPLACE FUNCTION entity_reports_webhook_events()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM enqueue_webhook_event('report_filed', jsonb_build_object(
+        'text', format('report filed (%s)', NEW.reason),
+        'id', NEW.id,
+        'entity_id', NEW.entity_id,
+        'reason', NEW.reason,
+        'details', NEW.details
+    ));
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER entity_reports_webhook_events
+AFTER INSERT ON entity_reports
+FOR EACH ROW
+EXECUTE FUNCTION entity_reports_webhook_events();
@@ -0,0 +1,8 @@
+CREATE TYPE notification_frequency AS ENUM ('never', 'immediate', 'daily');
+
+ALTER TABLE users
+    ADD COLUMN email TEXT,
+    ADD COLUMN notification_frequency notification_frequency NOT NULL DEFAULT 'never',
+    -- Locale of the notification templates
+    ADD COLUMN notification_locale TEXT NOT NULL DEFAULT 'fr',
+    ADD COLUMN last_notified_at TIMESTAMP;
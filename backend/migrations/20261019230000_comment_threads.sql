@@ -0,0 +1,8 @@
+ALTER TABLE comments
+    -- Comment replied to, on the same entity
+    ADD COLUMN parent_id UUID REFERENCES comments(id) ON DELETE CASCADE,
+    -- Response of the moderators or of the organisation running the map
+    ADD COLUMN is_official BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX comments_parent_id_idx ON comments(parent_id);
+CREATE INDEX comments_entity_id_created_at_idx ON comments(entity_id, created_at) WHERE parent_id IS NULL AND moderated;
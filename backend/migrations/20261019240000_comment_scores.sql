@@ -0,0 +1,331 @@
+-- Aggregate the user facing DiscreteScore fields of the comment forms over the moderated comments
+-- of each entity. Official responses are not reviews and are left out.
+CREATE MATERIALIZED VIEW entities_comment_scores AS
+WITH families_score_fields AS (
+    SELECT f.id AS family_id, field->>'key' AS key
+    FROM families f, jsonb_array_elements(f.comment_form->'fields') AS field
+    WHERE
+        (field->>'field_type')::text = 'DiscreteScore'
+        AND (field->>'user_facing')::boolean IS TRUE
+),
+comments_scores AS (
+    SELECT c.entity_id, sf.key, (c.data->>sf.key)::float8 AS score
+    FROM comments c
+    JOIN entities e ON e.id = c.entity_id
+    JOIN categories cat ON cat.id = e.category_id
+    JOIN families_score_fields sf ON sf.family_id = cat.family_id
+    WHERE
+        c.moderated
+        AND NOT c.is_official
+        AND jsonb_typeof(c.data->sf.key) = 'number'
+),
+scores_distributions AS (
+    SELECT entity_id, key, score, COUNT(*) AS scores_count
+    FROM comments_scores
+    GROUP BY entity_id, key, score
+),
+scores_aggregates AS (
+    SELECT
+        entity_id,
+        key,
+        SUM(score * scores_count) / SUM(scores_count) AS mean,
+        SUM(scores_count)::bigint AS scores_count,
+        jsonb_object_agg(score::text, scores_count ORDER BY score) AS distribution
+    FROM scores_distributions
+    GROUP BY entity_id, key
+)
+SELECT
+    entity_id,
+    jsonb_object_agg(
+        key,
+        jsonb_build_object('mean', mean, 'count', scores_count, 'distribution', distribution)
+    ) AS scores,
+    -- Flattened means, to match score constraints with `value_constraints_match`
+    jsonb_object_agg(key, mean) AS means
+FROM scores_aggregates
+GROUP BY entity_id;
+
+-- Create unique index on entity ID for concurrency refresh
+CREATE UNIQUE INDEX entities_comment_scores_entity_id_idx ON entities_comment_scores(entity_id);
+
+CREATE OR REPLACE FUNCTION trigger_refresh_entities_comment_scores()
+RETURNS TRIGGER AS $$
+BEGIN
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_comment_scores;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER refresh_entities_comment_scores_on_comments
+AFTER INSERT OR UPDATE OR DELETE ON comments
+FOR EACH STATEMENT
+EXECUTE FUNCTION trigger_refresh_entities_comment_scores();
+
+-- Score fields depend on the families comment forms and entities categories, which trigger
+-- the refresh of the entities cache
+CREATE OR REPLACE FUNCTION refresh_entities_caches() RETURNS void AS $$
+BEGIN
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches;
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches_clusters;
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_comment_scores;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Return the scores of the results, filter on their means and optionally sort by one of them
+DROP FUNCTION search_entities(
+    TEXT, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    BIGINT, BIGINT,
+    UUID[], UUID[], UUID[],
+    BOOL,
+    JSONB, JSONB
+);
+
+CREATE OR REPLACE FUNCTION search_entities(
+    search_query TEXT,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    current_page BIGINT,
+    page_size BIGINT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+
+    require_locations BOOL,
+
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB,
+
+    -- Constraints on the means of the comments scores, in the format of `user_value_constraints`
+    user_score_constraints JSONB,
+    -- Key of the score whose mean sorts the results, best rated first, instead of the relevance
+    sort_score_key TEXT
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parents JSONB,
+    locations JSONB,
+    highlight TEXT,
+    scores JSONB,
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+DECLARE
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.*, ecs.scores, ecs.means AS score_means
+        FROM entities_caches ec
+        LEFT JOIN entities_comment_scores ecs ON ecs.entity_id = ec.entity_id
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT
+            ie.*,
+            CASE
+                WHEN has_query AND normalized_query <> '' AND
+                    (immutable_unaccent(ie.display_name) ILIKE '%' || normalized_query || '%')
+                THEN 1 ELSE 0
+            END AS exact_match_score,
+            CASE
+                WHEN has_query THEN ts_rank(ie.full_text_search_ts, ts_query)
+                ELSE 0
+            END AS text_rank,
+            CASE
+                WHEN has_query THEN word_similarity(normalized_query, ie.trigram_search_text)
+                ELSE 0
+            END AS similarity
+        FROM included_entities ie
+        WHERE
+            (
+                NOT has_query OR entity_matches_search(
+                    ie.full_text_search_ts, ie.display_name, ie.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ie.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            AND ie.family_id = input_family_id
+            AND NOT ie.hidden
+            -- Categories
+            AND (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags
+            AND (at_allow_all_tags OR (ie.tags_ids && at_allowed_tags_ids))
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+            -- Value constraints
+            AND value_constraints_match(ie.scalars, user_value_constraints)
+            -- Score constraints
+            AND value_constraints_match(COALESCE(ie.score_means, '{}'::jsonb), user_score_constraints)
+    ),
+    aggregated_entities AS (
+        SELECT
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'id', fe.parent_id,
+                        'display_name', fe.parent_display_name
+                    )
+                ) FILTER (
+                    WHERE fe.parent_id IS NOT NULL
+                        AND fe.parent_id IS NOT NULL
+                        AND fe.parent_display_name IS NOT NULL
+                ),
+                '[]'::jsonb
+            ) AS parents,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'x', ST_X(fe.web_mercator_location),
+                        'y', ST_Y(fe.web_mercator_location),
+                        'plain_text', fe.plain_text_location
+                    )
+                ) FILTER (
+                    WHERE web_mercator_location IS NOT NULL
+                        AND fe.plain_text_location IS NOT NULL),
+                '[]'::jsonb
+            ) AS locations,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config,
+            fe.scores,
+            (fe.score_means->>sort_score_key)::float8 AS sort_score
+        FROM filtered_entities fe
+        GROUP BY
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config,
+            fe.scores,
+            fe.score_means
+    ),
+    ranked_entities AS (
+        SELECT
+            ae.*,
+            RANK() OVER (
+                ORDER BY
+                    ae.exact_match_score DESC,
+                    ae.text_rank DESC,
+                    ae.similarity DESC
+            ) AS rank
+        FROM aggregated_entities ae
+        WHERE ((NOT require_locations) OR jsonb_array_length(ae.locations) > 0)
+    ),
+    total_count AS (
+        SELECT COUNT(*) AS total_results FROM ranked_entities
+    ),
+    paginated_results AS (
+        SELECT
+            re.entity_id AS id,
+            re.entity_id,
+            re.category_id,
+            re.tags_ids,
+            re.family_id,
+            re.display_name,
+            re.parents,
+            re.locations,
+            re.full_text,
+            re.search_config,
+            re.rank,
+            re.scores,
+            re.sort_score,
+            tc.total_results,
+            CEIL(tc.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+            current_page as response_current_page
+        FROM ranked_entities re, total_count tc
+        -- Without a sort score, it is NULL for every entity and the relevance prevails
+        ORDER BY re.sort_score DESC NULLS LAST, re.rank, re.display_name, re.entity_id
+        LIMIT page_size
+        OFFSET (current_page - 1) * page_size
+    )
+    SELECT
+        pr.id,
+        pr.entity_id,
+        pr.category_id,
+        pr.tags_ids,
+        pr.family_id,
+        pr.display_name,
+        pr.parents,
+        pr.locations,
+        -- Snippets are only computed for the returned page, they are costly. The text is escaped
+        -- before being marked, so that the marks are the only markup of the snippets.
+        CASE
+            WHEN has_query AND numnode(ts_query) > 0 THEN
+                ts_headline(
+                    pr.search_config,
+                    html_escape(pr.full_text),
+                    ts_query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=8, MaxFragments=2'
+                )
+            ELSE NULL
+        END AS highlight,
+        COALESCE(pr.scores, '{}'::jsonb) AS scores,
+        pr.total_results,
+        pr.total_pages,
+        pr.response_current_page
+    FROM paginated_results pr
+    ORDER BY pr.sort_score DESC NULLS LAST, pr.rank, pr.display_name, pr.entity_id;
+END;
+$$ LANGUAGE plpgsql
+-- Lower than the default 0.6 to tolerate single typos in short words
+SET pg_trgm.word_similarity_threshold = 0.5;
@@ -0,0 +1,719 @@
+-- Explicit sorting of the search results. Each sort is expressed as the same keys, compared in
+-- ascending order: three numbers, the normalized name and the entity ID. This allows the admin
+-- search to paginate with a cursor made of the keys of the last returned entity.
+--   relevance: exact match, text rank and similarity, best first
+--   name: alphabetical order
+--   created_at, updated_at: most recent first
+--   distance: closest location to a point first, entities without locations last
+--   score: best mean of a comments score first, entities without scores last
+--   moderation_status: pending, changes requested, rejected then approved
+CREATE OR REPLACE FUNCTION moderation_status_sort_order(status moderation_status) RETURNS INT AS $$
+    SELECT CASE status
+        WHEN 'pending' THEN 0
+        WHEN 'changes_requested' THEN 1
+        WHEN 'rejected' THEN 2
+        WHEN 'approved' THEN 3
+    END
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Distance in meters between two Web Mercator points
+CREATE OR REPLACE FUNCTION web_mercator_distance(a GEOMETRY, b GEOMETRY) RETURNS DOUBLE PRECISION AS $$
+    SELECT ST_DistanceSphere(ST_Transform(a, 4326), ST_Transform(b, 4326))
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Sort keys of an entity, the values only used by the given sort are required
+CREATE OR REPLACE FUNCTION entity_sort_values(
+    sort_by TEXT,
+    exact_match_score DOUBLE PRECISION,
+    text_rank DOUBLE PRECISION,
+    similarity DOUBLE PRECISION,
+    created_at TIMESTAMP,
+    updated_at TIMESTAMP,
+    status moderation_status,
+    distance DOUBLE PRECISION,
+    score DOUBLE PRECISION
+) RETURNS DOUBLE PRECISION[] AS $$
+    SELECT CASE sort_by
+        WHEN 'relevance' THEN ARRAY[-exact_match_score, -text_rank, -similarity]
+        WHEN 'created_at' THEN ARRAY[-EXTRACT(EPOCH FROM created_at)::float8, 0, 0]
+        WHEN 'updated_at' THEN ARRAY[-EXTRACT(EPOCH FROM updated_at)::float8, 0, 0]
+        WHEN 'distance' THEN ARRAY[(distance IS NULL)::int, COALESCE(distance, 0), 0]
+        WHEN 'score' THEN ARRAY[(score IS NULL)::int, COALESCE(-score, 0), 0]
+        WHEN 'moderation_status' THEN ARRAY[moderation_status_sort_order(status), 0, 0]
+        ELSE ARRAY[0, 0, 0]
+    END::float8[]
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Also cache the entities awaiting moderation, for the admin search to list and sort them.
+-- The `hidden` column now tells if an entity is hidden from the public, being either hidden or
+-- not moderated, the admin search reading the actual values from the entities.
+DROP MATERIALIZED VIEW entities_caches CASCADE;
+
+CREATE MATERIALIZED VIEW entities_caches AS
+-- Get the indexed fields for each family
+WITH families_indexed_fields AS (
+    SELECT
+        f.id AS family_id,
+        f.text_search_language,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('EnumSingleOption', 'EnumMultiOption')
+        ) AS indexed_enums,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('SingleLineText', 'MultiLineText', 'RichText')
+        ) AS indexed_strings,
+        (
+            SELECT jsonb_object_agg(field->>'key', field->>'field_type')
+            FROM jsonb_array_elements(f.entity_form->'fields') AS field
+            WHERE
+                (field->>'indexed')::boolean IS TRUE
+                AND
+                (field->>'field_type')::text IN ('Number', 'DiscreteScore', 'Boolean', 'Date')
+        ) AS indexed_scalars
+    FROM families f
+),
+-- For each location of each parent, get a row with the parent and its location flattened
+transitive_locations AS (
+    SELECT
+        ee.child_id,
+        e.id AS parent_id,
+        e.display_name AS parent_display_name,
+        parent_location.value,
+        parent_location.ordinality AS location_index
+    FROM entities_entities ee
+    JOIN entities e ON ee.parent_id = e.id
+    -- Join the locations from the array of locations
+    LEFT JOIN LATERAL (
+        SELECT value, ordinality
+        FROM jsonb_array_elements(e.locations) WITH ORDINALITY AS location(value, ordinality)
+    ) AS parent_location ON true
+    WHERE e.moderated
+),
+-- For each location of each entity, get a row with the entity and its location
+direct_locations AS (
+    SELECT
+        e.id AS entity_id,
+        e.category_id,
+        e.display_name,
+        c.family_id,
+        e.hidden OR NOT e.moderated AS hidden,
+        location.value as location,
+        location.ordinality AS location_index,
+        array_remove(array_agg(DISTINCT et.tag_id), NULL) AS tags_ids,
+        COALESCE(
+            jsonb_object_agg(
+                key,
+                CASE
+                    WHEN jsonb_typeof(transformed_fields.value) = 'array' THEN transformed_fields.value
+                    ELSE
+                        CASE
+                            WHEN transformed_fields.value IS NULL THEN '[]'::jsonb
+                            ELSE jsonb_build_array(transformed_fields.value)
+                        END
+                    END
+            ) FILTER (WHERE key IS NOT NULL),
+            '{}'::jsonb
+        )AS enums,
+        (
+            SELECT string_agg(value::text, ' ')
+            FROM jsonb_each_text(e.data)
+            WHERE key IN (
+                SELECT jsonb_object_keys(f.indexed_strings)
+                FROM families_indexed_fields f
+                WHERE f.family_id = c.family_id
+            )
+        ) AS indexed_string_values,
+        COALESCE(
+            (
+                SELECT jsonb_object_agg(
+                    field_data.key,
+                    CASE
+                        WHEN indexed.field_type = 'Date' THEN to_jsonb(
+                            extract(epoch FROM safe_to_timestamptz(field_data.value #>> '{}'))::float8
+                        )
+                        ELSE field_data.value
+                    END
+                )
+                FROM jsonb_each(e.data) AS field_data
+                JOIN families_indexed_fields f ON f.family_id = c.family_id
+                JOIN jsonb_each_text(f.indexed_scalars) AS indexed(key, field_type)
+                    ON indexed.key = field_data.key
+                WHERE
+                    (
+                        indexed.field_type IN ('Number', 'DiscreteScore')
+                        AND jsonb_typeof(field_data.value) = 'number'
+                    )
+                    OR (
+                        indexed.field_type = 'Boolean'
+                        AND jsonb_typeof(field_data.value) = 'boolean'
+                    )
+                    OR (
+                        indexed.field_type = 'Date'
+                        AND safe_to_timestamptz(field_data.value #>> '{}') IS NOT NULL
+                    )
+            ),
+            '{}'::jsonb
+        ) AS scalars
+    FROM entities e
+    JOIN categories c ON e.category_id = c.id
+    LEFT JOIN entity_tags et ON e.id = et.entity_id
+    LEFT JOIN entities_entities ee ON e.id = ee.parent_id
+    LEFT JOIN entities e2 ON ee.child_id = e2.id
+    LEFT JOIN entity_tags cet ON ee.child_id = cet.entity_id
+    LEFT JOIN LATERAL (
+        SELECT value, ordinality
+        FROM jsonb_array_elements(e.locations) WITH ORDINALITY AS location(value, ordinality)
+    ) AS location ON true
+    LEFT JOIN LATERAL (
+        SELECT
+            key,
+            value
+        FROM jsonb_each(e.data)
+        WHERE key IN (
+            SELECT jsonb_object_keys(f.indexed_enums)
+            FROM families_indexed_fields f
+            WHERE f.family_id = c.family_id
+        )
+    ) AS transformed_fields ON true
+    GROUP BY e.id, c.family_id, e.display_name, e.category_id, location.value, location.ordinality
+),
+-- Searchable text of each entity, along with the text search language of its family
+searchable_locations AS (
+    SELECT
+        dl.*,
+        dl.display_name || ' ' || COALESCE(dl.indexed_string_values, '') AS full_text,
+        text_search_config(fif.text_search_language) AS search_config
+    FROM direct_locations dl
+    JOIN families_indexed_fields fif ON fif.family_id = dl.family_id
+)
+-- Add the entities with their locations to the materialized view
+SELECT
+    md5(sl.entity_id::text || COALESCE(sl.location_index, -1)::text || 'alone_loc')::uuid AS id,
+    sl.entity_id,
+    sl.category_id,
+    sl.display_name,
+    sl.family_id,
+    sl.location_index,
+    (sl.location ->> 'long')::double precision AS longitude,
+    (sl.location ->> 'lat')::double precision AS latitude,
+    ST_Transform(ST_SetSRID(ST_MakePoint((sl.location ->> 'long')::double precision, (sl.location ->> 'lat')::double precision), 4326), 3857) AS web_mercator_location,
+    sl.location ->> 'plain_text' AS plain_text_location,
+    sl.tags_ids,
+    NULL AS parent_id,
+    NULL AS parent_display_name,
+    sl.hidden,
+    sl.full_text,
+    sl.search_config,
+    to_tsvector(sl.search_config, sl.full_text) AS full_text_search_ts,
+    immutable_unaccent(lower(sl.full_text)) AS trigram_search_text,
+    sl.enums,
+    sl.scalars
+FROM searchable_locations sl
+
+UNION
+
+-- Add the entities with their parents locations to the materialized view
+SELECT
+    md5(tl.child_id::text || tl.parent_id::text || tl.location_index::text || 'with_parent')::uuid AS id,
+    tl.child_id AS entity_id,
+    sl.category_id,
+    sl.display_name,
+    sl.family_id,
+    tl.location_index,
+    (tl.value ->> 'long')::double precision AS longitude,
+    (tl.value ->> 'lat')::double precision AS latitude,
+    ST_Transform(ST_SetSRID(ST_MakePoint((tl.value ->> 'long')::double precision, (tl.value ->> 'lat')::double precision), 4326), 3857) AS web_mercator_location,
+    tl.value ->> 'plain_text' AS plain_text_location,
+    sl.tags_ids,
+    tl.parent_id,
+    tl.parent_display_name,
+    sl.hidden,
+    sl.full_text,
+    sl.search_config,
+    to_tsvector(sl.search_config, sl.full_text) AS full_text_search_ts,
+    immutable_unaccent(lower(sl.full_text)) AS trigram_search_text,
+    sl.enums,
+    sl.scalars
+FROM transitive_locations tl
+JOIN searchable_locations sl ON tl.child_id = sl.entity_id;
+
+-- Create unique index on ID for concurrency refresh
+CREATE UNIQUE INDEX entities_caches_id_idx ON entities_caches(id);
+
+-- Create indexes on the materialized view
+CREATE INDEX entities_caches_entity_id_idx ON entities_caches(entity_id);
+CREATE INDEX entities_caches_category_id_idx ON entities_caches(category_id);
+CREATE INDEX entities_caches_family_id_idx ON entities_caches(family_id);
+CREATE INDEX entities_caches_hidden_idx ON entities_caches (hidden);
+CREATE INDEX entities_caches_enums_idx ON entities_caches USING GIN (enums);
+CREATE INDEX entities_caches_scalars_idx ON entities_caches USING GIN (scalars);
+CREATE INDEX entities_caches_gps_location_idx ON entities_caches USING GIST((ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)));
+CREATE INDEX entities_caches_web_mercator_location_idx ON entities_caches USING GIST(web_mercator_location);
+CREATE INDEX entities_caches_full_text_search_idx ON entities_caches USING GIN(full_text_search_ts);
+CREATE INDEX entities_caches_display_name_gist_trgm ON entities_caches USING GIST(display_name gist_trgm_ops);
+CREATE INDEX entities_caches_trigram_search_text_gin_trgm ON entities_caches USING GIN(trigram_search_text gin_trgm_ops);
+
+-- Recreate the clusters dropped along with the cache
+CREATE MATERIALIZED VIEW entities_caches_clusters AS
+SELECT
+    ec.id AS cache_id,
+    ec.family_id,
+    zl.zoom_level,
+    cluster_cell_id(
+        zl.zoom_level,
+        floor(ST_X(ec.web_mercator_location) / zl.cell_size)::bigint,
+        floor(ST_Y(ec.web_mercator_location) / zl.cell_size)::bigint
+    ) AS cluster_id
+FROM entities_caches ec
+CROSS JOIN (
+    SELECT zoom_level, cluster_cell_size(zoom_level) AS cell_size
+    FROM generate_series(0, 18) AS zoom_level
+) zl
+WHERE ec.web_mercator_location IS NOT NULL;
+
+CREATE UNIQUE INDEX entities_caches_clusters_id_idx ON entities_caches_clusters(cache_id, zoom_level);
+CREATE INDEX entities_caches_clusters_cluster_id_idx ON entities_caches_clusters(cluster_id);
+
+DROP FUNCTION search_entities(
+    TEXT, TEXT, UUID,
+    BOOL, BOOL, UUID[], UUID[], UUID[], UUID[],
+    BIGINT, BIGINT,
+    UUID[], UUID[], UUID[],
+    BOOL,
+    JSONB, JSONB,
+    JSONB, TEXT
+);
+
+CREATE OR REPLACE FUNCTION search_entities(
+    search_query TEXT,
+    geographic_restriction TEXT,
+    input_family_id UUID,
+
+    at_allow_all_categories BOOL,
+    at_allow_all_tags BOOL,
+    at_allowed_categories_ids  UUID[],
+    at_allowed_tags_ids UUID[],
+    at_excluded_categories_ids UUID[],
+    at_excluded_tags_ids UUID[],
+
+    current_page BIGINT,
+    page_size BIGINT,
+
+    user_active_categories_ids UUID[],
+    user_required_tags_ids UUID[],
+    user_excluded_tags_ids UUID[],
+
+    require_locations BOOL,
+
+    user_enum_constraints JSONB,
+    user_value_constraints JSONB,
+
+    -- Constraints on the means of the comments scores, in the format of `user_value_constraints`
+    user_score_constraints JSONB,
+    -- Sort of the results, see `entity_sort_values`
+    sort_by TEXT,
+    -- Web Mercator point of the distance sort
+    sort_x DOUBLE PRECISION,
+    sort_y DOUBLE PRECISION,
+    -- Key of the score whose mean sorts the results in the score sort
+    sort_score_key TEXT
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    parents JSONB,
+    locations JSONB,
+    highlight TEXT,
+    scores JSONB,
+    total_results BIGINT,
+    total_pages BIGINT,
+    response_current_page BIGINT
+) AS $$
+DECLARE
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+    sort_point GEOMETRY := ST_SetSRID(ST_MakePoint(sort_x, sort_y), 3857);
+BEGIN
+    RETURN QUERY
+    WITH included_entities AS (
+        SELECT ec.*, ecs.scores, ecs.means AS score_means
+        FROM entities_caches ec
+        LEFT JOIN entities_comment_scores ecs ON ecs.entity_id = ec.entity_id
+        WHERE
+            -- Family filter
+            ec.family_id = input_family_id
+            -- Hidden filter
+            AND NOT ec.hidden
+            -- Access tokens blacklists
+            AND NOT (ec.category_id = ANY(at_excluded_categories_ids))
+            AND NOT (ec.tags_ids && at_excluded_tags_ids)
+            -- User filters blacklists
+            AND NOT (ec.tags_ids && user_excluded_tags_ids)
+    ),
+    filtered_entities AS (
+        SELECT
+            ie.*,
+            CASE
+                WHEN has_query AND normalized_query <> '' AND
+                    (immutable_unaccent(ie.display_name) ILIKE '%' || normalized_query || '%')
+                THEN 1 ELSE 0
+            END AS exact_match_score,
+            CASE
+                WHEN has_query THEN ts_rank(ie.full_text_search_ts, ts_query)
+                ELSE 0
+            END AS text_rank,
+            CASE
+                WHEN has_query THEN word_similarity(normalized_query, ie.trigram_search_text)
+                ELSE 0
+            END AS similarity,
+            CASE
+                WHEN sort_by = 'distance' THEN web_mercator_distance(ie.web_mercator_location, sort_point)
+            END AS distance
+        FROM included_entities ie
+        WHERE
+            (
+                NOT has_query OR entity_matches_search(
+                    ie.full_text_search_ts, ie.display_name, ie.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+            AND (
+                geographic_restriction IS NULL OR
+                ST_Intersects(ie.web_mercator_location, st_geomfromtext(geographic_restriction))
+            )
+            AND ie.family_id = input_family_id
+            AND NOT ie.hidden
+            -- Categories
+            AND (at_allow_all_categories OR ie.category_id = ANY(at_allowed_categories_ids))
+            -- Tags
+            AND (at_allow_all_tags OR (ie.tags_ids && at_allowed_tags_ids))
+            -- User filters
+            AND (ie.category_id = ANY(user_active_categories_ids))
+            AND (array_length(user_required_tags_ids, 1) = 0 OR user_required_tags_ids <@ ie.tags_ids)
+            -- Enum constraints
+            AND (
+                user_enum_constraints IS NULL OR
+                user_enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ie.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(user_enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ie.enums ? key
+                )
+            )
+            -- Value constraints
+            AND value_constraints_match(ie.scalars, user_value_constraints)
+            -- Score constraints
+            AND value_constraints_match(COALESCE(ie.score_means, '{}'::jsonb), user_score_constraints)
+    ),
+    aggregated_entities AS (
+        SELECT
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'id', fe.parent_id,
+                        'display_name', fe.parent_display_name
+                    )
+                ) FILTER (
+                    WHERE fe.parent_id IS NOT NULL
+                        AND fe.parent_id IS NOT NULL
+                        AND fe.parent_display_name IS NOT NULL
+                ),
+                '[]'::jsonb
+            ) AS parents,
+            COALESCE (
+                jsonb_agg(
+                    DISTINCT jsonb_build_object(
+                        'x', ST_X(fe.web_mercator_location),
+                        'y', ST_Y(fe.web_mercator_location),
+                        'plain_text', fe.plain_text_location
+                    )
+                ) FILTER (
+                    WHERE web_mercator_location IS NOT NULL
+                        AND fe.plain_text_location IS NOT NULL),
+                '[]'::jsonb
+            ) AS locations,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config,
+            fe.scores,
+            (fe.score_means->>sort_score_key)::float8 AS score,
+            MIN(fe.distance) AS distance
+        FROM filtered_entities fe
+        GROUP BY
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            fe.full_text,
+            fe.search_config,
+            fe.scores,
+            fe.score_means
+    ),
+    sorted_entities AS (
+        SELECT
+            ae.*,
+            entity_sort_values(
+                sort_by,
+                ae.exact_match_score,
+                ae.text_rank,
+                ae.similarity,
+                e.created_at,
+                e.updated_at,
+                e.moderation_status,
+                ae.distance,
+                ae.score
+            ) AS sort_values,
+            immutable_unaccent(lower(ae.display_name)) AS sort_name
+        FROM aggregated_entities ae
+        JOIN entities e ON e.id = ae.entity_id
+        WHERE ((NOT require_locations) OR jsonb_array_length(ae.locations) > 0)
+    ),
+    total_count AS (
+        SELECT COUNT(*) AS total_results FROM sorted_entities
+    ),
+    paginated_results AS (
+        SELECT
+            re.entity_id AS id,
+            re.entity_id,
+            re.category_id,
+            re.tags_ids,
+            re.family_id,
+            re.display_name,
+            re.parents,
+            re.locations,
+            re.full_text,
+            re.search_config,
+            re.scores,
+            re.sort_values,
+            re.sort_name,
+            tc.total_results,
+            CEIL(tc.total_results / page_size::FLOAT)::BIGINT AS total_pages,
+            current_page as response_current_page
+        FROM sorted_entities re, total_count tc
+        ORDER BY re.sort_values, re.sort_name, re.entity_id
+        LIMIT page_size
+        OFFSET (current_page - 1) * page_size
+    )
+    SELECT
+        pr.id,
+        pr.entity_id,
+        pr.category_id,
+        pr.tags_ids,
+        pr.family_id,
+        pr.display_name,
+        pr.parents,
+        pr.locations,
+        -- Snippets are only computed for the returned page, they are costly. The text is escaped
+        -- before being marked, so that the marks are the only markup of the snippets.
+        CASE
+            WHEN has_query AND numnode(ts_query) > 0 THEN
+                ts_headline(
+                    pr.search_config,
+                    html_escape(pr.full_text),
+                    ts_query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=8, MaxFragments=2'
+                )
+            ELSE NULL
+        END AS highlight,
+        COALESCE(pr.scores, '{}'::jsonb) AS scores,
+        pr.total_results,
+        pr.total_pages,
+        pr.response_current_page
+    FROM paginated_results pr
+    ORDER BY pr.sort_values, pr.sort_name, pr.entity_id;
+END;
+$$ LANGUAGE plpgsql
+-- Lower than the default 0.6 to tolerate single typos in short words
+SET pg_trgm.word_similarity_threshold = 0.5;
+
+-- Paginate the admin search with a cursor rather than counting the results on every request
+DROP FUNCTION search_entities_admin(
+    TEXT, UUID,
+    BIGINT, BIGINT,
+    UUID[], UUID[], UUID[],
+    JSONB
+);
+
+CREATE OR REPLACE FUNCTION search_entities_admin(
+    search_query TEXT,
+    input_family_id UUID,
+
+    -- Sort keys of the last entity of the previous page, NULL for the first page
+    after_sort_values DOUBLE PRECISION[],
+    after_sort_name TEXT,
+    after_entity_id UUID,
+    page_size BIGINT,
+
+    active_categories_ids UUID[],
+    required_tags_ids UUID[],
+    excluded_tags_ids UUID[],
+
+    enum_constraints JSONB,
+
+    -- Sort of the results, see `entity_sort_values`
+    sort_by TEXT,
+    sort_x DOUBLE PRECISION,
+    sort_y DOUBLE PRECISION,
+    sort_score_key TEXT
+) RETURNS TABLE (
+    id UUID,
+    entity_id UUID,
+    category_id UUID,
+    tags_ids UUID[],
+    family_id UUID,
+    display_name TEXT,
+    hidden BOOL,
+    moderation_status moderation_status,
+
+    sort_values DOUBLE PRECISION[],
+    sort_name TEXT
+) AS $$
+DECLARE
+    has_query BOOL := search_query IS NOT NULL AND search_query <> '';
+    search_config regconfig := text_search_config(
+        (SELECT f.text_search_language FROM families f WHERE f.id = input_family_id)
+    );
+    normalized_query TEXT := fuzzy_search_query(search_query);
+    excluded_query tsquery := excluded_search_query(search_config, search_query);
+    ts_query tsquery := CASE
+        WHEN has_query THEN websearch_to_tsquery(search_config, search_query)
+    END;
+    sort_point GEOMETRY := ST_SetSRID(ST_MakePoint(sort_x, sort_y), 3857);
+BEGIN
+    RETURN QUERY
+    WITH filtered_entities AS (
+        SELECT
+            ec.*,
+            CASE
+                WHEN has_query AND normalized_query <> '' AND
+                    immutable_unaccent(ec.display_name) ILIKE '%' || normalized_query || '%'
+                THEN 1 ELSE 0
+            END AS exact_match_score,
+            CASE
+                WHEN has_query THEN ts_rank(ec.full_text_search_ts, ts_query)
+                ELSE 0
+            END AS text_rank,
+            CASE
+                WHEN has_query THEN word_similarity(normalized_query, ec.trigram_search_text)
+                ELSE 0
+            END AS similarity,
+            CASE
+                WHEN sort_by = 'distance' THEN web_mercator_distance(ec.web_mercator_location, sort_point)
+            END AS distance
+        FROM entities_caches ec
+        WHERE
+            (
+                NOT has_query OR entity_matches_search(
+                    ec.full_text_search_ts, ec.display_name, ec.trigram_search_text,
+                    ts_query, normalized_query, excluded_query
+                )
+            )
+            AND ec.family_id = input_family_id
+            AND ec.category_id = ANY(active_categories_ids)
+            -- Categories and tags constraints
+            AND (array_length(required_tags_ids, 1) = 0 OR required_tags_ids <@ ec.tags_ids)
+            AND NOT (ec.tags_ids && excluded_tags_ids)
+            -- Enum constraints
+            AND (
+                enum_constraints IS NULL OR
+                enum_constraints = '{}'::jsonb OR
+                (
+                    SELECT bool_and(
+                        ec.enums->key ?| array(SELECT jsonb_array_elements_text(value))
+                    )
+                    FROM jsonb_each(enum_constraints) AS constraints(key, value)
+                    WHERE key IS NOT NULL AND ec.enums ? key
+                )
+            )
+    ),
+    distinct_entities AS (
+        SELECT DISTINCT ON (fe.entity_id)
+            fe.id,
+            fe.entity_id,
+            fe.category_id,
+            fe.tags_ids,
+            fe.family_id,
+            fe.display_name,
+            fe.hidden,
+            fe.exact_match_score,
+            fe.text_rank,
+            fe.similarity,
+            MIN(fe.distance) OVER (PARTITION BY fe.entity_id) AS distance
+        FROM filtered_entities fe
+        ORDER BY fe.entity_id
+    ),
+    sorted_entities AS (
+        SELECT
+            de.*,
+            e.hidden AS entity_hidden,
+            e.moderation_status AS status,
+            entity_sort_values(
+                sort_by,
+                de.exact_match_score,
+                de.text_rank,
+                de.similarity,
+                e.created_at,
+                e.updated_at,
+                e.moderation_status,
+                de.distance,
+                (ecs.means->>sort_score_key)::float8
+            ) AS sort_keys,
+            immutable_unaccent(lower(de.display_name)) AS sort_key_name
+        FROM distinct_entities de
+        JOIN entities e ON e.id = de.entity_id
+        LEFT JOIN entities_comment_scores ecs ON ecs.entity_id = de.entity_id
+    )
+    SELECT
+        se.id,
+        se.entity_id,
+        se.category_id,
+        se.tags_ids,
+        se.family_id,
+        se.display_name,
+        se.entity_hidden,
+        se.status,
+        se.sort_keys,
+        se.sort_key_name
+    FROM sorted_entities se
+    WHERE
+        after_entity_id IS NULL
+        OR (se.sort_keys, se.sort_key_name, se.entity_id)
+            > (after_sort_values, after_sort_name, after_entity_id)
+    ORDER BY se.sort_keys, se.sort_key_name, se.entity_id
+    LIMIT page_size;
+END;
+$$ LANGUAGE plpgsql
+-- Lower than the default 0.6 to tolerate single typos in short words
+SET pg_trgm.word_similarity_threshold = 0.5;
@@ -0,0 +1,30 @@
+-- Statistics of the refreshes of the entities cache, exposed by the metrics endpoint.
+-- The refreshes are triggered by the database itself, so they are counted here.
+CREATE TABLE entities_caches_refreshes (
+    id BOOL PRIMARY KEY DEFAULT TRUE CHECK (id),
+    refreshes_count BIGINT NOT NULL DEFAULT 0,
+    total_duration DOUBLE PRECISION NOT NULL DEFAULT 0,
+    last_duration DOUBLE PRECISION,
+    last_refreshed_at TIMESTAMPTZ
+);
+
+INSERT INTO entities_caches_refreshes DEFAULT VALUES;
+
+CREATE OR REPLACE FUNCTION refresh_entities_caches() RETURNS void AS $$
+DECLARE
+    started_at TIMESTAMPTZ := clock_timestamp();
+    duration DOUBLE PRECISION;
+BEGIN
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches;
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_caches_clusters;
+    REFRESH MATERIALIZED VIEW CONCURRENTLY entities_comment_scores;
+
+    duration := EXTRACT(EPOCH FROM clock_timestamp() - started_at);
+
+    UPDATE entities_caches_refreshes SET
+        refreshes_count = refreshes_count + 1,
+        total_duration = total_duration + duration,
+        last_duration = duration,
+        last_refreshed_at = clock_timestamp();
+END;
+$$ LANGUAGE plpgsql;
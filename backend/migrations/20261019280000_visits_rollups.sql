@@ -0,0 +1,55 @@
+-- Daily counts of the visits, so that the statistics do not scan the individual visits. They
+-- are kept for a limited time once counted.
+CREATE TABLE access_tokens_visits_daily (
+    token_id UUID NOT NULL,
+    visit_date DATE NOT NULL,
+    referrer TEXT NOT NULL,
+    visits BIGINT NOT NULL,
+
+    PRIMARY KEY (token_id, visit_date, referrer),
+    FOREIGN KEY (token_id) REFERENCES access_tokens(id) ON DELETE CASCADE
+);
+CREATE INDEX ON access_tokens_visits_daily (visit_date);
+
+-- The visits before this date are counted in the daily table
+CREATE TABLE access_tokens_visits_rollup (
+    id BOOL PRIMARY KEY DEFAULT TRUE CHECK (id),
+    rolled_up_until DATE NOT NULL
+);
+INSERT INTO access_tokens_visits_rollup (rolled_up_until) VALUES ('-infinity');
+
+-- Counts the visits of the days over, leaving an hour for the visits still buffered by the
+-- instances, then deletes the counted visits older than the retention period
+CREATE OR REPLACE FUNCTION rollup_access_tokens_visits(retention_days INT) RETURNS void AS $$
+DECLARE
+    from_date DATE;
+    until_date DATE := (NOW() - INTERVAL '1 hour')::date;
+BEGIN
+    SELECT rolled_up_until INTO from_date FROM access_tokens_visits_rollup FOR UPDATE;
+
+    IF from_date < until_date THEN
+        INSERT INTO access_tokens_visits_daily (token_id, visit_date, referrer, visits)
+        SELECT token_id, visited_at::date, COALESCE(referrer, 'unknown'), COUNT(*)
+        FROM access_tokens_visits
+        WHERE visited_at >= from_date AND visited_at < until_date
+        GROUP BY 1, 2, 3
+        ON CONFLICT (token_id, visit_date, referrer) DO UPDATE SET visits = EXCLUDED.visits;
+
+        UPDATE access_tokens_visits_rollup SET rolled_up_until = until_date;
+        from_date := until_date;
+    END IF;
+
+    DELETE FROM access_tokens_visits
+    WHERE visited_at < LEAST(from_date, CURRENT_DATE - retention_days);
+END;
+$$ LANGUAGE plpgsql;
+
+-- Daily counts of all the visits, including those not rolled up yet
+CREATE VIEW access_tokens_daily_visits AS
+    SELECT token_id, visit_date, referrer, visits
+    FROM access_tokens_visits_daily
+    UNION ALL
+    SELECT token_id, visited_at::date, COALESCE(referrer, 'unknown'), COUNT(*)
+    FROM access_tokens_visits
+    WHERE visited_at >= (SELECT rolled_up_until FROM access_tokens_visits_rollup)
+    GROUP BY 1, 2, 3;
@@ -0,0 +1,116 @@
+-- Salts of the visitors hashes, a new one is drawn every day so that the visitors cannot be
+-- followed from a day to another. They are pruned by the backend when it draws the salt of a new
+-- day, with the same clock.
+CREATE TABLE visitors_salts (
+    salt_date DATE PRIMARY KEY,
+    salt TEXT NOT NULL DEFAULT uuid_generate_v4()::text
+);
+
+-- The visits predating these columns are counted as unknown
+ALTER TABLE access_tokens_visits
+    ADD COLUMN visitor_hash TEXT,
+    ADD COLUMN device_class TEXT,
+    ADD COLUMN language TEXT;
+
+ALTER TABLE access_tokens_visits_daily
+    ADD COLUMN device_class TEXT NOT NULL DEFAULT 'unknown',
+    ADD COLUMN language TEXT NOT NULL DEFAULT 'unknown',
+    DROP CONSTRAINT access_tokens_visits_daily_pkey,
+    ADD PRIMARY KEY (token_id, visit_date, referrer, device_class, language);
+
+-- Distinct visitors hashes of each day, which cannot be summed over several days
+CREATE TABLE access_tokens_visitors_daily (
+    token_id UUID NOT NULL,
+    visit_date DATE NOT NULL,
+    visitors BIGINT NOT NULL,
+
+    PRIMARY KEY (token_id, visit_date),
+    FOREIGN KEY (token_id) REFERENCES access_tokens(id) ON DELETE CASCADE
+);
+CREATE INDEX ON access_tokens_visitors_daily (visit_date);
+
+-- Views of the families and categories, counted directly by day
+CREATE TABLE families_views_daily (
+    family_id UUID NOT NULL,
+    view_date DATE NOT NULL,
+    map_views BIGINT NOT NULL DEFAULT 0,
+    entity_views BIGINT NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (family_id, view_date),
+    FOREIGN KEY (family_id) REFERENCES families(id) ON DELETE CASCADE
+);
+
+CREATE TABLE categories_views_daily (
+    category_id UUID NOT NULL,
+    view_date DATE NOT NULL,
+    map_views BIGINT NOT NULL DEFAULT 0,
+    entity_views BIGINT NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (category_id, view_date),
+    FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
+CREATE OR REPLACE FUNCTION rollup_access_tokens_visits(retention_days INT) RETURNS void AS $$
+DECLARE
+    from_date DATE;
+    until_date DATE := (NOW() - INTERVAL '1 hour')::date;
+BEGIN
+    SELECT rolled_up_until INTO from_date FROM access_tokens_visits_rollup FOR UPDATE;
+
+    IF from_date < until_date THEN
+        INSERT INTO access_tokens_visits_daily
+            (token_id, visit_date, referrer, device_class, language, visits)
+        SELECT
+            token_id,
+            visited_at::date,
+            COALESCE(referrer, 'unknown'),
+            COALESCE(device_class, 'unknown'),
+            COALESCE(language, 'unknown'),
+            COUNT(*)
+        FROM access_tokens_visits
+        WHERE visited_at >= from_date AND visited_at < until_date
+        GROUP BY 1, 2, 3, 4, 5
+        ON CONFLICT (token_id, visit_date, referrer, device_class, language)
+        DO UPDATE SET visits = EXCLUDED.visits;
+
+        INSERT INTO access_tokens_visitors_daily (token_id, visit_date, visitors)
+        SELECT token_id, visited_at::date, COUNT(DISTINCT visitor_hash)
+        FROM access_tokens_visits
+        WHERE visited_at >= from_date AND visited_at < until_date
+        GROUP BY 1, 2
+        ON CONFLICT (token_id, visit_date) DO UPDATE SET visitors = EXCLUDED.visitors;
+
+        UPDATE access_tokens_visits_rollup SET rolled_up_until = until_date;
+        from_date := until_date;
+    END IF;
+
+    DELETE FROM access_tokens_visits
+    WHERE visited_at < LEAST(from_date, CURRENT_DATE - retention_days);
+END;
+$$ LANGUAGE plpgsql;
+
+DROP VIEW access_tokens_daily_visits;
+CREATE VIEW access_tokens_daily_visits AS
+    SELECT token_id, visit_date, referrer, device_class, language, visits
+    FROM access_tokens_visits_daily
+    UNION ALL
+    SELECT
+        token_id,
+        visited_at::date,
+        COALESCE(referrer, 'unknown'),
+        COALESCE(device_class, 'unknown'),
+        COALESCE(language, 'unknown'),
+        COUNT(*)
+    FROM access_tokens_visits
+    WHERE visited_at >= (SELECT rolled_up_until FROM access_tokens_visits_rollup)
+    GROUP BY 1, 2, 3, 4, 5;
+
+-- Daily visitors of all the visits, including those not rolled up yet
+CREATE VIEW access_tokens_daily_visitors AS
+    SELECT token_id, visit_date, visitors
+    FROM access_tokens_visitors_daily
+    UNION ALL
+    SELECT token_id, visited_at::date, COUNT(DISTINCT visitor_hash)
+    FROM access_tokens_visits
+    WHERE visited_at >= (SELECT rolled_up_until FROM access_tokens_visits_rollup)
+    GROUP BY 1, 2;
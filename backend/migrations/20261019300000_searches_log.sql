@@ -0,0 +1,33 @@
+-- Searches of the map, without anything identifying the visitor, to find what is missing. The
+-- queries are normalised so that their variants are counted together.
+CREATE TABLE searches_log (
+    id BIGSERIAL PRIMARY KEY,
+    searched_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    family_id UUID NOT NULL,
+    query TEXT NOT NULL,
+    filters JSONB NOT NULL,
+    results_count BIGINT NOT NULL,
+
+    FOREIGN KEY (family_id) REFERENCES families(id) ON DELETE CASCADE
+);
+CREATE INDEX ON searches_log (searched_at);
+
+-- Lowercase, unaccented, with single spaces and cut to a reasonable length. Email addresses and
+-- numbers (phone numbers, addresses, identifiers...) are removed, as they could identify who
+-- searched them.
+CREATE OR REPLACE FUNCTION normalise_search_query(query TEXT) RETURNS TEXT AS $$
+    SELECT left(
+        regexp_replace(
+            regexp_replace(
+                lower(immutable_unaccent(regexp_replace(btrim(query), '\s+', ' ', 'g'))),
+                '\S+@\S+',
+                '<email>',
+                'g'
+            ),
+            '[0-9]+',
+            '#',
+            'g'
+        ),
+        200
+    )
+$$ LANGUAGE sql IMMUTABLE;
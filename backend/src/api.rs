@@ -1,40 +1,116 @@
 pub mod admin;
 pub mod auth;
+pub mod health;
 pub mod icons;
 pub mod map;
+pub mod metrics;
+pub mod rate_limit;
 pub mod root;
 
 use crate::{
+    api::rate_limit::RateLimiter,
     config::SafeHavenConfig,
+    helpers::{
+        captcha::ProofOfWork,
+        metrics::Metrics,
+        notifications::{Mailer, Template},
+        tasks::WorkQueue,
+        webhooks,
+    },
     models::{
+        access_token::{AccessToken, Visit},
+        analytics::{LoggedSearch, View, ViewKind, ViewsCounts, VisitorsSalt},
+        moderation::Moderation,
+        notification::{ModerationSummary, NotificationFrequency, NotificationRecipient},
         options::SafeHavenOptions,
         user::{NewOrUpdatedUser, User},
+        view_preset::ViewPreset,
+        webhook::WebhookDelivery,
     },
 };
 use axum::{
     async_trait,
-    extract::{FromRef, FromRequest, FromRequestParts},
-    http::{request::Parts, StatusCode},
+    extract::{ConnectInfo, FromRef, FromRequest, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
+use chrono::Utc;
 use serde::Serialize;
 use sqlx::{
+    migrate::Migrator,
     postgres::{PgListener, PgPoolOptions},
     PgConnection, Pool, Postgres,
 };
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{Notify, RwLock};
+use tokio_util::sync::CancellationToken;
 use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
 
 pub type DynOptions = Arc<RwLock<SafeHavenOptions>>;
 pub type IconCache = Arc<RwLock<HashMap<String, (Vec<u8>, String)>>>;
 
+/// Retries are due without any notification, so the deliveries are also polled regularly
+const WEBHOOKS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const WEBHOOKS_BATCH_SIZE: i64 = 20;
+const WEBHOOK_DELIVERIES_RETENTION_DAYS: i32 = 30;
+
+/// Days the anonymous share codes are kept after they were last opened
+const SHARED_PRESETS_RETENTION_DAYS: i32 = 365;
+
+/// Delay between the checks of the users due for a notification
+const NOTIFICATIONS_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Visits waiting to be registered, the next ones are dropped when it is full
+const VISITS_QUEUE_CAPACITY: usize = 10_000;
+/// Visits are registered by batches, at least this often
+const VISITS_BATCH_SIZE: usize = 500;
+const VISITS_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// Delay between the rollups of the visits into daily counts
+const VISITS_ROLLUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Searches waiting to be logged, the next ones are dropped when it is full
+const SEARCHES_QUEUE_CAPACITY: usize = 10_000;
+const SEARCHES_BATCH_SIZE: usize = 500;
+const SEARCHES_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Views waiting to be counted, the next ones are dropped when it is full
+const VIEWS_QUEUE_CAPACITY: usize = 10_000;
+/// Views are counted in memory and registered at this interval
+const VIEWS_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<SafeHavenConfig>,
     pub dyn_config: DynOptions,
     pub pool: Pool<Postgres>,
     pub icon_cache: IconCache,
+    pub proof_of_work: ProofOfWork,
+    pub rate_limiter: RateLimiter,
+    /// Woken up when webhook deliveries are queued
+    pub webhooks_wakeup: Arc<Notify>,
+    /// Sends the email notifications, if configured
+    pub mailer: Option<Mailer>,
+    pub metrics: Metrics,
+    /// Whether the connection listening for the PostgreSQL notifications is up
+    pub events_listener_connected: Arc<AtomicBool>,
+    /// Visits of the map, registered in the background
+    pub visits: WorkQueue<Visit>,
+    pub visitors_salt: VisitorsSalt,
+    /// Views of the families and categories, counted in the background
+    pub views: WorkQueue<View>,
+    /// Searches of the map, logged anonymously in the background
+    pub searches: WorkQueue<LoggedSearch>,
 }
 
 impl AppState {
@@ -47,10 +123,7 @@ impl AppState {
             .await
             .expect("can't connect to database");
 
-        sqlx::migrate!()
-            .run(&pool)
-            .await
-            .expect("can't run migrations");
+        MIGRATOR.run(&pool).await.expect("can't run migrations");
 
         tracing::info!("Migrations executed");
 
@@ -93,17 +166,35 @@ impl AppState {
         tracing::info!("Loading dynamic configuration from database");
         let dyn_config = Arc::new(RwLock::new(SafeHavenOptions::load(&mut conn).await));
 
+        let proof_of_work = ProofOfWork::new(&config.token_secret, pool.clone());
+        let rate_limiter = RateLimiter::new(&config.rate_limit, pool.clone());
+        let mailer = Mailer::from_config(&config.smtp).expect("invalid SMTP configuration");
+
         Self {
             config,
             pool,
             dyn_config,
             icon_cache: Arc::new(RwLock::new(HashMap::new())),
+            proof_of_work,
+            rate_limiter,
+            webhooks_wakeup: Arc::new(Notify::new()),
+            mailer,
+            metrics: Metrics::new(),
+            events_listener_connected: Arc::new(AtomicBool::new(false)),
+            visits: WorkQueue::new(VISITS_QUEUE_CAPACITY),
+            visitors_salt: VisitorsSalt::default(),
+            views: WorkQueue::new(VIEWS_QUEUE_CAPACITY),
+            searches: WorkQueue::new(SEARCHES_QUEUE_CAPACITY),
         }
     }
 
     pub async fn listen_postgresql_events(&self) {
         loop {
-            match self.internal_listen().await {
+            let result = self.internal_listen().await;
+            self.events_listener_connected
+                .store(false, Ordering::Relaxed);
+
+            match result {
                 Ok(_) => break,
                 Err(e) => {
                     tracing::error!("Error listening for PostgreSQL notifications: {:?}", e);
@@ -119,12 +210,18 @@ impl AppState {
         let mut listener = PgListener::connect_with(&self.pool).await?;
 
         listener.listen("reload_options").await?;
+        listener.listen("webhook_deliveries").await?;
 
         tracing::info!("Listening for PostgreSQL notifications");
+        self.events_listener_connected
+            .store(true, Ordering::Relaxed);
 
         loop {
             while let Some(notification) = listener.try_recv().await? {
                 match notification.channel() {
+                    "webhook_deliveries" => {
+                        self.webhooks_wakeup.notify_one();
+                    }
                     "reload_options" => {
                         tracing::info!("Received notification to reload options");
 
@@ -148,6 +245,315 @@ impl AppState {
         }
     }
 
+    /// Registers the queued visits by batches, and those still queued on shutdown
+    pub async fn register_visits(&self, shutdown: CancellationToken) {
+        self.visits
+            .process_batches(
+                VISITS_BATCH_SIZE,
+                VISITS_FLUSH_INTERVAL,
+                shutdown,
+                |batch| self.flush_visits(batch),
+            )
+            .await;
+    }
+
+    async fn flush_visits(&self, batch: Vec<Visit>) {
+        tracing::trace!("Registering {} visits", batch.len());
+        let registered = match self.pool.acquire().await {
+            Ok(mut conn) => AccessToken::register_visits(&batch, &mut conn).await,
+            Err(e) => Err(AppError::Database(e)),
+        };
+
+        if let Err(e) = registered {
+            tracing::error!("Failed to register {} visits: {:?}", batch.len(), e);
+        }
+    }
+
+    /// Logs the queued searches by batches, and those still queued on shutdown
+    pub async fn register_searches(&self, shutdown: CancellationToken) {
+        self.searches
+            .process_batches(
+                SEARCHES_BATCH_SIZE,
+                SEARCHES_FLUSH_INTERVAL,
+                shutdown,
+                |batch| self.flush_searches(batch),
+            )
+            .await;
+    }
+
+    async fn flush_searches(&self, batch: Vec<LoggedSearch>) {
+        let retention_days = self.config.analytics.searches_retention_days as i32;
+        let registered = match self.pool.acquire().await {
+            Ok(mut conn) => LoggedSearch::register(&batch, retention_days, &mut conn).await,
+            Err(e) => Err(AppError::Database(e)),
+        };
+
+        if let Err(e) = registered {
+            tracing::error!("Failed to log {} searches: {:?}", batch.len(), e);
+        }
+    }
+
+    /// Queues a view of the family and categories to be counted in the background
+    pub fn record_view(
+        &self,
+        kind: ViewKind,
+        visitor_hash: Option<String>,
+        family_id: Uuid,
+        categories_ids: Vec<Uuid>,
+    ) {
+        if !self.views.push(View {
+            kind,
+            viewed_on: Utc::now().date_naive(),
+            visitor_hash,
+            family_id,
+            categories_ids,
+        }) {
+            tracing::warn!("Views queue is full, view dropped");
+        }
+    }
+
+    /// Counts the queued views in memory and registers them regularly, and on shutdown
+    pub async fn register_views(&self, shutdown: CancellationToken) {
+        let mut views = self.views.receiver().await;
+        let mut counts = ViewsCounts::default();
+        let mut interval = tokio::time::interval(VIEWS_FLUSH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                view = views.recv() => match view {
+                    Some(view) => {
+                        counts.add(view);
+                        continue;
+                    }
+                    None => break,
+                },
+                _ = interval.tick() => {},
+                _ = shutdown.cancelled() => break,
+            }
+
+            self.flush_views(&mut counts).await;
+        }
+
+        views.close();
+        while let Some(view) = views.recv().await {
+            counts.add(view);
+        }
+        self.flush_views(&mut counts).await;
+    }
+
+    async fn flush_views(&self, counts: &mut ViewsCounts) {
+        if counts.is_empty() {
+            return;
+        }
+
+        let registered = match self.pool.acquire().await {
+            Ok(mut conn) => counts.register(&mut conn).await,
+            Err(e) => Err(AppError::Database(e)),
+        };
+
+        if let Err(e) = registered {
+            tracing::error!("Failed to register views: {:?}", e);
+        }
+        counts.clear(Utc::now().date_naive());
+    }
+
+    /// Counts the visits by day and deletes the old individual visits, every hour
+    pub async fn rollup_visits(&self) {
+        let mut interval = tokio::time::interval(VISITS_ROLLUP_INTERVAL);
+        let retention_days = self.config.analytics.visits_retention_days as i32;
+
+        loop {
+            interval.tick().await;
+
+            let rolled_up = match self.pool.acquire().await {
+                Ok(mut conn) => AccessToken::rollup_visits(retention_days, &mut conn).await,
+                Err(e) => Err(AppError::Database(e)),
+            };
+
+            if let Err(e) = rolled_up {
+                tracing::error!("Error rolling up visits: {:?}", e);
+            }
+        }
+    }
+
+    /// Deletes the submissions rejected for longer than the appeal period, every hour
+    pub async fn purge_rejected_submissions(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+
+        loop {
+            interval.tick().await;
+
+            let appeal_period_days = self.dyn_config.read().await.moderation.appeal_period_days;
+
+            let purged = match self.pool.acquire().await {
+                Ok(mut conn) => Moderation::purge_rejected(appeal_period_days, &mut conn).await,
+                Err(e) => Err(AppError::Database(e)),
+            };
+
+            match purged {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} rejected submissions", count),
+                Err(e) => tracing::error!("Error purging rejected submissions: {:?}", e),
+            }
+        }
+    }
+
+    /// Deletes the anonymous share codes not opened for a long time, every day
+    pub async fn purge_unused_presets(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(24 * 3600));
+
+        loop {
+            interval.tick().await;
+
+            let purged = match self.pool.acquire().await {
+                Ok(mut conn) => {
+                    ViewPreset::purge_unused(SHARED_PRESETS_RETENTION_DAYS, &mut conn).await
+                }
+                Err(e) => Err(AppError::Database(e)),
+            };
+
+            match purged {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} unused share codes", count),
+                Err(e) => tracing::error!("Error purging unused share codes: {:?}", e),
+            }
+        }
+    }
+
+    /// Sends the queued webhook deliveries, as soon as they are notified and when due for a retry
+    pub async fn deliver_webhooks(&self) {
+        let client = reqwest::Client::new();
+        let mut pruned_at: Option<Instant> = None;
+
+        loop {
+            let _ =
+                tokio::time::timeout(WEBHOOKS_POLL_INTERVAL, self.webhooks_wakeup.notified()).await;
+
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Error acquiring connection for webhooks: {:?}", e);
+                    continue;
+                }
+            };
+
+            loop {
+                let due = match WebhookDelivery::claim_due(WEBHOOKS_BATCH_SIZE, &mut conn).await {
+                    Ok(due) if !due.is_empty() => due,
+                    Ok(_) => break,
+                    Err(e) => {
+                        tracing::error!("Error claiming webhook deliveries: {:?}", e);
+                        break;
+                    }
+                };
+
+                for delivery in due {
+                    let (response_status, error) = webhooks::send(&client, &delivery).await;
+
+                    if let Some(error) = &error {
+                        tracing::warn!(
+                            "Webhook delivery {} failed (attempt {}): {}",
+                            delivery.id,
+                            delivery.attempts,
+                            error
+                        );
+                    }
+
+                    if let Err(e) = WebhookDelivery::record_attempt(
+                        &delivery,
+                        response_status,
+                        error,
+                        &mut conn,
+                    )
+                    .await
+                    {
+                        tracing::error!("Error recording webhook delivery: {:?}", e);
+                    }
+                }
+            }
+
+            if pruned_at.is_none_or(|at| at.elapsed() > Duration::from_secs(3600)) {
+                if let Err(e) =
+                    WebhookDelivery::prune(WEBHOOK_DELIVERIES_RETENTION_DAYS, &mut conn).await
+                {
+                    tracing::error!("Error pruning webhook deliveries: {:?}", e);
+                }
+                pruned_at = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Emails the users due for a notification of the content awaiting review
+    pub async fn send_notifications(&self) {
+        let Some(mailer) = &self.mailer else {
+            return std::future::pending().await;
+        };
+
+        let mut interval = tokio::time::interval(NOTIFICATIONS_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Error acquiring connection for notifications: {:?}", e);
+                    continue;
+                }
+            };
+
+            let recipients = match NotificationRecipient::claim_due(&mut conn).await {
+                Ok(recipients) => recipients,
+                Err(e) => {
+                    tracing::error!("Error claiming notification recipients: {:?}", e);
+                    continue;
+                }
+            };
+
+            for recipient in recipients {
+                let summary = match ModerationSummary::since(recipient.since, &mut conn).await {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        tracing::error!("Error summarizing moderation: {:?}", e);
+                        if let Err(e) = recipient.release(&mut conn).await {
+                            tracing::error!("Error releasing {}: {:?}", recipient.name, e);
+                        }
+                        continue;
+                    }
+                };
+
+                // Digests are only sent when there is something to review
+                if summary.is_empty() {
+                    continue;
+                }
+
+                let template = match recipient.frequency {
+                    NotificationFrequency::Daily => Template::Digest,
+                    _ => Template::Immediate,
+                };
+                let mut values = summary.template_values();
+                values.push(("name", recipient.name.clone()));
+                values.push(("admin_url", mailer.admin_url()));
+
+                if let Err(e) = mailer
+                    .send(
+                        &recipient.name,
+                        &recipient.email,
+                        &recipient.locale,
+                        template,
+                        &values,
+                    )
+                    .await
+                {
+                    tracing::error!("Error notifying {}: {:?}", recipient.name, e);
+                    if let Err(e) = recipient.release(&mut conn).await {
+                        tracing::error!("Error releasing {}: {:?}", recipient.name, e);
+                    }
+                }
+            }
+        }
+    }
+
     /// Reload the dynamic configuration from the database
     async fn reload_data(&self, conn: &mut PgConnection) {
         let mut dyn_config = self.dyn_config.write().await;
@@ -187,6 +593,53 @@ where
     }
 }
 
+/// Address of the client, taken from the X-Forwarded-For header when the configuration trusts it
+pub struct ClientIp(pub IpAddr);
+
+/// Address appended to X-Forwarded-For by the farthest of the trusted proxies, the hops before it
+/// are sent by the client and cannot be trusted
+fn forwarded_client_ip(headers: &HeaderMap, trusted_proxies: usize) -> Option<IpAddr> {
+    let hops: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    hops.len()
+        .checked_sub(trusted_proxies.max(1))
+        .and_then(|index| hops[index].parse().ok())
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+
+        if app_state.config.trust_forwarded_for {
+            let forwarded_ip =
+                forwarded_client_ip(&parts.headers, app_state.config.trusted_proxies);
+
+            if let Some(ip) = forwarded_ip {
+                return Ok(Self(ip));
+            }
+        }
+
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| Self(addr.ip()))
+            .ok_or_else(|| AppError::Internal(Some("Client address unavailable".to_string())))
+    }
+}
+
 #[derive(Debug)]
 pub enum AppError {
     Pool,
@@ -199,6 +652,8 @@ pub enum AppError {
     InvalidPagination,
     Internal(Option<String>),
     NotFound,
+    /// Rate limit exceeded, with the number of seconds to wait
+    TooManyRequests(u64),
 }
 
 #[derive(FromRequest)]
@@ -257,6 +712,17 @@ impl IntoResponse for AppError {
             AppError::InvalidPagination => (StatusCode::BAD_REQUEST, "invalid_pagination", None),
             AppError::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", e),
             AppError::Forbidden => (StatusCode::FORBIDDEN, "forbidden", None),
+            AppError::TooManyRequests(retry_after) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    AppJson(ErrorResponse {
+                        error_code: "too_many_requests".to_string(),
+                        details: None,
+                    }),
+                )
+                    .into_response();
+            }
         };
 
         let resp = (
@@ -270,3 +736,26 @@ impl IntoResponse for AppError {
         resp.into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_client_ip_ignores_the_hops_set_by_the_client() {
+        let mut headers = HeaderMap::new();
+        headers.append("X-Forwarded-For", "1.1.1.1, 2.2.2.2".parse().unwrap());
+        headers.append("X-Forwarded-For", "3.3.3.3".parse().unwrap());
+
+        assert_eq!(
+            forwarded_client_ip(&headers, 1),
+            Some("3.3.3.3".parse().unwrap())
+        );
+        assert_eq!(
+            forwarded_client_ip(&headers, 2),
+            Some("2.2.2.2".parse().unwrap())
+        );
+        assert_eq!(forwarded_client_ip(&headers, 4), None);
+        assert_eq!(forwarded_client_ip(&HeaderMap::new(), 1), None);
+    }
+}
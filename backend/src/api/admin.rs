@@ -4,10 +4,14 @@ pub mod categories;
 pub mod comments;
 pub mod entities;
 pub mod families;
+pub mod moderation;
 pub mod options;
+pub mod reports;
 pub mod statistics;
+pub mod suggestions;
 pub mod tags;
 pub mod users;
+pub mod webhooks;
 
 use crate::api::{AppError, AppJson, AppState, DbConn};
 use crate::models::user::User;
@@ -33,6 +37,9 @@ pub fn routes(state: &AppState) -> Router<AppState> {
     let authenticated_router: Router<AppState> = Router::new()
         // home statistic
         .route("/stats", get(statistics::admin_home_stats))
+        .route("/stats/visitors", get(statistics::admin_visitors_stats))
+        .route("/stats/views", get(statistics::admin_views_stats))
+        .route("/stats/searches", get(statistics::admin_searches_stats))
         // sessions
         .route("/session", get(admin_login_check))
         // options
@@ -47,6 +54,18 @@ pub fn routes(state: &AppState) -> Router<AppState> {
             "/users/self/password",
             put(users::admin_user_change_self_password),
         )
+        .route(
+            "/users/self/notifications",
+            get(users::admin_user_get_self_notifications),
+        )
+        .route(
+            "/users/self/notifications",
+            put(users::admin_user_update_self_notifications),
+        )
+        .route(
+            "/users/self/notifications/test",
+            post(users::admin_user_test_self_notifications),
+        )
         .route("/users/:id", put(users::admin_user_update))
         .route("/users/:id", delete(users::admin_user_delete))
         // access_tokens
@@ -74,9 +93,29 @@ pub fn routes(state: &AppState) -> Router<AppState> {
             "/access_tokens/:id",
             delete(access_tokens::admin_access_token_delete),
         )
+        .route(
+            "/access_tokens/:id/presets",
+            get(access_tokens::admin_access_token_presets_list),
+        )
+        .route(
+            "/access_tokens/:id/presets",
+            post(access_tokens::admin_access_token_preset_new),
+        )
+        .route(
+            "/access_tokens/:id/presets/:preset_id",
+            put(access_tokens::admin_access_token_preset_update),
+        )
+        .route(
+            "/access_tokens/:id/presets/:preset_id",
+            delete(access_tokens::admin_access_token_preset_delete),
+        )
         // families
         .route("/families", get(families::admin_families_list))
         .route("/families", post(families::admin_family_new))
+        .route(
+            "/families/text_search_languages",
+            get(families::admin_families_text_search_languages),
+        )
         .route("/families/:id", get(families::admin_family_get))
         .route("/families/:id", put(families::admin_family_update))
         .route("/families/:id", delete(families::admin_family_delete))
@@ -119,6 +158,11 @@ pub fn routes(state: &AppState) -> Router<AppState> {
             "/entities/:id/comments",
             get(entities::admin_entity_get_comments),
         )
+        .route("/entities/:id/reports", get(reports::admin_entity_reports))
+        .route(
+            "/entities/:id/reports/resolve",
+            post(reports::admin_entity_reports_resolve),
+        )
         .route(
             "/entities/:parent_id/parent/:child_id",
             post(entities::admin_entity_register_parent),
@@ -133,6 +177,46 @@ pub fn routes(state: &AppState) -> Router<AppState> {
         .route("/comments/:id", get(comments::admin_comment_get))
         .route("/comments/:id", put(comments::admin_comment_update))
         .route("/comments/:id", delete(comments::admin_comment_delete))
+        // moderation
+        .route(
+            "/moderation/claim",
+            post(moderation::admin_moderation_claim),
+        )
+        .route(
+            "/moderation/assign",
+            post(moderation::admin_moderation_assign),
+        )
+        .route(
+            "/moderation/decisions",
+            post(moderation::admin_moderation_decide),
+        )
+        .route("/moderation/stats", get(moderation::admin_moderation_stats))
+        // reports
+        .route("/reports", get(reports::admin_reported_entities))
+        // suggestions
+        .route("/suggestions", get(suggestions::admin_suggestions_list))
+        .route(
+            "/suggestions/:id/apply",
+            post(suggestions::admin_suggestion_apply),
+        )
+        .route(
+            "/suggestions/:id/reject",
+            post(suggestions::admin_suggestion_reject),
+        )
+        // webhooks
+        .route("/webhooks", get(webhooks::admin_webhooks_list))
+        .route("/webhooks", post(webhooks::admin_webhook_new))
+        .route("/webhooks/:id", get(webhooks::admin_webhook_get))
+        .route("/webhooks/:id", put(webhooks::admin_webhook_update))
+        .route("/webhooks/:id", delete(webhooks::admin_webhook_delete))
+        .route(
+            "/webhooks/:id/deliveries",
+            get(webhooks::admin_webhook_deliveries),
+        )
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/retry",
+            post(webhooks::admin_webhook_delivery_retry),
+        )
         // stats
         .route(
             "/stats/count-comments-entities",
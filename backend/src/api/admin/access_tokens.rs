@@ -1,6 +1,9 @@
 use crate::{
     api::{AppError, AppJson, DbConn},
-    models::access_token::{AccessToken, AccessTokenStats, NewOrUpdateAccessToken},
+    models::{
+        access_token::{AccessToken, AccessTokenStats, NewOrUpdateAccessToken},
+        view_preset::{NewOrUpdateViewPreset, ViewPreset},
+    },
 };
 use axum::{extract::Path, Json};
 use uuid::Uuid;
@@ -117,3 +120,85 @@ pub async fn admin_access_token_delete(
     AccessToken::delete(id, &mut conn).await?;
     Ok(AppJson(()))
 }
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/access_tokens/{id}/presets",
+    params(
+        ("id" = Uuid, Path, description = "Access token identifier")
+    ),
+    responses(
+        (status = 200, description = "View presets of the access token", body = Vec<ViewPreset>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_access_token_presets_list(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+) -> Result<AppJson<Vec<ViewPreset>>, AppError> {
+    Ok(AppJson(ViewPreset::list_named(id, &mut conn).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/access_tokens/{id}/presets",
+    request_body = NewOrUpdateViewPreset,
+    params(
+        ("id" = Uuid, Path, description = "Access token identifier")
+    ),
+    responses(
+        (status = 200, description = "View preset created", body = ViewPreset),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_access_token_preset_new(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+    Json(new_preset): Json<NewOrUpdateViewPreset>,
+) -> Result<AppJson<ViewPreset>, AppError> {
+    Ok(AppJson(ViewPreset::new(id, new_preset, &mut conn).await?))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/access_tokens/{id}/presets/{preset_id}",
+    request_body = NewOrUpdateViewPreset,
+    params(
+        ("id" = Uuid, Path, description = "Access token identifier"),
+        ("preset_id" = Uuid, Path, description = "View preset identifier")
+    ),
+    responses(
+        (status = 200, description = "View preset updated", body = ViewPreset),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+        (status = 404, description = "Not found", body = ErrorResponse),
+    )
+)]
+pub async fn admin_access_token_preset_update(
+    DbConn(mut conn): DbConn,
+    Path((id, preset_id)): Path<(Uuid, Uuid)>,
+    Json(update): Json<NewOrUpdateViewPreset>,
+) -> Result<AppJson<ViewPreset>, AppError> {
+    Ok(AppJson(
+        ViewPreset::update(id, preset_id, update, &mut conn).await?,
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/access_tokens/{id}/presets/{preset_id}",
+    params(
+        ("id" = Uuid, Path, description = "Access token identifier"),
+        ("preset_id" = Uuid, Path, description = "View preset identifier")
+    ),
+    responses(
+        (status = 200, description = "Deletion successful"),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_access_token_preset_delete(
+    DbConn(mut conn): DbConn,
+    Path((id, preset_id)): Path<(Uuid, Uuid)>,
+) -> Result<AppJson<()>, AppError> {
+    ViewPreset::delete(id, preset_id, &mut conn).await?;
+    Ok(AppJson(()))
+}
@@ -1,14 +1,25 @@
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
 use uuid::Uuid;
 
 use crate::{
     api::{AppError, AppJson, DbConn},
-    models::comment::{AdminComment, AdminListedComment, AdminNewOrUpdateComment},
+    models::{
+        comment::{AdminComment, AdminListedComment, AdminNewOrUpdateComment},
+        moderation::ModerationStatus,
+    },
 };
 
+use super::moderation::ModerationQueueQuery;
+
 #[utoipa::path(
     get,
     path = "/api/admin/comments/pending",
+    params(
+        ("status" = Option<ModerationStatus>, Query, description = "Moderation status of the listed comments (default: pending)")
+    ),
     responses(
         (status = 200, description = "List of pending comments", body = Vec<AdminListedComment>),
         (status = 401, description = "Invalid permissions", body = ErrorResponse),
@@ -16,8 +27,11 @@ use crate::{
 )]
 pub async fn admin_comments_pending(
     DbConn(mut conn): DbConn,
+    Query(query): Query<ModerationQueueQuery>,
 ) -> Result<AppJson<Vec<AdminListedComment>>, AppError> {
-    Ok(AppJson(AdminComment::pending(&mut conn).await?))
+    Ok(AppJson(
+        AdminComment::pending(query.status.unwrap_or(ModerationStatus::Pending), &mut conn).await?,
+    ))
 }
 
 #[utoipa::path(
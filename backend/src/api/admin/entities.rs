@@ -14,14 +14,17 @@ use crate::{
         comment::AdminComment,
         entity::{AdminEntity, AdminListedEntity, AdminNewOrUpdateEntity, UnprocessedLocation},
         entity_cache::{
-            AdminCachedEntitiesWithPagination, AdminCachedEntity, AdminSearchEntitiesRequest,
+            AdminCachedEntitiesPage, AdminCachedEntity, AdminSearchCursor,
+            AdminSearchEntitiesRequest, EntitiesSort,
         },
+        moderation::ModerationStatus,
     },
 };
 
+use super::moderation::ModerationQueueQuery;
+
 #[derive(Deserialize, Debug)]
 pub struct SearchQuery {
-    pub page: Option<i64>,
     pub page_size: Option<i64>,
 }
 
@@ -33,6 +36,11 @@ pub struct AdminSearchRequest {
     pub required_tags_ids: Vec<Uuid>,
     pub excluded_tags_ids: Vec<Uuid>,
     pub enums_constraints: Value,
+    #[serde(default)]
+    pub sort: EntitiesSort,
+    /// Cursor of the page to fetch, as returned along the previous page
+    #[serde(default)]
+    pub cursor: Option<AdminSearchCursor>,
 }
 
 #[derive(FromRow, Deserialize, Serialize, ToSchema, Debug)]
@@ -62,11 +70,11 @@ pub struct AdminEntityWithRelations {
     path = "/api/admin/entities/search",
     request_body = AdminSearchRequest,
     params(
-        ("page" = i64, Query, description = "Current page (default: 1)"),
-        ("page_size" = i64, Query, description = "Number of items per page (default: 20)")
+        ("page_size" = i64, Query, description = "Number of items per page, from 1 to 100 (default: 20)")
     ),
     responses(
-        (status = 200, description = "Search results for entities", body = AdminCachedEntitiesWithPagination),
+        (status = 200, description = "Search results for entities", body = AdminCachedEntitiesPage),
+        (status = 400, description = "Invalid pagination", body = ErrorResponse),
         (status = 401, description = "Invalid permissions", body = ErrorResponse),
     )
 )]
@@ -74,8 +82,7 @@ pub async fn admin_entities_search(
     DbConn(mut conn): DbConn,
     Query(search_query): Query<SearchQuery>,
     Json(search_req): Json<AdminSearchRequest>,
-) -> Result<AppJson<AdminCachedEntitiesWithPagination>, AppError> {
-    let page = search_query.page.unwrap_or(1);
+) -> Result<AppJson<AdminCachedEntitiesPage>, AppError> {
     let page_size = search_query.page_size.unwrap_or(20);
 
     Ok(AppJson(
@@ -83,12 +90,13 @@ pub async fn admin_entities_search(
             AdminSearchEntitiesRequest {
                 search_query: search_req.search,
                 family_id: search_req.family,
-                page,
+                after: search_req.cursor,
                 page_size,
                 active_categories_ids: search_req.active_categories_ids,
                 required_tags_ids: search_req.required_tags_ids,
                 excluded_tags_ids: search_req.excluded_tags_ids,
                 enums_constraints: search_req.enums_constraints,
+                sort: search_req.sort,
             },
             &mut conn,
         )
@@ -99,6 +107,9 @@ pub async fn admin_entities_search(
 #[utoipa::path(
     get,
     path = "/api/admin/entities/pending",
+    params(
+        ("status" = Option<ModerationStatus>, Query, description = "Moderation status of the listed entities (default: pending)")
+    ),
     responses(
         (status = 200, description = "List of pending entities", body = Vec<AdminListedEntity>),
         (status = 401, description = "Invalid permissions", body = ErrorResponse),
@@ -106,8 +117,11 @@ pub async fn admin_entities_search(
 )]
 pub async fn admin_entities_pending(
     DbConn(mut conn): DbConn,
+    Query(query): Query<ModerationQueueQuery>,
 ) -> Result<AppJson<Vec<AdminListedEntity>>, AppError> {
-    Ok(AppJson(AdminEntity::pending(&mut conn).await?))
+    Ok(AppJson(
+        AdminEntity::pending(query.status.unwrap_or(ModerationStatus::Pending), &mut conn).await?,
+    ))
 }
 
 #[utoipa::path(
@@ -49,6 +49,22 @@ pub async fn admin_family_new(
     Ok(AppJson(Family::new(new_family, &mut conn).await?))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/admin/families/text_search_languages",
+    responses(
+        (status = 200, description = "Languages available for the full text search", body = Vec<String>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_families_text_search_languages(
+    DbConn(mut conn): DbConn,
+) -> Result<AppJson<Vec<String>>, AppError> {
+    Ok(AppJson(
+        Family::list_text_search_languages(&mut conn).await?,
+    ))
+}
+
 #[utoipa::path(
     get,
     path = "/api/admin/families/{id}",
@@ -0,0 +1,115 @@
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    api::{AppError, AppJson, DbConn},
+    models::moderation::{
+        Moderation, ModerationDecision, ModerationItem, ModerationStatus, ModeratorStats,
+    },
+};
+
+use super::auth::AdminUserIdentity;
+
+#[derive(Deserialize)]
+pub struct ModerationQueueQuery {
+    pub status: Option<ModerationStatus>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct ClaimRequest {
+    pub items: Vec<ModerationItem>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct AssignRequest {
+    pub items: Vec<ModerationItem>,
+    /// Moderator to assign the items to, none to unassign them
+    pub user_id: Option<Uuid>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/moderation/claim",
+    request_body = ClaimRequest,
+    responses(
+        (status = 200, description = "Items claimed, the ones assigned to other moderators are left out", body = Vec<ModerationItem>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_moderation_claim(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Json(request): Json<ClaimRequest>,
+) -> Result<AppJson<Vec<ModerationItem>>, AppError> {
+    Ok(AppJson(
+        Moderation::assign(
+            &request.items,
+            Some(user.admin_id),
+            user.admin_id,
+            false,
+            &mut conn,
+        )
+        .await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/moderation/assign",
+    request_body = AssignRequest,
+    responses(
+        (status = 200, description = "Items assigned, only administrators can reassign the items of other moderators", body = Vec<ModerationItem>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_moderation_assign(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Json(request): Json<AssignRequest>,
+) -> Result<AppJson<Vec<ModerationItem>>, AppError> {
+    Ok(AppJson(
+        Moderation::assign(
+            &request.items,
+            request.user_id,
+            user.admin_id,
+            user.is_admin,
+            &mut conn,
+        )
+        .await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/moderation/decisions",
+    request_body = ModerationDecision,
+    responses(
+        (status = 200, description = "Decision applied"),
+        (status = 400, description = "Missing reason", body = ErrorResponse),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_moderation_decide(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Json(decision): Json<ModerationDecision>,
+) -> Result<AppJson<()>, AppError> {
+    Moderation::decide(decision, user.admin_id, &mut conn).await?;
+    Ok(AppJson(()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/moderation/stats",
+    responses(
+        (status = 200, description = "Decisions and assigned items of each moderator", body = Vec<ModeratorStats>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_moderation_stats(
+    DbConn(mut conn): DbConn,
+) -> Result<AppJson<Vec<ModeratorStats>>, AppError> {
+    Ok(AppJson(Moderation::stats(&mut conn).await?))
+}
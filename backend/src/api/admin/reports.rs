@@ -0,0 +1,65 @@
+use axum::{extract::Path, Json};
+use uuid::Uuid;
+
+use crate::{
+    api::{AppError, AppJson, DbConn},
+    models::report::{AdminReport, AdminReportedEntity, AdminResolveReports, EntityReport},
+};
+
+use super::auth::AdminUserIdentity;
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/reports",
+    responses(
+        (status = 200, description = "Entities with unresolved reports, the most reported first", body = Vec<AdminReportedEntity>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_reported_entities(
+    DbConn(mut conn): DbConn,
+) -> Result<AppJson<Vec<AdminReportedEntity>>, AppError> {
+    Ok(AppJson(
+        EntityReport::list_reported_entities(&mut conn).await?,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/entities/{id}/reports",
+    params(
+        ("id" = Uuid, Path, description = "Entity identifier")
+    ),
+    responses(
+        (status = 200, description = "Reports of the entity", body = Vec<AdminReport>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_entity_reports(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+) -> Result<AppJson<Vec<AdminReport>>, AppError> {
+    Ok(AppJson(EntityReport::list_for_entity(id, &mut conn).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/entities/{id}/reports/resolve",
+    params(
+        ("id" = Uuid, Path, description = "Entity identifier")
+    ),
+    request_body = AdminResolveReports,
+    responses(
+        (status = 200, description = "Reports of the entity resolved"),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_entity_reports_resolve(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+    Json(resolution): Json<AdminResolveReports>,
+) -> Result<AppJson<()>, AppError> {
+    EntityReport::resolve_for_entity(id, resolution, user.admin_id, &mut conn).await?;
+    Ok(AppJson(()))
+}
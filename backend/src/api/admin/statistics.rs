@@ -1,6 +1,12 @@
+use axum::extract::Query;
+use serde::Deserialize;
+use uuid::Uuid;
+
 use crate::{
     api::{AppError, AppJson, DbConn},
-    models::statistics::{self, CountResult, HomePageStats},
+    models::statistics::{
+        self, CountResult, HomePageStats, SearchesStats, ViewsStats, VisitorsStats,
+    },
 };
 
 #[utoipa::path(
@@ -17,6 +23,87 @@ pub async fn admin_home_stats(
     Ok(AppJson(statistics::home_page_stats(&mut conn).await?))
 }
 
+#[derive(Deserialize, Debug)]
+pub struct VisitorsStatsQuery {
+    token_id: Option<Uuid>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/stats/visitors",
+    params(
+        ("token_id" = Option<Uuid>, Query, description = "Access token to restrict the statistics to")
+    ),
+    responses(
+        (status = 200, description = "Unique visitors, devices and languages of the visits", body = VisitorsStats),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_visitors_stats(
+    DbConn(mut conn): DbConn,
+    Query(query): Query<VisitorsStatsQuery>,
+) -> Result<AppJson<VisitorsStats>, AppError> {
+    Ok(AppJson(
+        statistics::visitors_stats(query.token_id, &mut conn).await?,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/stats/views",
+    responses(
+        (status = 200, description = "Map and entity views by family and category id", body = ViewsStats),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_views_stats(DbConn(mut conn): DbConn) -> Result<AppJson<ViewsStats>, AppError> {
+    Ok(AppJson(statistics::views_stats(&mut conn).await?))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchesStatsQuery {
+    family_id: Option<Uuid>,
+    days: Option<i32>,
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/stats/searches",
+    params(
+        ("family_id" = Option<Uuid>, Query, description = "Family to restrict the statistics to"),
+        ("days" = Option<i32>, Query, description = "Length of the period in days, from 1 to 365 (default: 30)"),
+        ("limit" = Option<i64>, Query, description = "Number of queries of each list, from 1 to 100 (default: 20)")
+    ),
+    responses(
+        (status = 200, description = "Top, zero-result and trending search queries", body = SearchesStats),
+        (status = 400, description = "Invalid period or limit", body = ErrorResponse),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_searches_stats(
+    DbConn(mut conn): DbConn,
+    Query(query): Query<SearchesStatsQuery>,
+) -> Result<AppJson<SearchesStats>, AppError> {
+    let days = query.days.unwrap_or(30);
+    let limit = query.limit.unwrap_or(20);
+
+    if !(1..=365).contains(&days) {
+        return Err(AppError::Validation(
+            "The period must be from 1 to 365 days".to_string(),
+        ));
+    }
+    if !(1..=100).contains(&limit) {
+        return Err(AppError::Validation(
+            "The limit must be from 1 to 100".to_string(),
+        ));
+    }
+
+    Ok(AppJson(
+        statistics::searches_stats(query.family_id, days, limit, &mut conn).await?,
+    ))
+}
+
 #[utoipa::path(
     get,
     path = "/api/admin/stats/count-comments-entities",
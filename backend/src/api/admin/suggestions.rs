@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    api::{AppError, AppJson, DbConn},
+    models::{
+        moderation::ModerationStatus,
+        suggestion::{AdminEntitySuggestion, EntitySuggestion},
+    },
+};
+
+use super::{auth::AdminUserIdentity, moderation::ModerationQueueQuery};
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct RejectSuggestionRequest {
+    pub reason: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/suggestions",
+    params(
+        ("status" = Option<ModerationStatus>, Query, description = "Moderation status of the listed suggestions (default: pending)")
+    ),
+    responses(
+        (status = 200, description = "Suggested edits, compared to the live entities", body = Vec<AdminEntitySuggestion>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_suggestions_list(
+    DbConn(mut conn): DbConn,
+    Query(query): Query<ModerationQueueQuery>,
+) -> Result<AppJson<Vec<AdminEntitySuggestion>>, AppError> {
+    Ok(AppJson(
+        EntitySuggestion::list(query.status.unwrap_or(ModerationStatus::Pending), &mut conn)
+            .await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/suggestions/{id}/apply",
+    params(
+        ("id" = Uuid, Path, description = "Suggestion identifier")
+    ),
+    responses(
+        (status = 200, description = "Changes applied to the entity"),
+        (status = 400, description = "The entity would be invalid", body = ErrorResponse),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+        (status = 404, description = "Suggestion not found or already moderated", body = ErrorResponse),
+    )
+)]
+pub async fn admin_suggestion_apply(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+) -> Result<AppJson<()>, AppError> {
+    EntitySuggestion::apply(id, user.admin_id, &mut conn).await?;
+    Ok(AppJson(()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/suggestions/{id}/reject",
+    params(
+        ("id" = Uuid, Path, description = "Suggestion identifier")
+    ),
+    request_body = RejectSuggestionRequest,
+    responses(
+        (status = 200, description = "Suggestion rejected"),
+        (status = 400, description = "Missing reason", body = ErrorResponse),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+        (status = 404, description = "Suggestion not found or already moderated", body = ErrorResponse),
+    )
+)]
+pub async fn admin_suggestion_reject(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RejectSuggestionRequest>,
+) -> Result<AppJson<()>, AppError> {
+    EntitySuggestion::reject(id, request.reason, user.admin_id, &mut conn).await?;
+    Ok(AppJson(()))
+}
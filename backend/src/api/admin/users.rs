@@ -1,9 +1,16 @@
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
 use uuid::Uuid;
 
 use crate::{
-    api::{AppError, AppJson, DbConn},
-    models::user::{NewOrUpdatedUser, User},
+    api::{AppError, AppJson, AppState, DbConn},
+    helpers::notifications::Template,
+    models::{
+        notification::NotificationPreferences,
+        user::{NewOrUpdatedUser, User},
+    },
 };
 
 use super::auth::AdminUserIdentity;
@@ -97,6 +104,81 @@ pub async fn admin_user_change_self_password(
     ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/admin/users/self/notifications",
+    responses(
+        (status = 200, description = "Notification preferences of the user", body = NotificationPreferences),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_user_get_self_notifications(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+) -> Result<AppJson<NotificationPreferences>, AppError> {
+    Ok(AppJson(
+        NotificationPreferences::get(user.admin_id, &mut conn).await?,
+    ))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/users/self/notifications",
+    request_body = NotificationPreferences,
+    responses(
+        (status = 200, description = "Notification preferences of the user", body = NotificationPreferences),
+        (status = 400, description = "Invalid email address or locale", body = ErrorResponse),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_user_update_self_notifications(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Json(preferences): Json<NotificationPreferences>,
+) -> Result<AppJson<NotificationPreferences>, AppError> {
+    Ok(AppJson(
+        NotificationPreferences::update(user.admin_id, preferences, &mut conn).await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/self/notifications/test",
+    responses(
+        (status = 200, description = "Test email sent"),
+        (status = 400, description = "Notifications not configured or no email address", body = ErrorResponse),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_user_test_self_notifications(
+    user: AdminUserIdentity,
+    State(app_state): State<AppState>,
+    DbConn(mut conn): DbConn,
+) -> Result<AppJson<()>, AppError> {
+    let mailer = app_state.mailer.as_ref().ok_or(AppError::Validation(
+        "Email notifications are not configured".to_string(),
+    ))?;
+    let preferences = NotificationPreferences::get(user.admin_id, &mut conn).await?;
+    let email = preferences.email.ok_or(AppError::Validation(
+        "No email address to send the notification to".to_string(),
+    ))?;
+
+    mailer
+        .send(
+            &user.username,
+            &email,
+            &preferences.locale,
+            Template::Test,
+            &[
+                ("name", user.username.clone()),
+                ("admin_url", mailer.admin_url()),
+            ],
+        )
+        .await?;
+
+    Ok(AppJson(()))
+}
+
 #[utoipa::path(
     put,
     path = "/api/admin/users/{id}",
@@ -0,0 +1,177 @@
+use axum::{extract::Path, Json};
+use uuid::Uuid;
+
+use crate::{
+    api::{AppError, AppJson, DbConn},
+    models::webhook::{NewOrUpdateWebhook, Webhook, WebhookDelivery},
+};
+
+use super::auth::AdminUserIdentity;
+
+/// Deliveries listed in the log of a webhook
+const DELIVERIES_LOG_SIZE: i64 = 100;
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhooks",
+    responses(
+        (status = 200, description = "List of webhooks", body = Vec<Webhook>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhooks_list(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+) -> Result<AppJson<Vec<Webhook>>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(AppJson(Webhook::list(&mut conn).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/webhooks",
+    request_body = NewOrUpdateWebhook,
+    responses(
+        (status = 200, description = "Webhook created", body = Webhook),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhook_new(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Json(new_webhook): Json<NewOrUpdateWebhook>,
+) -> Result<AppJson<Webhook>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(AppJson(Webhook::new(new_webhook, &mut conn).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhooks/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Webhook identifier")
+    ),
+    responses(
+        (status = 200, description = "Webhook details", body = Webhook),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhook_get(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+) -> Result<AppJson<Webhook>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(AppJson(Webhook::get(id, &mut conn).await?))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/webhooks/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Webhook identifier")
+    ),
+    request_body = NewOrUpdateWebhook,
+    responses(
+        (status = 200, description = "Webhook updated", body = Webhook),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhook_update(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+    Json(update): Json<NewOrUpdateWebhook>,
+) -> Result<AppJson<Webhook>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(AppJson(Webhook::update(id, update, &mut conn).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/webhooks/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Webhook identifier")
+    ),
+    responses(
+        (status = 200, description = "Webhook deleted"),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhook_delete(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+) -> Result<AppJson<()>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Webhook::delete(id, &mut conn).await?;
+    Ok(AppJson(()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhooks/{id}/deliveries",
+    params(
+        ("id" = Uuid, Path, description = "Webhook identifier")
+    ),
+    responses(
+        (status = 200, description = "Latest deliveries of the webhook", body = Vec<WebhookDelivery>),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhook_deliveries(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path(id): Path<Uuid>,
+) -> Result<AppJson<Vec<WebhookDelivery>>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(AppJson(
+        WebhookDelivery::list_for_webhook(id, DELIVERIES_LOG_SIZE, &mut conn).await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/webhooks/{id}/deliveries/{delivery_id}/retry",
+    params(
+        ("id" = Uuid, Path, description = "Webhook identifier"),
+        ("delivery_id" = Uuid, Path, description = "Delivery identifier")
+    ),
+    responses(
+        (status = 200, description = "Delivery scheduled again"),
+        (status = 401, description = "Invalid permissions", body = ErrorResponse),
+        (status = 404, description = "Delivery not found or already delivered", body = ErrorResponse),
+    )
+)]
+pub async fn admin_webhook_delivery_retry(
+    user: AdminUserIdentity,
+    DbConn(mut conn): DbConn,
+    Path((id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> Result<AppJson<()>, AppError> {
+    if !user.is_admin {
+        return Err(AppError::Unauthorized);
+    }
+
+    WebhookDelivery::retry(id, delivery_id, &mut conn).await?;
+    Ok(AppJson(()))
+}
@@ -24,6 +24,10 @@ use crate::{
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MapUserTokenClaims {
+    /// Identifier of the access token the claims were issued for, nil in the tokens issued before
+    /// it was added, which are renewed
+    #[serde(default)]
+    pub token_id: Uuid,
     pub perms: Permissions,
     pub fam_priv_idx: HashMap<Uuid, Vec<String>>,
     pub exp: usize,
@@ -76,10 +80,10 @@ pub async fn viewer_authentication_middleware(
 
     let (token_data, new_jwt) = match token_data {
         // If the token is valid, we return the claims directly
-        Ok(token_data) => (token_data.claims, None),
+        Ok(token_data) if !token_data.claims.token_id.is_nil() => (token_data.claims, None),
 
         // If the token is invalid, we try to get the plain access token from the X-SH-Plain-AccessToken header
-        Err(_) => {
+        _ => {
             // Get string from custom header X-SH-Plain-AccessToken
             let plain_access_token_header = request
                 .headers()
@@ -113,6 +117,7 @@ pub async fn viewer_authentication_middleware(
 
             // Create a new token
             let new_claims = MapUserTokenClaims {
+                token_id: access_token.id,
                 iat: Utc::now().timestamp() as usize,
                 exp: (Utc::now() + TimeDelta::try_hours(1).expect("valid duration")).timestamp()
                     as usize,
@@ -0,0 +1,157 @@
+use std::{collections::HashSet, sync::atomic::Ordering};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, Router},
+};
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{migrate::Migrate, PgConnection};
+use utoipa::ToSchema;
+
+use crate::models::entity_cache::CacheRefreshStats;
+
+use super::{AppError, AppJson, AppState, MIGRATOR};
+
+/// Extensions the migrations and the search functions rely on
+const REQUIRED_EXTENSIONS: [&str; 4] = ["uuid-ossp", "postgis", "pg_trgm", "unaccent"];
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/live", get(live))
+        .route("/ready", get(ready))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LivenessResponse {
+    status: &'static str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses(
+        (status = 200, description = "The server is running", body = LivenessResponse)
+    )
+)]
+pub async fn live() -> AppJson<LivenessResponse> {
+    AppJson(LivenessResponse { status: "ok" })
+}
+
+#[derive(Serialize, ToSchema, Default)]
+pub struct ReadinessChecks {
+    /// A connection to the database could be acquired
+    database: bool,
+    /// All the migrations of this version are applied
+    migrations: bool,
+    /// The required PostgreSQL extensions are installed
+    extensions: bool,
+    /// The connection listening for the PostgreSQL notifications is up
+    events_listener: bool,
+    /// The entities cache is populated and up to date
+    entities_cache: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    /// 'ok' when all the checks pass, 'unavailable' otherwise
+    status: &'static str,
+    checks: ReadinessChecks,
+    /// Seconds since the last refresh of the entities cache, if it was refreshed since the last
+    /// upgrade
+    entities_cache_age: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "The server is ready to handle requests", body = ReadinessResponse),
+        (status = 503, description = "A dependency of the server is unavailable", body = ReadinessResponse),
+    )
+)]
+pub async fn ready(State(app_state): State<AppState>) -> Response {
+    let mut checks = ReadinessChecks {
+        events_listener: app_state.events_listener_connected.load(Ordering::Relaxed),
+        ..Default::default()
+    };
+    let mut entities_cache_age = None;
+
+    if let Ok(mut conn) = app_state.pool.acquire().await {
+        checks.database = true;
+        checks.migrations = check_migrations(&mut conn).await.unwrap_or_else(|e| {
+            tracing::warn!("Cannot check the migrations: {:?}", e);
+            false
+        });
+        checks.extensions = check_extensions(&mut conn).await.unwrap_or_else(|e| {
+            tracing::warn!("Cannot check the extensions: {:?}", e);
+            false
+        });
+
+        match CacheRefreshStats::get(&mut conn).await {
+            Ok(stats) => {
+                checks.entities_cache = stats.populated && !stats.stale;
+                entities_cache_age = stats.last_refreshed_at.map(|last_refreshed_at| {
+                    (Utc::now() - last_refreshed_at).num_milliseconds() as f64 / 1000.0
+                });
+            }
+            Err(e) => tracing::warn!("Cannot check the entities cache: {:?}", e),
+        }
+    }
+
+    let is_ready = checks.database
+        && checks.migrations
+        && checks.extensions
+        && checks.events_listener
+        && checks.entities_cache;
+
+    (
+        if is_ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        AppJson(ReadinessResponse {
+            status: if is_ready { "ok" } else { "unavailable" },
+            checks,
+            entities_cache_age,
+        }),
+    )
+        .into_response()
+}
+
+/// Checks that the migrations embedded in this version are applied, unchanged and not dirty
+async fn check_migrations(conn: &mut PgConnection) -> Result<bool, AppError> {
+    let migrate_error = |e| AppError::Internal(Some(format!("{:?}", e)));
+
+    if conn.dirty_version().await.map_err(migrate_error)?.is_some() {
+        return Ok(false);
+    }
+
+    let applied = conn
+        .list_applied_migrations()
+        .await
+        .map_err(migrate_error)?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect::<HashSet<_>>();
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .all(|migration| applied.contains(&(migration.version, migration.checksum.clone()))))
+}
+
+async fn check_extensions(conn: &mut PgConnection) -> Result<bool, AppError> {
+    let installed = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM pg_extension WHERE extname = ANY($1)"#,
+        &REQUIRED_EXTENSIONS.map(String::from)[..]
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(installed == REQUIRED_EXTENSIONS.len() as i64)
+}
@@ -30,8 +30,18 @@ async fn get_icon_internal(
 ) -> Result<(Vec<u8>, String), AppError> {
     // Check cache firsts
     if let Some(icon) = state.icon_cache.read().await.get(&hash) {
+        state
+            .metrics
+            .icon_cache_requests
+            .with_label_values(&["hit"])
+            .inc();
         return Ok(icon.clone());
     }
+    state
+        .metrics
+        .icon_cache_requests
+        .with_label_values(&["miss"])
+        .inc();
 
     // If not found in cache, query the database
     let icon = Icon::get(hash.clone(), conn).await?;
@@ -1,19 +1,31 @@
-use crate::api::{AppError, AppJson, AppState, DbConn};
-use crate::helpers::hcaptcha::{self, HCaptchaValidationError};
-use crate::models::comment::{PublicComment, PublicNewComment};
+use crate::api::{AppError, AppJson, AppState, ClientIp, DbConn};
+use crate::helpers::captcha::{self, CaptchaValidationError};
+use crate::helpers::visitors;
+use crate::models::analytics::{LoggedSearch, ViewKind};
+use crate::models::comment::{
+    CommentsRequest, PublicComment, PublicCommentsWithPagination, PublicNewComment, ScoreAggregate,
+};
 use crate::models::entity::{PublicEntity, PublicListedEntity, PublicNewEntity};
 use crate::models::entity_cache::{
-    EntitiesAndClusters, FindEntitiesRequest, SearchEntitiesRequest,
-    ViewerCachedEntitiesWithPagination, ViewerCachedEntity,
+    EntitiesAndClusters, EntitiesSort, ExpandedCluster, Facets, FetchClusterMembersRequest,
+    FetchFacetsRequest, FindEntitiesRequest, SearchEntitiesRequest, ValueConstraint,
+    ViewerCachedEntity, ViewerSearchedCachedEntity,
+};
+use crate::models::report::{EntityReport, PublicNewReport};
+use crate::models::spam::{SpamAssessment, SubmissionOrigin};
+use crate::models::suggestion::{
+    EntitySuggestion, PublicModerationState, PublicSuggestionStatus, TrackingCode,
 };
+use crate::models::view_preset::{ViewPreset, ViewPresetState};
 use axum::extract::{Path, State};
 use axum::middleware;
 use axum::{
+    http::{header, HeaderMap},
     routing::{post, Router},
     Json,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::fmt::Display;
 use tracing::debug;
@@ -21,14 +33,40 @@ use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::auth::{viewer_authentication_middleware, MapUserTokenClaims};
+use super::rate_limit::{rate_limit_middleware, Access, RateLimitedRoutes, RouteGroup};
 
 pub fn routes(state: &AppState) -> Router<AppState> {
-    Router::new()
+    // Most of the reading routes use POST for their filters, so they are rate limited separately
+    let read_router = Router::new()
         .route("/view", post(viewer_view_request))
+        .route("/clusters/:id", post(viewer_expand_cluster))
         .route("/search", post(viewer_search_request))
         .route("/entities/:id", post(viewer_fetch_entity))
+        .route("/entities/:id/comments", post(viewer_fetch_entity_comments))
+        .route("/tracking/:code", post(viewer_fetch_tracked_entity))
+        .route_layer(middleware::from_fn_with_state(
+            RateLimitedRoutes::new(state, RouteGroup::Public, Access::Read),
+            rate_limit_middleware,
+        ));
+
+    let write_router = Router::new()
         .route("/entities", post(viewer_new_entity))
         .route("/comments", post(viewer_new_comment))
+        .route("/presets", post(viewer_share_preset))
+        .route("/entities/:id/suggestions", post(viewer_suggest_correction))
+        .route("/entities/:id/reports", post(viewer_report_entity))
+        .route(
+            "/tracking/:code/suggestions",
+            post(viewer_suggest_tracked_entity_edit),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            RateLimitedRoutes::new(state, RouteGroup::Public, Access::Write),
+            rate_limit_middleware,
+        ));
+
+    Router::new()
+        .merge(read_router)
+        .merge(write_router)
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             viewer_authentication_middleware,
@@ -42,6 +80,22 @@ fn require_permission(condition: bool) -> Result<(), AppError> {
     Ok(())
 }
 
+/// The family and category of the entity must be allowed, and none of its tags excluded
+fn require_entity_visible(
+    token: &MapUserTokenClaims,
+    entity: &PublicEntity,
+) -> Result<(), AppError> {
+    require_permission(is_family_allowed_by_token(token, &entity.family_id))?;
+    require_permission(is_category_allowed_by_token(token, &entity.category_id))?;
+    entity
+        .tags
+        .iter()
+        .find_map(|tag_id| {
+            require_permission(!is_tag_explicitly_excluded_by_token(token, tag_id)).err()
+        })
+        .map_or(Ok(()), Err)
+}
+
 #[derive(Serialize, Deserialize, ToSchema, Debug)]
 pub struct ViewRequest {
     family_id: Uuid,
@@ -54,6 +108,12 @@ pub struct ViewRequest {
     active_required_tags: Vec<Uuid>,
     active_hidden_tags: Vec<Uuid>,
     enums_constraints: HashMap<String, Vec<Value>>,
+    /// Constraints on the indexed Number, DiscreteScore, Boolean and Date fields
+    #[serde(default)]
+    values_constraints: HashMap<String, ValueConstraint>,
+    /// Also count the entities in the view per category, tag and enum value
+    #[serde(default)]
+    with_facets: bool,
 }
 
 impl Display for ViewRequest {
@@ -66,19 +126,15 @@ impl Display for ViewRequest {
     }
 }
 
-fn clusterize(
-    characteristic_distance: f64,
-    declustering_speed: f64,
-    minimal_cluster_size: i32,
-    zoom: f64,
-) -> Option<(f64, i32)> {
-    if zoom > 18.9 {
+/// Clusters are computed on a precomputed grid (see `entities_caches_clusters`), which is only
+/// available up to this zoom level
+const MAX_CLUSTERED_ZOOM_LEVEL: u8 = 18;
+
+fn clusterize(minimal_cluster_size: i32, zoom: u8) -> Option<(i32, i32)> {
+    if zoom > MAX_CLUSTERED_ZOOM_LEVEL {
         return None;
     }
-    Some((
-        characteristic_distance * 100000. * declustering_speed.powf(-zoom),
-        minimal_cluster_size,
-    ))
+    Some((zoom as i32, minimal_cluster_size))
 }
 
 fn is_family_allowed_by_token(token: &MapUserTokenClaims, family_id: &Uuid) -> bool {
@@ -134,20 +190,52 @@ fn is_tag_explicitly_excluded_by_token(token: &MapUserTokenClaims, tag_id: &Uuid
 fn are_constraints_allowed(
     family_id: &Uuid,
     forbidden_indexed: &HashMap<Uuid, Vec<String>>,
-    constraints: &HashMap<String, Vec<Value>>,
+    enums_constraints: &HashMap<String, Vec<Value>>,
+    values_constraints: &HashMap<String, ValueConstraint>,
 ) -> Result<(), AppError> {
     let default_array = vec![];
     let forbidden_fields = forbidden_indexed.get(family_id).unwrap_or(&default_array);
 
-    match constraints
+    let enums_allowed = enums_constraints
         .iter()
-        .all(|(k, v)| !forbidden_fields.contains(&k.to_string()) || v.is_empty())
-    {
+        .all(|(k, v)| !forbidden_fields.contains(&k.to_string()) || v.is_empty());
+    let values_allowed = values_constraints
+        .keys()
+        .all(|k| !forbidden_fields.contains(k));
+
+    match enums_allowed && values_allowed {
         true => Ok(()),
         false => Err(AppError::Forbidden),
     }
 }
 
+/// Facets request restricted by the token permissions, without any user filter
+fn token_facets_request(token: &MapUserTokenClaims, family_id: Uuid) -> FetchFacetsRequest {
+    FetchFacetsRequest {
+        search_query: None,
+        view: None,
+        geographic_restriction: token.perms.geographic_restrictions.clone(),
+        family_id,
+        allow_all_categories: token.perms.categories_policy.allow_all,
+        allow_all_tags: token.perms.tags_policy.allow_all,
+        categories_list: token.perms.categories_policy.allow_list.clone(),
+        tags_list: token.perms.tags_policy.allow_list.clone(),
+        exclude_categories_list: token.perms.categories_policy.force_exclude.clone(),
+        exclude_tags_list: token.perms.tags_policy.force_exclude.clone(),
+        active_categories: vec![],
+        active_required_tags: vec![],
+        active_hidden_tags: vec![],
+        require_locations: false,
+        enums_constraints: Value::Null,
+        values_constraints: Value::Null,
+        private_enum_keys: token
+            .fam_priv_idx
+            .get(&family_id)
+            .cloned()
+            .unwrap_or_default(),
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/api/map/view",
@@ -160,6 +248,8 @@ fn are_constraints_allowed(
 pub async fn viewer_view_request(
     State(app_state): State<AppState>,
     DbConn(mut conn): DbConn,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
     token: MapUserTokenClaims,
     Json(request): Json<ViewRequest>,
 ) -> Result<AppJson<EntitiesAndClusters>, AppError> {
@@ -174,19 +264,47 @@ pub async fn viewer_view_request(
         &request.family_id,
         &token.fam_priv_idx,
         &request.enums_constraints,
+        &request.values_constraints,
     )?;
 
     tracing::trace!("Received view request {}", request);
 
+    // The map is counted once a day per visitor, for the categories it is allowed to show
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let salt = app_state.visitors_salt.get(&mut conn).await?;
+    app_state.record_view(
+        ViewKind::Map,
+        Some(visitors::visitor_hash(&salt, client_ip, user_agent)),
+        request.family_id,
+        request
+            .active_categories
+            .iter()
+            .filter(|category_id| is_category_allowed_by_token(&token, category_id))
+            .copied()
+            .collect(),
+    );
+
     let dyn_config = app_state.dyn_config.read().await;
 
     let cluster_params = clusterize(
-        dyn_config.cartography_cluster.characteristic_distance,
-        dyn_config.cartography_cluster.declustering_speed,
         dyn_config.cartography_cluster.minimal_cluster_size,
-        request.zoom_level as f64,
+        request.zoom_level,
     );
 
+    let facets_request = request.with_facets.then(|| FetchFacetsRequest {
+        view: Some((request.xmin, request.ymin, request.xmax, request.ymax)),
+        active_categories: request.active_categories.clone(),
+        active_required_tags: request.active_required_tags.clone(),
+        active_hidden_tags: request.active_hidden_tags.clone(),
+        require_locations: true,
+        enums_constraints: serde_json::to_value(&request.enums_constraints)
+            .expect("Enums should be serializable"),
+        values_constraints: ValueConstraint::to_database_constraints(&request.values_constraints),
+        ..token_facets_request(&token, request.family_id)
+    });
+
     // Doing the request
     let request = FindEntitiesRequest {
         xmin: request.xmin,
@@ -207,10 +325,86 @@ pub async fn viewer_view_request(
         active_hidden_tags: request.active_hidden_tags,
         enums_constraints: serde_json::to_value(request.enums_constraints)
             .expect("Enums should be serializable"),
+        values_constraints: ValueConstraint::to_database_constraints(&request.values_constraints),
+    };
+
+    let mut results = ViewerCachedEntity::find_entities_in_rectangle(request, &mut conn).await?;
+
+    if let Some(facets_request) = facets_request {
+        results.facets = Some(ViewerCachedEntity::fetch_facets(facets_request, &mut conn).await?);
+    }
+
+    Ok(AppJson(results))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ExpandClusterRequest {
+    /// The view request that returned the cluster
+    view: ViewRequest,
+    page: i64,
+    /// From 1 to 100
+    page_size: i64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/map/clusters/{id}",
+    params(
+        ("id" = i64, Path, description = "Cluster identifier")
+    ),
+    request_body = ExpandClusterRequest,
+    responses(
+        (status = 200, description = "Cluster members and bounding box", body = ExpandedCluster),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 404, description = "Cluster not found", body = ErrorResponse),
+    )
+)]
+pub async fn viewer_expand_cluster(
+    DbConn(mut conn): DbConn,
+    token: MapUserTokenClaims,
+    Path(id): Path<i64>,
+    Json(request): Json<ExpandClusterRequest>,
+) -> Result<AppJson<ExpandedCluster>, AppError> {
+    let view = request.view;
+
+    // The token must allow to list entities
+    require_permission(token.perms.can_list_entities)?;
+
+    // The family must be allowed
+    require_permission(is_family_allowed_by_token(&token, &view.family_id))?;
+
+    // Check if some of the constraints are forbidden
+    are_constraints_allowed(
+        &view.family_id,
+        &token.fam_priv_idx,
+        &view.enums_constraints,
+        &view.values_constraints,
+    )?;
+
+    tracing::trace!("Received cluster {} expansion request {}", id, view);
+
+    let request = FetchClusterMembersRequest {
+        cluster_id: id,
+        geographic_restriction: token.perms.geographic_restrictions.clone(),
+        family_id: view.family_id,
+        allow_all_categories: token.perms.categories_policy.allow_all,
+        allow_all_tags: token.perms.tags_policy.allow_all,
+        categories_list: token.perms.categories_policy.allow_list.clone(),
+        tags_list: token.perms.tags_policy.allow_list.clone(),
+        exclude_categories_list: token.perms.categories_policy.force_exclude.clone(),
+        exclude_tags_list: token.perms.tags_policy.force_exclude.clone(),
+        active_categories: view.active_categories,
+        active_required_tags: view.active_required_tags,
+        active_hidden_tags: view.active_hidden_tags,
+        enums_constraints: serde_json::to_value(view.enums_constraints)
+            .expect("Enums should be serializable"),
+        values_constraints: ValueConstraint::to_database_constraints(&view.values_constraints),
+        page: request.page,
+        page_size: request.page_size,
     };
 
     Ok(AppJson(
-        ViewerCachedEntity::find_entities_in_rectangle(request, &mut conn).await?,
+        ViewerCachedEntity::fetch_cluster_members(request, &mut conn).await?,
     ))
 }
 
@@ -225,6 +419,17 @@ pub struct SearchRequest {
     active_hidden_tags: Vec<Uuid>,
     require_locations: bool,
     enums_constraints: HashMap<String, Vec<Value>>,
+    /// Constraints on the indexed Number, DiscreteScore, Boolean and Date fields
+    #[serde(default)]
+    values_constraints: HashMap<String, ValueConstraint>,
+    /// Also count the search results per category, tag and enum value
+    #[serde(default)]
+    with_facets: bool,
+    /// Constraints on the means of the comments scores, by score field key
+    #[serde(default)]
+    scores_constraints: HashMap<String, ValueConstraint>,
+    #[serde(default)]
+    sort: EntitiesSort,
 }
 
 impl Display for SearchRequest {
@@ -242,15 +447,16 @@ impl Display for SearchRequest {
     path = "/api/map/search",
     request_body = SearchRequest,
     responses(
-        (status = 200, description = "List of entities", body = ViewerCachedEntitiesWithPagination),
+        (status = 200, description = "List of entities", body = ViewerSearchResults),
         (status = 401, description = "Invalid token", body = ErrorResponse),
     )
 )]
 async fn viewer_search_request(
+    State(app_state): State<AppState>,
     DbConn(mut conn): DbConn,
     token: MapUserTokenClaims,
     Json(request): Json<SearchRequest>,
-) -> Result<AppJson<ViewerCachedEntitiesWithPagination>, AppError> {
+) -> Result<AppJson<ViewerSearchResults>, AppError> {
     // The token must allow to list entities
     require_permission(token.perms.can_list_entities)?;
 
@@ -266,11 +472,25 @@ async fn viewer_search_request(
     // The token must allow to list entities without query or the query must be at least 4 characters long
     require_permission(token.perms.can_list_without_query || request.search_query.len() >= 4)?;
 
-    // The token must allow to list entities with enum constraints or the request must not have any enum constraints
+    // The token must allow to list entities with enum constraints or the request must not have any enum
+    // or value constraints
     require_permission(
-        token.perms.can_list_with_enum_constraints || request.enums_constraints.is_empty(),
+        token.perms.can_list_with_enum_constraints
+            || (request.enums_constraints.is_empty()
+                && request.values_constraints.is_empty()
+                && request.scores_constraints.is_empty()),
     )?;
 
+    // Scores come from the comments, the token must allow to access them to filter or sort on them
+    require_permission(
+        token.perms.can_access_comments
+            || (request.scores_constraints.is_empty()
+                && !matches!(request.sort, EntitiesSort::Score { .. })),
+    )?;
+
+    // Moderation is not public
+    require_permission(request.sort != EntitiesSort::ModerationStatus)?;
+
     tracing::trace!("Received search request {}", request);
 
     // Check if some of the constraints are forbidden
@@ -278,8 +498,21 @@ async fn viewer_search_request(
         &request.family_id,
         &token.fam_priv_idx,
         &request.enums_constraints,
+        &request.values_constraints,
     )?;
 
+    let facets_request = request.with_facets.then(|| FetchFacetsRequest {
+        search_query: Some(request.search_query.clone()),
+        active_categories: request.active_categories.clone(),
+        active_required_tags: request.active_required_tags.clone(),
+        active_hidden_tags: request.active_hidden_tags.clone(),
+        require_locations: request.require_locations,
+        enums_constraints: serde_json::to_value(&request.enums_constraints)
+            .expect("Enums should be serializable"),
+        values_constraints: ValueConstraint::to_database_constraints(&request.values_constraints),
+        ..token_facets_request(&token, request.family_id)
+    });
+
     let request = SearchEntitiesRequest {
         search_query: request.search_query,
         geographic_restriction: token.perms.geographic_restrictions.clone(),
@@ -298,11 +531,70 @@ async fn viewer_search_request(
         require_locations: request.require_locations,
         enums_constraints: serde_json::to_value(request.enums_constraints)
             .expect("Enums should be serializable"),
+        values_constraints: ValueConstraint::to_database_constraints(&request.values_constraints),
+        scores_constraints: ValueConstraint::to_database_constraints(&request.scores_constraints),
+        sort: request.sort,
     };
 
-    Ok(AppJson(
-        ViewerCachedEntity::search_entities(request, &mut conn).await?,
-    ))
+    let logged_search =
+        (request.page <= 1 && !request.search_query.trim().is_empty()).then(|| LoggedSearch {
+            family_id: request.family_id,
+            query: request.search_query.clone(),
+            filters: json!({
+                "active_categories": request.active_categories,
+                "active_required_tags": request.active_required_tags,
+                "active_hidden_tags": request.active_hidden_tags,
+                "require_locations": request.require_locations,
+                "enums_constraints": request.enums_constraints,
+                "values_constraints": request.values_constraints,
+                "scores_constraints": request.scores_constraints,
+            }),
+            results_count: 0,
+        });
+
+    let mut results = ViewerCachedEntity::search_entities(request, &mut conn).await?;
+
+    // Only the first pages of the searches with a query are logged
+    if let Some(logged_search) = logged_search {
+        if !app_state.searches.push(LoggedSearch {
+            results_count: results.total_results,
+            ..logged_search
+        }) {
+            tracing::warn!("Searches queue is full, search dropped");
+        }
+    }
+
+    if !token.perms.can_access_comments {
+        results
+            .entities
+            .iter_mut()
+            .for_each(|entity| entity.scores.clear());
+    }
+
+    let facets = match facets_request {
+        Some(facets_request) => {
+            Some(ViewerCachedEntity::fetch_facets(facets_request, &mut conn).await?)
+        }
+        None => None,
+    };
+
+    Ok(AppJson(ViewerSearchResults {
+        entities: results.entities,
+        total_results: results.total_results,
+        total_pages: results.total_pages,
+        response_current_page: results.response_current_page,
+        facets,
+    }))
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ViewerSearchResults {
+    pub entities: Vec<ViewerSearchedCachedEntity>,
+    pub total_results: i64,
+    pub total_pages: i64,
+    pub response_current_page: i64,
+    /// Only set when requested
+    pub facets: Option<Facets>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema, Debug)]
@@ -316,6 +608,8 @@ pub struct PublicNewEntityRequest {
 pub struct PublicNewEntityResponse {
     entity: PublicEntity,
     comment: Option<PublicComment>,
+    /// Secret code for the submitter to follow the entity up and propose edits
+    tracking_code: String,
 }
 
 async fn check_captcha(state: AppState, response: Option<String>) -> Result<(), AppError> {
@@ -324,24 +618,37 @@ async fn check_captcha(state: AppState, response: Option<String>) -> Result<(),
     if dyn_config.safe_mode.enabled {
         match response {
             Some(response) => {
-                hcaptcha::check_hcaptcha(
-                    response,
-                    dyn_config.safe_mode.hcaptcha_secret.clone(),
-                    None,
-                )
-                .await
-                .map_err(|e| match e {
-                    HCaptchaValidationError::NetworkError() => {
-                        AppError::Validation("HCaptcha network error".to_string())
-                    }
-                    HCaptchaValidationError::HCaptchaError(errors) => {
-                        AppError::Validation(format!("HCaptcha errors: {:?}", errors))
-                    }
-                })?;
+                captcha::verifier_from_config(&dyn_config.safe_mode, &state.proof_of_work)
+                    .verify(response, None)
+                    .await
+                    .map_err(|e| match e {
+                        CaptchaValidationError::NetworkError() => {
+                            state
+                                .metrics
+                                .captcha_failures
+                                .with_label_values(&["network"])
+                                .inc();
+                            AppError::Validation("Captcha network error".to_string())
+                        }
+                        CaptchaValidationError::ProviderError(errors) => {
+                            state
+                                .metrics
+                                .captcha_failures
+                                .with_label_values(&["rejected"])
+                                .inc();
+                            AppError::Validation(format!("Captcha errors: {:?}", errors))
+                        }
+                        CaptchaValidationError::Database(error) => AppError::Database(error),
+                    })?;
             }
             None => {
+                state
+                    .metrics
+                    .captcha_failures
+                    .with_label_values(&["missing"])
+                    .inc();
                 return Err(AppError::Validation(
-                    "HCaptcha token is required".to_string(),
+                    "Captcha token is required".to_string(),
                 ));
             }
         }
@@ -362,6 +669,7 @@ async fn check_captcha(state: AppState, response: Option<String>) -> Result<(),
 async fn viewer_new_entity(
     DbConn(mut conn): DbConn,
     State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
     token: MapUserTokenClaims,
     Json(request): Json<PublicNewEntityRequest>,
 ) -> Result<AppJson<PublicNewEntityResponse>, AppError> {
@@ -371,22 +679,201 @@ async fn viewer_new_entity(
     // The token must allow to add comments or the request must not have any comment
     require_permission(token.perms.can_add_comment || request.comment.is_none())?;
 
-    check_captcha(state, request.hcaptcha_token).await?;
+    check_captcha(state.clone(), request.hcaptcha_token).await?;
+
+    let spam_filter = state.dyn_config.read().await.spam_filter.clone();
+    let spam_assessment = match spam_filter.enabled {
+        true => {
+            let origin =
+                SubmissionOrigin::new(client_ip, token.token_id, &state.config.token_secret);
+            let assessment =
+                SpamAssessment::for_entity(&request.entity, &origin, &spam_filter, &mut conn)
+                    .await?;
+            origin.record(&mut conn).await?;
+            Some(assessment)
+        }
+        false => None,
+    };
 
-    let db_entity = PublicEntity::new(request.entity, &mut conn).await?;
+    let tracking_code = TrackingCode::generate();
+    let db_entity = PublicEntity::new(
+        request.entity,
+        spam_assessment.clone(),
+        &TrackingCode::hash(&tracking_code),
+        &mut conn,
+    )
+    .await?;
     let mut db_comment = None;
 
+    // The comment is moderated along with the entity, so it shares its assessment
     if let Some(mut comment) = request.comment {
         comment.entity_id = db_entity.id;
-        db_comment = Some(PublicComment::new(comment, &mut conn).await?);
+        db_comment = Some(PublicComment::new(comment, spam_assessment, &mut conn).await?);
     }
 
     Ok(AppJson(PublicNewEntityResponse {
         entity: db_entity,
         comment: db_comment,
+        tracking_code,
     }))
 }
 
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct TrackedEntityResponse {
+    entity: PublicEntity,
+    moderation: PublicModerationState,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/map/tracking/{code}",
+    params(
+        ("code" = String, Path, description = "Tracking code given to the submitter")
+    ),
+    responses(
+        (status = 200, description = "Submitted entity and its moderation state", body = TrackedEntityResponse),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 404, description = "Unknown tracking code", body = ErrorResponse),
+    )
+)]
+async fn viewer_fetch_tracked_entity(
+    DbConn(mut conn): DbConn,
+    Path(code): Path<String>,
+) -> Result<AppJson<TrackedEntityResponse>, AppError> {
+    let entity =
+        PublicEntity::get_by_tracking_code_hash(&TrackingCode::hash(&code), &mut conn).await?;
+    let moderation = EntitySuggestion::moderation_state(entity.id, &mut conn).await?;
+
+    Ok(AppJson(TrackedEntityResponse { entity, moderation }))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct SuggestEditRequest {
+    /// Edited version of the entity, only its changed fields are kept
+    entity: PublicNewEntity,
+    captcha_token: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/map/tracking/{code}/suggestions",
+    params(
+        ("code" = String, Path, description = "Tracking code given to the submitter")
+    ),
+    request_body = SuggestEditRequest,
+    responses(
+        (status = 200, description = "Edit waiting for moderation", body = PublicSuggestionStatus),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 404, description = "Unknown tracking code", body = ErrorResponse),
+    )
+)]
+async fn viewer_suggest_tracked_entity_edit(
+    DbConn(mut conn): DbConn,
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    token: MapUserTokenClaims,
+    Json(request): Json<SuggestEditRequest>,
+) -> Result<AppJson<PublicSuggestionStatus>, AppError> {
+    // The token must still allow to add entities to the family of the entity
+    require_permission(token.perms.can_add_entity)?;
+
+    let entity =
+        PublicEntity::get_by_tracking_code_hash(&TrackingCode::hash(&code), &mut conn).await?;
+    require_permission(is_family_allowed_by_token(&token, &entity.family_id))?;
+
+    check_captcha(state, request.captcha_token).await?;
+
+    Ok(AppJson(
+        EntitySuggestion::submit(entity.id, request.entity, true, &mut conn).await?,
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct SuggestCorrectionRequest {
+    /// Corrected version of the entity, only its changed fields are kept
+    entity: PublicNewEntity,
+    captcha_token: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/map/entities/{id}/suggestions",
+    params(
+        ("id" = Uuid, Path, description = "Entity identifier")
+    ),
+    request_body = SuggestCorrectionRequest,
+    responses(
+        (status = 200, description = "Correction waiting for moderation", body = PublicSuggestionStatus),
+        (status = 400, description = "Invalid or empty correction", body = ErrorResponse),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 404, description = "Entity not found", body = ErrorResponse),
+    )
+)]
+async fn viewer_suggest_correction(
+    DbConn(mut conn): DbConn,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    token: MapUserTokenClaims,
+    Json(request): Json<SuggestCorrectionRequest>,
+) -> Result<AppJson<PublicSuggestionStatus>, AppError> {
+    // The token must allow to view the entity and to suggest corrections
+    require_permission(token.perms.can_access_entity && token.perms.can_suggest_correction)?;
+
+    let entity = PublicEntity::get(id, &mut conn).await?;
+    require_entity_visible(&token, &entity)?;
+
+    check_captcha(state, request.captcha_token).await?;
+
+    Ok(AppJson(
+        EntitySuggestion::submit(entity.id, request.entity, false, &mut conn).await?,
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ReportEntityRequest {
+    report: PublicNewReport,
+    captcha_token: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/map/entities/{id}/reports",
+    params(
+        ("id" = Uuid, Path, description = "Entity identifier")
+    ),
+    request_body = ReportEntityRequest,
+    responses(
+        (status = 200, description = "Report registered"),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 404, description = "Entity not found", body = ErrorResponse),
+    )
+)]
+async fn viewer_report_entity(
+    DbConn(mut conn): DbConn,
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    Path(id): Path<Uuid>,
+    token: MapUserTokenClaims,
+    Json(request): Json<ReportEntityRequest>,
+) -> Result<AppJson<()>, AppError> {
+    // The token must allow to view the entity and to report it
+    require_permission(token.perms.can_access_entity && token.perms.can_report)?;
+
+    let entity = PublicEntity::get(id, &mut conn).await?;
+    require_entity_visible(&token, &entity)?;
+
+    check_captcha(state.clone(), request.captcha_token).await?;
+
+    let origin = SubmissionOrigin::new(client_ip, token.token_id, &state.config.token_secret);
+    let moderation = state.dyn_config.read().await.moderation.clone();
+
+    if EntityReport::submit(entity.id, request.report, &origin, &moderation, &mut conn).await? {
+        tracing::info!("Entity {} hidden after being reported", entity.id);
+    }
+
+    Ok(AppJson(()))
+}
+
 #[derive(Serialize, Deserialize, ToSchema, Debug)]
 pub struct NewCommentRequest {
     comment: PublicNewComment,
@@ -405,6 +892,7 @@ pub struct NewCommentRequest {
 async fn viewer_new_comment(
     DbConn(mut conn): DbConn,
     State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
     token: MapUserTokenClaims,
     Json(request): Json<NewCommentRequest>,
 ) -> Result<AppJson<PublicComment>, AppError> {
@@ -416,15 +904,36 @@ async fn viewer_new_comment(
     // The token must allow to add comments to the entity's family
     require_permission(is_family_allowed_by_token(&token, &target_entity.family_id))?;
 
-    check_captcha(state, request.hcaptcha_token).await?;
-    let db_comment = PublicComment::new(request.comment, &mut conn).await?;
+    check_captcha(state.clone(), request.hcaptcha_token).await?;
+
+    let spam_filter = state.dyn_config.read().await.spam_filter.clone();
+    let spam_assessment = match spam_filter.enabled {
+        true => {
+            let origin =
+                SubmissionOrigin::new(client_ip, token.token_id, &state.config.token_secret);
+            let assessment =
+                SpamAssessment::for_comment(&request.comment, &origin, &spam_filter, &mut conn)
+                    .await?;
+            origin.record(&mut conn).await?;
+            Some(assessment)
+        }
+        false => None,
+    };
+
+    let db_comment = PublicComment::new(request.comment, spam_assessment, &mut conn).await?;
     Ok(AppJson(db_comment))
 }
 
 #[derive(Serialize, Deserialize, ToSchema, Debug)]
 pub struct FetchedEntity {
     pub entity: PublicEntity,
+    /// First page of the comments, as requested
     pub comments: Vec<PublicComment>,
+    /// Number of comment threads
+    pub comments_total_results: i64,
+    pub comments_total_pages: i64,
+    /// Aggregates of the comments scores, by field key
+    pub scores: HashMap<String, ScoreAggregate>,
     pub parents: Vec<PublicListedEntity>,
     pub children: Vec<PublicListedEntity>,
 }
@@ -434,6 +943,9 @@ pub struct FetchEntityRequest {
     pub active_categories: Vec<Uuid>,
     pub active_required_tags: Vec<Uuid>,
     pub active_hidden_tags: Vec<Uuid>,
+    /// Page of the comments to include
+    #[serde(default)]
+    pub comments: CommentsRequest,
 }
 
 #[utoipa::path(
@@ -446,6 +958,7 @@ pub struct FetchEntityRequest {
     )
 )]
 async fn viewer_fetch_entity(
+    State(app_state): State<AppState>,
     DbConn(mut conn): DbConn,
     token: MapUserTokenClaims,
     Path(id): Path<Uuid>,
@@ -527,10 +1040,7 @@ async fn viewer_fetch_entity(
         .into_iter()
         // filter against request
         .filter(|child| {
-            request
-                .active_categories
-                .iter()
-                .any(|cat| *cat == child.category_id)
+            request.active_categories.contains(&child.category_id)
                 && request
                     .active_required_tags
                     .iter()
@@ -570,15 +1080,109 @@ async fn viewer_fetch_entity(
         filtered_parents.len()
     );
 
-    let comments = match token.perms.can_access_comments {
-        true => PublicComment::list_for_public_entity(id, &entity.comment_form, &mut conn).await?,
-        false => vec![],
+    let (comments, scores) = match token.perms.can_access_comments {
+        true => (
+            PublicComment::list_for_public_entity(
+                id,
+                &entity.comment_form,
+                &request.comments,
+                &mut conn,
+            )
+            .await?,
+            ScoreAggregate::for_entity(id, &mut conn).await?,
+        ),
+        false => (
+            PublicCommentsWithPagination {
+                comments: vec![],
+                total_results: 0,
+                total_pages: 0,
+                response_current_page: request.comments.page,
+            },
+            HashMap::new(),
+        ),
     };
 
+    app_state.record_view(
+        ViewKind::Entity,
+        None,
+        entity.family_id,
+        vec![entity.category_id],
+    );
+
     Ok(AppJson(FetchedEntity {
         entity,
-        comments,
+        comments: comments.comments,
+        comments_total_results: comments.total_results,
+        comments_total_pages: comments.total_pages,
+        scores,
         parents: filtered_parents,
         children: filtered_children,
     }))
 }
+
+#[utoipa::path(
+    post,
+    path = "/api/map/entities/{id}/comments",
+    params(
+        ("id" = Uuid, Path, description = "Entity identifier")
+    ),
+    request_body = CommentsRequest,
+    responses(
+        (status = 200, description = "Page of the comment threads of the entity", body = PublicCommentsWithPagination),
+        (status = 400, description = "Invalid pagination", body = ErrorResponse),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 404, description = "Entity not found", body = ErrorResponse),
+    )
+)]
+async fn viewer_fetch_entity_comments(
+    DbConn(mut conn): DbConn,
+    token: MapUserTokenClaims,
+    Path(id): Path<Uuid>,
+    Json(request): Json<CommentsRequest>,
+) -> Result<AppJson<PublicCommentsWithPagination>, AppError> {
+    // The token must allow to view the entity and its comments
+    require_permission(token.perms.can_access_entity && token.perms.can_access_comments)?;
+
+    let entity = PublicEntity::get(id, &mut conn).await?;
+    require_entity_visible(&token, &entity)?;
+
+    Ok(AppJson(
+        PublicComment::list_for_public_entity(id, &entity.comment_form, &request, &mut conn)
+            .await?,
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct SharePresetResponse {
+    /// Code to give to the bootstrap endpoint to restore the state
+    code: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/map/presets",
+    request_body = ViewPresetState,
+    responses(
+        (status = 200, description = "Share code of the state", body = SharePresetResponse),
+        (status = 401, description = "Invalid token", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    )
+)]
+pub async fn viewer_share_preset(
+    DbConn(mut conn): DbConn,
+    token: MapUserTokenClaims,
+    Json(state): Json<ViewPresetState>,
+) -> Result<AppJson<SharePresetResponse>, AppError> {
+    require_permission(token.perms.can_list_entities)?;
+    require_permission(is_family_allowed_by_token(&token, &state.family_id))?;
+    are_constraints_allowed(
+        &state.family_id,
+        &token.fam_priv_idx,
+        &state.enums_constraints,
+        &state.values_constraints,
+    )?;
+
+    let preset = ViewPreset::share(token.token_id, state, &mut conn).await?;
+
+    Ok(AppJson(SharePresetResponse { code: preset.code }))
+}
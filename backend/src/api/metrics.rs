@@ -0,0 +1,108 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, Router},
+};
+
+use crate::{
+    helpers::metrics::Metrics,
+    models::{entity_cache::CacheRefreshStats, notification::ModerationSummary},
+};
+
+use super::{AppError, AppState};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+/// Counts the requests and their durations by route, the unmatched requests are left out
+pub async fn track_requests(
+    State(metrics): State<Metrics>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(path) = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+    else {
+        return next.run(request).await;
+    };
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    metrics
+        .http_request_duration
+        .with_label_values(&[&method, &path])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+async fn get_metrics(State(state): State<AppState>) -> Result<Response, AppError> {
+    let metrics = &state.metrics;
+
+    // Read before the connection of the scrape is acquired
+    let idle_connections = state.pool.num_idle() as i64;
+    let connections = state.pool.size() as i64;
+    metrics
+        .db_pool_connections
+        .with_label_values(&["idle"])
+        .set(idle_connections);
+    metrics
+        .db_pool_connections
+        .with_label_values(&["used"])
+        .set(connections - idle_connections);
+    metrics
+        .db_pool_max_connections
+        .set(state.config.database.pool_size as i64);
+
+    {
+        let icon_cache = state.icon_cache.read().await;
+        metrics.icon_cache_entries.set(icon_cache.len() as i64);
+        metrics
+            .icon_cache_bytes
+            .set(icon_cache.values().map(|(data, _)| data.len() as i64).sum());
+    }
+
+    let mut conn = state.pool.acquire().await.map_err(AppError::Database)?;
+
+    let refreshes = CacheRefreshStats::get(&mut conn).await?;
+    Metrics::catch_up(&metrics.cache_refreshes, refreshes.refreshes_count as u64);
+    Metrics::catch_up_float(&metrics.cache_refresh_duration, refreshes.total_duration);
+    if let Some(last_duration) = refreshes.last_duration {
+        metrics.cache_last_refresh_duration.set(last_duration);
+    }
+    if let Some(last_refreshed_at) = refreshes.last_refreshed_at {
+        metrics
+            .cache_last_refresh_timestamp
+            .set(last_refreshed_at.timestamp_millis() as f64 / 1000.0);
+    }
+
+    let moderation = ModerationSummary::since(None, &mut conn).await?;
+    for (kind, count) in [
+        ("entities", moderation.pending_entities),
+        ("comments", moderation.pending_comments),
+        ("reports", moderation.pending_reports),
+    ] {
+        metrics
+            .pending_moderation
+            .with_label_values(&[kind])
+            .set(count);
+    }
+
+    let (body, content_type) = metrics
+        .encode()
+        .map_err(|e| AppError::Internal(Some(e.to_string())))?;
+
+    Ok(([("Content-Type", content_type)], body).into_response())
+}
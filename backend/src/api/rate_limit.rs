@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{FromRef, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{Pool, Postgres};
+
+use crate::config::{RateLimit, RateLimitBudget, RateLimitStore};
+
+use super::{auth::MapUserTokenClaims, AppError, AppState, ClientIp};
+
+/// In-memory buckets are split in shards locked separately, each holding a bounded number of them
+const MEMORY_SHARDS: usize = 16;
+const MAX_MEMORY_BUCKETS_PER_SHARD: usize = 8192;
+/// Buckets among which the least recently used is evicted from a full shard
+const MEMORY_EVICTION_SAMPLE: usize = 8;
+/// In-memory buckets unused for this long are full again, and forgotten by the regular sweeps
+const MEMORY_BUCKETS_TTL: Duration = Duration::from_secs(3600);
+const MEMORY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub enum RouteGroup {
+    Public,
+    Admin,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    /// Read for GET and HEAD requests, write otherwise
+    ByMethod,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+struct MemoryBuckets {
+    shards: Vec<Mutex<HashMap<String, Bucket>>>,
+    hasher: RandomState,
+}
+
+impl MemoryBuckets {
+    fn new() -> Self {
+        Self {
+            shards: (0..MEMORY_SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn take(&self, key: String, capacity: f64, refill_rate: f64) -> Option<Duration> {
+        let shard = &self.shards[self.hasher.hash_one(&key) as usize % MEMORY_SHARDS];
+        let mut buckets = shard.lock().unwrap();
+        let now = Instant::now();
+
+        // A full shard makes room by evicting an old bucket, only looking at a few of them
+        if buckets.len() >= MAX_MEMORY_BUCKETS_PER_SHARD && !buckets.contains_key(&key) {
+            let evicted = buckets
+                .iter()
+                .take(MEMORY_EVICTION_SAMPLE)
+                .min_by_key(|(_, bucket)| bucket.updated_at)
+                .map(|(key, _)| key.clone());
+            if let Some(evicted) = evicted {
+                buckets.remove(&evicted);
+            }
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        bucket.tokens =
+            capacity.min(bucket.tokens + (now - bucket.updated_at).as_secs_f64() * refill_rate);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1. - bucket.tokens) / refill_rate))
+        }
+    }
+
+    /// Forgets the buckets unused for long, one shard at a time
+    fn sweep(&self, now: Instant) {
+        for shard in &self.shards {
+            shard
+                .lock()
+                .unwrap()
+                .retain(|_, bucket| now - bucket.updated_at < MEMORY_BUCKETS_TTL);
+        }
+    }
+}
+
+#[derive(Clone)]
+enum BucketStore {
+    Memory(Arc<MemoryBuckets>),
+    Postgres(Pool<Postgres>),
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Arc<RateLimit>,
+    store: BucketStore,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit, pool: Pool<Postgres>) -> Self {
+        let store = match config.store {
+            RateLimitStore::Memory => BucketStore::Memory(Arc::new(MemoryBuckets::new())),
+            RateLimitStore::Postgres => BucketStore::Postgres(pool),
+        };
+
+        Self {
+            config: Arc::new(config.clone()),
+            store,
+        }
+    }
+
+    fn budget(&self, group: RouteGroup, access: Access) -> RateLimitBudget {
+        match (group, access) {
+            (RouteGroup::Public, Access::Read) => self.config.public_read,
+            (RouteGroup::Public, _) => self.config.public_write,
+            (RouteGroup::Admin, Access::Read) => self.config.admin_read,
+            (RouteGroup::Admin, _) => self.config.admin_write,
+        }
+    }
+
+    /// Forgets the in-memory buckets unused for long, regularly. The database buckets are pruned
+    /// by the database itself.
+    pub async fn sweep_memory_buckets(&self) {
+        let BucketStore::Memory(buckets) = &self.store else {
+            return;
+        };
+        let mut interval = tokio::time::interval(MEMORY_SWEEP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            buckets.sweep(Instant::now());
+        }
+    }
+
+    /// Takes a token from the bucket of the key, returns the time to wait when it is empty
+    async fn take(&self, key: String, capacity: f64, refill_rate: f64) -> Option<Duration> {
+        match &self.store {
+            BucketStore::Memory(buckets) => buckets.take(key, capacity, refill_rate),
+            BucketStore::Postgres(pool) => {
+                let retry_after = sqlx::query_scalar!(
+                    r#"SELECT take_rate_limit_token($1, $2, $3) AS "retry_after!""#,
+                    key,
+                    capacity,
+                    refill_rate
+                )
+                .fetch_one(pool)
+                .await;
+
+                match retry_after {
+                    Ok(retry_after) if retry_after > 0. => {
+                        Some(Duration::from_secs_f64(retry_after))
+                    }
+                    Ok(_) => None,
+                    Err(e) => {
+                        // The limits are not worth failing the requests
+                        tracing::error!("Rate limiting bucket unavailable: {:?}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// State of the rate limiting middleware for a group of routes
+#[derive(Clone)]
+pub struct RateLimitedRoutes {
+    state: AppState,
+    group: RouteGroup,
+    access: Access,
+}
+
+impl RateLimitedRoutes {
+    pub fn new(state: &AppState, group: RouteGroup, access: Access) -> Self {
+        Self {
+            state: state.clone(),
+            group,
+            access,
+        }
+    }
+}
+
+impl FromRef<RateLimitedRoutes> for AppState {
+    fn from_ref(routes: &RateLimitedRoutes) -> Self {
+        routes.state.clone()
+    }
+}
+
+pub async fn rate_limit_middleware(
+    State(routes): State<RateLimitedRoutes>,
+    client_ip: Option<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let rate_limiter = &routes.state.rate_limiter;
+
+    if !rate_limiter.config.enabled {
+        return next.run(request).await;
+    }
+
+    let access = match routes.access {
+        Access::ByMethod if matches!(*request.method(), Method::GET | Method::HEAD) => Access::Read,
+        Access::ByMethod => Access::Write,
+        access => access,
+    };
+    let budget = rate_limiter.budget(routes.group, access);
+    let prefix = format!("{:?}:{:?}", routes.group, access);
+
+    let mut buckets = vec![];
+    if let Some(ClientIp(ip)) = client_ip {
+        buckets.push((format!("{}:client:{}", prefix, ip), 1.));
+    }
+    // Claims are only available once the viewer authentication middleware has run
+    if let Some(claims) = request.extensions().get::<MapUserTokenClaims>() {
+        buckets.push((
+            format!("{}:token:{}", prefix, claims.token_id),
+            rate_limiter.config.access_token_factor,
+        ));
+    }
+
+    for (key, factor) in buckets {
+        if let Some(retry_after) = rate_limiter
+            .take(key, budget.burst * factor, budget.per_second * factor)
+            .await
+        {
+            return AppError::TooManyRequests(retry_after.as_secs_f64().ceil() as u64)
+                .into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets_count(buckets: &MemoryBuckets) -> usize {
+        buckets
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn memory_buckets_take_tokens() {
+        let buckets = MemoryBuckets::new();
+
+        assert!(buckets.take("client".to_string(), 2., 1.).is_none());
+        assert!(buckets.take("client".to_string(), 2., 1.).is_none());
+        let retry_after = buckets.take("client".to_string(), 2., 1.).unwrap();
+        assert!(retry_after <= Duration::from_secs(1));
+        assert!(buckets.take("other".to_string(), 2., 1.).is_none());
+    }
+
+    #[test]
+    fn memory_buckets_are_bounded_and_swept() {
+        let buckets = MemoryBuckets::new();
+        let max_buckets = MEMORY_SHARDS * MAX_MEMORY_BUCKETS_PER_SHARD;
+
+        for client in 0..max_buckets + 1000 {
+            buckets.take(format!("client:{}", client), 10., 1.);
+        }
+        assert!(buckets_count(&buckets) <= max_buckets);
+
+        // The buckets unused for long are swept, the others are kept
+        let later = Instant::now() + MEMORY_BUCKETS_TTL;
+        for shard in buckets.shards.iter().skip(1).step_by(2) {
+            for bucket in shard.lock().unwrap().values_mut() {
+                bucket.updated_at = later;
+            }
+        }
+        let used_count: usize = buckets
+            .shards
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|shard| shard.lock().unwrap().len())
+            .sum();
+        buckets.sweep(later);
+        assert_eq!(buckets_count(&buckets), used_count);
+    }
+}
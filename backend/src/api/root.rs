@@ -1,20 +1,29 @@
 use std::collections::HashMap;
 
 use crate::api::auth::MapUserTokenClaims;
-use crate::api::{AppError, AppJson, AppState, DbConn};
+use crate::api::{AppError, AppJson, AppState, ClientIp, DbConn};
+use crate::helpers::captcha::ProofOfWorkChallenge;
+use crate::helpers::visitors;
 use crate::models::options::{
-    CartographyInitConfig, CartographySourceConfig, GeneralOptions, InitPopupOptions,
+    CaptchaProvider, CartographyInitConfig, CartographySourceConfig, GeneralOptions,
+    InitPopupOptions,
+};
+use crate::models::view_preset::{ViewPreset, ViewPresetState};
+use crate::models::{
+    access_token::{AccessToken, Visit},
+    category::Category,
+    family::Family,
+    tag::Tag,
 };
-use crate::models::{access_token::AccessToken, category::Category, family::Family, tag::Tag};
 use axum::extract::{Query, State};
 use axum::{
     extract::Path,
+    http::{header, HeaderMap},
     routing::{get, Router},
 };
 use chrono::{TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, NoneAsEmptyString};
-use tokio::task;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
@@ -22,6 +31,7 @@ pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/status", get(status))
         .route("/version", get(version))
+        .route("/captcha/challenge", get(captcha_challenge))
         .route("/bootstrap/:token", get(bootstrap))
 }
 
@@ -38,7 +48,8 @@ pub struct StatusResponse {
 #[derive(Serialize, ToSchema)]
 pub struct SafeMode {
     enabled: bool,
-    hcaptcha_sitekey: String,
+    captcha_provider: CaptchaProvider,
+    captcha_sitekey: String,
 }
 
 #[utoipa::path(
@@ -59,11 +70,38 @@ pub async fn status(State(app_state): State<AppState>) -> AppJson<StatusResponse
         cartography_source: config.cartography_source,
         safe_mode: SafeMode {
             enabled: config.safe_mode.enabled,
-            hcaptcha_sitekey: config.safe_mode.hcaptcha_sitekey,
+            captcha_provider: config.safe_mode.captcha_provider,
+            captcha_sitekey: config.safe_mode.captcha_sitekey,
         },
     })
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/captcha/challenge",
+    responses(
+        (status = 200, description = "Proof-of-work challenge to solve", body = ProofOfWorkChallenge),
+        (status = 400, description = "The proof-of-work captcha is not enabled", body = ErrorResponse),
+    )
+)]
+pub async fn captcha_challenge(
+    State(app_state): State<AppState>,
+) -> Result<AppJson<ProofOfWorkChallenge>, AppError> {
+    let safe_mode = app_state.dyn_config.read().await.safe_mode.clone();
+
+    if !safe_mode.enabled || safe_mode.captcha_provider != CaptchaProvider::ProofOfWork {
+        return Err(AppError::Validation(
+            "Proof-of-work captcha is not enabled".to_string(),
+        ));
+    }
+
+    Ok(AppJson(
+        app_state
+            .proof_of_work
+            .new_challenge(safe_mode.proof_of_work_difficulty),
+    ))
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct BootstrapPermissions {
     /// Permission to list entities
@@ -89,6 +127,12 @@ pub struct BootstrapPermissions {
 
     /// Permission to add a comment to an entity
     can_add_comment: bool,
+
+    /// Permission to suggest a correction of an entity
+    can_suggest_correction: bool,
+
+    /// Permission to report an entity
+    can_report: bool,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -113,6 +157,9 @@ pub struct BootstrapResponse {
 
     /// List of tags
     tags: Vec<Tag>,
+
+    /// Filter state of the requested preset, if it exists and its family is allowed
+    preset: Option<ViewPresetState>,
 }
 
 #[serde_as]
@@ -121,6 +168,10 @@ pub struct BootstrapQueryParams {
     #[serde_as(as = "NoneAsEmptyString")]
     #[serde(default)]
     pub referrer: Option<String>,
+
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    pub preset: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -158,7 +209,8 @@ pub async fn version() -> AppJson<SafeHavenVersionResponse> {
     path = "/api/bootstrap/{token}",
     params(
         ("token" = String, Path, description = "Access token"),
-        ("referrer" = Option<String>, Query, description = "The referrer URL to register the visit")
+        ("referrer" = Option<String>, Query, description = "The referrer URL to register the visit"),
+        ("preset" = Option<String>, Query, description = "Code of a view preset to open")
     ),
     responses(
         (status = 200, description = "Bootstraping data", body = BootstrapResponse)
@@ -168,6 +220,8 @@ async fn bootstrap(
     State(app_state): State<AppState>,
     Path(token): Path<String>,
     Query(query): Query<BootstrapQueryParams>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
     DbConn(mut conn): DbConn,
 ) -> Result<AppJson<BootstrapResponse>, AppError> {
     tracing::trace!("Bootstrapping");
@@ -189,7 +243,21 @@ async fn bootstrap(
     let fam_priv_idx: HashMap<Uuid, Vec<String>> =
         Family::get_privately_indexed_fields_for_families(&families);
 
+    let preset = match query.preset.as_deref() {
+        Some(code) => match ViewPreset::get_by_code(access_token.id, code, &mut conn).await {
+            Ok(preset) => Some(preset.state.0),
+            Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+                tracing::trace!("Bootstrapping: preset {} not found", code);
+                None
+            }
+            Err(e) => return Err(e),
+        },
+        None => None,
+    }
+    .filter(|preset| families_ids.contains(&preset.family_id));
+
     let signed_token = app_state.generate_token(MapUserTokenClaims {
+        token_id: access_token.id,
         iat: Utc::now().timestamp() as usize,
         exp: (Utc::now() + TimeDelta::try_minutes(2).expect("valid duration")).timestamp() as usize,
         fam_priv_idx,
@@ -209,14 +277,24 @@ async fn bootstrap(
     tracing::trace!("Loaded {} tags", tags.len());
 
     // Register visit in background to avoid blocking the response
-    task::spawn(async move {
-        tracing::trace!("Registering visit");
-        if let Err(e) =
-            AccessToken::register_visit(access_token.id, query.referrer, &mut conn).await
-        {
-            tracing::error!("Failed to register visit: {:?}", e);
-        }
-    });
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let salt = app_state.visitors_salt.get(&mut conn).await?;
+
+    if !app_state.visits.push(Visit {
+        token_id: access_token.id,
+        visited_at: Utc::now(),
+        referrer: query.referrer,
+        visitor_hash: visitors::visitor_hash(&salt, client_ip, user_agent),
+        device_class: visitors::device_class(user_agent),
+        language: visitors::language(accept_language),
+    }) {
+        tracing::warn!("Visits queue is full, visit dropped");
+    }
 
     let allowed_categories = match perms.categories_policy.allow_all {
         true => categories.iter().map(|c| c.id).collect(),
@@ -243,8 +321,11 @@ async fn bootstrap(
             can_access_comments: perms.can_access_comments,
             can_add_entity: perms.can_add_entity,
             can_add_comment: perms.can_add_comment,
+            can_suggest_correction: perms.can_suggest_correction,
+            can_report: perms.can_report,
         },
         tags,
+        preset,
     };
 
     Ok(AppJson(resp))
@@ -3,11 +3,16 @@ use crate::{
         admin::{
             self,
             entities::{AdminEntityWithRelations, AdminSearchRequest},
+            moderation::{AssignRequest, ClaimRequest},
+            suggestions::RejectSuggestionRequest,
             AdminUserIdentity, LoginRequest, LoginResponse,
         },
+        health::{self, LivenessResponse, ReadinessChecks, ReadinessResponse},
         map::{
-            self, FetchEntityRequest, FetchedEntity, NewCommentRequest, PublicNewEntityRequest,
-            PublicNewEntityResponse, SearchRequest as MapSearchRequest, ViewRequest,
+            self, ExpandClusterRequest, FetchEntityRequest, FetchedEntity, NewCommentRequest,
+            PublicNewEntityRequest, PublicNewEntityResponse, ReportEntityRequest,
+            SearchRequest as MapSearchRequest, SharePresetResponse, SuggestCorrectionRequest,
+            SuggestEditRequest, TrackedEntityResponse, ViewRequest, ViewerSearchResults,
         },
         root::{
             self, BootstrapPermissions, BootstrapResponse, SafeHavenVersionResponse, SafeMode,
@@ -15,34 +20,52 @@ use crate::{
         },
         ErrorResponse,
     },
-    helpers::postgis_polygons::MultiPolygon,
+    helpers::{captcha::ProofOfWorkChallenge, postgis_polygons::MultiPolygon},
     models::{
         access_token::{
             AccessToken, AccessTokenStats, NewOrUpdateAccessToken, PermissionPolicy, Permissions,
         },
         category::{Category, NewOrUpdateCategory},
         comment::{
-            AdminComment, AdminListedComment, AdminNewOrUpdateComment, PublicComment,
-            PublicNewComment,
+            AdminComment, AdminListedComment, AdminNewOrUpdateComment, CommentsOrder,
+            CommentsRequest, PublicComment, PublicCommentsWithPagination, PublicNewComment,
+            ScoreAggregate,
         },
         entity::{
             AdminEntity, AdminListedEntity, AdminNewOrUpdateEntity, PublicEntity,
             PublicListedEntity, PublicNewEntity, UnprocessedLocation,
         },
         entity_cache::{
-            AdminCachedEntitiesWithPagination, AdminCachedEntity, Cluster, EntitiesAndClusters,
-            LocationRepresentation, ParentRepresentation, ViewerCachedEntitiesWithPagination,
-            ViewerCachedEntity, ViewerSearchedCachedEntity,
+            AdminCachedEntitiesPage, AdminCachedEntity, AdminSearchCursor, Cluster,
+            EntitiesAndClusters, EntitiesSort, ExpandedCluster, Facets, LocationRepresentation,
+            ParentRepresentation, ValueConstraint, ViewerCachedEntity, ViewerSearchedCachedEntity,
         },
         family::{Family, Field, FieldType, Form, NewOrUpdateFamily},
+        moderation::{
+            ModerationDecision, ModerationItem, ModerationItemKind, ModerationStatus,
+            ModeratorStats,
+        },
+        notification::{NotificationFrequency, NotificationPreferences},
         options::{
-            CartographyClusterConfig, CartographyInitConfig, CartographySourceConfig,
-            ConfigurationOption, GeneralOptions, InitPopupOptions, SafeHavenOptions,
-            SafeModeConfig,
+            CaptchaProvider, CartographyClusterConfig, CartographyInitConfig,
+            CartographySourceConfig, ConfigurationOption, GeneralOptions, InitPopupOptions,
+            ModerationConfig, SafeHavenOptions, SafeModeConfig, SpamFilterConfig,
+        },
+        report::{
+            AdminReport, AdminReportedEntity, AdminResolveReports, PublicNewReport, ReportReason,
+        },
+        statistics::{
+            HomePageStats, SearchedQuery, SearchesStats, TrendingQuery, ViewsCount, ViewsStats,
+            VisitorsStats,
+        },
+        suggestion::{
+            AdminEntitySuggestion, FieldChange, PublicModerationState, PublicSuggestionStatus,
+            SuggestedChanges,
         },
-        statistics::HomePageStats,
         tag::{NewOrUpdateTag, Tag},
         user::{NewOrUpdatedUser, User},
+        view_preset::{NewOrUpdateViewPreset, ViewPreset, ViewPresetState},
+        webhook::{NewOrUpdateWebhook, Webhook, WebhookDelivery, WebhookEvent},
     },
 };
 use utoipa::OpenApi;
@@ -53,12 +76,22 @@ use utoipa::OpenApi;
         root::status,
         root::bootstrap,
         root::version,
+        root::captcha_challenge,
+        health::live,
+        health::ready,
         // map
         map::viewer_view_request,
+        map::viewer_expand_cluster,
         map::viewer_search_request,
         map::viewer_fetch_entity,
+        map::viewer_fetch_entity_comments,
         map::viewer_new_comment,
         map::viewer_new_entity,
+        map::viewer_share_preset,
+        map::viewer_fetch_tracked_entity,
+        map::viewer_suggest_tracked_entity_edit,
+        map::viewer_suggest_correction,
+        map::viewer_report_entity,
         // admin
         admin::admin_login,
         admin::admin_logout,
@@ -72,6 +105,9 @@ use utoipa::OpenApi;
         admin::users::admin_user_new,
         admin::users::admin_user_get,
         admin::users::admin_user_change_self_password,
+        admin::users::admin_user_get_self_notifications,
+        admin::users::admin_user_update_self_notifications,
+        admin::users::admin_user_test_self_notifications,
         admin::users::admin_user_update,
         admin::users::admin_user_delete,
         // admin::access_tokens
@@ -81,9 +117,14 @@ use utoipa::OpenApi;
         admin::access_tokens::admin_access_token_get_stats,
         admin::access_tokens::admin_access_token_update,
         admin::access_tokens::admin_access_token_delete,
+        admin::access_tokens::admin_access_token_presets_list,
+        admin::access_tokens::admin_access_token_preset_new,
+        admin::access_tokens::admin_access_token_preset_update,
+        admin::access_tokens::admin_access_token_preset_delete,
         // admin::families
         admin::families::admin_families_list,
         admin::families::admin_family_new,
+        admin::families::admin_families_text_search_languages,
         admin::families::admin_family_get,
         admin::families::admin_family_update,
         admin::families::admin_family_delete,
@@ -119,8 +160,32 @@ use utoipa::OpenApi;
         admin::comments::admin_comment_get,
         admin::comments::admin_comment_update,
         admin::comments::admin_comment_delete,
+        // admin::moderation
+        admin::moderation::admin_moderation_claim,
+        admin::moderation::admin_moderation_assign,
+        admin::moderation::admin_moderation_decide,
+        admin::moderation::admin_moderation_stats,
+        // admin::suggestions
+        admin::suggestions::admin_suggestions_list,
+        admin::suggestions::admin_suggestion_apply,
+        admin::suggestions::admin_suggestion_reject,
+        // admin::reports
+        admin::reports::admin_reported_entities,
+        admin::reports::admin_entity_reports,
+        admin::reports::admin_entity_reports_resolve,
+        // admin::webhooks
+        admin::webhooks::admin_webhooks_list,
+        admin::webhooks::admin_webhook_new,
+        admin::webhooks::admin_webhook_get,
+        admin::webhooks::admin_webhook_update,
+        admin::webhooks::admin_webhook_delete,
+        admin::webhooks::admin_webhook_deliveries,
+        admin::webhooks::admin_webhook_delivery_retry,
         // admin::statistics
         admin::statistics::admin_home_stats,
+        admin::statistics::admin_visitors_stats,
+        admin::statistics::admin_views_stats,
+        admin::statistics::admin_searches_stats,
         admin::statistics::admin_count_comments_entities
     ),
     components(schemas(
@@ -130,18 +195,32 @@ use utoipa::OpenApi;
         AdminUserIdentity,
         // stats
         HomePageStats,
+        VisitorsStats,
+        ViewsStats,
+        ViewsCount,
+        SearchesStats,
+        SearchedQuery,
+        TrendingQuery,
         // root
         StatusResponse,
         SafeMode,
+        ProofOfWorkChallenge,
         BootstrapResponse,
         BootstrapPermissions,
         SafeHavenVersionResponse,
+        // health
+        LivenessResponse,
+        ReadinessChecks,
+        ReadinessResponse,
         // options
         SafeHavenOptions,
         ConfigurationOption,
         GeneralOptions,
         InitPopupOptions,
         SafeModeConfig,
+        CaptchaProvider,
+        SpamFilterConfig,
+        ModerationConfig,
         CartographyInitConfig,
         CartographySourceConfig,
         CartographyClusterConfig,
@@ -169,11 +248,16 @@ use utoipa::OpenApi;
         ViewerSearchedCachedEntity,
         LocationRepresentation,
         ParentRepresentation,
-        ViewerCachedEntitiesWithPagination,
-        AdminCachedEntitiesWithPagination,
+        ViewerSearchResults,
+        AdminCachedEntitiesPage,
+        AdminSearchCursor,
+        EntitiesSort,
         AdminCachedEntity,
         Cluster,
         EntitiesAndClusters,
+        ExpandedCluster,
+        Facets,
+        ValueConstraint,
         UnprocessedLocation,
         AdminSearchRequest,
         PublicNewEntityResponse,
@@ -183,24 +267,66 @@ use utoipa::OpenApi;
         AdminNewOrUpdateComment,
         AdminListedComment,
         PublicComment,
+        CommentsOrder,
+        CommentsRequest,
+        PublicCommentsWithPagination,
+        ScoreAggregate,
+        // moderation
+        ModerationStatus,
+        ModerationItemKind,
+        ModerationItem,
+        ModerationDecision,
+        ModeratorStats,
+        ClaimRequest,
+        AssignRequest,
+        // suggestions
+        SuggestedChanges,
+        FieldChange,
+        AdminEntitySuggestion,
+        PublicSuggestionStatus,
+        PublicModerationState,
+        RejectSuggestionRequest,
+        // reports
+        ReportReason,
+        PublicNewReport,
+        AdminReport,
+        AdminReportedEntity,
+        AdminResolveReports,
+        // webhooks
+        WebhookEvent,
+        Webhook,
+        NewOrUpdateWebhook,
+        WebhookDelivery,
         // access_tokens
         AccessToken,
         AccessTokenStats,
         NewOrUpdateAccessToken,
         Permissions,
         PermissionPolicy,
+        // view presets
+        ViewPreset,
+        ViewPresetState,
+        NewOrUpdateViewPreset,
+        SharePresetResponse,
         // users
         NewOrUpdatedUser,
         User,
         LoginRequest,
         LoginResponse,
+        NotificationFrequency,
+        NotificationPreferences,
         // map
         ViewRequest,
+        ExpandClusterRequest,
         MapSearchRequest,
         NewCommentRequest,
         PublicNewEntityRequest,
         FetchEntityRequest,
         FetchedEntity,
+        TrackedEntityResponse,
+        SuggestEditRequest,
+        SuggestCorrectionRequest,
+        ReportEntityRequest,
         // helper postgis polygons
         MultiPolygon,
     ))
@@ -0,0 +1,327 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use utoipa::ToSchema;
+
+use crate::models::options::{CaptchaProvider, SafeModeConfig};
+
+/// Validity of the proof-of-work challenges, in seconds
+const CHALLENGE_VALIDITY: i64 = 600;
+
+pub enum CaptchaValidationError {
+    NetworkError(),
+    ProviderError(Vec<String>),
+    Database(sqlx::Error),
+}
+
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    /// Checks the response of the captcha widget sent by the client
+    async fn verify(
+        &self,
+        client_response: String,
+        remote_ip: Option<String>,
+    ) -> Result<(), CaptchaValidationError>;
+}
+
+/// Builds the verifier of the provider selected in the safe mode configuration
+pub fn verifier_from_config(
+    config: &SafeModeConfig,
+    proof_of_work: &ProofOfWork,
+) -> Box<dyn CaptchaVerifier> {
+    let verify_url = |default: &str| {
+        config
+            .captcha_verify_url
+            .clone()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| default.to_string())
+    };
+
+    match config.captcha_provider {
+        CaptchaProvider::HCaptcha => Box::new(SiteVerifyCaptcha {
+            verify_url: verify_url("https://hcaptcha.com/siteverify"),
+            secret: config.captcha_secret.clone(),
+        }),
+        CaptchaProvider::Turnstile => Box::new(SiteVerifyCaptcha {
+            verify_url: verify_url("https://challenges.cloudflare.com/turnstile/v0/siteverify"),
+            secret: config.captcha_secret.clone(),
+        }),
+        CaptchaProvider::FriendlyCaptcha => Box::new(FriendlyCaptcha {
+            verify_url: verify_url("https://api.friendlycaptcha.com/api/v1/siteverify"),
+            secret: config.captcha_secret.clone(),
+            sitekey: config.captcha_sitekey.clone(),
+        }),
+        CaptchaProvider::ProofOfWork => Box::new(proof_of_work.clone()),
+    }
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+
+    #[serde(rename = "error-codes")]
+    error_codes: Option<Vec<String>>,
+}
+
+/// hCaptcha and Cloudflare Turnstile share the same verification API
+struct SiteVerifyCaptcha {
+    verify_url: String,
+    secret: String,
+}
+
+#[async_trait]
+impl CaptchaVerifier for SiteVerifyCaptcha {
+    async fn verify(
+        &self,
+        client_response: String,
+        remote_ip: Option<String>,
+    ) -> Result<(), CaptchaValidationError> {
+        let client = reqwest::Client::new();
+
+        let mut form = vec![
+            ("response", client_response),
+            ("secret", self.secret.clone()),
+        ];
+
+        if let Some(remote_ip) = remote_ip {
+            form.push(("remoteip", remote_ip));
+        }
+
+        let response: SiteVerifyResponse = client
+            .post(&self.verify_url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|_| CaptchaValidationError::NetworkError())?
+            .json()
+            .await
+            .map_err(|_| CaptchaValidationError::NetworkError())?;
+
+        if response.success {
+            Ok(())
+        } else {
+            Err(CaptchaValidationError::ProviderError(
+                response.error_codes.unwrap_or_default(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FriendlyCaptchaResponse {
+    success: bool,
+    errors: Option<Vec<String>>,
+}
+
+struct FriendlyCaptcha {
+    verify_url: String,
+    secret: String,
+    sitekey: String,
+}
+
+#[async_trait]
+impl CaptchaVerifier for FriendlyCaptcha {
+    async fn verify(
+        &self,
+        client_response: String,
+        _remote_ip: Option<String>,
+    ) -> Result<(), CaptchaValidationError> {
+        let client = reqwest::Client::new();
+
+        let response: FriendlyCaptchaResponse = client
+            .post(&self.verify_url)
+            .json(&serde_json::json!({
+                "solution": client_response,
+                "secret": self.secret,
+                "sitekey": self.sitekey,
+            }))
+            .send()
+            .await
+            .map_err(|_| CaptchaValidationError::NetworkError())?
+            .json()
+            .await
+            .map_err(|_| CaptchaValidationError::NetworkError())?;
+
+        if response.success {
+            Ok(())
+        } else {
+            Err(CaptchaValidationError::ProviderError(
+                response.errors.unwrap_or_default(),
+            ))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChallengeClaims {
+    salt: String,
+    difficulty: u8,
+    exp: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProofOfWorkChallenge {
+    /// Signed challenge to solve
+    challenge: String,
+    /// Number of leading zero bits required in the SHA-256 hash of the solution
+    difficulty: u8,
+}
+
+/// Built-in captcha without any third party: the client must find a nonce such that the SHA-256
+/// hash of `<challenge>:<nonce>` starts with `difficulty` zero bits, and sends this string back.
+/// Challenges are signed with a key derived from the token secret, and the solved ones are
+/// recorded in the database so that they can only be used once.
+#[derive(Clone)]
+pub struct ProofOfWork {
+    key: Arc<Vec<u8>>,
+    pool: Pool<Postgres>,
+}
+
+impl ProofOfWork {
+    pub fn new(token_secret: &str, pool: Pool<Postgres>) -> Self {
+        // A dedicated key, so that a challenge can never be mistaken for a token
+        let mut mac = Hmac::<Sha256>::new_from_slice(token_secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(b"safehaven proof-of-work challenges");
+
+        Self {
+            key: Arc::new(mac.finalize().into_bytes().to_vec()),
+            pool,
+        }
+    }
+
+    pub fn new_challenge(&self, difficulty: u8) -> ProofOfWorkChallenge {
+        let claims = ChallengeClaims {
+            salt: Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+            difficulty,
+            exp: (Utc::now().timestamp() + CHALLENGE_VALIDITY) as usize,
+        };
+
+        let challenge = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(&self.key),
+        )
+        .expect("Could not generate challenge");
+
+        ProofOfWorkChallenge {
+            challenge,
+            difficulty,
+        }
+    }
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in hash {
+        count += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    count
+}
+
+fn invalid_solution(reason: &str) -> CaptchaValidationError {
+    CaptchaValidationError::ProviderError(vec![reason.to_string()])
+}
+
+impl ProofOfWork {
+    /// Checks the signature, the expiration and the work of a solution, returns its challenge
+    fn check_solution<'a>(&self, solution: &'a str) -> Result<&'a str, CaptchaValidationError> {
+        let (challenge, _nonce) = solution
+            .split_once(':')
+            .ok_or_else(|| invalid_solution("malformed-solution"))?;
+
+        let claims = jsonwebtoken::decode::<ChallengeClaims>(
+            challenge,
+            &jsonwebtoken::DecodingKey::from_secret(&self.key),
+            &jsonwebtoken::Validation::default(),
+        )
+        .map_err(|_| invalid_solution("invalid-or-expired-challenge"))?
+        .claims;
+
+        let hash = Sha256::digest(solution.as_bytes());
+        if leading_zero_bits(&hash) < claims.difficulty as u32 {
+            return Err(invalid_solution("insufficient-work"));
+        }
+
+        Ok(challenge)
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for ProofOfWork {
+    async fn verify(
+        &self,
+        client_response: String,
+        _remote_ip: Option<String>,
+    ) -> Result<(), CaptchaValidationError> {
+        let challenge = self.check_solution(&client_response)?;
+
+        // Spent challenges are kept until they expire, after which they are rejected anyway
+        let spent = sqlx::query!(
+            r#"
+            WITH pruned AS (
+                DELETE FROM spent_captcha_challenges
+                WHERE spent_at < NOW() - make_interval(secs => $2)
+            )
+            INSERT INTO spent_captcha_challenges (challenge_hash)
+            VALUES ($1)
+            ON CONFLICT (challenge_hash) DO NOTHING
+            "#,
+            format!("{:x}", Sha256::digest(challenge.as_bytes())),
+            CHALLENGE_VALIDITY as f64
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(CaptchaValidationError::Database)?
+        .rows_affected()
+            > 0;
+
+        if !spent {
+            return Err(invalid_solution("challenge-already-used"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff, 0x00]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x10]), 19);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[sqlx::test]
+    async fn test_proof_of_work(pool: Pool<Postgres>) {
+        let proof_of_work = ProofOfWork::new("secret", pool);
+        let ProofOfWorkChallenge {
+            challenge,
+            difficulty,
+        } = proof_of_work.new_challenge(8);
+
+        let solution = (0u64..)
+            .map(|nonce| format!("{}:{}", challenge, nonce))
+            .find(|s| leading_zero_bits(&Sha256::digest(s.as_bytes())) >= difficulty as u32)
+            .unwrap();
+
+        assert!(proof_of_work.verify(solution.clone(), None).await.is_ok());
+        assert!(proof_of_work.verify(solution, None).await.is_err());
+        assert!(proof_of_work
+            .verify(format!("{}:x", challenge), None)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use prometheus::{
+    Counter, Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Prometheus metrics of the instance. The values owned by the database or by other parts of the
+/// state are only read when the metrics are scraped.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_pool_connections: IntGaugeVec,
+    pub db_pool_max_connections: IntGauge,
+    pub cache_refreshes: IntCounter,
+    pub cache_refresh_duration: Counter,
+    pub cache_last_refresh_duration: Gauge,
+    pub cache_last_refresh_timestamp: Gauge,
+    pub icon_cache_requests: IntCounterVec,
+    pub icon_cache_entries: IntGauge,
+    pub icon_cache_bytes: IntGauge,
+    pub captcha_failures: IntCounterVec,
+    pub pending_moderation: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("safehaven".to_string()), None)
+            .expect("invalid metrics prefix");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled HTTP requests"),
+            &["method", "path", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Duration of the handling of the HTTP requests",
+            ),
+            &["method", "path"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Connections of the database pool, by state",
+            ),
+            &["state"],
+        )
+        .unwrap();
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum connections of the database pool",
+        )
+        .unwrap();
+        let cache_refreshes = IntCounter::new(
+            "entities_cache_refreshes_total",
+            "Refreshes of the entities cache",
+        )
+        .unwrap();
+        let cache_refresh_duration = Counter::new(
+            "entities_cache_refresh_duration_seconds_total",
+            "Total duration of the refreshes of the entities cache",
+        )
+        .unwrap();
+        let cache_last_refresh_duration = Gauge::new(
+            "entities_cache_last_refresh_duration_seconds",
+            "Duration of the last refresh of the entities cache",
+        )
+        .unwrap();
+        let cache_last_refresh_timestamp = Gauge::new(
+            "entities_cache_last_refresh_timestamp_seconds",
+            "Time of the last refresh of the entities cache",
+        )
+        .unwrap();
+        let icon_cache_requests = IntCounterVec::new(
+            Opts::new(
+                "icon_cache_requests_total",
+                "Lookups in the icon cache, by result",
+            ),
+            &["result"],
+        )
+        .unwrap();
+        let icon_cache_entries =
+            IntGauge::new("icon_cache_entries", "Icons kept in the icon cache").unwrap();
+        let icon_cache_bytes = IntGauge::new(
+            "icon_cache_bytes",
+            "Size of the icons kept in the icon cache",
+        )
+        .unwrap();
+        let captcha_failures = IntCounterVec::new(
+            Opts::new(
+                "captcha_failures_total",
+                "Submissions rejected by the captcha, by reason",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let pending_moderation = IntGaugeVec::new(
+            Opts::new(
+                "pending_moderation",
+                "Content waiting for a moderator, by kind",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(http_requests.clone()),
+            Box::new(http_request_duration.clone()),
+            Box::new(db_pool_connections.clone()),
+            Box::new(db_pool_max_connections.clone()),
+            Box::new(cache_refreshes.clone()),
+            Box::new(cache_refresh_duration.clone()),
+            Box::new(cache_last_refresh_duration.clone()),
+            Box::new(cache_last_refresh_timestamp.clone()),
+            Box::new(icon_cache_requests.clone()),
+            Box::new(icon_cache_entries.clone()),
+            Box::new(icon_cache_bytes.clone()),
+            Box::new(captcha_failures.clone()),
+            Box::new(pending_moderation.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector).expect("can't register metric");
+        }
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            db_pool_max_connections,
+            cache_refreshes,
+            cache_refresh_duration,
+            cache_last_refresh_duration,
+            cache_last_refresh_timestamp,
+            icon_cache_requests,
+            icon_cache_entries,
+            icon_cache_bytes,
+            captcha_failures,
+            pending_moderation,
+        }
+    }
+
+    /// Catches a counter up with a total kept elsewhere, which only grows
+    pub fn catch_up(counter: &IntCounter, total: u64) {
+        counter.inc_by(total.saturating_sub(counter.get()));
+    }
+
+    /// Catches a floating counter up with a total kept elsewhere, which only grows
+    pub fn catch_up_float(counter: &Counter, total: f64) {
+        let missing = total - counter.get();
+        if missing > 0. {
+            counter.inc_by(missing);
+        }
+    }
+
+    /// Text exposition of the metrics, with its content type
+    pub fn encode(&self) -> Result<(Vec<u8>, String), prometheus::Error> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        Ok((buffer, encoder.format_type().to_string()))
+    }
+}
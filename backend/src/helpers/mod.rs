@@ -1,3 +1,9 @@
+pub mod captcha;
 pub mod deserializers;
-pub mod hcaptcha;
+pub mod metrics;
+pub mod notifications;
 pub mod postgis_polygons;
+pub mod tasks;
+pub mod telemetry;
+pub mod visitors;
+pub mod webhooks;
@@ -0,0 +1,157 @@
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::{
+    api::AppError,
+    config::{Smtp, SmtpSecurity},
+};
+
+/// Locales of the notification templates, the first one being the fallback
+pub const LOCALES: &[&str] = &["fr", "en"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    /// Sent as soon as new content is awaiting review
+    Immediate,
+    /// Sent once a day with what is awaiting review
+    Digest,
+    /// Sent on request, to check the configuration
+    Test,
+}
+
+/// Templates start with the subject line, followed by an empty line and the body.
+/// Placeholders are written `{{name}}`.
+fn template_source(locale: &str, template: Template) -> &'static str {
+    match (locale, template) {
+        ("en", Template::Immediate) => {
+            include_str!("../../templates/notifications/en/immediate.txt")
+        }
+        ("en", Template::Digest) => include_str!("../../templates/notifications/en/digest.txt"),
+        ("en", Template::Test) => include_str!("../../templates/notifications/en/test.txt"),
+        (_, Template::Immediate) => include_str!("../../templates/notifications/fr/immediate.txt"),
+        (_, Template::Digest) => include_str!("../../templates/notifications/fr/digest.txt"),
+        (_, Template::Test) => include_str!("../../templates/notifications/fr/test.txt"),
+    }
+}
+
+/// Renders a template into its subject and body
+pub fn render(locale: &str, template: Template, values: &[(&str, String)]) -> (String, String) {
+    let rendered = values.iter().fold(
+        template_source(locale, template).to_string(),
+        |text, (name, value)| text.replace(&format!("{{{{{}}}}}", name), value),
+    );
+
+    match rendered.split_once("\n\n") {
+        Some((subject, body)) => (subject.trim().to_string(), body.to_string()),
+        None => (rendered.trim().to_string(), String::new()),
+    }
+}
+
+pub fn parse_address(email: &str) -> Result<Address, AppError> {
+    email
+        .parse()
+        .map_err(|_| AppError::Validation(format!("Invalid email address {}", email)))
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    public_url: String,
+}
+
+impl Mailer {
+    /// Builds the mailer from the configuration, unless the notifications are disabled
+    pub fn from_config(config: &Smtp) -> Result<Option<Mailer>, String> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                    .map_err(|e| e.to_string())?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .map_err(|e| e.to_string())?,
+        }
+        .port(config.port);
+
+        let builder = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            _ => builder,
+        };
+
+        Ok(Some(Mailer {
+            transport: builder.build(),
+            from: config.from.parse().map_err(|e| format!("{}", e))?,
+            public_url: config.public_url.trim_end_matches('/').to_string(),
+        }))
+    }
+
+    pub fn admin_url(&self) -> String {
+        format!("{}/admin/home", self.public_url)
+    }
+
+    pub async fn send(
+        &self,
+        name: &str,
+        email: &str,
+        locale: &str,
+        template: Template,
+        values: &[(&str, String)],
+    ) -> Result<(), AppError> {
+        let (subject, body) = render(locale, template, values);
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(Mailbox::new(Some(name.to_string()), parse_address(email)?))
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|e| AppError::Internal(Some(e.to_string())))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| AppError::Internal(Some(format!("Error sending email: {}", e))))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_complete_in_every_locale() {
+        let values = [
+            "name",
+            "admin_url",
+            "pending_entities",
+            "pending_comments",
+            "pending_reports",
+            "new_entities",
+            "new_comments",
+            "new_reports",
+        ]
+        .map(|name| (name, "value".to_string()));
+
+        for locale in LOCALES {
+            for template in [Template::Immediate, Template::Digest, Template::Test] {
+                let (subject, body) = render(locale, template, &values);
+                assert!(!subject.is_empty() && !body.is_empty());
+                assert!(!subject.contains("{{") && !body.contains("{{"));
+            }
+        }
+    }
+}
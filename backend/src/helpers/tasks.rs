@@ -0,0 +1,173 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use tokio::sync::{mpsc, Mutex, MutexGuard};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Delay before restarting a worker which stopped or panicked
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// Background workers of the server. They are restarted when they stop or panic, and stopped on
+/// shutdown.
+pub struct BackgroundTasks {
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl BackgroundTasks {
+    pub fn new() -> Self {
+        Self {
+            shutdown: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+        }
+    }
+
+    /// Runs a worker which is simply dropped on shutdown
+    pub fn spawn<T, F, Fut>(&self, name: &'static str, context: T, worker: F)
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn_with_shutdown(name, context, move |context, shutdown| {
+            let work = worker(context);
+            async move {
+                tokio::select! {
+                    _ = work => {},
+                    _ = shutdown.cancelled() => {},
+                }
+            }
+        });
+    }
+
+    /// Runs a worker which is given the shutdown token, and must return once it is cancelled
+    /// and the pending work is done
+    pub fn spawn_with_shutdown<T, F, Fut>(&self, name: &'static str, context: T, worker: F)
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(T, CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+
+        self.tracker.spawn(async move {
+            loop {
+                let result = tokio::spawn(worker(context.clone(), shutdown.clone())).await;
+
+                if shutdown.is_cancelled() {
+                    break;
+                }
+
+                match result {
+                    Ok(()) => tracing::error!("Background task {} stopped, restarting", name),
+                    Err(e) => {
+                        tracing::error!("Background task {} failed, restarting: {:?}", name, e)
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(RESTART_DELAY) => {},
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        });
+    }
+
+    /// Stops the workers, waiting at most the given duration for them to finish their work
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.shutdown.cancel();
+        self.tracker.close();
+
+        if tokio::time::timeout(timeout, self.tracker.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!("Background tasks did not stop in time, their work may be lost");
+        }
+    }
+}
+
+/// Bounded queue of work for a background worker, which takes the receiver while it runs
+pub struct WorkQueue<T> {
+    sender: mpsc::Sender<T>,
+    receiver: Arc<Mutex<mpsc::Receiver<T>>>,
+}
+
+impl<T> Clone for WorkQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+impl<T> WorkQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Self {
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    /// Queues an item without waiting, returns false when the queue is full
+    pub fn push(&self, item: T) -> bool {
+        self.sender.try_send(item).is_ok()
+    }
+
+    pub async fn receiver(&self) -> MutexGuard<'_, mpsc::Receiver<T>> {
+        self.receiver.lock().await
+    }
+
+    /// Hands the queued items to `flush` by batches of at most `batch_size` items, at least at
+    /// every `interval`. On shutdown, the items still queued are flushed before returning.
+    pub async fn process_batches<F, Fut>(
+        &self,
+        batch_size: usize,
+        interval: Duration,
+        shutdown: CancellationToken,
+        mut flush: F,
+    ) where
+        F: FnMut(Vec<T>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut receiver = self.receiver().await;
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            tokio::select! {
+                item = receiver.recv() => match item {
+                    Some(item) => {
+                        batch.push(item);
+                        if batch.len() < batch_size {
+                            continue;
+                        }
+                    }
+                    None => break,
+                },
+                _ = interval.tick() => {},
+                _ = shutdown.cancelled() => break,
+            }
+
+            if !batch.is_empty() {
+                flush(std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(batch_size),
+                ))
+                .await;
+            }
+        }
+
+        receiver.close();
+        while let Some(item) = receiver.recv().await {
+            batch.push(item);
+            if batch.len() >= batch_size {
+                flush(std::mem::take(&mut batch)).await;
+            }
+        }
+        if !batch.is_empty() {
+            flush(batch).await;
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::{
+    trace::{Span, SpanKind, TraceError, Tracer, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    runtime,
+    trace::{self, Sampler, TracerProvider},
+    Resource,
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_opentelemetry::{OtelData, PreSampledTracer};
+use tracing_subscriber::{filter::Targets, layer::Context, registry::LookupSpan, Layer};
+
+use crate::config::Telemetry;
+
+/// Creates the layer exporting the traces to the configured collector. The tracer provider must
+/// be shut down before exiting to flush the pending spans.
+pub fn layer<S>(config: &Telemetry) -> Result<(impl Layer<S>, TracerProvider), TraceError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.endpoint)
+        .build()?;
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]))
+        .build();
+    let tracer = provider.tracer("safehaven");
+
+    let targets = Targets::new()
+        .with_target("safehaven", Level::INFO)
+        .with_target("tower_http", Level::INFO);
+
+    let spans = tracing_opentelemetry::layer()
+        .with_tracer(tracer.clone())
+        .with_filter(targets.clone());
+    // sqlx only logs the queries, the spans they are children of must also go through the filter
+    // to be found. The cache refreshes have their own spans, see `CacheRefreshStats::trace`.
+    let database_spans =
+        DatabaseSpans { tracer }.with_filter(targets.with_target("sqlx::query", Level::DEBUG));
+
+    Ok((spans.and_then(database_spans), provider))
+}
+
+/// Turns the events logged after the database queries into spans, as children of the current span
+struct DatabaseSpans {
+    tracer: trace::Tracer,
+}
+
+#[derive(Default)]
+struct DatabaseEventFields {
+    summary: Option<String>,
+    statement: Option<String>,
+    rows_affected: Option<u64>,
+    rows_returned: Option<u64>,
+    elapsed_secs: Option<f64>,
+}
+
+impl Visit for DatabaseEventFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "summary" => self.summary = Some(value.to_owned()),
+            "db.statement" => self.statement = Some(value.trim().to_owned()),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "rows_affected" => self.rows_affected = Some(value),
+            "rows_returned" => self.rows_returned = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+impl DatabaseSpans {
+    fn record_span<S>(
+        &self,
+        event: &Event<'_>,
+        ctx: Context<'_, S>,
+        name: String,
+        elapsed_secs: f64,
+        attributes: Vec<KeyValue>,
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        // The queries of the background tasks are not part of a trace
+        let Some(parent) = ctx.event_span(event).and_then(|span| {
+            span.extensions_mut()
+                .get_mut::<OtelData>()
+                .map(|data| self.tracer.sampled_context(data))
+        }) else {
+            return;
+        };
+
+        let end = SystemTime::now();
+        let start = end - Duration::from_secs_f64(elapsed_secs.max(0.));
+
+        let mut span = self
+            .tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Client)
+            .with_start_time(start)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &parent);
+        span.end_with_timestamp(end);
+    }
+}
+
+impl<S> Layer<S> for DatabaseSpans
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() != "sqlx::query" {
+            return;
+        }
+
+        let mut fields = DatabaseEventFields::default();
+        event.record(&mut fields);
+
+        let Some(summary) = fields.summary else {
+            return;
+        };
+        // The statement is only given when it is longer than its summary
+        let statement = fields
+            .statement
+            .filter(|statement| !statement.is_empty())
+            .unwrap_or_else(|| summary.clone());
+
+        let mut attributes = vec![
+            KeyValue::new("db.system", "postgresql"),
+            KeyValue::new("db.statement", statement),
+        ];
+        if let Some(rows_affected) = fields.rows_affected {
+            attributes.push(KeyValue::new("db.rows_affected", rows_affected as i64));
+        }
+        if let Some(rows_returned) = fields.rows_returned {
+            attributes.push(KeyValue::new("db.rows_returned", rows_returned as i64));
+        }
+
+        self.record_span(
+            event,
+            ctx,
+            summary,
+            fields.elapsed_secs.unwrap_or(0.),
+            attributes,
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::IpAddr;
+
+use sha2::{Digest, Sha256};
+
+/// Hash identifying a visitor for the day of the salt, the address and the user agent are never
+/// stored. The parts are separated by a NUL byte, which none of them can contain.
+pub fn visitor_hash(salt: &str, client_ip: IpAddr, user_agent: Option<&str>) -> String {
+    let hash = Sha256::new()
+        .chain_update(salt)
+        .chain_update([0])
+        .chain_update(client_ip.to_string())
+        .chain_update([0])
+        .chain_update(user_agent.unwrap_or_default())
+        .finalize();
+    format!("{:x}", hash)
+}
+
+/// Coarse class of the device from its user agent: bot, tablet, mobile, desktop or unknown
+pub fn device_class(user_agent: Option<&str>) -> &'static str {
+    let Some(user_agent) = user_agent.filter(|user_agent| !user_agent.is_empty()) else {
+        return "unknown";
+    };
+    let user_agent = user_agent.to_lowercase();
+
+    if ["bot", "crawler", "spider"]
+        .iter()
+        .any(|word| user_agent.contains(word))
+    {
+        "bot"
+    } else if user_agent.contains("ipad")
+        || user_agent.contains("tablet")
+        || (user_agent.contains("android") && !user_agent.contains("mobile"))
+    {
+        "tablet"
+    } else if user_agent.contains("mobi") || user_agent.contains("iphone") {
+        "mobile"
+    } else {
+        "desktop"
+    }
+}
+
+/// Primary language preferred by the client, from its Accept-Language header
+pub fn language(accept_language: Option<&str>) -> String {
+    accept_language
+        .and_then(|header| header.split(',').next())
+        .and_then(|tag| tag.split(';').next())
+        .and_then(|tag| tag.trim().split(['-', '_']).next())
+        .filter(|primary| (2..=3).contains(&primary.len()))
+        .filter(|primary| primary.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(|primary| primary.to_ascii_lowercase())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_the_parts_of_the_visitor_hash() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert_eq!(
+            visitor_hash("salt", ip, Some("agent")),
+            visitor_hash("salt", ip, Some("agent"))
+        );
+        assert_ne!(
+            visitor_hash("salt", ip, Some("1agent")),
+            visitor_hash("salt", "10.0.0.11".parse().unwrap(), Some("agent"))
+        );
+        assert_ne!(
+            visitor_hash("salt1", "0.0.0.1".parse().unwrap(), None),
+            visitor_hash("salt", ip, None)
+        );
+    }
+
+    #[test]
+    fn classifies_devices_and_languages() {
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile/15E148";
+        let tablet = "Mozilla/5.0 (Linux; Android 13; SM-X200) AppleWebKit/537.36";
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+        assert_eq!(device_class(Some(iphone)), "mobile");
+        assert_eq!(device_class(Some(tablet)), "tablet");
+        assert_eq!(device_class(Some(firefox)), "desktop");
+        assert_eq!(device_class(Some("Googlebot/2.1")), "bot");
+        assert_eq!(device_class(None), "unknown");
+
+        assert_eq!(language(Some("fr-FR,fr;q=0.9,en;q=0.8")), "fr");
+        assert_eq!(language(Some("EN")), "en");
+        assert_eq!(language(Some("*")), "unknown");
+        assert_eq!(language(None), "unknown");
+    }
+}
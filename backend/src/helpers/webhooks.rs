@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::models::webhook::DueDelivery;
+
+/// Time given to a webhook endpoint to answer
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn hmac_sha256_hex(key: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message.as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Signature of a payload, computed over `<timestamp>.<body>` so the receivers can reject replays
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!(
+        "sha256={}",
+        hmac_sha256_hex(secret, &format!("{}.{}", timestamp, body))
+    )
+}
+
+/// Posts the payload of a delivery to its webhook. Returns the HTTP status of the response if
+/// any, and an error unless the endpoint accepted the payload.
+pub async fn send(
+    client: &reqwest::Client,
+    delivery: &DueDelivery,
+) -> (Option<u16>, Option<String>) {
+    let body = delivery.payload.to_string();
+    let timestamp = Utc::now().timestamp();
+
+    let response = client
+        .post(&delivery.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-SafeHaven-Delivery", delivery.id.to_string())
+        .header(
+            "X-SafeHaven-Event",
+            serde_json::to_value(delivery.event)
+                .ok()
+                .and_then(|event| event.as_str().map(str::to_string))
+                .unwrap_or_default(),
+        )
+        .header("X-SafeHaven-Timestamp", timestamp.to_string())
+        .header(
+            "X-SafeHaven-Signature",
+            signature(&delivery.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+        Ok(response) => (
+            Some(response.status().as_u16()),
+            Some(format!("Unexpected response status {}", response.status())),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_matches_reference_vector() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hmac_sha256_hex("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            signature("Jefe", 42, "{}"),
+            format!("sha256={}", hmac_sha256_hex("Jefe", "42.{}"))
+        );
+    }
+}
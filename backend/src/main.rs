@@ -4,17 +4,21 @@ mod doc;
 mod helpers;
 mod models;
 
+use api::rate_limit::{rate_limit_middleware, Access, RateLimitedRoutes, RouteGroup};
 use api::AppState;
-use axum::{extract::MatchedPath, http::Request, Router};
-use clap::{arg, command, Args, Parser, Subcommand};
-use config::SafeHavenConfig;
+use axum::{extract::MatchedPath, http::Request, middleware, Router};
+use clap::{Args, Parser, Subcommand};
+use config::{RateLimitStore, SafeHavenConfig};
+use helpers::tasks::BackgroundTasks;
 use std::fs;
-use std::{process::exit, sync::Arc};
+use std::net::SocketAddr;
+use std::{process::exit, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 use tracing::info_span;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 use utoipa::OpenApi;
 
 #[derive(Parser)]
@@ -88,14 +92,49 @@ fn openapi(args: &OpenapiArgs) {
     tracing::info!("OpenAPI file saved to {}", args.path);
 }
 
-async fn build_server(app_state: AppState, config: Arc<SafeHavenConfig>) {
+async fn build_server(
+    app_state: AppState,
+    config: Arc<SafeHavenConfig>,
+    shutdown: CancellationToken,
+) {
     tracing::info!("Starting server at {}", config.listen_addr);
 
+    // The map routes are rate limited by themselves, after the authentication of the access token
+    let public_rate_limit = middleware::from_fn_with_state(
+        RateLimitedRoutes::new(&app_state, RouteGroup::Public, Access::ByMethod),
+        rate_limit_middleware,
+    );
+    let admin_rate_limit = middleware::from_fn_with_state(
+        RateLimitedRoutes::new(&app_state, RouteGroup::Admin, Access::ByMethod),
+        rate_limit_middleware,
+    );
+
     let mut app = Router::new()
-        .nest("/api/", api::root::routes())
-        .nest("/api/icons", api::icons::routes())
+        .nest("/health", api::health::routes())
+        .nest(
+            "/api/",
+            api::root::routes().route_layer(public_rate_limit.clone()),
+        )
+        .nest(
+            "/api/icons",
+            api::icons::routes().route_layer(public_rate_limit),
+        )
         .nest("/api/map", api::map::routes(&app_state))
-        .nest("/api/admin", api::admin::routes(&app_state))
+        .nest(
+            "/api/admin",
+            api::admin::routes(&app_state).route_layer(admin_rate_limit),
+        );
+
+    if config.metrics.enabled && config.metrics.listen_addr.is_none() {
+        app = app.merge(api::metrics::routes());
+        tracing::info!("Serving metrics on /metrics");
+    }
+
+    let mut app = app
+        .route_layer(middleware::from_fn_with_state(
+            app_state.metrics.clone(),
+            api::metrics::track_requests,
+        ))
         .with_state(app_state)
         .layer(
             TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
@@ -109,6 +148,9 @@ async fn build_server(app_state: AppState, config: Arc<SafeHavenConfig>) {
                     method = ?request.method(),
                     matched_path,
                     some_other_field = tracing::field::Empty,
+                    // Name and kind of the span in the exported traces
+                    otel.name = format!("{} {}", request.method(), matched_path.unwrap_or("")).trim_end(),
+                    otel.kind = "server",
                 )
             }),
         );
@@ -126,12 +168,88 @@ async fn build_server(app_state: AppState, config: Arc<SafeHavenConfig>) {
 
     let listener = TcpListener::bind(&config.listen_addr).await.unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
+    .await
+    .unwrap();
+}
+
+/// Serves the metrics on their own address, if configured
+async fn build_metrics_server(
+    app_state: AppState,
+    config: Arc<SafeHavenConfig>,
+    shutdown: CancellationToken,
+) {
+    let Some(listen_addr) = config
+        .metrics
+        .listen_addr
+        .as_ref()
+        .filter(|_| config.metrics.enabled)
+    else {
+        return;
+    };
+
+    tracing::info!("Serving metrics at {}/metrics", listen_addr);
+
+    let app = api::metrics::routes().with_state(app_state);
+    let listener = TcpListener::bind(listen_addr).await.unwrap();
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .unwrap();
+}
+
+/// Cancels the token on SIGTERM or Ctrl+C
+async fn wait_for_shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("can't listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("can't listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    shutdown.cancel();
 }
 
 async fn serve(args: &ServeArgs) {
+    // Loaded first as the tracing depends on it
+    let config = config::load(args.config.as_str()).unwrap_or_else(|e| {
+        eprintln!("Cannot load configuration: {}", e);
+        exit(1);
+    });
+
+    let (telemetry_layer, tracer_provider) = if config.telemetry.enabled {
+        let (layer, provider) = helpers::telemetry::layer(&config.telemetry).unwrap_or_else(|e| {
+            eprintln!("Cannot set up the traces export: {}", e);
+            exit(1);
+        });
+        (Some(layer), Some(provider))
+    } else {
+        (None, None)
+    };
+
     tracing_subscriber::registry()
-        .with(
+        .with(telemetry_layer)
+        .with(tracing_subscriber::fmt::layer().with_filter(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
                 if cfg!(debug_assertions) {
                     // Development build
@@ -142,23 +260,91 @@ async fn serve(args: &ServeArgs) {
                 }
                 .into()
             }),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        ))
         .init();
 
-    let config = config::load(args.config.as_str()).unwrap_or_else(|e| {
-        tracing::error!("Cannot load configuration: {}", e);
-        exit(1);
-    });
+    if config.telemetry.enabled {
+        tracing::info!("Exporting traces to {}", config.telemetry.endpoint);
+    }
 
     let config = Arc::new(config);
     let app_state = AppState::from_config(config.clone()).await;
 
-    let server = build_server(app_state.clone(), config);
-    let db_notifier = app_state.listen_postgresql_events();
+    let tasks = BackgroundTasks::new();
+    tasks.spawn("db_notifier", app_state.clone(), |state| async move {
+        state.listen_postgresql_events().await
+    });
+    tasks.spawn("rejected_purger", app_state.clone(), |state| async move {
+        state.purge_rejected_submissions().await
+    });
+    tasks.spawn("presets_purger", app_state.clone(), |state| async move {
+        state.purge_unused_presets().await
+    });
+    tasks.spawn(
+        "webhooks_deliverer",
+        app_state.clone(),
+        |state| async move { state.deliver_webhooks().await },
+    );
+    tasks.spawn(
+        "notifications_sender",
+        app_state.clone(),
+        |state| async move { state.send_notifications().await },
+    );
+    tasks.spawn("visits_rollup", app_state.clone(), |state| async move {
+        state.rollup_visits().await
+    });
+    if matches!(config.rate_limit.store, RateLimitStore::Memory) {
+        tasks.spawn(
+            "rate_limit_sweeper",
+            app_state.clone(),
+            |state| async move { state.rate_limiter.sweep_memory_buckets().await },
+        );
+    }
+    tasks.spawn_with_shutdown(
+        "visits_recorder",
+        app_state.clone(),
+        |state, shutdown| async move { state.register_visits(shutdown).await },
+    );
+    tasks.spawn_with_shutdown(
+        "views_recorder",
+        app_state.clone(),
+        |state, shutdown| async move { state.register_views(shutdown).await },
+    );
+    tasks.spawn_with_shutdown(
+        "searches_recorder",
+        app_state.clone(),
+        |state, shutdown| async move { state.register_searches(shutdown).await },
+    );
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(wait_for_shutdown_signal(shutdown.clone()));
 
+    let servers = async {
+        tokio::join!(
+            build_server(app_state.clone(), config.clone(), shutdown.clone()),
+            build_metrics_server(app_state.clone(), config.clone(), shutdown.clone()),
+        )
+    };
+    tokio::pin!(servers);
+
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
     tokio::select! {
-        _ = server => {},
-        _ = db_notifier => {},
+        biased;
+        _ = shutdown.cancelled() => {
+            tracing::info!("Shutting down, waiting for the requests in flight");
+            if tokio::time::timeout(shutdown_timeout, &mut servers).await.is_err() {
+                tracing::warn!("Requests still in flight after {:?}, dropping them", shutdown_timeout);
+            }
+        },
+        _ = &mut servers => tracing::error!("Server stopped unexpectedly"),
+    }
+
+    tracing::info!("Stopping the background tasks");
+    tasks.shutdown(shutdown_timeout).await;
+
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            tracing::error!("Cannot flush the traces: {}", e);
+        }
     }
 }
@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, HashMap};
 
 use crate::{api::AppError, helpers::postgis_polygons::MultiPolygon};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, to_value};
 use sqlx::{
@@ -47,6 +48,14 @@ pub struct Permissions {
 
     /// Permission to add a comment to an entity
     pub can_add_comment: bool,
+
+    /// Permission to suggest a correction of an entity, reviewed by the moderators
+    #[serde(default)]
+    pub can_suggest_correction: bool,
+
+    /// Permission to report an entity to the moderators
+    #[serde(default)]
+    pub can_report: bool,
 }
 
 #[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
@@ -82,6 +91,18 @@ pub struct AccessTokenStats {
     pub visits_30_days: BTreeMap<String, u32>,
 }
 
+/// Visit of the map through an access token, buffered to be registered in batches
+#[derive(Debug)]
+pub struct Visit {
+    pub token_id: Uuid,
+    pub visited_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    /// Salted hash of the visitor, see `helpers::visitors::visitor_hash`
+    pub visitor_hash: String,
+    pub device_class: &'static str,
+    pub language: String,
+}
+
 impl AccessToken {
     pub async fn new(
         access_token: NewOrUpdateAccessToken,
@@ -133,7 +154,10 @@ impl AccessToken {
                 token,
                 permissions AS "permissions: Json<Permissions>",
                 active,
-                (SELECT COUNT(*) FROM access_tokens_visits WHERE token_id = id AND visited_at > NOW() - INTERVAL '1 week') AS "last_week_visits!"
+                (
+                    SELECT COALESCE(SUM(visits), 0)::bigint FROM access_tokens_daily_visits
+                    WHERE token_id = id AND visit_date >= CURRENT_DATE - 7
+                ) AS "last_week_visits!"
             "#,
             given_id,
             update.title,
@@ -198,7 +222,10 @@ impl AccessToken {
                 token,
                 permissions AS "permissions: Json<Permissions>",
                 active,
-                (SELECT COUNT(*) FROM access_tokens_visits WHERE token_id = id AND visited_at > NOW() - INTERVAL '1 week') AS "last_week_visits!"
+                (
+                    SELECT COALESCE(SUM(visits), 0)::bigint FROM access_tokens_daily_visits
+                    WHERE token_id = id AND visit_date >= CURRENT_DATE - 7
+                ) AS "last_week_visits!"
             FROM access_tokens
             WHERE id = $1
             "#,
@@ -219,7 +246,10 @@ impl AccessToken {
                 token,
                 permissions AS "permissions: Json<Permissions>",
                 active,
-                (SELECT COUNT(*) FROM access_tokens_visits WHERE token_id = id AND visited_at > NOW() - INTERVAL '1 week') AS "last_week_visits!"
+                (
+                    SELECT COALESCE(SUM(visits), 0)::bigint FROM access_tokens_daily_visits
+                    WHERE token_id = id AND visit_date >= CURRENT_DATE - 7
+                ) AS "last_week_visits!"
             FROM access_tokens
             "#
         )
@@ -228,18 +258,42 @@ impl AccessToken {
         .map_err(AppError::Database)
     }
 
-    pub async fn register_visit(
-        access_token_id: Uuid,
-        referrer: Option<String>,
+    pub async fn register_visits(
+        visits: &[Visit],
         conn: &mut PgConnection,
     ) -> Result<(), AppError> {
+        let tokens_ids: Vec<Uuid> = visits.iter().map(|visit| visit.token_id).collect();
+        let visited_at: Vec<DateTime<Utc>> = visits.iter().map(|visit| visit.visited_at).collect();
+        let referrers: Vec<Option<String>> =
+            visits.iter().map(|visit| visit.referrer.clone()).collect();
+        let visitors_hashes: Vec<String> = visits
+            .iter()
+            .map(|visit| visit.visitor_hash.clone())
+            .collect();
+        let devices_classes: Vec<String> = visits
+            .iter()
+            .map(|visit| visit.device_class.to_string())
+            .collect();
+        let languages: Vec<String> = visits.iter().map(|visit| visit.language.clone()).collect();
+
+        // The tokens deleted since the visits are skipped
         sqlx::query!(
             r#"
-            INSERT INTO access_tokens_visits (token_id, referrer)
-            VALUES ($1, $2)
+            INSERT INTO access_tokens_visits
+                (token_id, visited_at, referrer, visitor_hash, device_class, language)
+            SELECT
+                v.token_id, v.visited_at::timestamp, v.referrer,
+                v.visitor_hash, v.device_class, v.language
+            FROM UNNEST($1::uuid[], $2::timestamptz[], $3::text[], $4::text[], $5::text[], $6::text[])
+                AS v(token_id, visited_at, referrer, visitor_hash, device_class, language)
+            WHERE EXISTS (SELECT 1 FROM access_tokens WHERE id = v.token_id)
             "#,
-            access_token_id,
-            referrer
+            &tokens_ids,
+            &visited_at,
+            &referrers as &[Option<String>],
+            &visitors_hashes,
+            &devices_classes,
+            &languages
         )
         .execute(conn)
         .await
@@ -247,17 +301,29 @@ impl AccessToken {
         Ok(())
     }
 
+    /// Counts the visits of the days over, and deletes those older than the retention period
+    pub async fn rollup_visits(
+        retention_days: i32,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        sqlx::query!("SELECT rollup_access_tokens_visits($1)", retention_days)
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)?;
+        Ok(())
+    }
+
     pub async fn get_stats(
         access_token_id: Uuid,
         conn: &mut PgConnection,
     ) -> Result<AccessTokenStats, AppError> {
         let result = sqlx::query!(
-                r#"
+            r#"
                 SELECT
                 COALESCE((
                     WITH origins AS (
-                        SELECT DISTINCT(COALESCE(referrer, 'unknown')) AS referrer, COUNT(*) AS total
-                        FROM access_tokens_visits
+                        SELECT referrer, SUM(visits) AS total
+                        FROM access_tokens_daily_visits
                         WHERE token_id = $1
                         GROUP BY referrer
                     )
@@ -274,14 +340,14 @@ impl AccessToken {
                     aggregated_visits AS (
                         SELECT
                             ds.visit_date,
-                            COALESCE(COUNT(atv.visited_at), 0) AS visit_count
+                            COALESCE(SUM(dv.visits), 0) AS visit_count
                         FROM
                             date_series ds
                         LEFT JOIN
-                            access_tokens_visits atv
+                            access_tokens_daily_visits dv
                         ON
-                            ds.visit_date = DATE(atv.visited_at) 
-                            AND atv.token_id = $1
+                            ds.visit_date = dv.visit_date
+                            AND dv.token_id = $1
                         GROUP BY
                             ds.visit_date
                         ORDER BY
@@ -294,11 +360,11 @@ impl AccessToken {
                     FROM aggregated_visits
                 ) AS "visits_30_days: JsonValue"
                 "#,
-                access_token_id
-            )
-            .fetch_one(conn)
-            .await
-            .map_err(AppError::Database)?;
+            access_token_id
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)?;
 
         // Provide a default empty object if origins is None
         let origins_json: JsonValue = result.origins.unwrap_or_else(|| json!({}));
@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use chrono::{NaiveDate, Utc};
+use serde_json::Value;
+use sqlx::PgConnection;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::api::AppError;
+
+/// Salt of the visitors hashes of the current day, shared by the instances through the database
+#[derive(Clone, Default)]
+pub struct VisitorsSalt {
+    current: Arc<Mutex<Option<(NaiveDate, String)>>>,
+}
+
+impl VisitorsSalt {
+    pub async fn get(&self, conn: &mut PgConnection) -> Result<String, AppError> {
+        let today = Utc::now().date_naive();
+        let mut current = self.current.lock().await;
+
+        if let Some((date, salt)) = current.as_ref() {
+            if *date == today {
+                return Ok(salt.clone());
+            }
+        }
+
+        // The salts are pruned here so they follow the same clock as the one choosing them. The
+        // visits of the previous day may still be buffered by the instances.
+        let salt = sqlx::query_scalar!(
+            r#"
+            WITH pruned AS (
+                DELETE FROM visitors_salts WHERE salt_date < $1::date - 1
+            )
+            INSERT INTO visitors_salts (salt_date) VALUES ($1)
+            ON CONFLICT (salt_date) DO UPDATE SET salt = visitors_salts.salt
+            RETURNING salt
+            "#,
+            today
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        *current = Some((today, salt.clone()));
+        Ok(salt)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ViewKind {
+    /// The map of the family was browsed, counted once a day per visitor
+    Map,
+    /// An entity of the family was opened
+    Entity,
+}
+
+/// View of a family and some of its categories, buffered to be counted in batches
+#[derive(Debug)]
+pub struct View {
+    pub kind: ViewKind,
+    pub viewed_on: NaiveDate,
+    /// Visitor of the map views, see `helpers::visitors::visitor_hash`
+    pub visitor_hash: Option<String>,
+    pub family_id: Uuid,
+    pub categories_ids: Vec<Uuid>,
+}
+
+/// Families and categories whose map was seen by a visitor, forgotten at once past this size
+const MAX_SEEN_MAP_VIEWS: usize = 100_000;
+
+/// Map and entity views counted in memory by day, until they are registered
+#[derive(Default)]
+pub struct ViewsCounts {
+    families: HashMap<(Uuid, NaiveDate), (i64, i64)>,
+    categories: HashMap<(Uuid, NaiveDate), (i64, i64)>,
+    /// Families and categories whose map was already counted for a visitor, kept for the day
+    seen_map_views: HashSet<(NaiveDate, String, Uuid)>,
+}
+
+fn count(counts: &mut (i64, i64), kind: ViewKind) {
+    match kind {
+        ViewKind::Map => counts.0 += 1,
+        ViewKind::Entity => counts.1 += 1,
+    }
+}
+
+/// Flattens the counts into the arrays of the registering queries
+fn unzip_counts(
+    counts: &HashMap<(Uuid, NaiveDate), (i64, i64)>,
+) -> (Vec<Uuid>, Vec<NaiveDate>, Vec<i64>, Vec<i64>) {
+    let mut columns = (vec![], vec![], vec![], vec![]);
+    for ((id, date), (map_views, entity_views)) in counts {
+        columns.0.push(*id);
+        columns.1.push(*date);
+        columns.2.push(*map_views);
+        columns.3.push(*entity_views);
+    }
+    columns
+}
+
+impl ViewsCounts {
+    /// Whether the view of the family or category is to be counted, the map is only counted the
+    /// first time a visitor browses it in the day
+    fn is_new(&mut self, view: &View, id: Uuid) -> bool {
+        let (ViewKind::Map, Some(visitor_hash)) = (view.kind, &view.visitor_hash) else {
+            return true;
+        };
+
+        if self.seen_map_views.len() >= MAX_SEEN_MAP_VIEWS {
+            self.seen_map_views.clear();
+        }
+        self.seen_map_views
+            .insert((view.viewed_on, visitor_hash.clone(), id))
+    }
+
+    pub fn add(&mut self, view: View) {
+        if self.is_new(&view, view.family_id) {
+            count(
+                self.families
+                    .entry((view.family_id, view.viewed_on))
+                    .or_default(),
+                view.kind,
+            );
+        }
+        for &category_id in &view.categories_ids {
+            if self.is_new(&view, category_id) {
+                count(
+                    self.categories
+                        .entry((category_id, view.viewed_on))
+                        .or_default(),
+                    view.kind,
+                );
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty() && self.categories.is_empty()
+    }
+
+    /// Forgets the registered counts, and the map views seen before the day
+    pub fn clear(&mut self, today: NaiveDate) {
+        self.families.clear();
+        self.categories.clear();
+        self.seen_map_views.retain(|(date, _, _)| *date >= today);
+    }
+
+    /// Adds the counts to the daily views, the families and categories deleted since are skipped
+    pub async fn register(&self, conn: &mut PgConnection) -> Result<(), AppError> {
+        let (ids, dates, map_views, entity_views) = unzip_counts(&self.families);
+        sqlx::query!(
+            r#"
+            INSERT INTO families_views_daily (family_id, view_date, map_views, entity_views)
+            SELECT v.id, v.view_date, v.map_views, v.entity_views
+            FROM UNNEST($1::uuid[], $2::date[], $3::bigint[], $4::bigint[])
+                AS v(id, view_date, map_views, entity_views)
+            WHERE EXISTS (SELECT 1 FROM families WHERE id = v.id)
+            ON CONFLICT (family_id, view_date) DO UPDATE SET
+                map_views = families_views_daily.map_views + EXCLUDED.map_views,
+                entity_views = families_views_daily.entity_views + EXCLUDED.entity_views
+            "#,
+            &ids,
+            &dates,
+            &map_views,
+            &entity_views
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        let (ids, dates, map_views, entity_views) = unzip_counts(&self.categories);
+        sqlx::query!(
+            r#"
+            INSERT INTO categories_views_daily (category_id, view_date, map_views, entity_views)
+            SELECT v.id, v.view_date, v.map_views, v.entity_views
+            FROM UNNEST($1::uuid[], $2::date[], $3::bigint[], $4::bigint[])
+                AS v(id, view_date, map_views, entity_views)
+            WHERE EXISTS (SELECT 1 FROM categories WHERE id = v.id)
+            ON CONFLICT (category_id, view_date) DO UPDATE SET
+                map_views = categories_views_daily.map_views + EXCLUDED.map_views,
+                entity_views = categories_views_daily.entity_views + EXCLUDED.entity_views
+            "#,
+            &ids,
+            &dates,
+            &map_views,
+            &entity_views
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// Search of the map, logged without the access token nor anything about the visitor
+#[derive(Debug)]
+pub struct LoggedSearch {
+    pub family_id: Uuid,
+    pub query: String,
+    /// Categories, tags and constraints the search was restricted to
+    pub filters: Value,
+    pub results_count: i64,
+}
+
+impl LoggedSearch {
+    /// Logs the searches with their normalised queries, and forgets those older than the
+    /// retention period. The families deleted since are skipped.
+    pub async fn register(
+        searches: &[LoggedSearch],
+        retention_days: i32,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        let families_ids: Vec<Uuid> = searches.iter().map(|search| search.family_id).collect();
+        let queries: Vec<String> = searches.iter().map(|search| search.query.clone()).collect();
+        let filters: Vec<Value> = searches
+            .iter()
+            .map(|search| search.filters.clone())
+            .collect();
+        let results_counts: Vec<i64> = searches.iter().map(|search| search.results_count).collect();
+
+        sqlx::query!(
+            r#"
+            WITH pruned AS (
+                DELETE FROM searches_log
+                WHERE searched_at < NOW() - make_interval(days => $5)
+            )
+            INSERT INTO searches_log (family_id, query, filters, results_count)
+            SELECT s.family_id, normalise_search_query(s.query), s.filters, s.results_count
+            FROM UNNEST($1::uuid[], $2::text[], $3::jsonb[], $4::bigint[])
+                AS s(family_id, query, filters, results_count)
+            WHERE EXISTS (SELECT 1 FROM families WHERE id = s.family_id)
+            "#,
+            &families_ids,
+            &queries,
+            &filters,
+            &results_counts,
+            retention_days
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY_ID: Uuid = Uuid::from_u128(1);
+    const FIRST_CATEGORY_ID: Uuid = Uuid::from_u128(2);
+    const SECOND_CATEGORY_ID: Uuid = Uuid::from_u128(3);
+
+    fn view(kind: ViewKind, visitor: &str, day: u32, categories_ids: &[Uuid]) -> View {
+        View {
+            kind,
+            viewed_on: NaiveDate::from_ymd_opt(2024, 6, day).unwrap(),
+            visitor_hash: Some(visitor.to_string()),
+            family_id: FAMILY_ID,
+            categories_ids: categories_ids.to_vec(),
+        }
+    }
+
+    #[test]
+    fn counts_map_views_once_a_day_per_visitor() {
+        let mut counts = ViewsCounts::default();
+        let first_day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+
+        counts.add(view(ViewKind::Map, "first", 1, &[FIRST_CATEGORY_ID]));
+        counts.add(view(ViewKind::Map, "first", 1, &[FIRST_CATEGORY_ID]));
+        counts.add(view(
+            ViewKind::Map,
+            "first",
+            1,
+            &[FIRST_CATEGORY_ID, SECOND_CATEGORY_ID],
+        ));
+        counts.add(view(ViewKind::Map, "second", 1, &[FIRST_CATEGORY_ID]));
+        counts.add(view(ViewKind::Entity, "first", 1, &[FIRST_CATEGORY_ID]));
+        counts.add(view(ViewKind::Entity, "first", 1, &[FIRST_CATEGORY_ID]));
+
+        assert_eq!(counts.families[&(FAMILY_ID, first_day)], (2, 2));
+        assert_eq!(counts.categories[&(FIRST_CATEGORY_ID, first_day)], (2, 2));
+        assert_eq!(counts.categories[&(SECOND_CATEGORY_ID, first_day)], (1, 0));
+
+        // The visitors are still known once the counts are registered, until the next day
+        counts.clear(first_day);
+        counts.add(view(ViewKind::Map, "first", 1, &[FIRST_CATEGORY_ID]));
+        assert!(counts.is_empty());
+
+        let second_day = NaiveDate::from_ymd_opt(2024, 6, 2).unwrap();
+        counts.clear(second_day);
+        counts.add(view(ViewKind::Map, "first", 2, &[FIRST_CATEGORY_ID]));
+        assert_eq!(counts.families[&(FAMILY_ID, second_day)], (1, 0));
+        assert!(counts
+            .seen_map_views
+            .iter()
+            .all(|(date, _, _)| *date == second_day));
+    }
+}
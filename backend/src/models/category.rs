@@ -1,4 +1,5 @@
 use crate::api::AppError;
+use crate::models::entity_cache::CacheRefreshStats;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use utoipa::ToSchema;
@@ -31,30 +32,33 @@ impl Category {
         category: NewOrUpdateCategory,
         conn: &mut PgConnection,
     ) -> Result<Category, AppError> {
-        sqlx::query_as!(
-            Category,
-            r#"
-            INSERT INTO categories (title, family_id, default_status, fill_color, border_color)
-            VALUES ($1, $2, $3, $4, $5)
-            RETURNING
-                id,
-                title,
-                family_id,
-                default_status,
-                (SELECT hash FROM icons WHERE id = icon_id) AS icon_hash,
-                fill_color,
-                border_color,
-                version
-            "#,
-            category.title,
-            category.family_id,
-            category.default_status,
-            category.fill_color,
-            category.border_color
-        )
-        .fetch_one(conn)
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                Category,
+                r#"
+                INSERT INTO categories (title, family_id, default_status, fill_color, border_color)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING
+                    id,
+                    title,
+                    family_id,
+                    default_status,
+                    (SELECT hash FROM icons WHERE id = icon_id) AS icon_hash,
+                    fill_color,
+                    border_color,
+                    version
+                "#,
+                category.title,
+                category.family_id,
+                category.default_status,
+                category.fill_color,
+                category.border_color
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
         .await
-        .map_err(AppError::Database)
     }
 
     pub async fn get(given_id: Uuid, conn: &mut PgConnection) -> Result<Category, AppError> {
@@ -89,46 +93,52 @@ impl Category {
             return Err(AppError::Validation("Version is required".to_string()));
         }
 
-        sqlx::query_as!(
-            Category,
-            r#"
-            UPDATE categories
-            SET title = $2, family_id = $3, default_status = $4, fill_color = $5, border_color = $6, version = $7
-            WHERE id = $1
-            RETURNING
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                Category,
+                r#"
+                UPDATE categories
+                SET title = $2, family_id = $3, default_status = $4, fill_color = $5, border_color = $6, version = $7
+                WHERE id = $1
+                RETURNING
+                    id,
+                    title,
+                    family_id,
+                    default_status,
+                    (SELECT hash FROM icons WHERE id = icon_id) AS icon_hash,
+                    fill_color,
+                    border_color,
+                    version
+                "#,
                 id,
-                title,
-                family_id,
-                default_status,
-                (SELECT hash FROM icons WHERE id = icon_id) AS icon_hash,
-                fill_color,
-                border_color,
-                version
-            "#,
-            id,
-            update.title,
-            update.family_id,
-            update.default_status,
-            update.fill_color,
-            update.border_color,
-            update.version
-        )
-        .fetch_one(conn)
+                update.title,
+                update.family_id,
+                update.default_status,
+                update.fill_color,
+                update.border_color,
+                update.version
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
         .await
-        .map_err(AppError::Database)
     }
 
     pub async fn delete(given_id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"
-            DELETE FROM categories
-            WHERE id = $1
-            "#,
-            given_id
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                DELETE FROM categories
+                WHERE id = $1
+                "#,
+                given_id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
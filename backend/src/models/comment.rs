@@ -1,8 +1,11 @@
 use super::family::{Family, Form};
 use crate::api::AppError;
+use crate::models::moderation::ModerationStatus;
+use crate::models::spam::{SpamAssessment, SPAM_REJECTION_REASON};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::{FromRow, PgConnection};
+use sqlx::{types::Json, FromRow, PgConnection};
+use std::collections::HashMap;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
@@ -13,18 +16,145 @@ pub struct PublicNewComment {
     pub text: String,
     pub data: Value,
     pub entity_category_id: Uuid,
+    /// Comment replied to
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
 }
 
 #[derive(FromRow, Deserialize, Serialize, ToSchema, Debug)]
 pub struct PublicComment {
     pub id: Uuid,
+    /// Comment replied to
+    pub parent_id: Option<Uuid>,
     pub author: String,
     pub text: String,
     pub data: Value,
+    /// Response of the moderators or of the organisation running the map
+    pub is_official: bool,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
 }
 
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentsOrder {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+#[serde(default)]
+pub struct CommentsRequest {
+    pub page: i64,
+    /// Threads per page, at most 100
+    pub page_size: i64,
+    /// Order of the threads, replies are always listed oldest first
+    pub order: CommentsOrder,
+}
+
+impl Default for CommentsRequest {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            page_size: 20,
+            order: CommentsOrder::OldestFirst,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct PublicCommentsWithPagination {
+    /// Comments of the threads of the page, each top-level comment followed by its replies
+    pub comments: Vec<PublicComment>,
+    /// Number of threads
+    pub total_results: i64,
+    pub total_pages: i64,
+    pub response_current_page: i64,
+}
+
+/// Aggregate of a DiscreteScore field of the comment form over the moderated comments of an entity
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
+pub struct ScoreAggregate {
+    pub mean: f64,
+    /// Number of comments giving a score
+    pub count: i64,
+    /// Number of comments per given score
+    pub distribution: HashMap<String, i64>,
+}
+
+struct PaginatedPublicComment {
+    id: Uuid,
+    parent_id: Option<Uuid>,
+    author: String,
+    text: String,
+    data: Value,
+    is_official: bool,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+    total_results: i64,
+}
+
+/// The replied comment must belong to the same entity, and be visible for public replies. When
+/// an existing comment is updated, it can neither reply to itself nor to one of its replies.
+async fn check_parent(
+    comment_id: Option<Uuid>,
+    parent_id: Option<Uuid>,
+    entity_id: Uuid,
+    require_moderated: bool,
+    conn: &mut PgConnection,
+) -> Result<(), AppError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+
+    if let Some(comment_id) = comment_id {
+        // UNION stops on cycles that would already exist
+        let creates_cycle = sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT id, parent_id FROM comments WHERE id = $1
+                UNION
+                SELECT c.id, c.parent_id
+                FROM comments c
+                JOIN ancestors a ON c.id = a.parent_id
+            )
+            SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = $2) AS "creates_cycle!"
+            "#,
+            parent_id,
+            comment_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        if parent_id == comment_id || creates_cycle {
+            return Err(AppError::Validation(
+                "A comment cannot reply to itself or to one of its replies".to_string(),
+            ));
+        }
+    }
+
+    let parent = sqlx::query!(
+        r#"SELECT entity_id, moderated FROM comments WHERE id = $1"#,
+        parent_id
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    match parent {
+        Some(parent)
+            if parent.entity_id == entity_id && (parent.moderated || !require_moderated) =>
+        {
+            Ok(())
+        }
+        _ => Err(AppError::Validation(
+            "The replied comment does not belong to the entity".to_string(),
+        )),
+    }
+}
+
 impl PublicComment {
     /// Remove all data that is not user_facing from the data object using the comment_form
     pub fn cleanup_data(&mut self, comment_form: &Form) {
@@ -43,56 +173,153 @@ impl PublicComment {
 
     pub async fn new(
         comment: PublicNewComment,
+        spam_assessment: Option<SpamAssessment>,
         conn: &mut PgConnection,
     ) -> Result<PublicComment, AppError> {
         let family = Family::get_from_entity(comment.entity_id, conn).await?;
         family
             .comment_form
             .validate_data(&comment.data, comment.entity_category_id)?;
+        check_parent(None, comment.parent_id, comment.entity_id, true, conn).await?;
+        let (spam_score, spam_reasons, rejected) = match spam_assessment {
+            Some(assessment) => (
+                Some(assessment.score),
+                assessment.reasons,
+                assessment.rejected,
+            ),
+            None => (None, vec![], false),
+        };
+        // Rejected submissions are kept until the end of the appeal period, like the items
+        // rejected by the moderators
+        let (moderation_status, moderation_reason) = if rejected {
+            (ModerationStatus::Rejected, Some(SPAM_REJECTION_REASON))
+        } else {
+            (ModerationStatus::Pending, None)
+        };
 
         sqlx::query_as!(
             PublicComment,
             r#"
-            INSERT INTO comments (entity_id, author, text, data)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, author, text, data, created_at, updated_at
+            INSERT INTO comments (entity_id, author, text, data, spam_score, spam_reasons, parent_id, moderation_status, moderation_reason)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, parent_id, author, text, data, is_official, created_at, updated_at
             "#,
             comment.entity_id,
             comment.author,
             comment.text,
-            comment.data
+            comment.data,
+            spam_score,
+            &spam_reasons,
+            comment.parent_id,
+            moderation_status as ModerationStatus,
+            moderation_reason
         )
         .fetch_one(conn)
         .await
         .map_err(AppError::Database)
     }
 
+    /// Lists a page of the visible threads of an entity
     pub async fn list_for_public_entity(
         given_entity_id: Uuid,
         comment_form: &Form,
+        request: &CommentsRequest,
         conn: &mut PgConnection,
-    ) -> Result<Vec<PublicComment>, AppError> {
-        let result = sqlx::query_as!(
-            PublicComment,
+    ) -> Result<PublicCommentsWithPagination, AppError> {
+        if request.page < 1 || !(1..=100).contains(&request.page_size) {
+            return Err(AppError::InvalidPagination);
+        }
+
+        let results = sqlx::query_as!(
+            PaginatedPublicComment,
             r#"
-            SELECT id, author, text, data, created_at, updated_at
-            FROM comments 
-            WHERE entity_id = $1 AND moderated
-            ORDER BY created_at
+            WITH RECURSIVE roots AS (
+                SELECT id, position, total_results
+                FROM (
+                    SELECT
+                        id,
+                        ROW_NUMBER() OVER (
+                            ORDER BY CASE WHEN $2 THEN created_at END DESC, created_at, id
+                        ) AS position,
+                        COUNT(*) OVER () AS total_results
+                    FROM comments
+                    WHERE entity_id = $1 AND moderated AND parent_id IS NULL
+                ) ranked
+                ORDER BY position
+                LIMIT $4::bigint OFFSET ($3::bigint - 1) * $4::bigint
+            ),
+            threads AS (
+                SELECT c.id, r.position, ARRAY[c.created_at] AS path, r.total_results
+                FROM roots r
+                INNER JOIN comments c ON c.id = r.id
+                UNION ALL
+                SELECT c.id, t.position, t.path || c.created_at, t.total_results
+                FROM threads t
+                INNER JOIN comments c ON c.parent_id = t.id
+                WHERE c.moderated
+            )
+            SELECT c.id, c.parent_id, c.author, c.text, c.data, c.is_official, c.created_at,
+                c.updated_at, t.total_results AS "total_results!"
+            FROM threads t
+            INNER JOIN comments c ON c.id = t.id
+            ORDER BY t.position, t.path
             "#,
-            given_entity_id
+            given_entity_id,
+            request.order == CommentsOrder::NewestFirst,
+            request.page,
+            request.page_size
         )
         .fetch_all(conn)
         .await
-        .map_err(AppError::Database)?
-        .into_iter()
-        .map(|mut comment| {
-            comment.cleanup_data(comment_form);
-            comment
+        .map_err(AppError::Database)?;
+
+        let total_results = results.first().map_or(0, |c| c.total_results);
+        let comments = results
+            .into_iter()
+            .map(|c| {
+                let mut comment = PublicComment {
+                    id: c.id,
+                    parent_id: c.parent_id,
+                    author: c.author,
+                    text: c.text,
+                    data: c.data,
+                    is_official: c.is_official,
+                    created_at: c.created_at,
+                    updated_at: c.updated_at,
+                };
+                comment.cleanup_data(comment_form);
+                comment
+            })
+            .collect();
+
+        Ok(PublicCommentsWithPagination {
+            comments,
+            total_results,
+            total_pages: (total_results + request.page_size - 1) / request.page_size,
+            response_current_page: request.page,
         })
-        .collect();
+    }
+}
+
+impl ScoreAggregate {
+    /// Aggregates of the user facing score fields of the entity, by field key
+    pub async fn for_entity(
+        entity_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<HashMap<String, ScoreAggregate>, AppError> {
+        let scores = sqlx::query_scalar!(
+            r#"
+            SELECT scores AS "scores!: Json<HashMap<String, ScoreAggregate>>"
+            FROM entities_comment_scores
+            WHERE entity_id = $1
+            "#,
+            entity_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map_err(AppError::Database)?;
 
-        Ok(result)
+        Ok(scores.map(|scores| scores.0).unwrap_or_default())
     }
 }
 
@@ -100,6 +327,8 @@ impl PublicComment {
 pub struct AdminComment {
     pub id: Uuid,
     pub entity_id: Uuid,
+    /// Comment replied to
+    pub parent_id: Option<Uuid>,
     pub author: String,
     pub text: String,
     pub data: Value,
@@ -107,6 +336,8 @@ pub struct AdminComment {
     pub updated_at: chrono::NaiveDateTime,
     pub moderated: bool,
     pub version: i32,
+    /// Response of the moderators or of the organisation running the map
+    pub is_official: bool,
     pub entity_display_name: String,
     pub entity_category_id: Uuid,
 }
@@ -121,6 +352,15 @@ pub struct AdminListedComment {
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
     pub moderated: bool,
+    /// Spam score of public submissions, higher is more suspicious
+    pub spam_score: Option<f32>,
+    /// Reasons of the spam score
+    pub spam_reasons: Vec<String>,
+    pub moderation_status: ModerationStatus,
+    /// Reason given with the last moderation decision
+    pub moderation_reason: Option<String>,
+    /// Moderator in charge of the comment
+    pub assigned_to: Option<Uuid>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema, Default)]
@@ -132,6 +372,12 @@ pub struct AdminNewOrUpdateComment {
     pub moderated: bool,
     pub version: i32,
     pub entity_category_id: Uuid,
+    /// Comment replied to
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    /// Response of the moderators or of the organisation running the map
+    #[serde(default)]
+    pub is_official: bool,
 }
 
 impl AdminComment {
@@ -143,17 +389,25 @@ impl AdminComment {
         family
             .comment_form
             .validate_data(&new_comment.data, new_comment.entity_category_id)?;
+        check_parent(
+            None,
+            new_comment.parent_id,
+            new_comment.entity_id,
+            false,
+            conn,
+        )
+        .await?;
 
         sqlx::query_as!(
             AdminComment,
             r#"
             WITH inserted AS (
-                INSERT INTO comments (entity_id, author, text, data, moderated)
-                VALUES ($1, $2, $3, $4, $5)
+                INSERT INTO comments (entity_id, author, text, data, moderated, parent_id, is_official)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
                 RETURNING *
             )
-            SELECT i.id, i.entity_id, i.author, i.text, i.data, i.created_at, i.updated_at, i.moderated, i.version, 
-                display_name AS entity_display_name, category_id AS entity_category_id
+            SELECT i.id, i.entity_id, i.parent_id, i.author, i.text, i.data, i.created_at, i.updated_at, i.moderated, i.version,
+                i.is_official, display_name AS entity_display_name, category_id AS entity_category_id
             FROM inserted i
             JOIN entities e 
             ON e.id = entity_id
@@ -162,7 +416,9 @@ impl AdminComment {
             new_comment.author,
             new_comment.text,
             new_comment.data,
-            new_comment.moderated
+            new_comment.moderated,
+            new_comment.parent_id,
+            new_comment.is_official
         )
         .fetch_one(conn)
         .await
@@ -178,6 +434,7 @@ impl AdminComment {
         family
             .comment_form
             .validate_data(&update.data, update.entity_category_id)?;
+        check_parent(Some(id), update.parent_id, update.entity_id, false, conn).await?;
 
         sqlx::query_as!(
             AdminComment,
@@ -190,12 +447,14 @@ impl AdminComment {
                     text = $4,
                     data = $5,
                     moderated = $6,
-                    version = $7
+                    version = $7,
+                    parent_id = $8,
+                    is_official = $9
                 WHERE id = $1
                 RETURNING *
             )
-            SELECT i.id, i.entity_id, i.author, i.text, i.data, i.created_at, i.updated_at, i.moderated, i.version, 
-                e.display_name AS entity_display_name, e.category_id AS entity_category_id
+            SELECT i.id, i.entity_id, i.parent_id, i.author, i.text, i.data, i.created_at, i.updated_at, i.moderated, i.version,
+                i.is_official, e.display_name AS entity_display_name, e.category_id AS entity_category_id
             FROM inserted i
             JOIN entities e 
             ON e.id = entity_id
@@ -206,7 +465,9 @@ impl AdminComment {
             update.text,
             update.data,
             update.moderated,
-            update.version
+            update.version,
+            update.parent_id,
+            update.is_official
         )
         .fetch_one(conn)
         .await
@@ -217,8 +478,8 @@ impl AdminComment {
         sqlx::query_as!(
             AdminComment,
             r#"
-            SELECT c.id, c.entity_id, c.author, c.text, c.data, c.created_at, c.updated_at, c.moderated, c.version,
-                e.display_name AS entity_display_name, e.category_id AS entity_category_id
+            SELECT c.id, c.entity_id, c.parent_id, c.author, c.text, c.data, c.created_at, c.updated_at, c.moderated, c.version,
+                c.is_official, e.display_name AS entity_display_name, e.category_id AS entity_category_id
             FROM comments c
             JOIN entities e ON e.id = c.entity_id
             WHERE c.id = $1
@@ -252,8 +513,8 @@ impl AdminComment {
         sqlx::query_as!(
             AdminComment,
             r#"
-            SELECT c.id, c.entity_id, c.author, c.text, c.data, c.created_at, c.updated_at, c.moderated, c.version,
-                e.display_name AS entity_display_name, e.category_id AS entity_category_id
+            SELECT c.id, c.entity_id, c.parent_id, c.author, c.text, c.data, c.created_at, c.updated_at, c.moderated, c.version,
+                c.is_official, e.display_name AS entity_display_name, e.category_id AS entity_category_id
             FROM comments c
             JOIN entities e ON e.id = c.entity_id
             WHERE entity_id = $1
@@ -266,17 +527,23 @@ impl AdminComment {
         .map_err(AppError::Database)
     }
 
-    pub async fn pending(conn: &mut PgConnection) -> Result<Vec<AdminListedComment>, AppError> {
+    pub async fn pending(
+        status: ModerationStatus,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<AdminListedComment>, AppError> {
         sqlx::query_as!(
             AdminListedComment,
             r#"
             SELECT c.id, c.entity_id, e.display_name AS entity_display_name, e.category_id AS entity_category_id, c.created_at,
-                c.author, c.moderated, c.updated_at
+                c.author, c.moderated, c.updated_at, c.spam_score, c.spam_reasons,
+                c.moderation_status AS "moderation_status: ModerationStatus",
+                c.moderation_reason, c.assigned_to
             FROM comments c
             INNER JOIN entities e ON c.entity_id = e.id
-            WHERE NOT c.moderated
-            ORDER BY created_at
-            "#
+            WHERE c.moderation_status = $1
+            ORDER BY c.created_at
+            "#,
+            status as ModerationStatus
         )
         .fetch_all(conn)
         .await
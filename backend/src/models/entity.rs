@@ -1,6 +1,9 @@
 use crate::api::AppError;
 use crate::helpers::deserializers::empty_string_is_invalid;
+use crate::models::entity_cache::CacheRefreshStats;
 use crate::models::family::Family;
+use crate::models::moderation::ModerationStatus;
+use crate::models::spam::{SpamAssessment, SPAM_REJECTION_REASON};
 use serde::{Deserialize, Serialize};
 use serde_json::{to_value, Value};
 use sqlx::{types::Json, Acquire, FromRow, PgConnection, Postgres, Transaction};
@@ -71,6 +74,8 @@ impl PublicEntity {
 
     pub async fn new(
         entity: PublicNewEntity,
+        spam_assessment: Option<SpamAssessment>,
+        tracking_code_hash: &str,
         conn: &mut PgConnection,
     ) -> Result<PublicEntity, AppError> {
         let family = Family::get_from_category(entity.category_id, conn).await?;
@@ -79,42 +84,96 @@ impl PublicEntity {
             .validate_data(&entity.data, entity.category_id)?;
 
         let locations = to_value(entity.locations).unwrap();
+        let (spam_score, spam_reasons, rejected) = match spam_assessment {
+            Some(assessment) => (
+                Some(assessment.score),
+                assessment.reasons,
+                assessment.rejected,
+            ),
+            None => (None, vec![], false),
+        };
+        // Rejected submissions are kept until the end of the appeal period, like the items
+        // rejected by the moderators
+        let (moderation_status, moderation_reason) = if rejected {
+            (ModerationStatus::Rejected, Some(SPAM_REJECTION_REASON))
+        } else {
+            (ModerationStatus::Pending, None)
+        };
+
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                PublicEntity,
+                r#"
+                WITH inserted AS (
+                    INSERT INTO entities (display_name, category_id, locations, data, spam_score, spam_reasons, tracking_code_hash, moderation_status, moderation_reason)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    RETURNING *
+                ) 
+                SELECT 
+                    i.id, 
+                    i.category_id, 
+                    i.display_name, 
+                    i.data,
+                    i.locations AS "locations: Json<Vec<UnprocessedLocation>>",
+                    i.created_at,
+                    i.updated_at,
+                    array[]::uuid[] AS "tags!", 
+                    c.family_id,
+                    f.entity_form AS "entity_form: Json<Form>", 
+                    f.comment_form AS "comment_form: Json<Form>"
+                FROM inserted i
+                JOIN categories c ON c.id = i.category_id
+                JOIN families f ON f.id = c.family_id
+                "#,
+                entity.display_name,
+                entity.category_id,
+                locations,
+                entity.data,
+                spam_score,
+                &spam_reasons,
+                tracking_code_hash,
+                moderation_status as ModerationStatus,
+                moderation_reason
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await
+    }
 
-        sqlx::query_as!(
+    pub async fn get(given_id: Uuid, conn: &mut PgConnection) -> Result<PublicEntity, AppError> {
+        let mut public_entity = sqlx::query_as!(
             PublicEntity,
             r#"
-            WITH inserted AS (
-                INSERT INTO entities (display_name, category_id, locations, data)
-                VALUES ($1, $2, $3, $4)
-                RETURNING *
-            ) 
-            SELECT 
-                i.id, 
-                i.category_id, 
-                i.display_name, 
-                i.data,
-                i.locations AS "locations: Json<Vec<UnprocessedLocation>>",
-                i.created_at,
-                i.updated_at,
-                array[]::uuid[] AS "tags!", 
-                c.family_id,
-                f.entity_form AS "entity_form: Json<Form>", 
+            SELECT e.id, c.family_id, e.category_id, e.display_name, e.data, e.created_at, e.updated_at,
+                e.locations AS "locations: Json<Vec<UnprocessedLocation>>",
+                COALESCE(
+                    (SELECT array_agg(t.tag_id) FROM entity_tags t WHERE t.entity_id = e.id), 
+                    array[]::uuid[]
+                ) AS "tags!",
+                f.entity_form AS "entity_form: Json<Form>",
                 f.comment_form AS "comment_form: Json<Form>"
-            FROM inserted i
-            JOIN categories c ON c.id = i.category_id
-            JOIN families f ON f.id = c.family_id
+            FROM entities e
+            INNER JOIN categories c ON e.category_id = c.id
+            INNER JOIN families f ON c.family_id = f.id
+            WHERE e.id = $1 AND e.moderated AND NOT e.hidden
             "#,
-            entity.display_name,
-            entity.category_id,
-            locations,
-            entity.data,
+            given_id
         )
         .fetch_one(conn)
         .await
-        .map_err(AppError::Database)
+        .map_err(AppError::Database)?;
+
+        public_entity.cleanup_data();
+        Ok(public_entity)
     }
 
-    pub async fn get(given_id: Uuid, conn: &mut PgConnection) -> Result<PublicEntity, AppError> {
+    /// Fetches the entity followed by its submitter, whether it is published or not
+    pub async fn get_by_tracking_code_hash(
+        tracking_code_hash: &str,
+        conn: &mut PgConnection,
+    ) -> Result<PublicEntity, AppError> {
         let mut public_entity = sqlx::query_as!(
             PublicEntity,
             r#"
@@ -129,9 +188,9 @@ impl PublicEntity {
             FROM entities e
             INNER JOIN categories c ON e.category_id = c.id
             INNER JOIN families f ON c.family_id = f.id
-            WHERE e.id = $1 AND e.moderated AND NOT e.hidden
+            WHERE e.tracking_code_hash = $1
             "#,
-            given_id
+            tracking_code_hash
         )
         .fetch_one(conn)
         .await
@@ -216,6 +275,15 @@ pub struct AdminListedEntity {
     pub tags: Vec<Uuid>,
     pub hidden: bool,
     pub moderated: bool,
+    /// Spam score of public submissions, higher is more suspicious
+    pub spam_score: Option<f32>,
+    /// Reasons of the spam score
+    pub spam_reasons: Vec<String>,
+    pub moderation_status: ModerationStatus,
+    /// Reason given with the last moderation decision
+    pub moderation_reason: Option<String>,
+    /// Moderator in charge of the entity
+    pub assigned_to: Option<Uuid>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
 }
@@ -256,58 +324,64 @@ impl AdminEntity {
         let locations = to_value(new_entity.locations).unwrap();
 
         // Insert the new entity using a CTE (WITH clause) and fetch the result
-        let mut created_entity = sqlx::query_as!(
-            AdminEntity,
-            r#"
-            WITH inserted AS (
-                INSERT INTO entities (display_name, category_id, locations, data, hidden, moderation_notes, moderated)
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
-                RETURNING *
+        let mut created_entity = CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query_as!(
+                AdminEntity,
+                r#"
+                WITH inserted AS (
+                    INSERT INTO entities (display_name, category_id, locations, data, hidden, moderation_notes, moderated)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    RETURNING *
+                )
+                SELECT 
+                    i.id,
+                    i.display_name,
+                    i.category_id,
+                    i.locations AS "locations: Json<Vec<UnprocessedLocation>>",
+                    i.data,
+                    i.hidden,
+                    i.moderation_notes,
+                    i.moderated,
+                    i.created_at,
+                    i.updated_at,
+                    i.version,
+                    c.family_id,
+                    COALESCE(array(
+                        SELECT tag_id
+                        FROM entity_tags
+                        WHERE entity_id = i.id
+                    ), array[]::uuid[]) AS "tags!"
+                FROM inserted i
+                JOIN categories c ON c.id = i.category_id
+                "#,
+                new_entity.display_name,
+                new_entity.category_id,
+                locations,
+                new_entity.data,
+                new_entity.hidden,
+                new_entity.moderation_notes,
+                new_entity.moderated
             )
-            SELECT 
-                i.id,
-                i.display_name,
-                i.category_id,
-                i.locations AS "locations: Json<Vec<UnprocessedLocation>>",
-                i.data,
-                i.hidden,
-                i.moderation_notes,
-                i.moderated,
-                i.created_at,
-                i.updated_at,
-                i.version,
-                c.family_id,
-                COALESCE(array(
-                    SELECT tag_id
-                    FROM entity_tags
-                    WHERE entity_id = i.id
-                ), array[]::uuid[]) AS "tags!"
-            FROM inserted i
-            JOIN categories c ON c.id = i.category_id
-            "#,
-            new_entity.display_name,
-            new_entity.category_id,
-            locations,
-            new_entity.data,
-            new_entity.hidden,
-            new_entity.moderation_notes,
-            new_entity.moderated
-        )
-        .fetch_one(&mut *tx)
-        .await
-        .map_err(AppError::Database)?;
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         // Handle the many-to-many relationship for tags
-        sqlx::query!(
-            r#"
-                SELECT replace_tags_for_entity($1, $2)
-                "#,
-            created_entity.id,
-            &new_entity.tags
-        )
-        .execute(&mut *tx)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query!(
+                r#"
+                    SELECT replace_tags_for_entity($1, $2)
+                    "#,
+                created_entity.id,
+                &new_entity.tags
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         // Commit the transaction if all operations succeeded
         tx.commit().await.map_err(AppError::Database)?;
@@ -339,69 +413,75 @@ impl AdminEntity {
         let locations = to_value(update.locations).unwrap();
 
         // Handle the many-to-many relationship for tags
-        sqlx::query!(
-            r#"
-            SELECT replace_tags_for_entity($1, $2)
-            "#,
-            id,
-            &update.tags
-        )
-        .execute(&mut *tx)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query!(
+                r#"
+                SELECT replace_tags_for_entity($1, $2)
+                "#,
+                id,
+                &update.tags
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         // Update the entity itselfand return the updated record using a CTE
-        let updated_entity = sqlx::query_as!(
-            AdminEntity,
-            r#"
-            WITH updated AS (
-                UPDATE entities
-                SET 
-                    display_name = $2, 
-                    category_id = $3, 
-                    locations = $4, 
-                    data = $5, 
-                    hidden = $6, 
-                    moderation_notes = $7, 
-                    moderated = $8,
-                    version = $9
-                WHERE id = $1
-                RETURNING *
+        let updated_entity = CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query_as!(
+                AdminEntity,
+                r#"
+                WITH updated AS (
+                    UPDATE entities
+                    SET 
+                        display_name = $2, 
+                        category_id = $3, 
+                        locations = $4, 
+                        data = $5, 
+                        hidden = $6, 
+                        moderation_notes = $7, 
+                        moderated = $8,
+                        version = $9
+                    WHERE id = $1
+                    RETURNING *
+                )
+                SELECT 
+                    u.id,
+                    u.display_name,
+                    u.category_id,
+                    u.locations AS "locations: Json<Vec<UnprocessedLocation>>",
+                    u.data,
+                    u.hidden,
+                    u.moderation_notes,
+                    u.moderated,
+                    u.created_at,
+                    u.updated_at,
+                    u.version,
+                    c.family_id,
+                    COALESCE(array(
+                        SELECT tag_id
+                        FROM entity_tags
+                        WHERE entity_id = u.id
+                    ), array[]::uuid[]) AS "tags!"
+                FROM updated u
+                JOIN categories c ON c.id = u.category_id
+                "#,
+                id,
+                update.display_name,
+                update.category_id,
+                locations,
+                update.data,
+                update.hidden,
+                update.moderation_notes,
+                update.moderated,
+                update.version
             )
-            SELECT 
-                u.id,
-                u.display_name,
-                u.category_id,
-                u.locations AS "locations: Json<Vec<UnprocessedLocation>>",
-                u.data,
-                u.hidden,
-                u.moderation_notes,
-                u.moderated,
-                u.created_at,
-                u.updated_at,
-                u.version,
-                c.family_id,
-                COALESCE(array(
-                    SELECT tag_id
-                    FROM entity_tags
-                    WHERE entity_id = u.id
-                ), array[]::uuid[]) AS "tags!"
-            FROM updated u
-            JOIN categories c ON c.id = u.category_id
-            "#,
-            id,
-            update.display_name,
-            update.category_id,
-            locations,
-            update.data,
-            update.hidden,
-            update.moderation_notes,
-            update.moderated,
-            update.version
-        )
-        .fetch_one(&mut *tx)
-        .await
-        .map_err(AppError::Database)?;
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         // Commit the transaction if all operations succeeded
         tx.commit().await.map_err(AppError::Database)?;
@@ -410,16 +490,19 @@ impl AdminEntity {
     }
 
     pub async fn delete(id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"
-            DELETE FROM entities
-            WHERE id = $1
-            "#,
-            id
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                DELETE FROM entities
+                WHERE id = $1
+                "#,
+                id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
@@ -429,17 +512,20 @@ impl AdminEntity {
         child_id: Uuid,
         conn: &mut PgConnection,
     ) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"
-            INSERT INTO entities_entities (parent_id, child_id)
-            VALUES ($1, $2)
-            "#,
-            parent_id,
-            child_id
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                INSERT INTO entities_entities (parent_id, child_id)
+                VALUES ($1, $2)
+                "#,
+                parent_id,
+                child_id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
@@ -449,17 +535,20 @@ impl AdminEntity {
         child_id: Uuid,
         conn: &mut PgConnection,
     ) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"
-            DELETE FROM entities_entities
-            WHERE parent_id = $1 AND child_id = $2
-            "#,
-            parent_id,
-            child_id
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                DELETE FROM entities_entities
+                WHERE parent_id = $1 AND child_id = $2
+                "#,
+                parent_id,
+                child_id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
@@ -472,7 +561,9 @@ impl AdminEntity {
             AdminListedEntity,
             r#"
             SELECT e.id, e.display_name, e.category_id, e.created_at, e.hidden,
-                    e.moderated, e.updated_at,
+                    e.moderated, e.updated_at, e.spam_score, e.spam_reasons,
+                    e.moderation_status AS "moderation_status: ModerationStatus",
+                    e.moderation_reason, e.assigned_to,
                     COALESCE(
                         (SELECT array_agg(t.tag_id) FROM entity_tags t WHERE t.entity_id = e.id), 
                         array[]::uuid[]
@@ -496,7 +587,9 @@ impl AdminEntity {
             AdminListedEntity,
             r#"
             SELECT e.id, e.display_name, e.category_id, e.created_at, e.hidden,
-                    e.moderated, e.updated_at,
+                    e.moderated, e.updated_at, e.spam_score, e.spam_reasons,
+                    e.moderation_status AS "moderation_status: ModerationStatus",
+                    e.moderation_reason, e.assigned_to,
                     COALESCE(
                         (SELECT array_agg(t.tag_id) FROM entity_tags t WHERE t.entity_id = e.id), 
                         array[]::uuid[]
@@ -535,20 +628,26 @@ impl AdminEntity {
         .map_err(AppError::Database)
     }
 
-    pub async fn pending(conn: &mut PgConnection) -> Result<Vec<AdminListedEntity>, AppError> {
+    pub async fn pending(
+        status: ModerationStatus,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<AdminListedEntity>, AppError> {
         sqlx::query_as!(
             AdminListedEntity,
             r#"
             SELECT e.id, e.display_name, e.category_id, e.created_at, e.hidden,
-                    e.moderated, e.updated_at,
+                    e.moderated, e.updated_at, e.spam_score, e.spam_reasons,
+                    e.moderation_status AS "moderation_status: ModerationStatus",
+                    e.moderation_reason, e.assigned_to,
                     COALESCE(
                         (SELECT array_agg(t.tag_id) FROM entity_tags t WHERE t.entity_id = e.id), 
                         array[]::uuid[]
                     ) AS "tags!"
             FROM entities e
-            WHERE NOT e.moderated
+            WHERE e.moderation_status = $1
             ORDER BY created_at
-            "#
+            "#,
+            status as ModerationStatus
         )
         .fetch_all(conn)
         .await
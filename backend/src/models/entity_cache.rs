@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 
-use crate::{api::AppError, helpers::postgis_polygons::MultiPolygon};
+use crate::{
+    api::AppError,
+    helpers::postgis_polygons::MultiPolygon,
+    models::{comment::ScoreAggregate, moderation::ModerationStatus},
+};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sqlx::{query_as, types::Json, PgConnection};
+use serde_json::{json, Value};
+use sqlx::{query_as, query_scalar, types::Json, PgConnection};
+use tracing::Instrument;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
@@ -30,6 +36,11 @@ pub struct ViewerSearchedCachedEntity {
     pub display_name: String,
     pub parents: Vec<ParentRepresentation>,
     pub locations: Vec<LocationRepresentation>,
+    /// Excerpts of the indexed text matching the search query, HTML-escaped, with the matched
+    /// words wrapped in `<mark>` tags
+    pub highlight: Option<String>,
+    /// Aggregates of the comments scores, by field key
+    pub scores: HashMap<String, ScoreAggregate>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema, Debug)]
@@ -41,6 +52,7 @@ pub struct AdminCachedEntity {
     pub family_id: Uuid,
     pub display_name: String,
     pub hidden: bool,
+    pub moderation_status: ModerationStatus,
 }
 
 #[derive(Deserialize, Serialize, Debug, ToSchema)]
@@ -65,6 +77,8 @@ struct PaginatedCachedEntity {
     pub display_name: String,
     pub parents: Json<Vec<ParentRepresentation>>,
     pub locations: Json<Vec<LocationRepresentation>>,
+    pub highlight: Option<String>,
+    pub scores: Json<HashMap<String, ScoreAggregate>>,
     pub total_results: i64,
     pub total_pages: i64,
     pub response_current_page: i64,
@@ -88,6 +102,8 @@ impl From<Vec<PaginatedCachedEntity>> for ViewerCachedEntitiesWithPagination {
                 display_name: paginated_entity.display_name,
                 parents: paginated_entity.parents.0,
                 locations: paginated_entity.locations.0,
+                highlight: paginated_entity.highlight,
+                scores: paginated_entity.scores.0,
             };
             entities.push(entity);
         }
@@ -102,7 +118,7 @@ impl From<Vec<PaginatedCachedEntity>> for ViewerCachedEntitiesWithPagination {
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-struct AdminPaginatedCachedEntity {
+struct AdminSortedCachedEntity {
     pub id: Uuid,
     pub entity_id: Uuid,
     pub category_id: Uuid,
@@ -110,47 +126,28 @@ struct AdminPaginatedCachedEntity {
     pub family_id: Uuid,
     pub display_name: String,
     pub hidden: bool,
-    pub total_results: i64,
-    pub total_pages: i64,
-    pub response_current_page: i64,
+    pub moderation_status: ModerationStatus,
+    pub sort_values: Vec<f64>,
+    pub sort_name: String,
 }
 
-impl From<Vec<AdminPaginatedCachedEntity>> for AdminCachedEntitiesWithPagination {
-    fn from(paginated_entities: Vec<AdminPaginatedCachedEntity>) -> Self {
-        let mut entities = Vec::new();
-        let total_results = paginated_entities.first().map_or(0, |e| e.total_results);
-        let total_pages = paginated_entities.first().map_or(0, |e| e.total_pages);
-        let response_current_page = paginated_entities
-            .first()
-            .map_or(0, |e| e.response_current_page);
-
-        for paginated_entity in paginated_entities {
-            let entity = AdminCachedEntity {
-                id: paginated_entity.id,
-                entity_id: paginated_entity.entity_id,
-                category_id: paginated_entity.category_id,
-                tags_ids: paginated_entity.tags_ids,
-                family_id: paginated_entity.entity_id,
-                display_name: paginated_entity.display_name,
-                hidden: paginated_entity.hidden,
-            };
-            entities.push(entity);
-        }
+/// Position of the last entity of a page in the sort, to fetch the next page
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
+pub struct AdminSearchCursor {
+    sort_values: Vec<f64>,
+    sort_name: String,
+    entity_id: Uuid,
+}
 
-        AdminCachedEntitiesWithPagination {
-            entities,
-            total_results,
-            total_pages,
-            response_current_page,
-        }
-    }
+#[derive(Serialize, ToSchema, Debug)]
+pub struct AdminCachedEntitiesPage {
+    pub entities: Vec<AdminCachedEntity>,
+    /// To be sent along the same search to get the next page, if there is one
+    pub next_cursor: Option<AdminSearchCursor>,
 }
 
 #[derive(Serialize, ToSchema, Debug)]
-#[aliases(
-    ViewerCachedEntitiesWithPagination = PaginatedVec<ViewerSearchedCachedEntity>,
-    AdminCachedEntitiesWithPagination = PaginatedVec<AdminCachedEntity>
-)]
+#[aliases(ViewerCachedEntitiesWithPagination = PaginatedVec<ViewerSearchedCachedEntity>)]
 pub struct PaginatedVec<T> {
     pub entities: Vec<T>,
 
@@ -171,31 +168,164 @@ pub struct CachedClusteredEntity {
     pub parent_id: Option<Uuid>,
     pub parent_display_name: Option<String>,
     pub plain_text_location: String,
-    pub cluster_id: Option<i32>,
+    pub cluster_id: Option<i64>,
     pub cluster_center_x: Option<f64>,
     pub cluster_center_y: Option<f64>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema, Debug)]
 pub struct Cluster {
-    pub id: u32,
+    /// Identifier of the grid cell of the cluster, stable across requests for a given zoom level
+    pub id: i64,
     pub center_x: f64,
     pub center_y: f64,
     pub count: i32,
-}
-
-impl Cluster {
-    fn calculate_id(&mut self) {
-        self.id = crc32fast::hash(
-            format!("{:.4}-{:.4}-{}", self.center_x, self.center_y, self.count).as_bytes(),
-        );
-    }
+    /// Number of clustered entities for each category
+    pub categories: HashMap<Uuid, i32>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema, Debug)]
 pub struct EntitiesAndClusters {
     pub entities: Vec<ViewerCachedEntity>,
     pub clusters: Vec<Cluster>,
+    /// Only set when requested
+    pub facets: Option<Facets>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug, Default)]
+pub struct Facets {
+    /// Number of matching entities in each category, regardless of the active categories
+    pub categories: HashMap<Uuid, i64>,
+    /// Number of matching entities having each filtering tag
+    pub tags: HashMap<Uuid, i64>,
+    /// Number of matching entities for each value of the indexed enum fields,
+    /// regardless of the constraints on the field itself
+    pub enums: HashMap<String, HashMap<String, i64>>,
+}
+
+#[derive(Debug)]
+struct FacetCount {
+    pub facet_kind: String,
+    pub facet_id: Option<Uuid>,
+    pub enum_key: Option<String>,
+    pub enum_value: Option<String>,
+    pub entities_count: i64,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct ExpandedCluster {
+    /// Bounding box containing every member of the cluster (Web Mercator)
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+
+    pub entities: Vec<ViewerCachedEntity>,
+
+    pub total_results: i64,
+    pub total_pages: i64,
+    pub response_current_page: i64,
+}
+
+#[derive(Debug)]
+struct PaginatedClusterMember {
+    pub id: Uuid,
+    pub entity_id: Uuid,
+    pub category_id: Uuid,
+    pub family_id: Uuid,
+    pub display_name: String,
+    pub parent_id: Option<Uuid>,
+    pub parent_display_name: Option<String>,
+    pub web_mercator_x: f64,
+    pub web_mercator_y: f64,
+    pub plain_text_location: String,
+    pub cluster_xmin: f64,
+    pub cluster_ymin: f64,
+    pub cluster_xmax: f64,
+    pub cluster_ymax: f64,
+    pub total_results: i64,
+    pub total_pages: i64,
+    pub response_current_page: i64,
+}
+
+/// Order of the search results
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "by", rename_all = "snake_case")]
+pub enum EntitiesSort {
+    /// Best matches of the search query first
+    #[default]
+    Relevance,
+    /// Alphabetical order of the display names
+    Name,
+    /// Most recently created first
+    CreatedAt,
+    /// Most recently updated first
+    UpdatedAt,
+    /// Closest to a Web Mercator point first, entities without locations last
+    Distance { x: f64, y: f64 },
+    /// Best mean of a comments score first, entities without this score last
+    Score { key: String },
+    /// Pending entities first, then those with changes requested, rejected and approved.
+    /// Reserved to administrators.
+    ModerationStatus,
+}
+
+impl EntitiesSort {
+    /// Converts the sort to the parameters of the search functions (see `entity_sort_values`):
+    /// the sort name, the point of the distance and the key of the score
+    fn to_database_params(&self) -> (&'static str, Option<f64>, Option<f64>, Option<String>) {
+        match self {
+            EntitiesSort::Relevance => ("relevance", None, None, None),
+            EntitiesSort::Name => ("name", None, None, None),
+            EntitiesSort::CreatedAt => ("created_at", None, None, None),
+            EntitiesSort::UpdatedAt => ("updated_at", None, None, None),
+            EntitiesSort::Distance { x, y } => ("distance", Some(*x), Some(*y), None),
+            EntitiesSort::Score { key } => ("score", None, None, Some(key.clone())),
+            EntitiesSort::ModerationStatus => ("moderation_status", None, None, None),
+        }
+    }
+}
+
+/// Constraint on an indexed Number, DiscreteScore, Boolean or Date field
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum ValueConstraint {
+    /// Inclusive range on a Number or DiscreteScore field, a missing bound is unbounded
+    NumberRange { min: Option<f64>, max: Option<f64> },
+    /// Exact value of a Number or DiscreteScore field
+    NumberEquals { value: f64 },
+    /// Inclusive range on a Date field, a missing bound is unbounded
+    DateRange {
+        min: Option<DateTime<Utc>>,
+        max: Option<DateTime<Utc>>,
+    },
+    /// Exact value of a Boolean field
+    BooleanEquals { value: bool },
+}
+
+impl ValueConstraint {
+    /// Converts the constraints to the representation expected by `value_constraints_match`,
+    /// where dates are compared as UNIX timestamps
+    pub fn to_database_constraints(constraints: &HashMap<String, ValueConstraint>) -> Value {
+        let timestamp = |date: &DateTime<Utc>| date.timestamp_millis() as f64 / 1000.0;
+
+        constraints
+            .iter()
+            .map(|(key, constraint)| {
+                let value = match constraint {
+                    ValueConstraint::NumberRange { min, max } => json!({ "min": min, "max": max }),
+                    ValueConstraint::NumberEquals { value } => json!({ "eq": value }),
+                    ValueConstraint::DateRange { min, max } => json!({
+                        "min": min.as_ref().map(timestamp),
+                        "max": max.as_ref().map(timestamp),
+                    }),
+                    ValueConstraint::BooleanEquals { value } => json!({ "eq": value }),
+                };
+                (key.clone(), value)
+            })
+            .collect::<serde_json::Map<String, Value>>()
+            .into()
+    }
 }
 
 pub struct FindEntitiesRequest {
@@ -215,13 +345,39 @@ pub struct FindEntitiesRequest {
     pub exclude_categories_list: Vec<Uuid>,
     pub exclude_tags_list: Vec<Uuid>,
 
-    pub cluster_params: Option<(f64, i32)>,
+    pub cluster_params: Option<(i32, i32)>,
 
     pub active_categories: Vec<Uuid>,
     pub active_required_tags: Vec<Uuid>,
     pub active_hidden_tags: Vec<Uuid>,
 
     pub enums_constraints: Value,
+    pub values_constraints: Value,
+}
+
+pub struct FetchClusterMembersRequest {
+    pub cluster_id: i64,
+    pub geographic_restriction: Option<MultiPolygon>,
+    pub family_id: Uuid,
+
+    pub allow_all_categories: bool,
+    pub allow_all_tags: bool,
+
+    pub categories_list: Vec<Uuid>,
+    pub tags_list: Vec<Uuid>,
+
+    pub exclude_categories_list: Vec<Uuid>,
+    pub exclude_tags_list: Vec<Uuid>,
+
+    pub active_categories: Vec<Uuid>,
+    pub active_required_tags: Vec<Uuid>,
+    pub active_hidden_tags: Vec<Uuid>,
+
+    pub enums_constraints: Value,
+    pub values_constraints: Value,
+
+    pub page: i64,
+    pub page_size: i64,
 }
 
 pub struct SearchEntitiesRequest {
@@ -248,6 +404,40 @@ pub struct SearchEntitiesRequest {
     pub require_locations: bool,
 
     pub enums_constraints: Value,
+    pub values_constraints: Value,
+    /// Constraints on the means of the comments scores
+    pub scores_constraints: Value,
+
+    pub sort: EntitiesSort,
+}
+
+pub struct FetchFacetsRequest {
+    pub search_query: Option<String>,
+    /// Viewport (xmin, ymin, xmax, ymax) in Web Mercator, if the facets are restricted to it
+    pub view: Option<(f64, f64, f64, f64)>,
+    pub geographic_restriction: Option<MultiPolygon>,
+    pub family_id: Uuid,
+
+    pub allow_all_categories: bool,
+    pub allow_all_tags: bool,
+
+    pub categories_list: Vec<Uuid>,
+    pub tags_list: Vec<Uuid>,
+
+    pub exclude_categories_list: Vec<Uuid>,
+    pub exclude_tags_list: Vec<Uuid>,
+
+    pub active_categories: Vec<Uuid>,
+    pub active_required_tags: Vec<Uuid>,
+    pub active_hidden_tags: Vec<Uuid>,
+
+    pub require_locations: bool,
+
+    pub enums_constraints: Value,
+    pub values_constraints: Value,
+
+    /// Privately indexed fields of the family, which are not counted
+    pub private_enum_keys: Vec<String>,
 }
 
 impl ViewerCachedEntity {
@@ -293,7 +483,8 @@ impl ViewerCachedEntity {
                 $15,
                 $16,
                 $17,
-                $18
+                $18,
+                $19
             )
             "#,
             request.xmin,
@@ -310,12 +501,13 @@ impl ViewerCachedEntity {
             &request.tags_list,
             &request.exclude_categories_list,
             &request.exclude_tags_list,
-            request.cluster_params.map(|(eps, _)| eps).unwrap_or(0.0),
+            request.cluster_params.map(|(zoom, _)| zoom).unwrap_or(-1),
             request.cluster_params.map(|(_, min)| min).unwrap_or(0),
             &request.active_categories,
             &request.active_required_tags,
             &request.active_hidden_tags,
-            &request.enums_constraints
+            &request.enums_constraints,
+            &request.values_constraints
         )
         .fetch_all(conn)
         .await
@@ -348,27 +540,144 @@ impl ViewerCachedEntity {
             .iter()
             .fold(
                 HashMap::new(),
-                |mut acc: std::collections::HashMap<u32, Cluster>, e| {
+                |mut acc: std::collections::HashMap<i64, Cluster>, e| {
                     if let Some(cluster_id) = e.cluster_id {
-                        let cluster = acc.entry(cluster_id as u32).or_insert(Cluster {
-                            id: cluster_id as u32,
+                        let cluster = acc.entry(cluster_id).or_insert(Cluster {
+                            id: cluster_id,
                             center_x: e.cluster_center_x.unwrap(),
                             center_y: e.cluster_center_y.unwrap(),
                             count: 0,
+                            categories: HashMap::new(),
                         });
                         cluster.count += 1;
+                        *cluster.categories.entry(e.category_id).or_insert(0) += 1;
                     }
                     acc
                 },
             )
             .into_values()
-            .map(|mut v| {
-                v.calculate_id();
-                v
+            .collect();
+
+        Ok(EntitiesAndClusters {
+            entities,
+            clusters,
+            facets: None,
+        })
+    }
+
+    /// This function fetches the members of a cluster returned by `find_entities_in_rectangle`,
+    /// along with the bounding box containing all of them, using the same permissions filters.
+    /// A cluster that no longer exists (or a page past its last member) is reported as not found.
+    pub async fn fetch_cluster_members(
+        request: FetchClusterMembersRequest,
+        conn: &mut PgConnection,
+    ) -> Result<ExpandedCluster, AppError> {
+        if request.page < 1 || !(1..=100).contains(&request.page_size) {
+            return Err(AppError::InvalidPagination);
+        }
+
+        let members = query_as!(
+            PaginatedClusterMember,
+            r#"
+            SELECT
+                id AS "id!",
+                entity_id AS "entity_id!",
+                category_id AS "category_id!",
+                family_id AS "family_id!",
+                display_name AS "display_name!",
+                parent_id,
+                parent_display_name,
+                web_mercator_x AS "web_mercator_x!",
+                web_mercator_y AS "web_mercator_y!",
+                plain_text_location AS "plain_text_location!",
+                cluster_xmin AS "cluster_xmin!",
+                cluster_ymin AS "cluster_ymin!",
+                cluster_xmax AS "cluster_xmax!",
+                cluster_ymax AS "cluster_ymax!",
+                total_results AS "total_results!",
+                total_pages AS "total_pages!",
+                response_current_page AS "response_current_page!"
+            FROM fetch_cluster_members(
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6,
+                $7,
+                $8,
+                $9,
+                $10,
+                $11,
+                $12,
+                $13,
+                $14,
+                $15,
+                $16
+            )
+            "#,
+            request.cluster_id,
+            request
+                .geographic_restriction
+                .map(|g| g.to_polygon_string(Some(3857))),
+            request.family_id,
+            request.allow_all_categories,
+            request.allow_all_tags,
+            &request.categories_list,
+            &request.tags_list,
+            &request.exclude_categories_list,
+            &request.exclude_tags_list,
+            &request.active_categories,
+            &request.active_required_tags,
+            &request.active_hidden_tags,
+            &request.enums_constraints,
+            &request.values_constraints,
+            request.page,
+            request.page_size
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        let first = members.first().ok_or(AppError::NotFound)?;
+        let (xmin, ymin, xmax, ymax) = (
+            first.cluster_xmin,
+            first.cluster_ymin,
+            first.cluster_xmax,
+            first.cluster_ymax,
+        );
+        let (total_results, total_pages, response_current_page) = (
+            first.total_results,
+            first.total_pages,
+            first.response_current_page,
+        );
+
+        let entities = members
+            .into_iter()
+            .map(|m| ViewerCachedEntity {
+                id: m.id,
+                entity_id: m.entity_id,
+                category_id: m.category_id,
+                family_id: m.family_id,
+                display_name: m.display_name,
+                parent_id: m.parent_id,
+                parent_display_name: m.parent_display_name,
+                web_mercator_x: Some(m.web_mercator_x),
+                web_mercator_y: Some(m.web_mercator_y),
+                plain_text_location: Some(m.plain_text_location),
             })
             .collect();
 
-        Ok(EntitiesAndClusters { entities, clusters })
+        Ok(ExpandedCluster {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+            entities,
+            total_results,
+            total_pages,
+            response_current_page,
+        })
     }
 
     pub async fn search_entities(
@@ -379,6 +688,8 @@ impl ViewerCachedEntity {
             return Err(AppError::InvalidPagination);
         }
 
+        let (sort_by, sort_x, sort_y, sort_score_key) = request.sort.to_database_params();
+
         let results = query_as!(
             PaginatedCachedEntity,
             r#"
@@ -390,6 +701,8 @@ impl ViewerCachedEntity {
                 display_name AS "display_name!",
                 parents AS "parents!: Json<Vec<ParentRepresentation>>",
                 locations AS "locations!: Json<Vec<LocationRepresentation>>",
+                highlight,
+                scores AS "scores!: Json<HashMap<String, ScoreAggregate>>",
                 total_results AS "total_results!",
                 total_pages AS "total_pages!",
                 response_current_page AS "response_current_page!"
@@ -409,7 +722,13 @@ impl ViewerCachedEntity {
                 $13,
                 $14,
                 $15,
-                $16
+                $16,
+                $17,
+                $18,
+                $19,
+                $20,
+                $21,
+                $22
             )
             "#,
             request.search_query,
@@ -429,7 +748,13 @@ impl ViewerCachedEntity {
             &request.active_required_tags,
             &request.active_hidden_tags,
             request.require_locations,
-            &request.enums_constraints
+            &request.enums_constraints,
+            &request.values_constraints,
+            &request.scores_constraints,
+            sort_by,
+            sort_x,
+            sort_y,
+            sort_score_key
         )
         .fetch_all(conn)
         .await
@@ -437,38 +762,148 @@ impl ViewerCachedEntity {
 
         Ok(results.into())
     }
+
+    /// This function counts the entities matching the same filters as `search_entities` (or
+    /// `find_entities_in_rectangle` when a view is given) per category, tag and enum value.
+    pub async fn fetch_facets(
+        request: FetchFacetsRequest,
+        conn: &mut PgConnection,
+    ) -> Result<Facets, AppError> {
+        let (xmin, ymin, xmax, ymax) = match request.view {
+            Some((xmin, ymin, xmax, ymax)) => (Some(xmin), Some(ymin), Some(xmax), Some(ymax)),
+            None => (None, None, None, None),
+        };
+
+        let counts = query_as!(
+            FacetCount,
+            r#"
+            SELECT
+                facet_kind AS "facet_kind!",
+                facet_id,
+                enum_key,
+                enum_value,
+                entities_count AS "entities_count!"
+            FROM fetch_entities_facets(
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6,
+                $7,
+                $8,
+                $9,
+                $10,
+                $11,
+                $12,
+                $13,
+                $14,
+                $15,
+                $16,
+                $17,
+                $18,
+                $19,
+                $20
+            )
+            "#,
+            request.search_query,
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+            request
+                .geographic_restriction
+                .map(|g| g.to_polygon_string(Some(3857))),
+            request.family_id,
+            request.allow_all_categories,
+            request.allow_all_tags,
+            &request.categories_list,
+            &request.tags_list,
+            &request.exclude_categories_list,
+            &request.exclude_tags_list,
+            &request.active_categories,
+            &request.active_required_tags,
+            &request.active_hidden_tags,
+            request.require_locations,
+            &request.enums_constraints,
+            &request.values_constraints,
+            &request.private_enum_keys
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(counts
+            .into_iter()
+            .fold(Facets::default(), |mut facets, count| {
+                match (count.facet_kind.as_str(), count.facet_id, count.enum_key) {
+                    ("category", Some(id), _) => {
+                        facets.categories.insert(id, count.entities_count);
+                    }
+                    ("tag", Some(id), _) => {
+                        facets.tags.insert(id, count.entities_count);
+                    }
+                    ("enum", _, Some(key)) => {
+                        facets
+                            .enums
+                            .entry(key)
+                            .or_default()
+                            .insert(count.enum_value.unwrap_or_default(), count.entities_count);
+                    }
+                    _ => {}
+                }
+                facets
+            }))
+    }
 }
 
 pub struct AdminSearchEntitiesRequest {
     pub search_query: String,
     pub family_id: Uuid,
-    pub page: i64,
+    /// Position of the last entity of the previous page, none for the first page
+    pub after: Option<AdminSearchCursor>,
     pub page_size: i64,
     pub active_categories_ids: Vec<Uuid>,
     pub required_tags_ids: Vec<Uuid>,
     pub excluded_tags_ids: Vec<Uuid>,
     pub enums_constraints: Value,
+    pub sort: EntitiesSort,
 }
 
 impl AdminCachedEntity {
     pub async fn search_entities(
         request: AdminSearchEntitiesRequest,
         conn: &mut PgConnection,
-    ) -> Result<AdminCachedEntitiesWithPagination, AppError> {
-        let results = query_as!(
-            AdminPaginatedCachedEntity,
+    ) -> Result<AdminCachedEntitiesPage, AppError> {
+        if !(1..=100).contains(&request.page_size) {
+            return Err(AppError::InvalidPagination);
+        }
+
+        let (sort_by, sort_x, sort_y, sort_score_key) = request.sort.to_database_params();
+        let (after_sort_values, after_sort_name, after_entity_id) = match request.after {
+            Some(cursor) => (
+                Some(cursor.sort_values),
+                Some(cursor.sort_name),
+                Some(cursor.entity_id),
+            ),
+            None => (None, None, None),
+        };
+
+        // One more entity than requested tells if there is a next page
+        let mut results = query_as!(
+            AdminSortedCachedEntity,
             r#"
-            SELECT 
+            SELECT
                 id AS "id!",
                 entity_id AS "entity_id!",
                 category_id AS "category_id!",
                 tags_ids AS "tags_ids!",
                 family_id AS "family_id!",
                 display_name AS "display_name!",
-                total_results AS "total_results!",
-                total_pages AS "total_pages!",
-                response_current_page AS "response_current_page!",
-                hidden AS "hidden!"
+                hidden AS "hidden!",
+                moderation_status AS "moderation_status!: ModerationStatus",
+                sort_values AS "sort_values!",
+                sort_name AS "sort_name!"
             FROM search_entities_admin(
                 $1,
                 $2,
@@ -477,22 +912,259 @@ impl AdminCachedEntity {
                 $5,
                 $6,
                 $7,
-                $8
+                $8,
+                $9,
+                $10,
+                $11,
+                $12,
+                $13,
+                $14
             )
             "#,
             request.search_query,
             request.family_id,
-            request.page,
-            request.page_size,
+            after_sort_values.as_deref(),
+            after_sort_name,
+            after_entity_id,
+            request.page_size + 1,
             &request.active_categories_ids,
             &request.required_tags_ids,
             &request.excluded_tags_ids,
-            &request.enums_constraints
+            &request.enums_constraints,
+            sort_by,
+            sort_x,
+            sort_y,
+            sort_score_key
         )
         .fetch_all(conn)
         .await
         .map_err(AppError::Database)?;
 
-        Ok(results.into())
+        let has_next_page = results.len() as i64 > request.page_size;
+        results.truncate(request.page_size as usize);
+
+        let next_cursor = results
+            .last()
+            .filter(|_| has_next_page)
+            .map(|last| AdminSearchCursor {
+                sort_values: last.sort_values.clone(),
+                sort_name: last.sort_name.clone(),
+                entity_id: last.entity_id,
+            });
+
+        Ok(AdminCachedEntitiesPage {
+            entities: results
+                .into_iter()
+                .map(|entity| AdminCachedEntity {
+                    id: entity.id,
+                    entity_id: entity.entity_id,
+                    category_id: entity.category_id,
+                    tags_ids: entity.tags_ids,
+                    family_id: entity.family_id,
+                    display_name: entity.display_name,
+                    hidden: entity.hidden,
+                    moderation_status: entity.moderation_status,
+                })
+                .collect(),
+            next_cursor,
+        })
+    }
+}
+
+/// Refreshes of the entities cache since its creation, durations in seconds
+pub struct CacheRefreshStats {
+    pub refreshes_count: i64,
+    pub total_duration: f64,
+    pub last_duration: Option<f64>,
+    pub last_refreshed_at: Option<DateTime<Utc>>,
+    /// The materialized view can be queried
+    pub populated: bool,
+    /// An entity was changed after the last refresh, which should never happen as the refreshes
+    /// are part of the changes transactions
+    pub stale: bool,
+}
+
+impl CacheRefreshStats {
+    pub async fn get(conn: &mut PgConnection) -> Result<Self, AppError> {
+        query_as!(
+            CacheRefreshStats,
+            r#"
+            SELECT
+                refreshes_count,
+                total_duration,
+                last_duration,
+                last_refreshed_at,
+                COALESCE(
+                    (SELECT ispopulated FROM pg_matviews WHERE matviewname = 'entities_caches'),
+                    FALSE
+                ) AS "populated!",
+                COALESCE(
+                    (SELECT MAX(updated_at) FROM entities) > last_refreshed_at,
+                    FALSE
+                ) AS "stale!"
+            FROM entities_caches_refreshes
+            "#
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Runs statements refreshing the entities cache through their triggers in a dedicated span,
+    /// with the duration of the last refresh they ran
+    pub async fn trace<T>(
+        conn: &mut PgConnection,
+        statements: impl AsyncFnOnce(&mut PgConnection) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let span = tracing::info_span!(
+            "entities_cache.refresh",
+            entities_cache.refresh_duration = tracing::field::Empty
+        );
+
+        async move {
+            let result = statements(&mut *conn).await?;
+
+            let last_duration =
+                query_scalar!("SELECT last_duration FROM entities_caches_refreshes")
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(AppError::Database)?;
+            tracing::Span::current().record("entities_cache.refresh_duration", last_duration);
+
+            Ok(result)
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    const FAMILY_ID: Uuid = Uuid::from_u128(0xf0000000_0000_0000_0000_000000000001);
+    const CATEGORY_ID: Uuid = Uuid::from_u128(0xc0000000_0000_0000_0000_000000000001);
+
+    fn search_request(search_query: &str) -> SearchEntitiesRequest {
+        SearchEntitiesRequest {
+            search_query: search_query.to_string(),
+            geographic_restriction: None,
+            family_id: FAMILY_ID,
+            allow_all_categories: true,
+            allow_all_tags: true,
+            categories_list: vec![],
+            tags_list: vec![],
+            exclude_categories_list: vec![],
+            exclude_tags_list: vec![],
+            page: 1,
+            page_size: 20,
+            active_categories: vec![CATEGORY_ID],
+            active_required_tags: vec![],
+            active_hidden_tags: vec![],
+            require_locations: false,
+            enums_constraints: json!({}),
+            values_constraints: json!({}),
+            scores_constraints: json!({}),
+            sort: EntitiesSort::Relevance,
+        }
+    }
+
+    async fn search_names(search_query: &str, conn: &mut PgConnection) -> Vec<String> {
+        ViewerCachedEntity::search_entities(search_request(search_query), conn)
+            .await
+            .unwrap()
+            .entities
+            .into_iter()
+            .map(|entity| entity.display_name)
+            .collect()
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_search_entities(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+
+        // Accents are ignored and the unmoderated entities are not listed
+        assert_eq!(search_names("cafe", &mut conn).await, ["Café des amis"]);
+        assert_eq!(
+            search_names("boulangerie", &mut conn).await,
+            ["Boulangerie <b>Dupont</b>"]
+        );
+
+        // Misspelled queries fall back to the fuzzy matching
+        assert_eq!(
+            search_names("librarie", &mut conn).await,
+            ["Librairie du centre"]
+        );
+        assert!(search_names("zzzz", &mut conn).await.is_empty());
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_search_highlights_are_escaped(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+
+        let results = ViewerCachedEntity::search_entities(search_request("dupont"), &mut conn)
+            .await
+            .unwrap();
+        let highlight = results.entities[0].highlight.as_deref().unwrap();
+
+        assert!(highlight.contains("&lt;b&gt;"));
+        assert!(!highlight.contains("<b>"));
+        assert!(highlight.contains("<mark>"));
+    }
+
+    fn admin_search_request(
+        after: Option<AdminSearchCursor>,
+        page_size: i64,
+    ) -> AdminSearchEntitiesRequest {
+        AdminSearchEntitiesRequest {
+            search_query: String::new(),
+            family_id: FAMILY_ID,
+            after,
+            page_size,
+            active_categories_ids: vec![CATEGORY_ID],
+            required_tags_ids: vec![],
+            excluded_tags_ids: vec![],
+            enums_constraints: json!({}),
+            sort: EntitiesSort::Name,
+        }
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_admin_search_cursor_pagination(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+
+        let mut names = vec![];
+        let mut after = None;
+        loop {
+            let page =
+                AdminCachedEntity::search_entities(admin_search_request(after, 2), &mut conn)
+                    .await
+                    .unwrap();
+            assert!(page.entities.len() <= 2);
+            names.extend(page.entities.into_iter().map(|entity| entity.display_name));
+
+            match page.next_cursor {
+                Some(cursor) => after = Some(cursor),
+                None => break,
+            }
+        }
+
+        // Every entity is listed once, in order, including the pending ones
+        assert_eq!(
+            names,
+            [
+                "Boulangerie <b>Dupont</b>",
+                "Boulangerie du marché",
+                "Café des amis",
+                "Librairie du centre",
+                "Pharmacie de la gare"
+            ]
+        );
+
+        assert!(matches!(
+            AdminCachedEntity::search_entities(admin_search_request(None, 101), &mut conn).await,
+            Err(AppError::InvalidPagination)
+        ));
     }
 }
@@ -8,6 +8,7 @@ use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::models::access_token::PermissionPolicy;
+use crate::models::entity_cache::CacheRefreshStats;
 #[derive(Serialize, Deserialize, ToSchema, Debug)]
 pub struct Form {
     pub title: String,
@@ -56,7 +57,7 @@ pub struct Field {
 
     /// Sets if the field is indexed, the field must be indexed for this setting to be used.
     /// Privately indexed means only administrators can constraint on this field.
-    /// It only works for EnumSingleOption and EnumMultiOption
+    /// It only works for EnumSingleOption, EnumMultiOption, Number, DiscreteScore, Boolean and Date
     pub privately_indexed: bool,
 
     /// Sets if the field is mandatory
@@ -88,6 +89,9 @@ pub struct Family {
     #[schema(value_type = Form)]
     pub comment_form: Json<Form>,
     pub sort_order: i32,
+    /// Text search language used to index the entities of the family (`french`, `english`...),
+    /// the database default language is used when not set
+    pub text_search_language: Option<String>,
     pub version: i32,
 }
 
@@ -97,6 +101,7 @@ pub struct NewOrUpdateFamily {
     pub entity_form: Form,
     pub comment_form: Form,
     pub sort_order: i32,
+    pub text_search_language: Option<String>,
     pub version: Option<i32>,
 }
 
@@ -150,9 +155,10 @@ impl Field {
         entity_category: Uuid,
     ) -> Result<(), AppError> {
         let field_required = self.mandatory
-            && self.categories.as_ref().map_or(true, |categories| {
-                categories.iter().any(|&c| c == entity_category)
-            });
+            && self
+                .categories
+                .as_ref()
+                .is_none_or(|categories| categories.contains(&entity_category));
 
         let field_value = match field_value {
             None if field_required => {
@@ -254,28 +260,33 @@ impl Family {
         let entity_form = to_value(family.entity_form).unwrap();
         let comment_form = to_value(family.comment_form).unwrap();
 
-        sqlx::query_as!(
-            Family,
-            r#"
-            INSERT INTO families (title, entity_form, comment_form, sort_order)
-            VALUES ($1, $2, $3, $4)
-            RETURNING 
-                id,
-                title,
-                (SELECT hash FROM icons WHERE id = icon_id) AS icon_hash,
-                entity_form AS "entity_form: Json<Form>",
-                comment_form AS "comment_form: Json<Form>",
-                sort_order,
-                version
-            "#,
-            family.title,
-            entity_form,
-            comment_form,
-            family.sort_order
-        )
-        .fetch_one(conn)
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                Family,
+                r#"
+                INSERT INTO families (title, entity_form, comment_form, sort_order, text_search_language)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING 
+                    id,
+                    title,
+                    (SELECT hash FROM icons WHERE id = icon_id) AS icon_hash,
+                    entity_form AS "entity_form: Json<Form>",
+                    comment_form AS "comment_form: Json<Form>",
+                    sort_order,
+                    text_search_language,
+                    version
+                "#,
+                family.title,
+                entity_form,
+                comment_form,
+                family.sort_order,
+                family.text_search_language
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
         .await
-        .map_err(AppError::Database)
     }
 
     pub async fn update(
@@ -294,44 +305,53 @@ impl Family {
         let entity_form = to_value(update.entity_form).unwrap();
         let comment_form = to_value(update.comment_form).unwrap();
 
-        sqlx::query_as!(
-            Family,
-            r#"
-            UPDATE families
-            SET title = $2, entity_form = $3, comment_form = $4, sort_order = $5, version = $6
-            WHERE id = $1
-            RETURNING 
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                Family,
+                r#"
+                UPDATE families
+                SET title = $2, entity_form = $3, comment_form = $4, sort_order = $5,
+                    text_search_language = $6, version = $7
+                WHERE id = $1
+                RETURNING 
+                    id,
+                    title,
+                    (SELECT hash FROM icons WHERE id = icon_id) as icon_hash,
+                    entity_form as "entity_form: Json<Form>",
+                    comment_form as "comment_form: Json<Form>",
+                    sort_order,
+                    text_search_language,
+                    version
+                "#,
                 id,
-                title,
-                (SELECT hash FROM icons WHERE id = icon_id) as icon_hash,
-                entity_form as "entity_form: Json<Form>",
-                comment_form as "comment_form: Json<Form>",
-                sort_order,
-                version
-            "#,
-            id,
-            update.title,
-            entity_form,
-            comment_form,
-            update.sort_order,
-            update.version
-        )
-        .fetch_one(conn)
+                update.title,
+                entity_form,
+                comment_form,
+                update.sort_order,
+                update.text_search_language,
+                update.version
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
         .await
-        .map_err(AppError::Database)
     }
 
     pub async fn delete(given_id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"
-            DELETE FROM families
-            WHERE id = $1
-            "#,
-            given_id
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                DELETE FROM families
+                WHERE id = $1
+                "#,
+                given_id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
@@ -344,6 +364,7 @@ impl Family {
                 entity_form as "entity_form: Json<Form>", 
                 comment_form as "comment_form: Json<Form>",
                 sort_order,
+                text_search_language,
                 version
             FROM families
             WHERE id = $1
@@ -363,6 +384,7 @@ impl Family {
                 entity_form AS "entity_form: Json<Form>", 
                 comment_form AS "comment_form: Json<Form>",
                 sort_order,
+                text_search_language,
                 version
             FROM families
             "#
@@ -383,6 +405,7 @@ impl Family {
                 entity_form as "entity_form: Json<Form>", 
                 comment_form as "comment_form: Json<Form>",
                 sort_order,
+                text_search_language,
                 version
             FROM families
             WHERE ($1 OR id = ANY($2)) AND NOT (id = ANY($3))
@@ -407,6 +430,7 @@ impl Family {
                 families.entity_form AS "entity_form: Json<Form>", 
                 families.comment_form AS "comment_form: Json<Form>",
                 families.sort_order,
+                families.text_search_language,
                 families.version
             FROM families
             JOIN categories ON families.id = categories.family_id
@@ -430,6 +454,7 @@ impl Family {
                 families.entity_form AS "entity_form: Json<Form>", 
                 families.comment_form AS "comment_form: Json<Form>",
                 families.sort_order,
+                families.text_search_language,
                 families.version
             FROM families
             JOIN categories ON families.id = categories.family_id
@@ -443,6 +468,22 @@ impl Family {
         .map_err(AppError::Database)
     }
 
+    pub async fn list_text_search_languages(
+        conn: &mut PgConnection,
+    ) -> Result<Vec<String>, AppError> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT regexp_replace(cfgname, '_unaccent$', '') AS "language!"
+            FROM pg_ts_config
+            WHERE cfgnamespace = 'public'::regnamespace AND cfgname LIKE '%\_unaccent'
+            ORDER BY cfgname
+            "#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
     pub fn get_privately_indexed_fields_for_families(
         families: &[Family],
     ) -> HashMap<Uuid, Vec<String>> {
@@ -0,0 +1,30 @@
+INSERT INTO families (id, title, entity_form, comment_form)
+VALUES (
+    'f0000000-0000-0000-0000-000000000001',
+    'Places',
+    '{"title": "Place", "help": null, "fields": []}',
+    '{"title": "Comment", "help": null, "fields": []}'
+);
+
+INSERT INTO categories (id, title, family_id)
+VALUES ('c0000000-0000-0000-0000-000000000001', 'Shops', 'f0000000-0000-0000-0000-000000000001');
+
+INSERT INTO entities (id, display_name, category_id, data, moderated)
+VALUES
+    ('e0000000-0000-0000-0000-000000000001', 'Café des amis', 'c0000000-0000-0000-0000-000000000001', '{}', TRUE),
+    ('e0000000-0000-0000-0000-000000000002', 'Boulangerie <b>Dupont</b>', 'c0000000-0000-0000-0000-000000000001', '{}', TRUE),
+    ('e0000000-0000-0000-0000-000000000003', 'Librairie du centre', 'c0000000-0000-0000-0000-000000000001', '{}', TRUE),
+    ('e0000000-0000-0000-0000-000000000004', 'Pharmacie de la gare', 'c0000000-0000-0000-0000-000000000001', '{}', TRUE),
+    ('e0000000-0000-0000-0000-000000000005', 'Boulangerie du marché', 'c0000000-0000-0000-0000-000000000001', '{}', FALSE);
+
+INSERT INTO comments (id, entity_id, author, text, data)
+VALUES ('d0000000-0000-0000-0000-000000000001', 'e0000000-0000-0000-0000-000000000001', 'Alice', 'Nice place', '{}');
+
+INSERT INTO access_tokens (id, title, token, permissions)
+VALUES
+    ('a0000000-0000-0000-0000-000000000001', 'First', 'FIRSTTOKEN', '{}'),
+    ('a0000000-0000-0000-0000-000000000002', 'Second', 'SECONDTOKEN', '{}'),
+    ('a0000000-0000-0000-0000-000000000003', 'Third', 'THIRDTOKEN', '{}');
+
+INSERT INTO users (id, name, password)
+VALUES ('b0000000-0000-0000-0000-000000000001', 'moderator', 'not a hash');
@@ -2,6 +2,7 @@ use sqlx::PgConnection;
 use uuid::Uuid;
 
 use crate::api::AppError;
+use crate::models::entity_cache::CacheRefreshStats;
 
 pub struct Icon {
     pub data: Vec<u8>,
@@ -58,15 +59,18 @@ impl Icon {
         http_mime_type: String,
         conn: &mut PgConnection,
     ) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"SELECT upsert_row_icon($1, $2, $3, 'families')"#,
-            family_id,
-            data,
-            http_mime_type
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"SELECT upsert_row_icon($1, $2, $3, 'families')"#,
+                family_id,
+                data,
+                http_mime_type
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
@@ -77,15 +81,18 @@ impl Icon {
         http_mime_type: String,
         conn: &mut PgConnection,
     ) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"SELECT upsert_row_icon($1, $2, $3, 'categories')"#,
-            category_id,
-            data,
-            http_mime_type
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"SELECT upsert_row_icon($1, $2, $3, 'categories')"#,
+                category_id,
+                data,
+                http_mime_type
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
@@ -1,11 +1,19 @@
 pub mod access_token;
+pub mod analytics;
 pub mod category;
 pub mod comment;
 pub mod entity;
 pub mod entity_cache;
 pub mod family;
 pub mod icon;
+pub mod moderation;
+pub mod notification;
 pub mod options;
+pub mod report;
+pub mod spam;
 pub mod statistics;
+pub mod suggestion;
 pub mod tag;
 pub mod user;
+pub mod view_preset;
+pub mod webhook;
@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::AppError;
+use crate::models::entity_cache::CacheRefreshStats;
+
+#[derive(sqlx::Type, Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[sqlx(type_name = "moderation_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationStatus {
+    /// Waiting for a moderator
+    Pending,
+    /// Published
+    Approved,
+    /// Kept for the appeal period, then deleted
+    Rejected,
+    /// Waiting for changes from the submitter
+    ChangesRequested,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationItemKind {
+    Entity,
+    Comment,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Debug)]
+pub struct ModerationItem {
+    pub kind: ModerationItemKind,
+    pub id: Uuid,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct ModerationDecision {
+    pub items: Vec<ModerationItem>,
+    /// Any status but pending
+    pub status: ModerationStatus,
+    /// Required to reject or request changes
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct ModeratorStats {
+    pub user_id: Uuid,
+    pub user_name: String,
+    pub approved: i64,
+    pub rejected: i64,
+    pub changes_requested: i64,
+    /// Items waiting for a decision of the moderator
+    pub assigned_pending: i64,
+    pub last_decision_at: Option<chrono::NaiveDateTime>,
+}
+
+fn items_ids(items: &[ModerationItem], kind: ModerationItemKind) -> Vec<Uuid> {
+    items
+        .iter()
+        .filter(|item| item.kind == kind)
+        .map(|item| item.id)
+        .collect()
+}
+
+pub struct Moderation;
+
+impl Moderation {
+    /// Assigns the items to a moderator, or unassigns them. Unless forced, only the items
+    /// unassigned or assigned to the acting moderator are changed. Returns the changed items.
+    pub async fn assign(
+        items: &[ModerationItem],
+        assignee_id: Option<Uuid>,
+        acting_user_id: Uuid,
+        force: bool,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<ModerationItem>, AppError> {
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+        let entities = CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query_scalar!(
+                r#"
+                UPDATE entities
+                SET assigned_to = $2, assigned_at = CASE WHEN $2::uuid IS NULL THEN NULL ELSE CURRENT_TIMESTAMP END
+                WHERE id = ANY($1) AND ($3 OR assigned_to IS NULL OR assigned_to = $4)
+                RETURNING id
+                "#,
+                &items_ids(items, ModerationItemKind::Entity),
+                assignee_id,
+                force,
+                acting_user_id
+            )
+            .fetch_all(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
+
+        let comments = sqlx::query_scalar!(
+            r#"
+            UPDATE comments
+            SET assigned_to = $2, assigned_at = CASE WHEN $2::uuid IS NULL THEN NULL ELSE CURRENT_TIMESTAMP END
+            WHERE id = ANY($1) AND ($3 OR assigned_to IS NULL OR assigned_to = $4)
+            RETURNING id
+            "#,
+            &items_ids(items, ModerationItemKind::Comment),
+            assignee_id,
+            force,
+            acting_user_id
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(entities
+            .into_iter()
+            .map(|id| ModerationItem {
+                kind: ModerationItemKind::Entity,
+                id,
+            })
+            .chain(comments.into_iter().map(|id| ModerationItem {
+                kind: ModerationItemKind::Comment,
+                id,
+            }))
+            .collect())
+    }
+
+    /// Applies a decision to the items and logs it for the moderator
+    pub async fn decide(
+        decision: ModerationDecision,
+        user_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        if decision.status == ModerationStatus::Pending {
+            return Err(AppError::Validation(
+                "A decision cannot put items back in the queue".to_string(),
+            ));
+        }
+
+        let reason = decision.reason.filter(|reason| !reason.trim().is_empty());
+
+        if reason.is_none()
+            && matches!(
+                decision.status,
+                ModerationStatus::Rejected | ModerationStatus::ChangesRequested
+            )
+        {
+            return Err(AppError::Validation(
+                "A reason is required to reject or request changes".to_string(),
+            ));
+        }
+
+        let entities_ids = items_ids(&decision.items, ModerationItemKind::Entity);
+        let comments_ids = items_ids(&decision.items, ModerationItemKind::Comment);
+
+        // Only the items actually changed by the decision are logged
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                WITH decided_entities AS (
+                    UPDATE entities
+                    SET moderation_status = $3, moderation_reason = $4
+                    WHERE id = ANY($1)
+                        AND (moderation_status <> $3 OR moderation_reason IS DISTINCT FROM $4)
+                    RETURNING id
+                ),
+                decided_comments AS (
+                    UPDATE comments
+                    SET moderation_status = $3, moderation_reason = $4
+                    WHERE id = ANY($2)
+                        AND (moderation_status <> $3 OR moderation_reason IS DISTINCT FROM $4)
+                    RETURNING id
+                )
+                INSERT INTO moderation_decisions (item_kind, item_id, user_id, decision, reason)
+                SELECT 'entity', id, $5::uuid, $3::moderation_status, $4::text FROM decided_entities
+                UNION ALL
+                SELECT 'comment', id, $5, $3, $4 FROM decided_comments
+                "#,
+                &entities_ids,
+                &comments_ids,
+                decision.status as ModerationStatus,
+                reason,
+                user_id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn stats(conn: &mut PgConnection) -> Result<Vec<ModeratorStats>, AppError> {
+        sqlx::query_as!(
+            ModeratorStats,
+            r#"
+            SELECT
+                u.id AS user_id,
+                u.name AS user_name,
+                COUNT(*) FILTER (WHERE d.decision = 'approved') AS "approved!",
+                COUNT(*) FILTER (WHERE d.decision = 'rejected') AS "rejected!",
+                COUNT(*) FILTER (WHERE d.decision = 'changes_requested') AS "changes_requested!",
+                (
+                    (SELECT COUNT(*) FROM entities e WHERE e.assigned_to = u.id AND e.moderation_status = 'pending')
+                    + (SELECT COUNT(*) FROM comments c WHERE c.assigned_to = u.id AND c.moderation_status = 'pending')
+                ) AS "assigned_pending!",
+                MAX(d.created_at) AS last_decision_at
+            FROM users u
+            LEFT JOIN moderation_decisions d ON d.user_id = u.id
+            GROUP BY u.id, u.name
+            ORDER BY u.name
+            "#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Deletes the items rejected for longer than the appeal period
+    pub async fn purge_rejected(
+        appeal_period_days: i32,
+        conn: &mut PgConnection,
+    ) -> Result<u64, AppError> {
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+        let deleted_comments = sqlx::query!(
+            r#"
+            DELETE FROM comments
+            WHERE moderation_status = 'rejected'
+                AND moderation_status_at < CURRENT_TIMESTAMP - make_interval(days => $1)
+            "#,
+            appeal_period_days
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .rows_affected();
+
+        let deleted_entities = CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query!(
+                r#"
+                DELETE FROM entities
+                WHERE moderation_status = 'rejected'
+                    AND moderation_status_at < CURRENT_TIMESTAMP - make_interval(days => $1)
+                "#,
+                appeal_period_days
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?
+        .rows_affected();
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(deleted_comments + deleted_entities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    const USER_ID: Uuid = Uuid::from_u128(0xb0000000_0000_0000_0000_000000000001);
+    const APPROVED_ENTITY_ID: Uuid = Uuid::from_u128(0xe0000000_0000_0000_0000_000000000001);
+    const PENDING_ENTITY_ID: Uuid = Uuid::from_u128(0xe0000000_0000_0000_0000_000000000005);
+    const PENDING_COMMENT_ID: Uuid = Uuid::from_u128(0xd0000000_0000_0000_0000_000000000001);
+
+    fn decision(status: ModerationStatus, reason: Option<&str>) -> ModerationDecision {
+        ModerationDecision {
+            items: vec![
+                ModerationItem {
+                    kind: ModerationItemKind::Entity,
+                    id: APPROVED_ENTITY_ID,
+                },
+                ModerationItem {
+                    kind: ModerationItemKind::Entity,
+                    id: PENDING_ENTITY_ID,
+                },
+                ModerationItem {
+                    kind: ModerationItemKind::Comment,
+                    id: PENDING_COMMENT_ID,
+                },
+                ModerationItem {
+                    kind: ModerationItemKind::Entity,
+                    id: Uuid::nil(),
+                },
+            ],
+            status,
+            reason: reason.map(str::to_string),
+        }
+    }
+
+    async fn logged_decisions(conn: &mut PgConnection) -> Vec<(String, Uuid)> {
+        sqlx::query_as("SELECT item_kind, item_id FROM moderation_decisions ORDER BY item_kind")
+            .fetch_all(conn)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_decide(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+
+        // Only the items whose status changes are logged
+        Moderation::decide(
+            decision(ModerationStatus::Approved, None),
+            USER_ID,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            logged_decisions(&mut conn).await,
+            [
+                ("comment".to_string(), PENDING_COMMENT_ID),
+                ("entity".to_string(), PENDING_ENTITY_ID)
+            ]
+        );
+
+        let moderated: bool = sqlx::query_scalar("SELECT moderated FROM entities WHERE id = $1")
+            .bind(PENDING_ENTITY_ID)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert!(moderated);
+
+        // Repeating the decision changes nothing
+        Moderation::decide(
+            decision(ModerationStatus::Approved, None),
+            USER_ID,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(logged_decisions(&mut conn).await.len(), 2);
+
+        // A rejection requires a reason
+        assert!(matches!(
+            Moderation::decide(
+                decision(ModerationStatus::Rejected, Some(" ")),
+                USER_ID,
+                &mut conn
+            )
+            .await,
+            Err(AppError::Validation(_))
+        ));
+        assert_eq!(logged_decisions(&mut conn).await.len(), 2);
+
+        // Items cannot be put back in the queue
+        assert!(matches!(
+            Moderation::decide(
+                decision(ModerationStatus::Pending, Some("Later")),
+                USER_ID,
+                &mut conn
+            )
+            .await,
+            Err(AppError::Validation(_))
+        ));
+        assert_eq!(logged_decisions(&mut conn).await.len(), 2);
+    }
+}
@@ -0,0 +1,239 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    api::AppError,
+    helpers::notifications::{parse_address, LOCALES},
+};
+
+#[derive(sqlx::Type, Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[sqlx(type_name = "notification_frequency", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationFrequency {
+    Never,
+    /// As soon as new content is awaiting review
+    Immediate,
+    /// Once a day, with everything awaiting review
+    Daily,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct NotificationPreferences {
+    pub email: Option<String>,
+    pub frequency: NotificationFrequency,
+    /// Locale of the emails
+    pub locale: String,
+}
+
+/// User due for a notification
+pub struct NotificationRecipient {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub locale: String,
+    pub frequency: NotificationFrequency,
+    /// Time of the previous notification
+    pub since: Option<NaiveDateTime>,
+    /// Time the recipient was claimed at, recorded as its last notification
+    pub notified_at: NaiveDateTime,
+}
+
+/// Content awaiting review, and how much of it is new since a given time
+pub struct ModerationSummary {
+    pub pending_entities: i64,
+    pub pending_comments: i64,
+    pub pending_reports: i64,
+    pub new_entities: i64,
+    pub new_comments: i64,
+    pub new_reports: i64,
+}
+
+impl NotificationPreferences {
+    fn validate(&self) -> Result<(), AppError> {
+        if !LOCALES.contains(&self.locale.as_str()) {
+            return Err(AppError::Validation(format!(
+                "Unsupported locale {}",
+                self.locale
+            )));
+        }
+
+        match &self.email {
+            Some(email) => parse_address(email).map(|_| ()),
+            None if self.frequency != NotificationFrequency::Never => Err(AppError::Validation(
+                "An email address is required to be notified".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn get(user_id: Uuid, conn: &mut PgConnection) -> Result<Self, AppError> {
+        sqlx::query_as!(
+            NotificationPreferences,
+            r#"
+            SELECT email, notification_frequency AS "frequency: NotificationFrequency",
+                notification_locale AS locale
+            FROM users
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn update(
+        user_id: Uuid,
+        preferences: NotificationPreferences,
+        conn: &mut PgConnection,
+    ) -> Result<Self, AppError> {
+        let preferences = NotificationPreferences {
+            email: preferences.email.filter(|email| !email.trim().is_empty()),
+            ..preferences
+        };
+        preferences.validate()?;
+
+        sqlx::query_as!(
+            NotificationPreferences,
+            r#"
+            UPDATE users
+            SET email = $2, notification_frequency = $3, notification_locale = $4
+            WHERE id = $1
+            RETURNING email, notification_frequency AS "frequency: NotificationFrequency",
+                notification_locale AS locale
+            "#,
+            user_id,
+            preferences.email,
+            preferences.frequency as NotificationFrequency,
+            preferences.locale
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+impl NotificationRecipient {
+    /// Claims the users due for a notification: daily ones once a day, immediate ones when
+    /// content was submitted since their last notification. They are marked as notified right
+    /// away, so concurrent instances do not notify them twice, and released if the notification
+    /// could not be sent.
+    pub async fn claim_due(conn: &mut PgConnection) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as!(
+            NotificationRecipient,
+            r#"
+            WITH due AS (
+                SELECT u.id, u.last_notified_at
+                FROM users u
+                WHERE u.email IS NOT NULL AND (
+                    (
+                        u.notification_frequency = 'daily'
+                        AND COALESCE(u.last_notified_at, '-infinity') <= NOW() - INTERVAL '1 day'
+                    )
+                    OR (
+                        u.notification_frequency = 'immediate'
+                        AND (
+                            EXISTS (
+                                SELECT 1 FROM entities e
+                                WHERE e.moderation_status = 'pending'
+                                    AND e.created_at > COALESCE(u.last_notified_at, '-infinity')
+                            )
+                            OR EXISTS (
+                                SELECT 1 FROM comments c
+                                WHERE c.moderation_status = 'pending'
+                                    AND c.created_at > COALESCE(u.last_notified_at, '-infinity')
+                            )
+                            OR EXISTS (
+                                SELECT 1 FROM entity_reports r
+                                WHERE NOT r.resolved
+                                    AND r.created_at > COALESCE(u.last_notified_at, '-infinity')
+                            )
+                        )
+                    )
+                )
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE users u
+            SET last_notified_at = NOW()
+            FROM due
+            WHERE u.id = due.id
+            RETURNING u.id, u.name, u.email AS "email!", u.notification_locale AS locale,
+                u.notification_frequency AS "frequency: NotificationFrequency",
+                due.last_notified_at AS since, u.last_notified_at AS "notified_at!"
+            "#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Restores the previous notification time of a recipient that was not notified, unless it
+    /// was claimed again since
+    pub async fn release(&self, conn: &mut PgConnection) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET last_notified_at = $2
+            WHERE id = $1 AND last_notified_at = $3
+            "#,
+            self.id,
+            self.since,
+            self.notified_at
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(())
+    }
+}
+
+impl ModerationSummary {
+    pub async fn since(
+        since: Option<NaiveDateTime>,
+        conn: &mut PgConnection,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as!(
+            ModerationSummary,
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM entities WHERE moderation_status = 'pending') AS "pending_entities!",
+                (SELECT COUNT(*) FROM comments WHERE moderation_status = 'pending') AS "pending_comments!",
+                (SELECT COUNT(DISTINCT entity_id) FROM entity_reports WHERE NOT resolved) AS "pending_reports!",
+                (
+                    SELECT COUNT(*) FROM entities
+                    WHERE moderation_status = 'pending' AND created_at > COALESCE($1::timestamp, '-infinity')
+                ) AS "new_entities!",
+                (
+                    SELECT COUNT(*) FROM comments
+                    WHERE moderation_status = 'pending' AND created_at > COALESCE($1::timestamp, '-infinity')
+                ) AS "new_comments!",
+                (
+                    SELECT COUNT(*) FROM entity_reports
+                    WHERE NOT resolved AND created_at > COALESCE($1::timestamp, '-infinity')
+                ) AS "new_reports!"
+            "#,
+            since
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending_entities == 0 && self.pending_comments == 0 && self.pending_reports == 0
+    }
+
+    pub fn template_values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("pending_entities", self.pending_entities.to_string()),
+            ("pending_comments", self.pending_comments.to_string()),
+            ("pending_reports", self.pending_reports.to_string()),
+            ("new_entities", self.new_entities.to_string()),
+            ("new_comments", self.new_comments.to_string()),
+            ("new_reports", self.new_reports.to_string()),
+        ]
+    }
+}
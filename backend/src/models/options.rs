@@ -9,6 +9,8 @@ pub struct SafeHavenOptions {
     pub general: GeneralOptions,
     pub init_popup: InitPopupOptions,
     pub safe_mode: SafeModeConfig,
+    pub spam_filter: SpamFilterConfig,
+    pub moderation: ModerationConfig,
     pub cartography_init: CartographyInitConfig,
     pub cartography_source: CartographySourceConfig,
     pub cartography_cluster: CartographyClusterConfig,
@@ -20,6 +22,8 @@ pub enum ConfigurationOption {
     General(GeneralOptions),
     InitPopup(InitPopupOptions),
     SafeMode(SafeModeConfig),
+    SpamFilter(SpamFilterConfig),
+    Moderation(ModerationConfig),
     CartographyInit(CartographyInitConfig),
     CartographySource(CartographySourceConfig),
     CartographyCluster(CartographyClusterConfig),
@@ -31,6 +35,8 @@ impl ConfigurationOption {
             ConfigurationOption::General(_) => GeneralOptions::option_name(),
             ConfigurationOption::InitPopup(_) => InitPopupOptions::option_name(),
             ConfigurationOption::SafeMode(_) => SafeModeConfig::option_name(),
+            ConfigurationOption::SpamFilter(_) => SpamFilterConfig::option_name(),
+            ConfigurationOption::Moderation(_) => ModerationConfig::option_name(),
             ConfigurationOption::CartographyInit(_) => CartographyInitConfig::option_name(),
             ConfigurationOption::CartographySource(_) => CartographySourceConfig::option_name(),
             ConfigurationOption::CartographyCluster(_) => CartographyClusterConfig::option_name(),
@@ -83,17 +89,34 @@ impl OptionConfig for InitPopupOptions {
     }
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, ToSchema, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    #[serde(rename = "hcaptcha")]
+    HCaptcha,
+    Turnstile,
+    FriendlyCaptcha,
+    /// Built-in proof-of-work challenge, without any third party
+    ProofOfWork,
+}
+
 #[derive(Deserialize, Serialize, Clone, ToSchema, Debug)]
 #[serde(default)]
-/// Safe mode configuration, when enabled, the application will require hcaptcha validation for
+/// Safe mode configuration, when enabled, the application will require captcha validation for
 /// almost every action making it harder to spam the application or dump the database content
 pub struct SafeModeConfig {
     /// Enable safe mode
     pub enabled: bool,
-    /// Secret key for hcaptcha
-    pub hcaptcha_secret: String,
-    /// Site key for hcaptcha
-    pub hcaptcha_sitekey: String,
+    /// Captcha provider used to validate the requests
+    pub captcha_provider: CaptchaProvider,
+    /// Secret key of the captcha provider
+    pub captcha_secret: String,
+    /// Site key of the captcha provider
+    pub captcha_sitekey: String,
+    /// Overrides the verification URL of the captcha provider
+    pub captcha_verify_url: Option<String>,
+    /// Number of leading zero bits required by the proof-of-work challenges
+    pub proof_of_work_difficulty: u8,
 }
 
 impl OptionConfig for SafeModeConfig {
@@ -106,8 +129,82 @@ impl Default for SafeModeConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            hcaptcha_sitekey: "".to_string(),
-            hcaptcha_secret: "".to_string(),
+            captcha_provider: CaptchaProvider::HCaptcha,
+            captcha_sitekey: "".to_string(),
+            captcha_secret: "".to_string(),
+            captcha_verify_url: None,
+            proof_of_work_difficulty: 18,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, Debug)]
+#[serde(default)]
+/// Scoring of the public submissions, the score and its reasons are shown to the moderators
+pub struct SpamFilterConfig {
+    /// Enable the scoring of public submissions
+    pub enabled: bool,
+    /// Submissions from the same client address within an hour before being suspicious
+    pub max_hourly_submissions_per_client: i64,
+    /// Submissions with the same access token within an hour before being suspicious
+    pub max_hourly_submissions_per_token: i64,
+    /// Maximal distance in meters between two entities with similar names to be duplicates
+    pub duplicate_distance: f64,
+    /// Links allowed in a submission before being suspicious
+    pub max_links: usize,
+    /// Words making a submission suspicious (case and accent insensitive)
+    pub banned_words: Vec<String>,
+    /// Submissions reaching this score are stored as rejected until appealed, none if empty
+    pub reject_threshold: Option<f32>,
+}
+
+impl OptionConfig for SpamFilterConfig {
+    fn option_name() -> &'static str {
+        "spam_filter"
+    }
+}
+
+impl Default for SpamFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_hourly_submissions_per_client: 5,
+            max_hourly_submissions_per_token: 200,
+            duplicate_distance: 100.,
+            max_links: 2,
+            banned_words: vec![],
+            reject_threshold: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, Debug)]
+#[serde(default)]
+/// Moderation workflow of entities and comments
+pub struct ModerationConfig {
+    /// Days a rejected submission is kept for an appeal before being deleted
+    pub appeal_period_days: i32,
+    /// Distinct clients reporting an entity for it to be hidden until moderated, never if empty
+    pub reports_hide_threshold: Option<i64>,
+    /// Hours the reports count toward the hiding threshold
+    pub reports_hide_window_hours: i32,
+    /// Entities that can be hidden by reports in an hour, the others wait for a moderator
+    pub max_hourly_reports_hides: i64,
+}
+
+impl OptionConfig for ModerationConfig {
+    fn option_name() -> &'static str {
+        "moderation"
+    }
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            appeal_period_days: 30,
+            reports_hide_threshold: None,
+            reports_hide_window_hours: 48,
+            max_hourly_reports_hides: 3,
         }
     }
 }
@@ -268,6 +365,12 @@ impl SafeHavenOptions {
         let safe_mode = Self::fetch_option::<SafeModeConfig>(conn)
             .await
             .expect("Failed to load safe mode");
+        let spam_filter = Self::fetch_option::<SpamFilterConfig>(conn)
+            .await
+            .expect("Failed to load spam filter");
+        let moderation = Self::fetch_option::<ModerationConfig>(conn)
+            .await
+            .expect("Failed to load moderation");
         let cartography_init = Self::fetch_option::<CartographyInitConfig>(conn)
             .await
             .expect("Failed to load cartography init");
@@ -282,6 +385,8 @@ impl SafeHavenOptions {
             general,
             init_popup,
             safe_mode,
+            spam_filter,
+            moderation,
             cartography_init,
             cartography_source,
             cartography_cluster,
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, Acquire, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::AppError;
+use crate::models::entity_cache::CacheRefreshStats;
+use crate::models::options::ModerationConfig;
+use crate::models::spam::SubmissionOrigin;
+
+/// Maximum length of the details of a report, in characters
+pub const MAX_REPORT_DETAILS_LENGTH: usize = 1000;
+
+#[derive(sqlx::Type, Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[sqlx(type_name = "report_reason", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Harmful,
+    WrongInformation,
+    Closed,
+    Duplicate,
+    Spam,
+    Other,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct PublicNewReport {
+    pub reason: ReportReason,
+    #[schema(max_length = 1000)]
+    pub details: Option<String>,
+}
+
+impl PublicNewReport {
+    fn validate(&self) -> Result<(), AppError> {
+        if self
+            .details
+            .as_ref()
+            .is_some_and(|details| details.chars().count() > MAX_REPORT_DETAILS_LENGTH)
+        {
+            return Err(AppError::Validation(format!(
+                "Details cannot exceed {} characters",
+                MAX_REPORT_DETAILS_LENGTH
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct AdminReport {
+    pub id: Uuid,
+    pub entity_id: Uuid,
+    pub reason: ReportReason,
+    pub details: Option<String>,
+    pub resolved: bool,
+    pub resolved_by: Option<Uuid>,
+    pub resolved_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct AdminResolveReports {
+    /// Makes the entity visible again, when hidden
+    pub unhide: bool,
+}
+
+/// Unresolved reports of an entity
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct AdminReportedEntity {
+    pub entity_id: Uuid,
+    pub entity_display_name: String,
+    pub entity_category_id: Uuid,
+    pub entity_hidden: bool,
+    /// When the entity was hidden automatically because of its reports
+    pub entity_reports_hidden_at: Option<chrono::NaiveDateTime>,
+    pub reports_count: i64,
+    /// Distinct clients the reports come from
+    pub reporters_count: i64,
+    /// Number of reports by reason
+    #[schema(value_type = HashMap<String, i64>)]
+    pub reasons: Json<HashMap<ReportReason, i64>>,
+    pub last_reported_at: chrono::NaiveDateTime,
+}
+
+pub struct EntityReport;
+
+impl EntityReport {
+    /// Registers a report, and hides the entity once reported by enough distinct clients within
+    /// the reports window, unless too many entities were hidden this way in the last hour.
+    /// Returns whether the entity was hidden by this report.
+    pub async fn submit(
+        entity_id: Uuid,
+        report: PublicNewReport,
+        origin: &SubmissionOrigin,
+        config: &ModerationConfig,
+        conn: &mut PgConnection,
+    ) -> Result<bool, AppError> {
+        report.validate()?;
+
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+        let reporters_count = sqlx::query_scalar!(
+            r#"
+            WITH inserted AS (
+                INSERT INTO entity_reports (entity_id, reason, details, client_hash, access_token_id)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING client_hash
+            )
+            SELECT COUNT(DISTINCT client_hash) AS "count!"
+            FROM (
+                SELECT client_hash
+                FROM entity_reports
+                WHERE entity_id = $1 AND NOT resolved
+                    AND created_at >= NOW() - make_interval(hours => $6)
+                UNION ALL
+                SELECT client_hash FROM inserted
+            ) reporters
+            "#,
+            entity_id,
+            report.reason as ReportReason,
+            report.details.filter(|details| !details.trim().is_empty()),
+            origin.client_hash,
+            origin.access_token_id,
+            config.reports_hide_window_hours
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let hidden = match config.reports_hide_threshold {
+            Some(threshold) if reporters_count >= threshold => {
+                CacheRefreshStats::trace(&mut tx, async |conn| {
+                    sqlx::query!(
+                        r#"
+                        UPDATE entities
+                        SET hidden = TRUE, reports_hidden_at = CURRENT_TIMESTAMP
+                        WHERE id = $1 AND NOT hidden
+                            AND (
+                                SELECT COUNT(*) FROM entities
+                                WHERE reports_hidden_at >= NOW() - INTERVAL '1 hour'
+                            ) < $2
+                        "#,
+                        entity_id,
+                        config.max_hourly_reports_hides
+                    )
+                    .execute(conn)
+                    .await
+                    .map_err(AppError::Database)
+                })
+                .await?
+                .rows_affected()
+                    > 0
+            }
+            _ => false,
+        };
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(hidden)
+    }
+
+    pub async fn list_reported_entities(
+        conn: &mut PgConnection,
+    ) -> Result<Vec<AdminReportedEntity>, AppError> {
+        sqlx::query_as!(
+            AdminReportedEntity,
+            r#"
+            SELECT e.id AS entity_id, e.display_name AS entity_display_name,
+                e.category_id AS entity_category_id, e.hidden AS entity_hidden,
+                e.reports_hidden_at AS entity_reports_hidden_at,
+                COUNT(*) AS "reports_count!",
+                COUNT(DISTINCT r.client_hash) AS "reporters_count!",
+                (
+                    SELECT jsonb_object_agg(reason, reports)
+                    FROM (
+                        SELECT reason, COUNT(*) AS reports
+                        FROM entity_reports
+                        WHERE entity_id = e.id AND NOT resolved
+                        GROUP BY reason
+                    ) reasons
+                ) AS "reasons!: Json<HashMap<ReportReason, i64>>",
+                MAX(r.created_at) AS "last_reported_at!"
+            FROM entity_reports r
+            INNER JOIN entities e ON e.id = r.entity_id
+            WHERE NOT r.resolved
+            GROUP BY e.id
+            ORDER BY COUNT(DISTINCT r.client_hash) DESC, MAX(r.created_at) DESC
+            "#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn list_for_entity(
+        entity_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<AdminReport>, AppError> {
+        sqlx::query_as!(
+            AdminReport,
+            r#"
+            SELECT id, entity_id, reason AS "reason: ReportReason", details, resolved,
+                resolved_by, resolved_at, created_at
+            FROM entity_reports
+            WHERE entity_id = $1
+            ORDER BY created_at DESC
+            "#,
+            entity_id
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Marks the reports of the entity as handled, and makes it visible again if asked to
+    pub async fn resolve_for_entity(
+        entity_id: Uuid,
+        resolution: AdminResolveReports,
+        user_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+        sqlx::query!(
+            r#"
+            UPDATE entity_reports
+            SET resolved = TRUE, resolved_by = $2, resolved_at = CURRENT_TIMESTAMP
+            WHERE entity_id = $1 AND NOT resolved
+            "#,
+            entity_id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        if resolution.unhide {
+            CacheRefreshStats::trace(&mut tx, async |conn| {
+                sqlx::query!(
+                    r#"
+                    UPDATE entities
+                    SET hidden = FALSE
+                    WHERE id = $1 AND hidden
+                    "#,
+                    entity_id
+                )
+                .execute(conn)
+                .await
+                .map_err(AppError::Database)
+            })
+            .await?;
+        }
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    const FIRST_ENTITY_ID: Uuid = Uuid::from_u128(0xe0000000_0000_0000_0000_000000000001);
+    const SECOND_ENTITY_ID: Uuid = Uuid::from_u128(0xe0000000_0000_0000_0000_000000000002);
+
+    fn origin(client: &str, token: u128) -> SubmissionOrigin {
+        SubmissionOrigin {
+            client_hash: client.to_string(),
+            access_token_id: Uuid::from_u128(0xa0000000_0000_0000_0000_000000000000 + token),
+        }
+    }
+
+    async fn report(
+        entity_id: Uuid,
+        client: &str,
+        token: u128,
+        config: &ModerationConfig,
+        conn: &mut PgConnection,
+    ) -> bool {
+        let report = PublicNewReport {
+            reason: ReportReason::Closed,
+            details: None,
+        };
+        EntityReport::submit(entity_id, report, &origin(client, token), config, conn)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_reports_hide_entities(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let config = ModerationConfig {
+            reports_hide_threshold: Some(2),
+            max_hourly_reports_hides: 1,
+            ..Default::default()
+        };
+
+        // Each client counts once, whatever the access token it reports through
+        assert!(!report(FIRST_ENTITY_ID, "first", 1, &config, &mut conn).await);
+        assert!(!report(FIRST_ENTITY_ID, "first", 2, &config, &mut conn).await);
+        assert!(report(FIRST_ENTITY_ID, "second", 1, &config, &mut conn).await);
+        assert!(!report(FIRST_ENTITY_ID, "third", 1, &config, &mut conn).await);
+
+        // Over the hourly cap, the entities wait for a moderator
+        assert!(!report(SECOND_ENTITY_ID, "first", 1, &config, &mut conn).await);
+        assert!(!report(SECOND_ENTITY_ID, "second", 1, &config, &mut conn).await);
+
+        let hidden: Vec<Uuid> =
+            sqlx::query_scalar("SELECT id FROM entities WHERE hidden ORDER BY id")
+                .fetch_all(&mut *conn)
+                .await
+                .unwrap();
+        assert_eq!(hidden, [FIRST_ENTITY_ID]);
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_reports_of_a_shared_token_hide_entities(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let config = ModerationConfig {
+            reports_hide_threshold: Some(3),
+            ..Default::default()
+        };
+
+        // A public map is served through a single access token
+        assert!(!report(FIRST_ENTITY_ID, "first", 1, &config, &mut conn).await);
+        assert!(!report(FIRST_ENTITY_ID, "second", 1, &config, &mut conn).await);
+        assert!(report(FIRST_ENTITY_ID, "third", 1, &config, &mut conn).await);
+    }
+
+    #[sqlx::test(fixtures("entities"))]
+    async fn test_reports_hiding_is_opt_in(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let config = ModerationConfig::default();
+
+        for client in ["first", "second", "third"] {
+            assert!(!report(FIRST_ENTITY_ID, client, 1, &config, &mut conn).await);
+        }
+    }
+}
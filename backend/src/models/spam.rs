@@ -0,0 +1,278 @@
+use std::net::IpAddr;
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    api::AppError,
+    models::{comment::PublicNewComment, entity::PublicNewEntity, options::SpamFilterConfig},
+};
+
+const CLIENT_BURST_SCORE: f32 = 0.5;
+const TOKEN_BURST_SCORE: f32 = 0.2;
+const DUPLICATE_SCORE: f32 = 0.6;
+const LINKS_SCORE: f32 = 0.3;
+const BANNED_WORD_SCORE: f32 = 0.5;
+
+/// Client and access token a public submission comes from
+pub struct SubmissionOrigin {
+    /// Salted hash of the client address, the address itself is never stored
+    pub client_hash: String,
+    pub access_token_id: Uuid,
+}
+
+impl SubmissionOrigin {
+    pub fn new(client_ip: IpAddr, access_token_id: Uuid, secret: &str) -> Self {
+        let client_hash = format!(
+            "{:x}",
+            Sha256::digest(format!("{}{}", secret, client_ip).as_bytes())
+        );
+
+        Self {
+            client_hash,
+            access_token_id,
+        }
+    }
+
+    /// Registers a submission from this origin, and forgets the ones older than a day
+    pub async fn record(&self, conn: &mut PgConnection) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            WITH pruned AS (
+                DELETE FROM public_submissions
+                WHERE created_at < NOW() - INTERVAL '1 day'
+            )
+            INSERT INTO public_submissions (client_hash, access_token_id)
+            VALUES ($1, $2)
+            "#,
+            self.client_hash,
+            self.access_token_id
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// Reason of the moderation decision of the submissions rejected by the spam filter
+pub const SPAM_REJECTION_REASON: &str = "Rejected by the spam filter";
+
+/// Spam score of a public submission, with the reasons explaining it
+#[derive(Clone, Debug, Default)]
+pub struct SpamAssessment {
+    pub score: f32,
+    pub reasons: Vec<String>,
+    /// The score reached the rejection threshold, the submission is stored as rejected
+    pub rejected: bool,
+}
+
+/// Collects the text of the string values of a form data object
+fn data_texts(data: &Value, texts: &mut Vec<String>) {
+    match data {
+        Value::String(s) => texts.push(s.clone()),
+        Value::Array(values) => values.iter().for_each(|v| data_texts(v, texts)),
+        Value::Object(values) => values.values().for_each(|v| data_texts(v, texts)),
+        _ => {}
+    }
+}
+
+impl SpamAssessment {
+    pub async fn for_entity(
+        entity: &PublicNewEntity,
+        origin: &SubmissionOrigin,
+        config: &SpamFilterConfig,
+        conn: &mut PgConnection,
+    ) -> Result<Self, AppError> {
+        let mut assessment = Self::default();
+        assessment.assess_origin(origin, config, conn).await?;
+
+        let locations = serde_json::to_value(&entity.locations).unwrap();
+        let duplicates = sqlx::query_scalar!(
+            r#"
+            SELECT e.id
+            FROM entities e
+            JOIN categories c ON c.id = e.category_id
+            WHERE c.family_id = (SELECT family_id FROM categories WHERE id = $1)
+                AND immutable_unaccent(e.display_name) % immutable_unaccent($2)
+                AND (
+                    -- Similar name close to one of the new locations
+                    (
+                        similarity(immutable_unaccent(e.display_name), immutable_unaccent($2)) >= 0.6
+                        AND EXISTS (
+                            SELECT 1
+                            FROM jsonb_array_elements(e.locations) existing,
+                                jsonb_array_elements($3::jsonb) new
+                            WHERE ST_DWithin(
+                                ST_Transform(ST_SetSRID(ST_MakePoint(
+                                    (existing->>'long')::float8, (existing->>'lat')::float8
+                                ), 4326), 3857),
+                                ST_Transform(ST_SetSRID(ST_MakePoint(
+                                    (new->>'long')::float8, (new->>'lat')::float8
+                                ), 4326), 3857),
+                                -- Web Mercator distances are stretched by 1 / cos(latitude)
+                                $4 / cos(radians((new->>'lat')::float8))
+                            )
+                        )
+                    )
+                    -- Almost the same name when there is no location to compare
+                    OR (
+                        jsonb_array_length($3::jsonb) = 0
+                        AND similarity(immutable_unaccent(e.display_name), immutable_unaccent($2)) >= 0.9
+                    )
+                )
+            ORDER BY similarity(immutable_unaccent(e.display_name), immutable_unaccent($2)) DESC
+            LIMIT 5
+            "#,
+            entity.category_id,
+            entity.display_name,
+            locations,
+            config.duplicate_distance
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        for duplicate in duplicates {
+            assessment.add(DUPLICATE_SCORE, format!("near_duplicate:{}", duplicate));
+        }
+
+        let mut texts = vec![entity.display_name.clone()];
+        data_texts(&entity.data, &mut texts);
+        assessment.assess_texts(texts, config, conn).await?;
+        assessment.apply_threshold(config);
+
+        Ok(assessment)
+    }
+
+    pub async fn for_comment(
+        comment: &PublicNewComment,
+        origin: &SubmissionOrigin,
+        config: &SpamFilterConfig,
+        conn: &mut PgConnection,
+    ) -> Result<Self, AppError> {
+        let mut assessment = Self::default();
+        assessment.assess_origin(origin, config, conn).await?;
+
+        let duplicates = sqlx::query_scalar!(
+            r#"
+            SELECT id
+            FROM comments
+            WHERE entity_id = $1 AND text % $2 AND similarity(text, $2) >= 0.9
+            ORDER BY similarity(text, $2) DESC
+            LIMIT 5
+            "#,
+            comment.entity_id,
+            comment.text
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        for duplicate in duplicates {
+            assessment.add(DUPLICATE_SCORE, format!("near_duplicate:{}", duplicate));
+        }
+
+        let mut texts = vec![comment.author.clone(), comment.text.clone()];
+        data_texts(&comment.data, &mut texts);
+        assessment.assess_texts(texts, config, conn).await?;
+        assessment.apply_threshold(config);
+
+        Ok(assessment)
+    }
+
+    fn apply_threshold(&mut self, config: &SpamFilterConfig) {
+        self.rejected = config
+            .reject_threshold
+            .is_some_and(|threshold| self.score >= threshold);
+    }
+
+    fn add(&mut self, score: f32, reason: String) {
+        self.score += score;
+        self.reasons.push(reason);
+    }
+
+    async fn assess_origin(
+        &mut self,
+        origin: &SubmissionOrigin,
+        config: &SpamFilterConfig,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        let counts = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE client_hash = $1) AS "client_submissions!",
+                COUNT(*) FILTER (WHERE access_token_id = $2) AS "token_submissions!"
+            FROM public_submissions
+            WHERE created_at > NOW() - INTERVAL '1 hour'
+                AND (client_hash = $1 OR access_token_id = $2)
+            "#,
+            origin.client_hash,
+            origin.access_token_id
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        if counts.client_submissions >= config.max_hourly_submissions_per_client {
+            self.add(CLIENT_BURST_SCORE, "client_burst".to_string());
+        }
+        if counts.token_submissions >= config.max_hourly_submissions_per_token {
+            self.add(TOKEN_BURST_SCORE, "token_burst".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn assess_texts(
+        &mut self,
+        texts: Vec<String>,
+        config: &SpamFilterConfig,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        let signals = sqlx::query!(
+            r#"
+            SELECT
+                (
+                    SELECT COUNT(*)
+                    FROM unnest($1::text[]) t, regexp_matches(t, '(https?://|www\.)\S+', 'gi')
+                ) AS "links!",
+                array(
+                    SELECT w
+                    FROM unnest($2::text[]) w
+                    WHERE trim(w) <> '' AND EXISTS (
+                        SELECT 1
+                        FROM unnest($1::text[]) t
+                        WHERE immutable_unaccent(lower(t)) ~ (
+                            '(^|[^[:alnum:]_])'
+                            || regexp_replace(
+                                immutable_unaccent(lower(trim(w))),
+                                '([^[:alnum:][:space:]])',
+                                '\\\1',
+                                'g'
+                            )
+                            || '([^[:alnum:]_]|$)'
+                        )
+                    )
+                ) AS "banned_words!"
+            "#,
+            &texts,
+            &config.banned_words
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        if signals.links > config.max_links as i64 {
+            self.add(LINKS_SCORE, format!("too_many_links:{}", signals.links));
+        }
+        for word in signals.banned_words {
+            self.add(BANNED_WORD_SCORE, format!("banned_word:{}", word));
+        }
+
+        Ok(())
+    }
+}
@@ -1,10 +1,15 @@
 // Note : this file is not an actual model, but instead contains a collection of statistical queries on the other models
 
 use crate::api::AppError;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use std::collections::{BTreeMap, HashMap};
 use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Queries searched fewer times over the period are not reported, as they are more likely to
+/// identify who searched them
+const MIN_REPORTED_QUERY_SEARCHES: i64 = 3;
 
 pub type CountResult = (
     HashMap<String, (u32, u32, u32, u32)>,
@@ -101,10 +106,16 @@ pub async fn home_page_stats(conn: &mut PgConnection) -> Result<HomePageStats, A
         SELECT
             (SELECT COUNT(*) FROM entities WHERE moderated) AS "total_entities!",
             (SELECT COUNT(*) FROM comments WHERE moderated) AS "total_comments!",
-            (SELECT COUNT(*) FROM entities WHERE NOT moderated) AS "pending_entities!",
-            (SELECT COUNT(*) FROM comments WHERE NOT moderated) AS "pending_comments!",
-            (SELECT COUNT(*) FROM access_tokens_visits WHERE visited_at >= NOW()::date - INTERVAL '30 days') AS "total_visits_30_days!",
-            (SELECT COUNT(*) FROM access_tokens_visits WHERE visited_at >= NOW()::date - INTERVAL '7 days') AS "total_visits_7_days!",
+            (SELECT COUNT(*) FROM entities WHERE moderation_status = 'pending') AS "pending_entities!",
+            (SELECT COUNT(*) FROM comments WHERE moderation_status = 'pending') AS "pending_comments!",
+            (
+                SELECT COALESCE(SUM(visits), 0)::bigint FROM access_tokens_daily_visits
+                WHERE visit_date >= NOW()::date - INTERVAL '30 days'
+            ) AS "total_visits_30_days!",
+            (
+                SELECT COALESCE(SUM(visits), 0)::bigint FROM access_tokens_daily_visits
+                WHERE visit_date >= NOW()::date - INTERVAL '7 days'
+            ) AS "total_visits_7_days!",
             (
                 WITH date_series AS (
                     SELECT generate_series(
@@ -116,13 +127,13 @@ pub async fn home_page_stats(conn: &mut PgConnection) -> Result<HomePageStats, A
                 aggregated_visits AS (
                     SELECT
                         ds.visit_date,
-                        COALESCE(COUNT(atv.visited_at), 0) AS visit_count
+                        COALESCE(SUM(dv.visits), 0) AS visit_count
                     FROM
                         date_series ds
                     LEFT JOIN
-                        access_tokens_visits atv
+                        access_tokens_daily_visits dv
                     ON
-                        ds.visit_date = DATE(atv.visited_at)
+                        ds.visit_date = dv.visit_date
                     WHERE
                         ds.visit_date >= NOW()::date - INTERVAL '30 days'
                     GROUP BY
@@ -159,3 +170,252 @@ pub async fn home_page_stats(conn: &mut PgConnection) -> Result<HomePageStats, A
         visits_30_days,
     })
 }
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct VisitorsStats {
+    /// Approximate unique visitors of each of the last 30 days. The visitors of several access
+    /// tokens are counted once per token.
+    pub visitors_30_days: BTreeMap<String, i64>,
+    /// Visits of the last 30 days by device class (bot, tablet, mobile, desktop or unknown)
+    pub devices_30_days: HashMap<String, i64>,
+    /// Visits of the last 30 days by preferred language
+    pub languages_30_days: HashMap<String, i64>,
+}
+
+fn deserialize_json<T: for<'de> Deserialize<'de>>(
+    value: serde_json::Value,
+    name: &str,
+) -> Result<T, AppError> {
+    serde_json::from_value(value).map_err(|err| {
+        AppError::Internal(format!("Failed to deserialize {}: {}", name, err).into())
+    })
+}
+
+/// Visitors statistics of all the access tokens, or only of the given one
+pub async fn visitors_stats(
+    token_id: Option<Uuid>,
+    conn: &mut PgConnection,
+) -> Result<VisitorsStats, AppError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            (
+                WITH date_series AS (
+                    SELECT generate_series(
+                        NOW()::date - INTERVAL '30 days',
+                        NOW()::date,
+                        INTERVAL '1 day'
+                    )::date AS visit_date
+                )
+                SELECT json_object_agg(TO_CHAR(ds.visit_date, 'YYYY-MM-DD'), COALESCE((
+                    SELECT SUM(dv.visitors) FROM access_tokens_daily_visitors dv
+                    WHERE dv.visit_date = ds.visit_date AND ($1::uuid IS NULL OR dv.token_id = $1)
+                ), 0))
+                FROM date_series ds
+            ) AS "visitors_30_days!",
+            (
+                SELECT COALESCE(json_object_agg(device_class, visits), '{}')
+                FROM (
+                    SELECT device_class, SUM(visits) AS visits FROM access_tokens_daily_visits
+                    WHERE visit_date >= NOW()::date - INTERVAL '30 days'
+                        AND ($1::uuid IS NULL OR token_id = $1)
+                    GROUP BY device_class
+                ) devices
+            ) AS "devices_30_days!",
+            (
+                SELECT COALESCE(json_object_agg(language, visits), '{}')
+                FROM (
+                    SELECT language, SUM(visits) AS visits FROM access_tokens_daily_visits
+                    WHERE visit_date >= NOW()::date - INTERVAL '30 days'
+                        AND ($1::uuid IS NULL OR token_id = $1)
+                    GROUP BY language
+                ) languages
+            ) AS "languages_30_days!"
+        "#,
+        token_id
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(VisitorsStats {
+        visitors_30_days: deserialize_json(result.visitors_30_days, "visitors_30_days")?,
+        devices_30_days: deserialize_json(result.devices_30_days, "devices_30_days")?,
+        languages_30_days: deserialize_json(result.languages_30_days, "languages_30_days")?,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ViewsCount {
+    /// Requests displaying the entities on the map
+    pub map_views: i64,
+    /// Openings of an entity
+    pub entity_views: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ViewsStats {
+    /// Views of the last 30 days by family id
+    pub families_30_days: HashMap<String, ViewsCount>,
+    /// Views of the last 30 days by category id, a map view counts for each displayed category
+    pub categories_30_days: HashMap<String, ViewsCount>,
+}
+
+pub async fn views_stats(conn: &mut PgConnection) -> Result<ViewsStats, AppError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            (
+                SELECT COALESCE(json_object_agg(
+                    family_id,
+                    json_build_object('map_views', map_views, 'entity_views', entity_views)
+                ), '{}')
+                FROM (
+                    SELECT family_id, SUM(map_views) AS map_views, SUM(entity_views) AS entity_views
+                    FROM families_views_daily
+                    WHERE view_date >= NOW()::date - INTERVAL '30 days'
+                    GROUP BY family_id
+                ) families
+            ) AS "families_30_days!",
+            (
+                SELECT COALESCE(json_object_agg(
+                    category_id,
+                    json_build_object('map_views', map_views, 'entity_views', entity_views)
+                ), '{}')
+                FROM (
+                    SELECT category_id, SUM(map_views) AS map_views, SUM(entity_views) AS entity_views
+                    FROM categories_views_daily
+                    WHERE view_date >= NOW()::date - INTERVAL '30 days'
+                    GROUP BY category_id
+                ) categories
+            ) AS "categories_30_days!"
+        "#
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(ViewsStats {
+        families_30_days: deserialize_json(result.families_30_days, "families_30_days")?,
+        categories_30_days: deserialize_json(result.categories_30_days, "categories_30_days")?,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SearchedQuery {
+    /// Normalised query: lowercase, unaccented and with single spaces
+    pub query: String,
+    pub searches: i64,
+    /// Searches of the query which did not find any entity
+    pub zero_result_searches: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TrendingQuery {
+    pub query: String,
+    pub searches: i64,
+    /// Searches of the query over the period of the same length just before
+    pub previous_searches: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SearchesStats {
+    /// Searches of each day of the period
+    pub searches_per_day: BTreeMap<String, i64>,
+    /// Most searched queries of the period
+    pub top_queries: Vec<SearchedQuery>,
+    /// Most searched queries of the period which never found any entity
+    pub zero_result_queries: Vec<SearchedQuery>,
+    /// Queries whose searches grew the most compared to the previous period
+    pub trending_queries: Vec<TrendingQuery>,
+}
+
+/// Statistics of the logged searches over the last days, of all the families or only of the given
+/// one. The queries are compared with the period of the same length just before.
+pub async fn searches_stats(
+    family_id: Option<Uuid>,
+    days: i32,
+    limit: i64,
+    conn: &mut PgConnection,
+) -> Result<SearchesStats, AppError> {
+    let result = sqlx::query!(
+        r#"
+        WITH searches AS (
+            SELECT
+                query,
+                results_count,
+                searched_at,
+                searched_at > NOW() - make_interval(days => $2) AS current
+            FROM searches_log
+            WHERE searched_at > NOW() - make_interval(days => 2 * $2)
+                AND ($1::uuid IS NULL OR family_id = $1)
+        ),
+        queries AS (
+            SELECT
+                query,
+                COUNT(*) FILTER (WHERE current) AS searches,
+                COUNT(*) FILTER (WHERE current AND results_count = 0) AS zero_result_searches,
+                COUNT(*) FILTER (WHERE NOT current) AS previous_searches
+            FROM searches
+            GROUP BY query
+            HAVING COUNT(*) FILTER (WHERE current) >= $4
+        ),
+        searches_per_day AS (
+            SELECT searched_at::date AS search_date, COUNT(*) AS searches
+            FROM searches
+            WHERE current
+            GROUP BY searched_at::date
+        )
+        SELECT
+            (
+                SELECT json_object_agg(
+                    TO_CHAR(ds.search_date, 'YYYY-MM-DD'),
+                    COALESCE(spd.searches, 0)
+                )
+                FROM generate_series(
+                    (NOW() - make_interval(days => $2))::date,
+                    NOW()::date,
+                    INTERVAL '1 day'
+                ) AS ds(search_date)
+                LEFT JOIN searches_per_day spd ON spd.search_date = ds.search_date::date
+            ) AS "searches_per_day!",
+            (
+                SELECT COALESCE(json_agg(q), '[]') FROM (
+                    SELECT query, searches, zero_result_searches FROM queries
+                    ORDER BY searches DESC, query
+                    LIMIT $3
+                ) q
+            ) AS "top_queries!",
+            (
+                SELECT COALESCE(json_agg(q), '[]') FROM (
+                    SELECT query, searches, zero_result_searches FROM queries
+                    WHERE zero_result_searches = searches
+                    ORDER BY searches DESC, query
+                    LIMIT $3
+                ) q
+            ) AS "zero_result_queries!",
+            (
+                SELECT COALESCE(json_agg(q), '[]') FROM (
+                    SELECT query, searches, previous_searches FROM queries
+                    WHERE searches > previous_searches
+                    ORDER BY searches - previous_searches DESC, searches DESC, query
+                    LIMIT $3
+                ) q
+            ) AS "trending_queries!"
+        "#,
+        family_id,
+        days,
+        limit,
+        MIN_REPORTED_QUERY_SEARCHES
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(SearchesStats {
+        searches_per_day: deserialize_json(result.searches_per_day, "searches_per_day")?,
+        top_queries: deserialize_json(result.top_queries, "top_queries")?,
+        zero_result_queries: deserialize_json(result.zero_result_queries, "zero_result_queries")?,
+        trending_queries: deserialize_json(result.trending_queries, "trending_queries")?,
+    })
+}
@@ -0,0 +1,468 @@
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use serde_json::{to_value, Map, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json, Acquire, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::AppError;
+use crate::models::entity::{PublicNewEntity, UnprocessedLocation};
+use crate::models::entity_cache::CacheRefreshStats;
+use crate::models::family::Family;
+use crate::models::moderation::ModerationStatus;
+
+const TRACKING_CODE_LENGTH: usize = 32;
+
+/// Secret code given to the submitter of a public entity, only its hash is stored
+pub struct TrackingCode;
+
+impl TrackingCode {
+    pub fn generate() -> String {
+        Alphanumeric.sample_string(&mut rand::thread_rng(), TRACKING_CODE_LENGTH)
+    }
+
+    pub fn hash(code: &str) -> String {
+        format!("{:x}", Sha256::digest(code.as_bytes()))
+    }
+}
+
+/// Fields of an entity changed by a suggestion, the other ones are left untouched
+#[derive(Deserialize, Serialize, ToSchema, Debug, Default)]
+pub struct SuggestedChanges {
+    pub display_name: Option<String>,
+    pub category_id: Option<Uuid>,
+    pub locations: Option<Vec<UnprocessedLocation>>,
+    /// Changed values of the user facing fields of the entity form
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub data: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct FieldChange {
+    /// display_name, category_id, locations, or data.<key> for the fields of the entity form
+    pub field: String,
+    pub current: Value,
+    pub proposed: Value,
+}
+
+/// Live version of an entity, to compare and apply the suggestions against
+struct LiveEntity {
+    display_name: String,
+    category_id: Uuid,
+    locations: Value,
+    data: Value,
+}
+
+impl SuggestedChanges {
+    /// Keeps the fields of a proposed version of the entity that differ from its live version.
+    /// Only the user facing fields of the data can be changed.
+    async fn between(
+        live: &LiveEntity,
+        proposal: PublicNewEntity,
+        conn: &mut PgConnection,
+    ) -> Result<Self, AppError> {
+        let family = Family::get_from_category(live.category_id, conn).await?;
+
+        if proposal.category_id != live.category_id
+            && Family::get_from_category(proposal.category_id, conn)
+                .await?
+                .id
+                != family.id
+        {
+            return Err(AppError::Validation(
+                "The category must belong to the family of the entity".to_string(),
+            ));
+        }
+
+        let mut changes = SuggestedChanges::default();
+
+        if proposal.display_name != live.display_name {
+            changes.display_name = Some(proposal.display_name);
+        }
+        if proposal.category_id != live.category_id {
+            changes.category_id = Some(proposal.category_id);
+        }
+        if to_value(&proposal.locations).unwrap() != live.locations {
+            changes.locations = Some(proposal.locations);
+        }
+
+        for field in family.entity_form.fields.iter().filter(|f| f.user_facing) {
+            let proposed = proposal.data.get(&field.key).unwrap_or(&Value::Null);
+            let current = live.data.get(&field.key).unwrap_or(&Value::Null);
+
+            if proposed != current {
+                changes.data.insert(field.key.clone(), proposed.clone());
+            }
+        }
+
+        if changes.is_empty() {
+            return Err(AppError::Validation("No changes proposed".to_string()));
+        }
+
+        // The entity must stay valid once the changes are applied
+        let merged = changes.merged_data(&live.data);
+        family
+            .entity_form
+            .validate_data(&merged, changes.category_id.unwrap_or(live.category_id))?;
+
+        Ok(changes)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.display_name.is_none()
+            && self.category_id.is_none()
+            && self.locations.is_none()
+            && self.data.is_empty()
+    }
+
+    fn merged_data(&self, live_data: &Value) -> Value {
+        let mut data = live_data.as_object().cloned().unwrap_or_default();
+        for (key, value) in &self.data {
+            data.insert(key.clone(), value.clone());
+        }
+        Value::Object(data)
+    }
+
+    fn diff(&self, live: &LiveEntity) -> Vec<FieldChange> {
+        let mut changes = vec![];
+
+        if let Some(display_name) = &self.display_name {
+            changes.push(FieldChange {
+                field: "display_name".to_string(),
+                current: Value::String(live.display_name.clone()),
+                proposed: Value::String(display_name.clone()),
+            });
+        }
+        if let Some(category_id) = &self.category_id {
+            changes.push(FieldChange {
+                field: "category_id".to_string(),
+                current: to_value(live.category_id).unwrap(),
+                proposed: to_value(category_id).unwrap(),
+            });
+        }
+        if let Some(locations) = &self.locations {
+            changes.push(FieldChange {
+                field: "locations".to_string(),
+                current: live.locations.clone(),
+                proposed: to_value(locations).unwrap(),
+            });
+        }
+        for (key, proposed) in &self.data {
+            changes.push(FieldChange {
+                field: format!("data.{}", key),
+                current: live.data.get(key).cloned().unwrap_or(Value::Null),
+                proposed: proposed.clone(),
+            });
+        }
+
+        changes
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct AdminEntitySuggestion {
+    pub id: Uuid,
+    pub entity_id: Uuid,
+    pub entity_display_name: String,
+    pub entity_category_id: Uuid,
+    /// Proposed by the submitter of the entity
+    pub from_submitter: bool,
+    /// Changed fields, compared to the live version of the entity
+    pub changes: Vec<FieldChange>,
+    pub moderation_status: ModerationStatus,
+    pub moderation_reason: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Suggestion as seen by its author
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct PublicSuggestionStatus {
+    pub id: Uuid,
+    pub moderation_status: ModerationStatus,
+    pub moderation_reason: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Moderation state of an entity, as seen by its submitter
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct PublicModerationState {
+    pub moderation_status: ModerationStatus,
+    /// Reason given with the last moderation decision
+    pub moderation_reason: Option<String>,
+    pub suggestions: Vec<PublicSuggestionStatus>,
+}
+
+pub struct EntitySuggestion;
+
+impl EntitySuggestion {
+    async fn live_entity(entity_id: Uuid, conn: &mut PgConnection) -> Result<LiveEntity, AppError> {
+        sqlx::query_as!(
+            LiveEntity,
+            r#"
+            SELECT display_name, category_id, locations, data
+            FROM entities
+            WHERE id = $1
+            "#,
+            entity_id
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Registers the fields of the proposal that differ from the live entity for moderation
+    pub async fn submit(
+        entity_id: Uuid,
+        proposal: PublicNewEntity,
+        from_submitter: bool,
+        conn: &mut PgConnection,
+    ) -> Result<PublicSuggestionStatus, AppError> {
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+        let live = Self::live_entity(entity_id, &mut tx).await?;
+
+        // Only the submitter can move the entity to another category
+        if !from_submitter && proposal.category_id != live.category_id {
+            return Err(AppError::Validation(
+                "The category of an entity cannot be corrected".to_string(),
+            ));
+        }
+
+        let changes = SuggestedChanges::between(&live, proposal, &mut tx).await?;
+
+        let suggestion = sqlx::query_as!(
+            PublicSuggestionStatus,
+            r#"
+            INSERT INTO entity_suggestions (entity_id, from_submitter, changes)
+            VALUES ($1, $2, $3)
+            RETURNING id, moderation_status AS "moderation_status: ModerationStatus",
+                moderation_reason, created_at
+            "#,
+            entity_id,
+            from_submitter,
+            Json(changes) as _
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        // The changes requested to the submitter are made, the entity waits for a moderator again
+        if from_submitter {
+            CacheRefreshStats::trace(&mut tx, async |conn| {
+                sqlx::query!(
+                    r#"
+                    UPDATE entities
+                    SET moderation_status = 'pending'
+                    WHERE id = $1 AND moderation_status = 'changes_requested'
+                    "#,
+                    entity_id
+                )
+                .execute(conn)
+                .await
+                .map_err(AppError::Database)
+            })
+            .await?;
+        }
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(suggestion)
+    }
+
+    pub async fn moderation_state(
+        entity_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<PublicModerationState, AppError> {
+        let entity = sqlx::query!(
+            r#"
+            SELECT moderation_status AS "moderation_status: ModerationStatus", moderation_reason
+            FROM entities
+            WHERE id = $1
+            "#,
+            entity_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        let suggestions = sqlx::query_as!(
+            PublicSuggestionStatus,
+            r#"
+            SELECT id, moderation_status AS "moderation_status: ModerationStatus",
+                moderation_reason, created_at
+            FROM entity_suggestions
+            WHERE entity_id = $1 AND from_submitter
+            ORDER BY created_at DESC
+            "#,
+            entity_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(PublicModerationState {
+            moderation_status: entity.moderation_status,
+            moderation_reason: entity.moderation_reason,
+            suggestions,
+        })
+    }
+
+    pub async fn list(
+        status: ModerationStatus,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<AdminEntitySuggestion>, AppError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT s.id, s.entity_id, s.from_submitter, s.moderation_reason, s.created_at,
+                s.changes AS "changes: Json<SuggestedChanges>",
+                s.moderation_status AS "moderation_status: ModerationStatus",
+                e.display_name, e.category_id, e.locations, e.data
+            FROM entity_suggestions s
+            INNER JOIN entities e ON e.id = s.entity_id
+            WHERE s.moderation_status = $1
+            ORDER BY s.created_at
+            "#,
+            status as ModerationStatus
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let live = LiveEntity {
+                    display_name: row.display_name,
+                    category_id: row.category_id,
+                    locations: row.locations,
+                    data: row.data,
+                };
+
+                AdminEntitySuggestion {
+                    id: row.id,
+                    entity_id: row.entity_id,
+                    changes: row.changes.diff(&live),
+                    entity_display_name: live.display_name,
+                    entity_category_id: live.category_id,
+                    from_submitter: row.from_submitter,
+                    moderation_status: row.moderation_status,
+                    moderation_reason: row.moderation_reason,
+                    created_at: row.created_at,
+                }
+            })
+            .collect())
+    }
+
+    /// Applies the changes of a pending suggestion to the live entity
+    pub async fn apply(id: Uuid, user_id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+        let suggestion = sqlx::query!(
+            r#"
+            SELECT entity_id, changes AS "changes: Json<SuggestedChanges>"
+            FROM entity_suggestions
+            WHERE id = $1 AND moderation_status = 'pending'
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let live = Self::live_entity(suggestion.entity_id, &mut tx).await?;
+        let changes = suggestion.changes.0;
+
+        // The entity may have changed since the suggestion was made
+        let category_id = changes.category_id.unwrap_or(live.category_id);
+        let data = changes.merged_data(&live.data);
+        Family::get_from_category(category_id, &mut tx)
+            .await?
+            .entity_form
+            .validate_data(&data, category_id)?;
+
+        CacheRefreshStats::trace(&mut tx, async |conn| {
+            sqlx::query!(
+                r#"
+                UPDATE entities
+                SET display_name = $2, category_id = $3, locations = $4, data = $5
+                WHERE id = $1
+                "#,
+                suggestion.entity_id,
+                changes.display_name.unwrap_or(live.display_name),
+                category_id,
+                changes
+                    .locations
+                    .map(|locations| to_value(locations).unwrap())
+                    .unwrap_or(live.locations),
+                data
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
+
+        Self::decide(id, ModerationStatus::Approved, None, user_id, &mut tx).await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Rejects a pending suggestion, the live entity is left untouched
+    pub async fn reject(
+        id: Uuid,
+        reason: Option<String>,
+        user_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        let reason = reason.filter(|reason| !reason.trim().is_empty());
+        if reason.is_none() {
+            return Err(AppError::Validation(
+                "A reason is required to reject a suggestion".to_string(),
+            ));
+        }
+
+        let mut tx = conn.begin().await.map_err(AppError::Database)?;
+        Self::decide(id, ModerationStatus::Rejected, reason, user_id, &mut tx).await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    async fn decide(
+        id: Uuid,
+        status: ModerationStatus,
+        reason: Option<String>,
+        user_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            WITH decided AS (
+                UPDATE entity_suggestions
+                SET moderation_status = $2, moderation_reason = $3, decided_by = $4,
+                    decided_at = CURRENT_TIMESTAMP
+                WHERE id = $1 AND moderation_status = 'pending'
+                RETURNING id
+            )
+            INSERT INTO moderation_decisions (item_kind, item_id, user_id, decision, reason)
+            SELECT 'suggestion', id, $4, $2, $3 FROM decided
+            "#,
+            id,
+            status as ModerationStatus,
+            reason,
+            user_id
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)
+        .and_then(|result| match result.rows_affected() {
+            0 => Err(AppError::NotFound),
+            _ => Ok(()),
+        })
+    }
+}
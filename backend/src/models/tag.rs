@@ -1,4 +1,5 @@
 use crate::api::AppError;
+use crate::models::entity_cache::CacheRefreshStats;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use utoipa::ToSchema;
@@ -31,26 +32,29 @@ pub struct NewOrUpdateTag {
 
 impl Tag {
     pub async fn new(tag: NewOrUpdateTag, conn: &mut PgConnection) -> Result<Tag, AppError> {
-        sqlx::query_as!(
-            Tag,
-            r#"
-            INSERT INTO tags (title, is_filter, is_primary_filter, 
-                filter_description, default_filter_status, fill_color, border_color)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING id, title, is_filter, is_primary_filter, filter_description,
-                default_filter_status, version, fill_color, border_color
-            "#,
-            tag.title,
-            tag.is_filter,
-            tag.is_primary_filter,
-            tag.filter_description,
-            tag.default_filter_status,
-            tag.fill_color,
-            tag.border_color,
-        )
-        .fetch_one(conn)
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                Tag,
+                r#"
+                INSERT INTO tags (title, is_filter, is_primary_filter, 
+                    filter_description, default_filter_status, fill_color, border_color)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id, title, is_filter, is_primary_filter, filter_description,
+                    default_filter_status, version, fill_color, border_color
+                "#,
+                tag.title,
+                tag.is_filter,
+                tag.is_primary_filter,
+                tag.filter_description,
+                tag.default_filter_status,
+                tag.fill_color,
+                tag.border_color,
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
         .await
-        .map_err(AppError::Database)
     }
 
     pub async fn update(
@@ -63,42 +67,48 @@ impl Tag {
             return Err(AppError::Validation("Version is required".to_string()));
         }
 
-        sqlx::query_as!(
-            Tag,
-            r#"
-            UPDATE tags
-            SET title = $2, is_filter = $3, is_primary_filter = $4, filter_description = $5, 
-                default_filter_status = $6, version = $7, fill_color = $8, border_color = $9
-            WHERE id = $1
-            RETURNING id, title, is_filter, is_primary_filter, filter_description, 
-                default_filter_status, version, fill_color, border_color
-            "#,
-            given_id,
-            update.title,
-            update.is_filter,
-            update.is_primary_filter,
-            update.filter_description,
-            update.default_filter_status,
-            update.version,
-            update.fill_color,
-            update.border_color
-        )
-        .fetch_one(conn)
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query_as!(
+                Tag,
+                r#"
+                UPDATE tags
+                SET title = $2, is_filter = $3, is_primary_filter = $4, filter_description = $5, 
+                    default_filter_status = $6, version = $7, fill_color = $8, border_color = $9
+                WHERE id = $1
+                RETURNING id, title, is_filter, is_primary_filter, filter_description, 
+                    default_filter_status, version, fill_color, border_color
+                "#,
+                given_id,
+                update.title,
+                update.is_filter,
+                update.is_primary_filter,
+                update.filter_description,
+                update.default_filter_status,
+                update.version,
+                update.fill_color,
+                update.border_color
+            )
+            .fetch_one(conn)
+            .await
+            .map_err(AppError::Database)
+        })
         .await
-        .map_err(AppError::Database)
     }
 
     pub async fn delete(given_id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
-        sqlx::query!(
-            r#"
-            DELETE FROM tags
-            WHERE id = $1
-            "#,
-            given_id
-        )
-        .execute(conn)
-        .await
-        .map_err(AppError::Database)?;
+        CacheRefreshStats::trace(conn, async |conn| {
+            sqlx::query!(
+                r#"
+                DELETE FROM tags
+                WHERE id = $1
+                "#,
+                given_id
+            )
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)
+        })
+        .await?;
 
         Ok(())
     }
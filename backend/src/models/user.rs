@@ -22,6 +22,8 @@ pub struct User {
     pub name: String,
     pub is_admin: bool,
     pub last_login: Option<NaiveDateTime>,
+    /// Address of the notifications
+    pub email: Option<String>,
 }
 
 #[derive(FromRow, Deserialize, Serialize, Debug)]
@@ -31,6 +33,7 @@ pub struct AuthenticableUser {
     pub password: String,
     pub is_admin: bool,
     pub last_login: Option<NaiveDateTime>,
+    pub email: Option<String>,
 }
 
 impl From<AuthenticableUser> for User {
@@ -40,6 +43,7 @@ impl From<AuthenticableUser> for User {
             name: val.name,
             is_admin: val.is_admin,
             last_login: val.last_login,
+            email: val.email,
         }
     }
 }
@@ -76,7 +80,8 @@ impl User {
                 id,
                 name, 
                 is_admin,
-                last_login
+                last_login,
+                email
             "#,
             user.name,
             password_hash,
@@ -110,7 +115,8 @@ impl User {
                         id,
                         name,
                         is_admin,
-                        last_login
+                        last_login,
+                        email
                     "#,
                     given_id,
                     updated_user.name,
@@ -131,7 +137,8 @@ impl User {
                         id,
                         name,
                         is_admin,
-                        last_login
+                        last_login,
+                        email
                     "#,
                 given_id,
                 updated_user.name,
@@ -152,7 +159,7 @@ impl User {
     ) -> Result<User, AppError> {
         let user_result: Result<AuthenticableUser, AppError> = sqlx::query_as!(
             AuthenticableUser,
-            r#"SELECT id, name, password, is_admin, last_login FROM users WHERE name = $1"#,
+            r#"SELECT id, name, password, is_admin, last_login, email FROM users WHERE name = $1"#,
             given_name
         )
         .fetch_one(&mut *conn)
@@ -198,10 +205,13 @@ impl User {
     }
 
     pub async fn list(conn: &mut PgConnection) -> Result<Vec<User>, AppError> {
-        sqlx::query_as!(User, r#"SELECT id, name, is_admin, last_login FROM users"#)
-            .fetch_all(conn)
-            .await
-            .map_err(AppError::Database)
+        sqlx::query_as!(
+            User,
+            r#"SELECT id, name, is_admin, last_login, email FROM users"#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
     }
 
     pub async fn delete(given_id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
@@ -215,7 +225,7 @@ impl User {
     pub async fn get(given_id: Uuid, conn: &mut PgConnection) -> Result<User, AppError> {
         sqlx::query_as!(
             User,
-            r#"SELECT id, name, is_admin, last_login FROM users WHERE id = $1"#,
+            r#"SELECT id, name, is_admin, last_login, email FROM users WHERE id = $1"#,
             given_id
         )
         .fetch_one(conn)
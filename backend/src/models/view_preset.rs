@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+
+use crate::{api::AppError, models::entity_cache::ValueConstraint};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use serde_json::{to_value, Value};
+use sqlx::{types::Json, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Length of the generated share codes
+const SHARE_CODE_LENGTH: usize = 8;
+
+/// Attempts of generating a share code before giving up on collisions
+const SHARE_CODE_ATTEMPTS: usize = 3;
+
+/// Share codes an access token can create per hour
+const MAX_HOURLY_SHARES_PER_TOKEN: i64 = 500;
+
+/// Constraint violated when the code of a preset is already used by the access token
+const CODE_UNIQUE_CONSTRAINT: &str = "view_presets_access_token_id_code_key";
+
+fn is_code_collision(error: &sqlx::Error) -> bool {
+    matches!(error, sqlx::Error::Database(e) if e.constraint() == Some(CODE_UNIQUE_CONSTRAINT))
+}
+
+fn map_code_collision(error: sqlx::Error) -> AppError {
+    if is_code_collision(&error) {
+        AppError::Validation("This code is already used by another preset".to_string())
+    } else {
+        AppError::Database(error)
+    }
+}
+
+/// Filter state of the map, as sent in view and search requests, along with the map position
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ViewPresetState {
+    pub family_id: Uuid,
+    pub active_categories: Vec<Uuid>,
+    pub active_required_tags: Vec<Uuid>,
+    pub active_hidden_tags: Vec<Uuid>,
+    #[serde(default)]
+    pub enums_constraints: HashMap<String, Vec<Value>>,
+    #[serde(default)]
+    pub values_constraints: HashMap<String, ValueConstraint>,
+    /// Latitude of the map center (WGS84)
+    pub center_lat: f64,
+    /// Longitude of the map center (WGS84)
+    pub center_lng: f64,
+    /// Zoom level of the map (from 2 to 20)
+    pub zoom: u8,
+}
+
+impl ViewPresetState {
+    fn validate(&self) -> Result<(), AppError> {
+        if !(2..=20).contains(&self.zoom) {
+            return Err(AppError::Validation(
+                "Zoom must be between 2 and 20".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ViewPreset {
+    pub id: Uuid,
+    pub access_token_id: Uuid,
+    /// Code used to open the preset with the access token
+    pub code: String,
+    /// Only set for the presets defined by administrators
+    pub title: Option<String>,
+    #[schema(value_type = ViewPresetState)]
+    pub state: Json<ViewPresetState>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct NewOrUpdateViewPreset {
+    /// Code used to open the preset with the access token (letters, digits, `-` and `_`)
+    pub code: String,
+    pub title: String,
+    pub state: ViewPresetState,
+}
+
+impl NewOrUpdateViewPreset {
+    fn validate(&self) -> Result<(), AppError> {
+        if self.title.is_empty() {
+            return Err(AppError::Validation("Title cannot be empty".to_string()));
+        }
+
+        if self.code.is_empty()
+            || self.code.len() > 64
+            || !self
+                .code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(AppError::Validation(
+                "Code must be 1 to 64 letters, digits, dashes or underscores".to_string(),
+            ));
+        }
+
+        self.state.validate()
+    }
+}
+
+impl ViewPreset {
+    pub async fn new(
+        access_token_id: Uuid,
+        preset: NewOrUpdateViewPreset,
+        conn: &mut PgConnection,
+    ) -> Result<ViewPreset, AppError> {
+        preset.validate()?;
+        let state = to_value(preset.state).expect("Failed to serialize state");
+
+        sqlx::query_as!(
+            ViewPreset,
+            r#"
+            INSERT INTO view_presets (access_token_id, code, title, state)
+            VALUES ($1, $2, $3, $4)
+            RETURNING
+                id,
+                access_token_id,
+                code,
+                title,
+                state AS "state: Json<ViewPresetState>",
+                created_at
+            "#,
+            access_token_id,
+            preset.code,
+            preset.title,
+            state
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(map_code_collision)
+    }
+
+    pub async fn update(
+        access_token_id: Uuid,
+        given_id: Uuid,
+        update: NewOrUpdateViewPreset,
+        conn: &mut PgConnection,
+    ) -> Result<ViewPreset, AppError> {
+        update.validate()?;
+        let state = to_value(update.state).expect("Failed to serialize state");
+
+        sqlx::query_as!(
+            ViewPreset,
+            r#"
+            UPDATE view_presets
+            SET code = $3, title = $4, state = $5
+            WHERE id = $1 AND access_token_id = $2 AND title IS NOT NULL
+            RETURNING
+                id,
+                access_token_id,
+                code,
+                title,
+                state AS "state: Json<ViewPresetState>",
+                created_at
+            "#,
+            given_id,
+            access_token_id,
+            update.code,
+            update.title,
+            state
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(map_code_collision)
+    }
+
+    pub async fn delete(
+        access_token_id: Uuid,
+        given_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM view_presets
+            WHERE id = $1 AND access_token_id = $2
+            "#,
+            given_id,
+            access_token_id
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(())
+    }
+
+    /// Lists the presets defined by administrators for an access token
+    pub async fn list_named(
+        access_token_id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<ViewPreset>, AppError> {
+        sqlx::query_as!(
+            ViewPreset,
+            r#"
+            SELECT
+                id,
+                access_token_id,
+                code,
+                title,
+                state AS "state: Json<ViewPresetState>",
+                created_at
+            FROM view_presets
+            WHERE access_token_id = $1 AND title IS NOT NULL
+            ORDER BY title
+            "#,
+            access_token_id
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn get_by_code(
+        access_token_id: Uuid,
+        code: &str,
+        conn: &mut PgConnection,
+    ) -> Result<ViewPreset, AppError> {
+        sqlx::query_as!(
+            ViewPreset,
+            r#"
+            UPDATE view_presets
+            SET last_used_at = CURRENT_TIMESTAMP
+            WHERE access_token_id = $1 AND code = $2
+            RETURNING
+                id,
+                access_token_id,
+                code,
+                title,
+                state AS "state: Json<ViewPresetState>",
+                created_at
+            "#,
+            access_token_id,
+            code
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Creates an anonymous share code for a filter state, or returns the existing one if the same
+    /// state was already shared with this access token
+    pub async fn share(
+        access_token_id: Uuid,
+        state: ViewPresetState,
+        conn: &mut PgConnection,
+    ) -> Result<ViewPreset, AppError> {
+        state.validate()?;
+        let state = to_value(state).expect("Failed to serialize state");
+
+        let hourly_shares = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM view_presets
+            WHERE access_token_id = $1 AND title IS NULL
+                AND created_at > CURRENT_TIMESTAMP - INTERVAL '1 hour'
+            "#,
+            access_token_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        if hourly_shares >= MAX_HOURLY_SHARES_PER_TOKEN {
+            return Err(AppError::TooManyRequests(3600));
+        }
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let code = Alphanumeric.sample_string(&mut rand::thread_rng(), SHARE_CODE_LENGTH);
+
+            let shared = sqlx::query_as!(
+                ViewPreset,
+                r#"
+                INSERT INTO view_presets (access_token_id, code, state)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (access_token_id, md5(state::text)) WHERE title IS NULL
+                DO UPDATE SET state = EXCLUDED.state, last_used_at = CURRENT_TIMESTAMP
+                RETURNING
+                    id,
+                    access_token_id,
+                    code,
+                    title,
+                    state AS "state: Json<ViewPresetState>",
+                    created_at
+                "#,
+                access_token_id,
+                code,
+                state
+            )
+            .fetch_one(&mut *conn)
+            .await;
+
+            match shared {
+                Err(e) if is_code_collision(&e) && attempts < SHARE_CODE_ATTEMPTS => continue,
+                shared => return shared.map_err(map_code_collision),
+            }
+        }
+    }
+
+    /// Deletes the anonymous share codes not opened for longer than the retention period
+    pub async fn purge_unused(
+        retention_days: i32,
+        conn: &mut PgConnection,
+    ) -> Result<u64, AppError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM view_presets
+            WHERE title IS NULL AND last_used_at < CURRENT_TIMESTAMP - make_interval(days => $1)
+            "#,
+            retention_days
+        )
+        .execute(conn)
+        .await
+        .map(|result| result.rows_affected())
+        .map_err(AppError::Database)
+    }
+}
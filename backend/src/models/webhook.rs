@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::AppError;
+
+/// Attempts of a delivery before giving up on it
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+/// Delay before the first retry of a delivery, doubled on each attempt
+const RETRY_BASE_DELAY_SECONDS: i32 = 30;
+
+#[derive(sqlx::Type, Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[sqlx(type_name = "webhook_event", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    EntityCreated,
+    EntityUpdated,
+    EntityModerated,
+    EntityDeleted,
+    CommentCreated,
+    ReportFiled,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct NewOrUpdateWebhook {
+    pub title: String,
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature of the payloads, never sent back. Required on creation,
+    /// the current key is kept when omitted on update.
+    pub secret: Option<String>,
+    /// Events sent to the webhook
+    pub events: Vec<WebhookEvent>,
+    pub active: bool,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub title: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub active: bool,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: WebhookEvent,
+    pub payload: Value,
+    /// pending, delivered or failed
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    /// HTTP status of the last attempt
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub delivered_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Delivery claimed by an instance, along with its destination
+pub struct DueDelivery {
+    pub id: Uuid,
+    pub event: WebhookEvent,
+    pub payload: Value,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+impl NewOrUpdateWebhook {
+    fn validate(&self, is_new: bool) -> Result<(), AppError> {
+        if !self.url.starts_with("https://") && !self.url.starts_with("http://") {
+            return Err(AppError::Validation(
+                "The URL of the webhook must use HTTP or HTTPS".to_string(),
+            ));
+        }
+
+        if self
+            .secret
+            .as_ref()
+            .map_or(is_new, |secret| secret.is_empty())
+        {
+            return Err(AppError::Validation(
+                "The secret of the webhook cannot be empty".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Webhook {
+    pub async fn new(
+        new_webhook: NewOrUpdateWebhook,
+        conn: &mut PgConnection,
+    ) -> Result<Webhook, AppError> {
+        new_webhook.validate(true)?;
+
+        sqlx::query_as!(
+            Webhook,
+            r#"
+            INSERT INTO webhooks (title, url, secret, events, active)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, title, url, events AS "events: Vec<WebhookEvent>", active,
+                created_at, updated_at
+            "#,
+            new_webhook.title,
+            new_webhook.url,
+            new_webhook.secret,
+            &new_webhook.events as &[WebhookEvent],
+            new_webhook.active
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn update(
+        id: Uuid,
+        update: NewOrUpdateWebhook,
+        conn: &mut PgConnection,
+    ) -> Result<Webhook, AppError> {
+        update.validate(false)?;
+
+        sqlx::query_as!(
+            Webhook,
+            r#"
+            UPDATE webhooks
+            SET title = $2, url = $3, secret = COALESCE($4, secret), events = $5, active = $6
+            WHERE id = $1
+            RETURNING id, title, url, events AS "events: Vec<WebhookEvent>", active,
+                created_at, updated_at
+            "#,
+            id,
+            update.title,
+            update.url,
+            update.secret,
+            &update.events as &[WebhookEvent],
+            update.active
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn delete(id: Uuid, conn: &mut PgConnection) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM webhooks
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn get(id: Uuid, conn: &mut PgConnection) -> Result<Webhook, AppError> {
+        sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT id, title, url, events AS "events: Vec<WebhookEvent>", active,
+                created_at, updated_at
+            FROM webhooks
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn list(conn: &mut PgConnection) -> Result<Vec<Webhook>, AppError> {
+        sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT id, title, url, events AS "events: Vec<WebhookEvent>", active,
+                created_at, updated_at
+            FROM webhooks
+            ORDER BY title
+            "#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+impl WebhookDelivery {
+    pub async fn list_for_webhook(
+        webhook_id: Uuid,
+        limit: i64,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            SELECT id, webhook_id, event AS "event: WebhookEvent", payload, status, attempts,
+                next_attempt_at, response_status, last_error, created_at, delivered_at
+            FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            webhook_id,
+            limit
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Schedules a new attempt of a delivery, even a failed one
+    pub async fn retry(
+        webhook_id: Uuid,
+        id: Uuid,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'pending', attempts = 0, next_attempt_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND webhook_id = $2 AND status != 'delivered'
+            "#,
+            id,
+            webhook_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        sqlx::query!("SELECT pg_notify('webhook_deliveries', 'retry')")
+            .execute(conn)
+            .await
+            .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Claims the deliveries due now. They are postponed while being sent, so the other
+    /// instances skip them, and are sent again if this instance stops before recording them.
+    pub async fn claim_due(
+        limit: i64,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<DueDelivery>, AppError> {
+        sqlx::query_as!(
+            DueDelivery,
+            r#"
+            WITH due AS (
+                SELECT d.id
+                FROM webhook_deliveries d
+                WHERE d.status = 'pending' AND d.next_attempt_at <= CURRENT_TIMESTAMP
+                ORDER BY d.next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            ),
+            claimed AS (
+                UPDATE webhook_deliveries d
+                SET attempts = d.attempts + 1,
+                    next_attempt_at = CURRENT_TIMESTAMP + INTERVAL '5 minutes'
+                FROM due
+                WHERE d.id = due.id
+                RETURNING d.id, d.webhook_id, d.event, d.payload, d.attempts
+            )
+            SELECT c.id AS "id!", c.event AS "event!: WebhookEvent", c.payload AS "payload!",
+                c.attempts AS "attempts!", w.url, w.secret
+            FROM claimed c
+            INNER JOIN webhooks w ON w.id = c.webhook_id
+            "#,
+            limit
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Records the outcome of an attempt, and schedules the next one with an exponential backoff
+    pub async fn record_attempt(
+        delivery: &DueDelivery,
+        response_status: Option<u16>,
+        error: Option<String>,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET response_status = $2,
+                last_error = $3,
+                status = CASE
+                    WHEN $3::text IS NULL THEN 'delivered'
+                    WHEN attempts >= $4 THEN 'failed'
+                    ELSE 'pending'
+                END,
+                delivered_at = CASE WHEN $3::text IS NULL THEN CURRENT_TIMESTAMP END,
+                next_attempt_at = CURRENT_TIMESTAMP
+                    + make_interval(secs => $5 * power(2, LEAST(attempts - 1, 10)))
+            WHERE id = $1
+            "#,
+            delivery.id,
+            response_status.map(i32::from),
+            error,
+            MAX_DELIVERY_ATTEMPTS,
+            RETRY_BASE_DELAY_SECONDS as f64
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Forgets the deliveries handled for longer than the retention period
+    pub async fn prune(retention_days: i32, conn: &mut PgConnection) -> Result<u64, AppError> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM webhook_deliveries
+            WHERE status != 'pending'
+                AND created_at < CURRENT_TIMESTAMP - make_interval(days => $1)
+            "#,
+            retention_days
+        )
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?
+        .rows_affected())
+    }
+}
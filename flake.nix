@@ -150,6 +150,15 @@
             backend = {
               command = "cargo run -- serve";
               working_dir = "./backend";
+              # Notifications are caught by mailpit, browse them on http://localhost:8025
+              environment = [
+                "SH__SMTP__ENABLED=true"
+                "SH__SMTP__PORT=1025"
+                "SH__SMTP__SECURITY=none"
+              ];
+            };
+            mailpit = {
+              command = "${pkgs.mailpit}/bin/mailpit";
             };
             frontend = {
               command = "npm run dev";
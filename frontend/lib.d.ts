@@ -13,6 +13,8 @@ export type InitConfig = api.components['schemas']['StatusResponse']
 export type SafeHavenVersion = api.components['schemas']['SafeHavenVersionResponse']
 
 export type SafeHavenOptions = api.components['schemas']['SafeHavenOptions']
+export type CaptchaProvider = api.components['schemas']['CaptchaProvider']
+export type ProofOfWorkChallenge = api.components['schemas']['ProofOfWorkChallenge']
 export type ConfigurationOption = api.components['schemas']['ConfigurationOption']
 
 export type Category = api.components['schemas']['Category']
@@ -27,7 +29,7 @@ export type Cluster = api.components['schemas']['Cluster']
 
 export type ViewerCachedEntity = api.components['schemas']['ViewerCachedEntity']
 export type ViewerSearchedCachedEntity = api.components['schemas']['ViewerSearchedCachedEntity']
-export type ViewerPaginatedCachedEntities = PaginatedVec<ViewerSearchedCachedEntity>
+export type ViewerPaginatedCachedEntities = api.components['schemas']['ViewerSearchResults']
 
 export type FetchedEntity = api.components['schemas']['FetchedEntity']
 export type ResolvedFetchedEntity = FetchedEntity & {
@@ -42,15 +44,25 @@ export type AdminEntityWithRelations = api.components['schemas']['AdminEntityWit
 export type AdminNewOrUpdateEntity = api.components['schemas']['AdminNewOrUpdateEntity']
 export type AdminSearchRequestBody = api.components['schemas']['AdminSearchRequest']
 export type AdminCachedEntity = api.components['schemas']['AdminCachedEntity']
-export type AdminPaginatedCachedEntities = PaginatedVec<AdminCachedEntity>
+export type ModerationStatus = api.components['schemas']['ModerationStatus']
+export type AdminCachedEntitiesPage = api.components['schemas']['AdminCachedEntitiesPage']
+export type AdminSearchCursor = api.components['schemas']['AdminSearchCursor']
+export type EntitiesSort = api.components['schemas']['EntitiesSort']
 
 export type PublicComment = api.components['schemas']['PublicComment']
 export type PublicNewComment = api.components['schemas']['PublicNewComment']
+export type PublicCommentsWithPagination = api.components['schemas']['PublicCommentsWithPagination']
 export type PublicNewCommentRequest = api.components['schemas']['NewCommentRequest']
 export type AdminComment = api.components['schemas']['AdminComment']
 export type AdminListedComment = api.components['schemas']['AdminListedComment']
 export type AdminNewOrUpdateComment = api.components['schemas']['AdminNewOrUpdateComment']
 
+export type ReportReason = api.components['schemas']['ReportReason']
+export type PublicNewReport = api.components['schemas']['PublicNewReport']
+export type ReportEntityRequest = api.components['schemas']['ReportEntityRequest']
+export type AdminReport = api.components['schemas']['AdminReport']
+export type AdminReportedEntity = api.components['schemas']['AdminReportedEntity']
+
 export type ErrorResponse = api.components['schemas']['ErrorResponse']
 
 export type NewOrUpdateCategory = api.components['schemas']['NewOrUpdateCategory']
@@ -64,6 +76,7 @@ export type BootstrapPermissions = api.components['schemas']['BootstrapPermissio
 
 export type User = api.components['schemas']['User']
 export type NewOrUpdatedUser = api.components['schemas']['NewOrUpdatedUser']
+export type NotificationPreferences = api.components['schemas']['NotificationPreferences']
 export type AdminUserTokenClaims = api.components['schemas']['AdminUserTokenClaims']
 
 export type UnprocessedLocation = api.components['schemas']['UnprocessedLocation']
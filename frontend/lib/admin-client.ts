@@ -16,6 +16,7 @@ import type {
   NewOrUpdateAccessToken,
   User,
   NewOrUpdatedUser,
+  NotificationPreferences,
   SafeHavenOptions,
   ConfigurationOption,
   AdminSearchRequestBody,
@@ -23,8 +24,10 @@ import type {
   AdminEntityWithRelations,
   SafeHavenVersion,
   Family,
-  AdminPaginatedCachedEntities,
+  AdminCachedEntitiesPage,
   AccessToken,
+  AdminReport,
+  AdminReportedEntity,
 } from '~/lib'
 
 // client as a closure
@@ -146,6 +149,25 @@ export default function useClient() {
       return data
     },
 
+    async getSelfNotifications(): Promise<NotificationPreferences> {
+      const { data, error } = await this.rawClient.GET('/api/admin/users/self/notifications')
+      if (error) throw error
+      return data
+    },
+
+    async updateSelfNotifications(preferences: NotificationPreferences): Promise<NotificationPreferences> {
+      const { data, error } = await this.rawClient.PUT('/api/admin/users/self/notifications', {
+        body: preferences,
+      })
+      if (error) throw error
+      return data
+    },
+
+    async sendSelfTestNotification(): Promise<void> {
+      const { error } = await this.rawClient.POST('/api/admin/users/self/notifications/test')
+      if (error) throw error
+    },
+
     async updateUser(id: string, updatedUser: NewOrUpdatedUser): Promise<User> {
       const { data, error } = await this.rawClient.PUT(`/api/admin/users/{id}`, {
         params: { path: { id } },
@@ -209,11 +231,11 @@ export default function useClient() {
     },
 
     async searchEntities(
-      pagination: { page: number, page_size: number },
+      page_size: number,
       search_request: AdminSearchRequestBody,
-    ): Promise<AdminPaginatedCachedEntities> {
+    ): Promise<AdminCachedEntitiesPage> {
       const { data, error } = await this.rawClient.POST('/api/admin/entities/search', {
-        params: { query: pagination },
+        params: { query: { page_size } },
         body: search_request,
       })
       if (error) throw error
@@ -384,6 +406,28 @@ export default function useClient() {
       return data
     },
 
+    async listReportedEntities(): Promise<AdminReportedEntity[]> {
+      const { data, error } = await this.rawClient.GET('/api/admin/reports')
+      if (error) throw error
+      return data
+    },
+
+    async listEntityReports(id: string): Promise<AdminReport[]> {
+      const { data, error } = await this.rawClient.GET('/api/admin/entities/{id}/reports', {
+        params: { path: { id } },
+      })
+      if (error) throw error
+      return data
+    },
+
+    async resolveEntityReports(id: string, unhide: boolean): Promise<void> {
+      const { error } = await this.rawClient.POST('/api/admin/entities/{id}/reports/resolve', {
+        body: { unhide },
+        params: { path: { id } },
+      })
+      if (error) throw error
+    },
+
     async listAccessTokens(): Promise<AccessToken[]> {
       const { data, error } = await this.rawClient.GET('/api/admin/access_tokens')
       if (error) throw error
@@ -15,6 +15,8 @@ import type {
   FamilyRecord,
   TagRecord,
   EnumFilter,
+  AdminSearchCursor,
+  EntitiesSort,
   SafeHavenVersion,
 } from '~/lib'
 
@@ -44,8 +46,10 @@ export class AppState {
   public tablesFilters: Record<string, DataTableFilterMeta> = {}
   public tablesQueryParams: Record<string, {
     search_query: string
-    currentPage: number
+    // Cursors of the pages visited so far, the last one being the current page
+    cursors: (AdminSearchCursor | null)[]
     pageSize: number
+    sortBy: EntitiesSort['by']
     categoryFilteringList?: (Category & { active: boolean })[]
     tagFilteringList?: (Tag & { active: boolean | null })[]
     enumsFilteringList: EnumFilter[]
@@ -110,8 +114,26 @@ export class AppState {
       },
       safe_mode: {
         enabled: false,
-        hcaptcha_secret: '',
-        hcaptcha_sitekey: '',
+        captcha_provider: 'hcaptcha',
+        captcha_secret: '',
+        captcha_sitekey: '',
+        captcha_verify_url: null,
+        proof_of_work_difficulty: 18,
+      },
+      spam_filter: {
+        enabled: false,
+        max_hourly_submissions_per_client: 5,
+        max_hourly_submissions_per_token: 200,
+        duplicate_distance: 100,
+        max_links: 2,
+        banned_words: [],
+        reject_threshold: null,
+      },
+      moderation: {
+        appeal_period_days: 30,
+        reports_hide_threshold: null,
+        reports_hide_window_hours: 48,
+        max_hourly_reports_hides: 3,
       },
       cartography_init: {
         center_lat: 0,
@@ -1,7 +1,7 @@
 import createClient from 'openapi-fetch'
 import type { paths } from './api'
 import createAuthMiddleware from './viewer-auth-middleware'
-import type { FetchedEntity, PublicNewCommentRequest, PublicNewEntityRequest, PublicComment, ViewerPaginatedCachedEntities, PublicNewEntityResponse } from '~/lib'
+import type { FetchedEntity, PublicNewCommentRequest, PublicNewEntityRequest, PublicComment, ViewerPaginatedCachedEntities, PublicNewEntityResponse, ReportEntityRequest, ProofOfWorkChallenge, PublicCommentsWithPagination } from '~/lib'
 
 type Callback = () => Promise<void>
 
@@ -22,6 +22,12 @@ export default function useClient() {
       return data
     },
 
+    async getCaptchaChallenge(): Promise<ProofOfWorkChallenge> {
+      const { data, error } = await rawClient.GET('/api/captcha/challenge')
+      if (error) throw error
+      return data
+    },
+
     async bootstrap(token: string) {
       const { data, error } = await rawClient.GET('/api/bootstrap/{token}', {
         params: { path: { token }, query: {
@@ -88,6 +94,7 @@ export default function useClient() {
           active_categories: activeCategories,
           active_required_tags: activeRequiredTags,
           active_hidden_tags: activeHiddenTags,
+          comments: { order: 'newest_first' },
         },
       })
       if (error) throw error
@@ -95,6 +102,16 @@ export default function useClient() {
       return data
     },
 
+    async listEntityComments(id: string, page: number): Promise<PublicCommentsWithPagination> {
+      const { data, error } = await rawClient.POST('/api/map/entities/{id}/comments', {
+        params: { path: { id } },
+        body: { page, page_size: 20, order: 'newest_first' },
+      })
+      if (error) throw error
+
+      return data
+    },
+
     async searchEntities(
       query: string,
       familyId: string,
@@ -135,5 +152,13 @@ export default function useClient() {
       if (error) throw error
       return data
     },
+
+    async reportEntity(id: string, report: ReportEntityRequest): Promise<void> {
+      const { error } = await this.rawClient.POST('/api/map/entities/{id}/reports', {
+        body: report,
+        params: { path: { id } },
+      })
+      if (error) throw error
+    },
   }
 }
@@ -162,8 +162,12 @@ export class AppState {
     return this.initConfig!.safe_mode
   }
 
-  get hCaptchaSiteKey() {
-    return this.initConfig?.safe_mode.hcaptcha_sitekey
+  get captchaProvider() {
+    return this.initConfig?.safe_mode.captcha_provider
+  }
+
+  get captchaSiteKey() {
+    return this.initConfig?.safe_mode.captcha_sitekey
   }
 
   get title() {